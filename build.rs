@@ -11,7 +11,7 @@ fn main() {
     };
     let target = env::var("TARGET").unwrap();
 
-    #[allow(clippy::single_match)]
+    #[allow(clippy::single_match, clippy::collapsible_match)]
     match env::var("CARGO_CFG_TARGET_OS").unwrap().as_ref() {
         "linux" => {
             // statically link libunwind if compiling for musl, dynamically link otherwise
@@ -0,0 +1,120 @@
+#![allow(unused_crate_dependencies)]
+
+// Prints the stacks of a process whose binaries were stripped of their debug info, downloading
+// the debug info from a debuginfod server (like https://debuginfod.elfutils.org) with curl:
+//
+//   cargo run --features unwind --example symbol_server <pid> <server url> [cache dir]
+
+#[cfg(all(feature = "unwind", target_os = "linux"))]
+mod source {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use remoteprocess::{Error, SymbolSource};
+
+    /// Downloads debug info from a server using the debuginfod URL layout, where the debug
+    /// info for a build id is at `<url>/buildid/<hex build id>/debuginfo`. Downloaded files
+    /// are kept in `cache_dir` and reused on later lookups.
+    pub struct DebuginfodSource {
+        pub url: String,
+        pub cache_dir: PathBuf,
+    }
+
+    impl SymbolSource for DebuginfodSource {
+        fn find_debug_file(
+            &self,
+            build_id: &[u8],
+            filename: &str,
+        ) -> Result<Option<PathBuf>, Error> {
+            let build_id: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+            let dest = self.cache_dir.join(format!("{}.debug", build_id));
+            if dest.exists() {
+                return Ok(Some(dest));
+            }
+
+            let url = format!(
+                "{}/buildid/{}/debuginfo",
+                self.url.trim_end_matches('/'),
+                build_id
+            );
+            println!("downloading debug info for {} from {}", filename, url);
+
+            // download to a temporary file first, so that an interrupted download isn't
+            // mistaken for a cached file later on
+            std::fs::create_dir_all(&self.cache_dir)?;
+            let partial = dest.with_extension("partial");
+            let status = Command::new("curl")
+                .args(["--silent", "--fail", "--location", "--output"])
+                .arg(&partial)
+                .arg(&url)
+                .status()?;
+            if !status.success() {
+                let _ = std::fs::remove_file(&partial);
+                // curl exits with 22 when --fail sees an error status, like a 404 for a build
+                // id the server doesn't have
+                return match status.code() {
+                    Some(22) => Ok(None),
+                    _ => Err(Error::Other(format!(
+                        "Failed to download {}: curl {}",
+                        url, status
+                    ))),
+                };
+            }
+            std::fs::rename(&partial, &dest)?;
+            Ok(Some(dest))
+        }
+    }
+}
+
+#[cfg(all(feature = "unwind", target_os = "linux"))]
+fn print_stacks(
+    pid: remoteprocess::Pid,
+    source: source::DebuginfodSource,
+) -> Result<(), remoteprocess::Error> {
+    let process = remoteprocess::Process::new(pid)?;
+    let unwinder = process.unwinder()?;
+    let mut symbolicator = process.symbolicator()?;
+    symbolicator.add_symbol_source(Box::new(source));
+
+    for thread in process.threads()?.iter() {
+        println!("Thread {}", thread.id()?);
+        let ips = {
+            let _lock = thread.lock()?;
+            unwinder.cursor(thread)?.collect::<Result<Vec<_>, _>>()?
+        };
+        for frames in symbolicator.symbolicate_all(&ips, true) {
+            for sf in frames? {
+                println!("\t{}", sf);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "unwind", target_os = "linux"))]
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        println!("usage: {} <pid> <server url> [cache dir]", args[0]);
+        std::process::exit(1);
+    }
+    let pid = args[1].parse().expect("invalid pid");
+    let source = source::DebuginfodSource {
+        url: args[2].clone(),
+        cache_dir: args
+            .get(3)
+            .map(Into::into)
+            .unwrap_or_else(|| std::env::temp_dir().join("debuginfod")),
+    };
+
+    if let Err(e) = print_stacks(pid, source) {
+        println!("Failed to get stacks {:?}", e);
+    }
+}
+
+#[cfg(not(all(feature = "unwind", target_os = "linux")))]
+fn main() {
+    panic!("symbol sources are only supported on linux with the unwind feature");
+}
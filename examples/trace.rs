@@ -1,3 +1,5 @@
+#![allow(unused_crate_dependencies)]
+
 #[cfg(feature = "unwind")]
 fn get_backtrace(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
     // Create a new handle to the process
@@ -8,19 +10,22 @@ fn get_backtrace(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
     let unwinder = process.unwinder()?;
     let symbolicator = process.symbolicator()?;
     for thread in process.threads()?.iter() {
+        // lock the thread to get a consistent snapshot (unwinding will fail otherwise)
+        // Note: the thread will appear idle when locked, so we ask the lock whether it
+        // was running right before it was stopped
+        let lock = thread.lock()?;
         println!(
             "Thread {} - {}",
             thread.id()?,
-            if thread.active()? { "running" } else { "idle" }
+            if lock.was_active().unwrap_or(false) {
+                "running"
+            } else {
+                "idle"
+            }
         );
 
-        // lock the thread to get a consistent snapshot (unwinding will fail otherwise)
-        // Note: the thread will appear idle when locked, so we are calling
-        // thread.active() before this
-        let _lock = thread.lock()?;
-
         // Iterate over the callstack for the current thread
-        for ip in unwinder.cursor(&thread)? {
+        for ip in unwinder.cursor(thread)? {
             let ip = ip?;
 
             // Lookup the current stack frame containing a filename/function/linenumber etc
@@ -0,0 +1,137 @@
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{Error, Pid, Process};
+
+/// What an address in a process points at, as returned by `Process::classify_address`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressClass {
+    /// Executable memory. `module` is the binary the code belongs to, or None for code
+    /// generated at runtime (like by a JIT).
+    Code { module: Option<String> },
+    /// The stack of the main thread. The stacks of other threads are `Anonymous`, since
+    /// they are allocated like any other memory.
+    Stack,
+    /// The heap grown with brk. Large allocations are mmapped separately, and are `Anonymous`.
+    Heap,
+    /// A file that is mapped without being executable, like the data of a binary
+    MappedFile { filename: String },
+    /// Memory that isn't backed by a file, like mmapped allocations and thread stacks
+    Anonymous,
+    /// Nothing is mapped at the address
+    Unmapped,
+}
+
+impl AddressClass {
+    /// Returns true if the address could be a valid instruction pointer
+    pub fn is_code(&self) -> bool {
+        matches!(self, Self::Code { .. })
+    }
+}
+
+/// A snapshot of the memory maps of a process, for classifying many addresses without reading
+/// the maps again for each one (like every frame of an unwind)
+#[derive(Debug, Clone)]
+pub struct AddressMap {
+    /// The mappings, sorted by address
+    regions: Vec<(Range<u64>, AddressClass)>,
+}
+
+impl AddressMap {
+    pub fn new(pid: Pid) -> Result<Self, Error> {
+        let mut regions: Vec<_> = proc_maps::get_process_maps(pid)?
+            .iter()
+            .map(|m| {
+                let start = m.start() as u64;
+                let range = start..start + m.size() as u64;
+                (range, classify(m.filename(), m.is_exec()))
+            })
+            .collect();
+        regions.sort_by_key(|(range, _)| range.start);
+        Ok(Self { regions })
+    }
+
+    pub fn classify(&self, addr: u64) -> &AddressClass {
+        let i = self
+            .regions
+            .partition_point(|(range, _)| range.start <= addr);
+        match i.checked_sub(1).map(|i| &self.regions[i]) {
+            Some((range, class)) if range.contains(&addr) => class,
+            _ => &AddressClass::Unmapped,
+        }
+    }
+
+    /// Returns the range of the mapping that contains `addr`, if any
+    pub fn mapping(&self, addr: u64) -> Option<Range<u64>> {
+        let i = self
+            .regions
+            .partition_point(|(range, _)| range.start <= addr);
+        let (range, _) = &self.regions[i.checked_sub(1)?];
+        range.contains(&addr).then(|| range.clone())
+    }
+}
+
+impl Process {
+    /// Classifies an address by the memory mapping it's in. This reads the memory maps of the
+    /// process on every call, so use an `AddressMap` to classify many addresses at once.
+    pub fn classify_address(&self, addr: u64) -> Result<AddressClass, Error> {
+        Ok(AddressMap::new(self.pid)?.classify(addr).clone())
+    }
+}
+
+fn classify(filename: Option<&Path>, executable: bool) -> AddressClass {
+    let name = filename.map(|f| f.to_string_lossy().into_owned());
+    match name.as_deref() {
+        _ if executable => AddressClass::Code {
+            module: name.filter(|name| !name.is_empty()),
+        },
+        Some("[stack]") => AddressClass::Stack,
+        Some("[heap]") => AddressClass::Heap,
+        Some(name) if !name.is_empty() && !name.starts_with('[') => AddressClass::MappedFile {
+            filename: name.to_string(),
+        },
+        _ => AddressClass::Anonymous,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify(Some(Path::new("/usr/lib/libc.so.6")), true),
+            AddressClass::Code {
+                module: Some("/usr/lib/libc.so.6".to_string())
+            }
+        );
+        assert_eq!(classify(None, true), AddressClass::Code { module: None });
+        assert_eq!(
+            classify(Some(Path::new("[stack]")), false),
+            AddressClass::Stack
+        );
+        assert_eq!(
+            classify(Some(Path::new("[heap]")), false),
+            AddressClass::Heap
+        );
+        assert_eq!(
+            classify(Some(Path::new("[vvar]")), false),
+            AddressClass::Anonymous
+        );
+        assert_eq!(classify(None, false), AddressClass::Anonymous);
+    }
+
+    #[test]
+    fn test_classify_address() {
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let code = test_classify_address as *const () as u64;
+        assert!(process.classify_address(code).unwrap().is_code());
+
+        let local = 0u64;
+        let map = AddressMap::new(std::process::id() as Pid).unwrap();
+        assert!(!map.classify(&local as *const u64 as u64).is_code());
+        assert!(map.mapping(code).is_some_and(|range| range.contains(&code)));
+        assert_eq!(map.classify(0), &AddressClass::Unmapped);
+    }
+}
@@ -0,0 +1,149 @@
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::{Error, Pid};
+
+/// A pollable handle that becomes readable when a process exits.
+///
+/// This wraps a kqueue with an EVFILT_PROC filter registered for the process, which can be
+/// added to an event loop through `AsRawFd`, or checked directly with `has_exited` and `wait`.
+pub struct ExitNotification {
+    pid: Pid,
+    kqueue: OwnedFd,
+    exited: AtomicBool,
+}
+
+impl ExitNotification {
+    /// Watches `pid` for exiting. `start_time` is the start time the process had when it was
+    /// opened, and `current_start_time` reads the start time of whatever process has the pid
+    /// now, so that a reused pid isn't mistaken for the original process.
+    pub(crate) fn new<T: PartialEq>(
+        pid: Pid,
+        start_time: Option<T>,
+        current_start_time: impl FnOnce(Pid) -> Result<T, Error>,
+    ) -> Result<Self, Error> {
+        let fd = unsafe { libc::kqueue() };
+        if fd < 0 {
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        let kqueue = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut change: libc::kevent = unsafe { std::mem::zeroed() };
+        change.ident = pid as libc::uintptr_t;
+        change.filter = libc::EVFILT_PROC;
+        change.flags = libc::EV_ADD | libc::EV_ONESHOT;
+        change.fflags = libc::NOTE_EXIT;
+        let ret =
+            unsafe { libc::kevent(fd, &change, 1, std::ptr::null_mut(), 0, std::ptr::null()) };
+        // ESRCH means the process is already gone
+        let exited = if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(Error::IOError(err));
+            }
+            true
+        } else {
+            false
+        };
+
+        // the pid might have been reused since the process was opened, in which case we are
+        // now watching some other process
+        if let (Some(expected), false) = (start_time, exited) {
+            if current_start_time(pid).is_ok_and(|current| current != expected) {
+                return Err(Error::Other(format!(
+                    "Process {} has exited and its pid has been reused",
+                    pid
+                )));
+            }
+        }
+
+        Ok(Self {
+            pid,
+            kqueue,
+            exited: AtomicBool::new(exited),
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns true if the process has exited, without blocking
+    pub fn has_exited(&self) -> Result<bool, Error> {
+        self.wait(Some(Duration::ZERO))
+    }
+
+    /// Blocks until the process exits or the timeout elapses, returning true if the process
+    /// has exited. A timeout of None waits forever.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        // the exit event is only delivered once, so remember that we've seen it
+        if self.exited.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+        let timeout = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        });
+        let timeout_ptr = match timeout.as_ref() {
+            Some(timeout) => timeout as *const libc::timespec,
+            None => std::ptr::null(),
+        };
+        let mut event: libc::kevent = unsafe { std::mem::zeroed() };
+        loop {
+            let ret = unsafe {
+                libc::kevent(
+                    self.kqueue.as_raw_fd(),
+                    std::ptr::null(),
+                    0,
+                    &mut event,
+                    1,
+                    timeout_ptr,
+                )
+            };
+            if ret >= 0 {
+                if ret > 0 {
+                    self.exited.store(true, Ordering::Relaxed);
+                }
+                return Ok(ret > 0);
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(Error::IOError(err));
+            }
+        }
+    }
+}
+
+impl AsRawFd for ExitNotification {
+    fn as_raw_fd(&self) -> RawFd {
+        self.kqueue.as_raw_fd()
+    }
+}
+
+impl AsFd for ExitNotification {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.kqueue.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_notification() {
+        let mut child = crate::tests::TestChild::sleep();
+        let pid = child.pid();
+        let notification = ExitNotification::new(pid, None::<()>, |_| Ok(())).unwrap();
+        assert!(!notification.has_exited().unwrap());
+        assert!(!notification.wait(Some(Duration::from_millis(1))).unwrap());
+
+        child.kill().unwrap();
+        assert!(notification.wait(Some(Duration::from_secs(5))).unwrap());
+        assert!(notification.has_exited().unwrap());
+
+        // a process whose pid now belongs to a different process isn't watched
+        assert!(ExitNotification::new(std::process::id() as Pid, Some(1), |_| Ok(2)).is_err());
+    }
+}
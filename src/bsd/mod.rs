@@ -0,0 +1,43 @@
+//! Implementations shared by the BSD based platforms, OSX and FreeBSD
+mod exit;
+
+pub use self::exit::ExitNotification;
+
+use crate::{Error, Pid};
+
+/// Sends a signal to a process. `exists` is whether the process that was opened still has
+/// this pid, since sending the signal to a process that reused it would be wrong.
+pub(crate) fn signal(pid: Pid, exists: bool, signal: i32) -> Result<(), Error> {
+    if !exists {
+        return Err(Error::ProcessExited(pid));
+    }
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            return Err(Error::ProcessExited(pid));
+        }
+        return Err(Error::IOError(err));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal() {
+        let mut child = crate::tests::TestChild::sleep();
+        let pid = child.pid();
+        assert!(matches!(
+            signal(pid, false, libc::SIGKILL).unwrap_err().root(),
+            Error::ProcessExited(p) if *p == pid
+        ));
+        signal(pid, true, libc::SIGKILL).unwrap();
+        child.wait().unwrap();
+        assert!(matches!(
+            signal(pid, true, libc::SIGKILL).unwrap_err().root(),
+            Error::ProcessExited(_)
+        ));
+    }
+}
@@ -2,6 +2,7 @@ use libc::{pid_t, waitpid, WIFSTOPPED};
 use log::error;
 
 use std::io::Error as IoError;
+use std::time::{Duration, Instant};
 
 use super::ptrace;
 use super::Error;
@@ -9,10 +10,12 @@ use super::Error;
 #[derive(Debug)]
 pub struct ProcessLock {
     pid: pid_t,
+    locked_at: Instant,
 }
 
 impl ProcessLock {
     pub fn new(pid: pid_t) -> Result<Self, Error> {
+        let locked_at = Instant::now();
         ptrace::attach(pid)?;
         let mut wait_status = 0;
 
@@ -25,7 +28,12 @@ impl ProcessLock {
             return Err(Error::IOError(IoError::last_os_error()));
         }
 
-        Ok(ProcessLock { pid })
+        Ok(ProcessLock { pid, locked_at })
+    }
+
+    /// How long the process has been held stopped by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.locked_at.elapsed()
     }
 }
 
@@ -34,5 +42,6 @@ impl Drop for ProcessLock {
         if let Err(e) = ptrace::detach(self.pid) {
             error!("Failed to detach from process {} : {}", self.pid, e);
         }
+        crate::pause_metrics().record_process(self.pid, self.locked_at.elapsed());
     }
 }
@@ -8,25 +8,56 @@ use read_process_memory::{CopyAddress, ProcessHandle};
 
 use std::convert::TryInto;
 use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
 
-use super::{Error, ProcessMemory};
+use super::{Error, ErrorContext, ProcessMemory, ResultExt};
 use crate::freebsd::lock::ProcessLock;
 
+pub use crate::bsd::ExitNotification;
+
 pub type Pid = pid_t;
 pub type Tid = lwpid_t;
 
 pub struct Process {
     pub pid: Pid,
     lock: Arc<Mutex<Weak<ProcessLock>>>,
+    start_time: Option<(i64, i64)>,
 }
 
 pub struct Thread {
     pub tid: lwpid_t,
     pid: pid_t,
     active: bool,
+    stopped: bool,
+    cpu_time: Duration,
     lock: Arc<Mutex<Weak<ProcessLock>>>,
 }
 
+/// Iterates over the threads of a process, as returned by `Process::iter_threads`
+pub struct ThreadIter {
+    threads: std::vec::IntoIter<kinfo_proc::kinfo_proc>,
+    pid: pid_t,
+    lock: Arc<Mutex<Weak<ProcessLock>>>,
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<Thread, Error>;
+
+    fn next(&mut self) -> Option<Result<Thread, Error>> {
+        self.threads.next().map(|th| {
+            Ok(Thread {
+                tid: th.ki_tid,
+                active: th.ki_stat == 2,
+                // SSTOP, which covers threads stopped by signals and by ptrace
+                stopped: th.ki_stat == 4,
+                cpu_time: Duration::from_micros(th.ki_runtime),
+                pid: self.pid,
+                lock: Arc::clone(&self.lock),
+            })
+        })
+    }
+}
+
 fn process_lock(pid: Pid, container: &Mutex<Weak<ProcessLock>>) -> Result<Arc<ProcessLock>, Error> {
     let mut mutex_lock = container.lock().unwrap();
     if let Some(ref lock) = Weak::upgrade(&mutex_lock) {
@@ -41,12 +72,70 @@ fn process_lock(pid: Pid, container: &Mutex<Weak<ProcessLock>>) -> Result<Arc<Pr
 
 impl Process {
     pub fn new(pid: Pid) -> Result<Process, Error> {
+        let status = procstat::status(pid).ok();
+        if status.as_ref().is_some_and(|status| status.kernel) {
+            return Err(Error::KernelThread(pid));
+        }
+        // remember when the process started, so that we can tell if the pid gets reused
+        let start_time = status.map(|status| status.start_time);
         Ok(Process {
             pid,
             lock: Arc::new(Mutex::new(Weak::new())),
+            start_time,
         })
     }
 
+    /// Returns true if the process still exists and is the same process this was created
+    /// for, rather than a new process that has been given the same pid
+    pub fn exists(&self) -> bool {
+        // EPERM means the process exists, but we aren't allowed to signal it
+        if unsafe { libc::kill(self.pid, 0) } != 0
+            && std::io::Error::last_os_error().raw_os_error() != Some(libc::EPERM)
+        {
+            return false;
+        }
+        match self.start_time {
+            Some(start_time) => {
+                procstat::status(self.pid)
+                    .ok()
+                    .map(|status| status.start_time)
+                    == Some(start_time)
+            }
+            None => true,
+        }
+    }
+
+    /// Sends a signal to the process.
+    ///
+    /// This fails with `Error::ProcessExited` if the process has exited, including when its
+    /// pid has since been reused by another process.
+    pub fn signal(&self, signal: i32) -> Result<(), Error> {
+        crate::bsd::signal(self.pid, self.exists(), signal)
+    }
+
+    /// Kills the process with SIGKILL
+    pub fn kill(&self) -> Result<(), Error> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Returns a handle that can be polled to find out when this process exits
+    pub fn exit_notification(&self) -> Result<ExitNotification, Error> {
+        ExitNotification::new(self.pid, self.start_time, |pid| {
+            Ok(procstat::status(pid)?.start_time)
+        })
+    }
+
+    /// Returns true if the process exists and hasn't exited. Unlike `exists`, this is false
+    /// for processes that have exited but haven't yet been reaped by their parent.
+    pub fn is_alive(&self) -> bool {
+        self.exists() && !self.is_zombie()
+    }
+
+    /// Returns true if the process has exited, but hasn't yet been reaped by its parent
+    pub fn is_zombie(&self) -> bool {
+        procstat::status(self.pid).is_ok_and(|status| status.zombie)
+    }
+
     pub fn exe(&self) -> Result<String, Error> {
         let filename = procstat::exe(self.pid)?;
         if filename.is_empty() {
@@ -59,19 +148,57 @@ impl Process {
         Ok(procstat::cwd(self.pid)?)
     }
 
+    /// Returns the id of the process group the process is in, which is the pid of the group's
+    /// leader. Shells put each job in its own group, so this is how to find the other
+    /// processes in the same pipeline.
+    pub fn process_group(&self) -> Result<Pid, Error> {
+        Ok(procstat::status(self.pid)?.process_group)
+    }
+
+    /// Returns the id of the session the process is in, which is the pid of the session's
+    /// leader (usually the shell of the terminal the process was started from)
+    pub fn session_id(&self) -> Result<Pid, Error> {
+        Ok(procstat::status(self.pid)?.session_id)
+    }
+
+    /// Returns the start time, owner, memory usage and thread count of the process, which all
+    /// come from a single kinfo_proc
+    pub fn stats(&self) -> Result<crate::ProcessStats, Error> {
+        let status = procstat::status(self.pid)
+            .context(|| ErrorContext::new("reading the process stats").pid(self.pid))?;
+        let (seconds, micros) = status.start_time;
+        let start_time = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(seconds as u64)
+            + Duration::from_micros(micros as u64);
+        Ok(crate::ProcessStats {
+            start_time,
+            uid: Some(status.uid),
+            memory: crate::MemoryStats {
+                resident: status.resident,
+                virtual_size: status.virtual_size,
+            },
+            thread_count: status.num_threads,
+        })
+    }
+
     pub fn threads(&self) -> Result<Vec<Thread>, Error> {
-        let threads = procstat::threads_info(self.pid)?;
-        let result = threads.iter().map(|th| Thread {
-            tid: th.ki_tid,
-            active: th.ki_stat == 2,
+        self.iter_threads()?.collect()
+    }
+
+    /// Returns an iterator over the threads of the process. This matches `iter_threads` on the
+    /// other platforms, but the threads are all read with a single sysctl on FreeBSD.
+    pub fn iter_threads(&self) -> Result<ThreadIter, Error> {
+        Ok(ThreadIter {
+            threads: procstat::threads_info(self.pid)?.into_iter(),
             pid: self.pid,
             lock: Arc::clone(&self.lock),
-        });
-
-        Ok(result.collect())
+        })
     }
 
     pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
+        if self.is_zombie() {
+            return Err(Error::ZombieProcess(self.pid));
+        }
         process_lock(self.pid, &self.lock)
     }
 
@@ -115,6 +242,19 @@ impl Process {
         Ok(crate::filter_child_pids(self.pid, &processes))
     }
 
+    /// Returns what can be done with this process. Reading memory and locking both use
+    /// ptrace, and stack unwinding and symbolication aren't supported on FreeBSD yet.
+    pub fn capabilities(&self) -> crate::Capabilities {
+        let can_read_mem = crate::can_read_memory(self, self.pid);
+        crate::Capabilities {
+            can_read_mem,
+            can_suspend: can_read_mem && !self.is_zombie(),
+            can_unwind: false,
+            can_symbolicate: false,
+            can_write: can_read_mem,
+        }
+    }
+
     pub fn unwinder(&self) -> Result<(), Error> {
         unimplemented!("No unwinding yet!")
     }
@@ -125,10 +265,27 @@ impl Thread {
         Ok(self.tid)
     }
 
+    /// The lwp id of the thread. FreeBSD allocates these from a large range, and we don't
+    /// have a start time for the thread to tell reuse apart.
+    pub(crate) fn thread_key(&self) -> Result<crate::threads::ThreadKey, Error> {
+        Ok((self.tid as u64, 0))
+    }
+
     pub fn active(&self) -> Result<bool, Error> {
         Ok(self.active)
     }
 
+    /// Returns the CPU time the thread had used when the threads were listed
+    pub fn cpu_time(&self) -> Result<Duration, Error> {
+        Ok(self.cpu_time)
+    }
+
+    /// Returns true if the thread was stopped, by a signal or by a debugger, when the threads
+    /// were listed
+    pub fn is_stopped(&self) -> Result<bool, Error> {
+        Ok(self.stopped)
+    }
+
     pub fn lock(&self) -> Result<Arc<ProcessLock>, Error> {
         process_lock(self.pid, &self.lock)
     }
@@ -141,7 +298,11 @@ impl Thread {
 impl ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
         let handle: ProcessHandle = self.pid.try_into()?;
-        Ok(handle.copy_address(addr, buf)?)
+        handle.copy_address(addr, buf).context(|| {
+            ErrorContext::new("reading memory")
+                .pid(self.pid)
+                .address(addr, buf.len())
+        })
     }
 }
 
@@ -158,3 +158,40 @@ pub fn processes() -> Result<std::collections::HashMap<pid_t, pid_t>, Error> {
         Ok(ret)
     })?
 }
+
+/// The parts of a process's kinfo_proc needed to tell what kind of process it is
+pub struct ProcessStatus {
+    /// When the process was started, as (seconds, microseconds)
+    pub start_time: (i64, i64),
+    pub zombie: bool,
+    pub kernel: bool,
+    pub process_group: pid_t,
+    pub session_id: pid_t,
+    pub uid: u32,
+    /// The resident set size in bytes
+    pub resident: u64,
+    /// The size of the address space in bytes
+    pub virtual_size: u64,
+    pub num_threads: usize,
+}
+
+pub fn status(pid: pid_t) -> Result<ProcessStatus, Error> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    procstat_call(KERN_PROC_PID, pid, 0, &|_, kinfo, count| {
+        if count < 1 {
+            return Err(Error::from_raw_os_error(libc::ESRCH));
+        }
+        let proc = unsafe { &*kinfo };
+        Ok(ProcessStatus {
+            start_time: (proc.ki_start.tv_sec as i64, proc.ki_start.tv_usec as i64),
+            zombie: proc.ki_stat as i32 == libc::SZOMB as i32,
+            kernel: proc.ki_flag as i64 & libc::P_KPROC as i64 != 0,
+            process_group: proc.ki_pgid,
+            session_id: proc.ki_sid,
+            uid: proc.ki_uid,
+            resident: proc.ki_rssize as u64 * page_size,
+            virtual_size: proc.ki_size as u64,
+            num_threads: proc.ki_numthreads as usize,
+        })
+    })?
+}
@@ -0,0 +1,712 @@
+//! A client for the GDB remote serial protocol, which lets the memory and threads of a target
+//! be read through gdbserver, QEMU's gdbstub or a hardware debug probe instead of the local
+//! process APIs. On OSX, `GdbRemote::attach_debugserver` uses Apple's debugserver (which speaks
+//! the same protocol) to attach to local processes.
+//!
+//! The target has to be stopped while it is inspected. That is the state these stubs leave the
+//! target in when a debugger connects, so this client never resumes or interrupts it.
+//!
+//! ```rust,no_run
+//! # fn run() -> Result<(), remoteprocess::Error> {
+//! use remoteprocess::ProcessMemory;
+//!
+//! let remote = remoteprocess::GdbRemote::connect("localhost:1234")?;
+//! for thread in remote.threads()? {
+//!     println!("thread {} has {} bytes of registers", thread.id(), thread.read_registers()?.len());
+//! }
+//! let header = remote.copy(0x400000, 4)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info, warn};
+
+use crate::{Error, ProcessMemory};
+
+/// The largest packet we send or ask for when the stub doesn't tell us its limit
+const DEFAULT_PACKET_SIZE: usize = 0x400;
+
+/// The smallest packet size we can work with, which fits a single byte of memory in an m reply
+/// along with the $ and #xx framing
+const MIN_PACKET_SIZE: usize = 6;
+
+/// A connection to a GDB remote stub.
+///
+/// Cloning this is cheap, and the clones share the connection - so that it can be handed to
+/// both a `SnapshotUnwinder` and a `Symbolicator` with `with_memory`.
+#[derive(Clone)]
+pub struct GdbRemote {
+    connection: Arc<Mutex<Connection>>,
+}
+
+/// A thread of the target of a `GdbRemote`. For QEMU and hardware probes, each CPU core shows
+/// up as a thread.
+#[derive(Clone)]
+pub struct GdbThread {
+    remote: GdbRemote,
+    id: u64,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    /// The maximum size of a packet the stub accepts
+    packet_size: usize,
+    /// False once the stub has agreed to QStartNoAckMode, after which packets aren't
+    /// acknowledged anymore
+    acks: bool,
+    /// The thread that register packets apply to, as last set with Hg
+    selected_thread: Option<u64>,
+    /// The debugserver we started for this connection, which is stopped along with it
+    server: Option<Child>,
+    detached: bool,
+}
+
+impl GdbRemote {
+    /// Connects to a stub listening on a TCP port, like `gdbserver :1234 ./program` or
+    /// `qemu-system-x86_64 -s`
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    /// Uses an already established connection to a stub, for example one with timeouts set
+    pub fn from_stream(stream: TcpStream) -> Result<Self, Error> {
+        Self::handshake(stream, None)
+    }
+
+    /// Negotiates the packet size and ack mode with the stub. `server` is the debugserver
+    /// process at the other end of the stream, if we started one.
+    fn handshake(stream: TcpStream, server: Option<Child>) -> Result<Self, Error> {
+        stream.set_nodelay(true)?;
+        // debugserver supports QStartNoAckMode without listing it in qSupported
+        let mut no_ack = server.is_some();
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+            packet_size: DEFAULT_PACKET_SIZE,
+            acks: true,
+            selected_thread: None,
+            server,
+            detached: false,
+        };
+
+        let supported = connection.request("qSupported")?;
+        for feature in supported.split(|&b| b == b';') {
+            if let Some(size) = feature.strip_prefix(b"PacketSize=") {
+                let size = parse_hex(size)? as usize;
+                if size < MIN_PACKET_SIZE {
+                    return Err(Error::Other(format!(
+                        "gdb remote stub has a packet size of {}, which is too small to use",
+                        size
+                    )));
+                }
+                connection.packet_size = size;
+            } else if feature == b"QStartNoAckMode+" {
+                no_ack = true;
+            }
+        }
+        if no_ack {
+            connection.expect_ok("QStartNoAckMode")?;
+            connection.acks = false;
+        }
+        info!(
+            "connected to gdb remote stub (packet size {}, acks {})",
+            connection.packet_size, connection.acks
+        );
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Returns the threads of the target
+    pub fn threads(&self) -> Result<Vec<GdbThread>, Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let mut threads = Vec::new();
+        let mut reply = connection.request("qfThreadInfo")?;
+        while let Some(ids) = reply.strip_prefix(b"m") {
+            for id in ids.split(|&b| b == b',') {
+                threads.push(GdbThread {
+                    remote: self.clone(),
+                    id: parse_hex(id)?,
+                });
+            }
+            reply = connection.request("qsThreadInfo")?;
+        }
+        if reply != b"l" {
+            return Err(unexpected_reply("qfThreadInfo", &reply));
+        }
+        Ok(threads)
+    }
+
+    /// Returns the path of the executable of the target, if the stub knows it. gdbserver does,
+    /// but stubs for bare metal targets usually don't.
+    pub fn exe(&self) -> Result<String, Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let chunk = connection.packet_size - 5;
+        let mut exe = Vec::new();
+        loop {
+            let command = format!("qXfer:exec-file:read::{:x},{:x}", exe.len(), chunk);
+            let reply = connection.request(&command)?;
+            match reply.split_first() {
+                Some((b'm', data)) => exe.extend_from_slice(data),
+                Some((b'l', data)) => {
+                    exe.extend_from_slice(data);
+                    break;
+                }
+                _ => return Err(unexpected_reply(&command, &reply)),
+            }
+        }
+        String::from_utf8(exe).map_err(|e| Error::Other(format!("Invalid exe path: {}", e)))
+    }
+
+    /// Writes to the memory of the target with M packets
+    pub fn write_memory(&self, addr: usize, data: &[u8]) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        // every byte takes two hex digits, and the header takes at most 40 bytes
+        let chunk_size = (connection.packet_size.saturating_sub(40) / 2).max(1);
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            let command = format!(
+                "M{:x},{:x}:{}",
+                addr + i * chunk_size,
+                chunk.len(),
+                encode_hex(chunk)
+            );
+            connection.expect_ok(&command)?;
+        }
+        Ok(())
+    }
+
+    /// Detaches from the target, which lets it continue running. The connection can't be used
+    /// after this.
+    pub fn detach(&self) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        connection.expect_ok("D")?;
+        connection.detached = true;
+        Ok(())
+    }
+}
+
+impl ProcessMemory for GdbRemote {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        // the reply has two hex digits for every byte, along with the $ and #xx framing
+        let chunk_size = (connection.packet_size - 4) / 2;
+        for (i, chunk) in buf.chunks_mut(chunk_size).enumerate() {
+            let command = format!("m{:x},{:x}", addr + i * chunk_size, chunk.len());
+            let reply = connection.request(&command)?;
+            // stubs can return less than was asked for, when the end is unreadable
+            if reply.len() != chunk.len() * 2 {
+                return Err(Error::Other(format!(
+                    "Failed to read {} bytes at 0x{:016x} from gdb remote",
+                    chunk.len(),
+                    addr + i * chunk_size
+                )));
+            }
+            decode_hex(&reply, chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl GdbThread {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the registers of this thread as sent by the stub, in the layout gdb uses for the
+    /// target's architecture. Registers the stub doesn't have are returned as zeros.
+    pub fn read_registers(&self) -> Result<Vec<u8>, Error> {
+        let reply = self.register_packet()?;
+        let hex: Vec<u8> = reply
+            .iter()
+            .map(|&b| if b == b'x' { b'0' } else { b })
+            .collect();
+        let mut registers = vec![0; hex.len() / 2];
+        decode_hex(&hex, &mut registers)?;
+        Ok(registers)
+    }
+
+    /// Overwrites the registers of this thread, in the same layout as `read_registers`
+    pub fn write_registers(&self, registers: &[u8]) -> Result<(), Error> {
+        let mut connection = self.remote.connection.lock().unwrap();
+        connection.select_thread(self.id)?;
+        connection.expect_ok(&format!("G{}", encode_hex(registers)))
+    }
+
+    /// Reads the g packet for this thread, as hex digits
+    fn register_packet(&self) -> Result<Vec<u8>, Error> {
+        let mut connection = self.remote.connection.lock().unwrap();
+        connection.select_thread(self.id)?;
+        connection.request("g")
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod debugserver {
+    use std::net::TcpListener;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use log::info;
+
+    use super::GdbRemote;
+    use crate::{Error, Pid};
+
+    /// How long debugserver gets to attach to the process and connect back to us
+    const ATTACH_TIMEOUT: Duration = Duration::from_secs(10);
+
+    impl GdbRemote {
+        /// Attaches to a process through Apple's debugserver, which ships with Xcode and the
+        /// command line tools.
+        ///
+        /// debugserver is signed with the entitlements needed to debug other processes, so
+        /// this works for the developer's own processes once developer mode is enabled
+        /// (`DevToolsSecurity -enable`), without this program being signed or run as root
+        /// like `Process::new` would need. The process is stopped while it's attached, and
+        /// continues running when the `GdbRemote` and all its clones are dropped.
+        pub fn attach_debugserver(pid: Pid) -> Result<Self, Error> {
+            Self::attach_debugserver_with(&find_debugserver()?, pid)
+        }
+
+        /// Like `attach_debugserver`, but runs the debugserver binary at `debugserver`
+        pub fn attach_debugserver_with(debugserver: &Path, pid: Pid) -> Result<Self, Error> {
+            // debugserver connects back to us, which saves picking a free port for it
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let addr = listener.local_addr()?;
+            info!("attaching {} to {}", debugserver.display(), pid);
+            let mut server = Command::new(debugserver)
+                .arg(format!("--attach={}", pid))
+                .arg("--reverse-connect")
+                .arg(addr.to_string())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .spawn()?;
+
+            listener.set_nonblocking(true)?;
+            let start = Instant::now();
+            let stream = loop {
+                match listener.accept() {
+                    Ok((stream, _)) => break stream,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e.into()),
+                }
+                if let Some(status) = server.try_wait()? {
+                    return Err(Error::Other(format!(
+                        "debugserver failed to attach to {} ({}). Is developer mode enabled?",
+                        pid, status
+                    )));
+                }
+                if start.elapsed() > ATTACH_TIMEOUT {
+                    let _ = server.kill();
+                    let _ = server.wait();
+                    return Err(Error::Other(format!(
+                        "Timed out waiting for debugserver to attach to {}",
+                        pid
+                    )));
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            };
+            stream.set_nonblocking(false)?;
+            Self::handshake(stream, Some(server))
+        }
+    }
+
+    /// Returns the path of the debugserver from the active developer directory, as set with
+    /// `xcode-select`
+    fn find_debugserver() -> Result<PathBuf, Error> {
+        let output = Command::new("xcode-select").arg("-p").output()?;
+        let developer_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        let candidates = [
+            // Xcode, where the developer dir is Xcode.app/Contents/Developer
+            developer_dir.join("../SharedFrameworks/LLDB.framework/Resources/debugserver"),
+            // the command line tools
+            developer_dir
+                .join("Library/PrivateFrameworks/LLDB.framework/Versions/A/Resources/debugserver"),
+        ];
+        candidates
+            .into_iter()
+            .find(|path| path.exists())
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "Failed to find debugserver in {}. Are the developer tools installed?",
+                    developer_dir.display()
+                ))
+            })
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod snapshot {
+    use super::{decode_hex, GdbThread};
+    use crate::{Error, ProcessMemory, Registers, StackSnapshot, Tid, DEFAULT_MAX_STACK_SIZE};
+
+    #[cfg(target_arch = "x86_64")]
+    const RED_ZONE: u64 = 128;
+    #[cfg(target_arch = "aarch64")]
+    const RED_ZONE: u64 = 0;
+
+    const PAGE_SIZE: u64 = 4096;
+
+    impl GdbThread {
+        /// Returns the general purpose registers of this thread. The target must have the same
+        /// architecture as this machine.
+        pub fn registers(&self) -> Result<Registers, Error> {
+            Ok(parse_registers(&self.register_packet()?))
+        }
+
+        /// Copies the registers and the live part of the stack of this thread, so that it can
+        /// be unwound with a `SnapshotUnwinder`
+        pub fn snapshot(&self) -> Result<StackSnapshot, Error> {
+            self.snapshot_with_limit(DEFAULT_MAX_STACK_SIZE)
+        }
+
+        /// Like `snapshot`, but copies at most `max_stack_size` bytes of the stack.
+        ///
+        /// The stub can't tell us where the stack ends, so this copies a page at a time until
+        /// it reaches memory that can't be read.
+        pub fn snapshot_with_limit(&self, max_stack_size: usize) -> Result<StackSnapshot, Error> {
+            let registers = self.registers()?;
+            let sp = registers.sp().ok_or_else(|| {
+                Error::Other(format!("Failed to get stack pointer for {}", self.id))
+            })?;
+
+            let stack_start = sp.saturating_sub(RED_ZONE);
+            let stack_end = stack_start.saturating_add(max_stack_size as u64);
+            let mut stack = Vec::new();
+            let mut addr = stack_start;
+            while addr < stack_end {
+                let next = ((addr / PAGE_SIZE + 1) * PAGE_SIZE).min(stack_end);
+                let mut page = vec![0; (next - addr) as usize];
+                if self.remote.read(addr as usize, &mut page).is_err() {
+                    break;
+                }
+                stack.extend_from_slice(&page);
+                addr = next;
+            }
+            if stack.is_empty() {
+                return Err(Error::Other(format!(
+                    "Failed to read the stack of thread {} at 0x{:016x}",
+                    self.id, sp
+                )));
+            }
+
+            Ok(StackSnapshot::new(
+                self.id as Tid,
+                registers,
+                stack_start,
+                stack,
+            ))
+        }
+    }
+
+    /// Converts a g packet to registers. gdb orders the x86_64 registers differently from their
+    /// DWARF numbers, while on aarch64 x0-x30, sp and pc match the order `Registers` uses.
+    pub(super) fn parse_registers(packet: &[u8]) -> Registers {
+        #[cfg(target_arch = "x86_64")]
+        const DWARF_NUMBERS: &[u16] = &[0, 3, 2, 1, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        #[cfg(target_arch = "aarch64")]
+        const DWARF_NUMBERS: &[u16] = &[
+            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
+            24, 25, 26, 27, 28, 29, 30, 31, 32,
+        ];
+
+        let mut registers = Registers::default();
+        for (hex, &register) in packet.chunks_exact(16).zip(DWARF_NUMBERS) {
+            let mut value = [0u8; 8];
+            // registers the stub can't read are sent as xx
+            if decode_hex(hex, &mut value).is_ok() {
+                registers.set(register, u64::from_le_bytes(value));
+            }
+        }
+        registers
+    }
+}
+
+impl Connection {
+    /// Sends a command, and returns the reply. Error replies are returned as errors.
+    fn request(&mut self, command: &str) -> Result<Vec<u8>, Error> {
+        self.send(command)?;
+        let reply = self.receive()?;
+        if reply.len() == 3 && reply[0] == b'E' {
+            return Err(Error::Other(format!(
+                "gdb remote returned error {} for {}",
+                String::from_utf8_lossy(&reply[1..]),
+                command
+            )));
+        }
+        Ok(reply)
+    }
+
+    fn expect_ok(&mut self, command: &str) -> Result<(), Error> {
+        let reply = self.request(command)?;
+        if reply != b"OK" {
+            return Err(unexpected_reply(command, &reply));
+        }
+        Ok(())
+    }
+
+    fn select_thread(&mut self, id: u64) -> Result<(), Error> {
+        if self.selected_thread != Some(id) {
+            self.expect_ok(&format!("Hg{:x}", id))?;
+            self.selected_thread = Some(id);
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), Error> {
+        debug!("gdb remote <- {}", command);
+        let packet = format!("${}#{:02x}", command, checksum(command.as_bytes()));
+        // retransmit until the stub acknowledges the packet
+        for _ in 0..3 {
+            self.reader.get_mut().write_all(packet.as_bytes())?;
+            if !self.acks {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                b => {
+                    return Err(Error::Other(format!(
+                        "Expected an ack from gdb remote, got {:?}",
+                        b as char
+                    )))
+                }
+            }
+        }
+        Err(Error::Other(format!("gdb remote rejected {}", command)))
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            // skip anything before the start of the packet, like acks from earlier retransmits
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data)?;
+            if data.pop() != Some(b'#') {
+                return Err(Error::Other("gdb remote closed the connection".to_string()));
+            }
+            let mut sum = [0u8; 2];
+            self.reader.read_exact(&mut sum)?;
+
+            let valid = parse_hex(&sum).ok() == Some(checksum(&data) as u64);
+            if self.acks {
+                self.reader
+                    .get_mut()
+                    .write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                let reply = decode_packet(&data)?;
+                debug!("gdb remote -> {}", String::from_utf8_lossy(&reply));
+                return Ok(reply);
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut byte = [0u8];
+        self.reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(mut server) = self.server.take() {
+            // detach first, so that the target isn't killed along with debugserver
+            if !self.detached {
+                if let Err(e) = self.expect_ok("D") {
+                    warn!("Failed to detach debugserver: {}", e);
+                }
+            }
+            let _ = server.kill();
+            let _ = server.wait();
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Expands the run length encoding and escaped bytes in the body of a packet
+fn decode_packet(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut ret = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => match bytes.next() {
+                Some(&escaped) => ret.push(escaped ^ 0x20),
+                None => break,
+            },
+            // '*' is followed by the number of extra repeats of the previous byte, plus 29
+            b'*' => match (bytes.next(), ret.last()) {
+                (Some(&count), Some(&previous)) if count >= 29 => {
+                    ret.extend(std::iter::repeat_n(previous, (count - 29) as usize));
+                }
+                _ => return Err(Error::Other("Invalid run length in gdb packet".to_string())),
+            },
+            _ => ret.push(b),
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_hex(hex: &[u8]) -> Result<u64, Error> {
+    std::str::from_utf8(hex)
+        .ok()
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| {
+            Error::Other(format!(
+                "Invalid hex number {:?} from gdb remote",
+                String::from_utf8_lossy(hex)
+            ))
+        })
+}
+
+fn decode_hex(hex: &[u8], buf: &mut [u8]) -> Result<(), Error> {
+    for (b, digits) in buf.iter_mut().zip(hex.chunks_exact(2)) {
+        *b = parse_hex(digits)? as u8;
+    }
+    Ok(())
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unexpected_reply(command: &str, reply: &[u8]) -> Error {
+    Error::Other(format!(
+        "Unexpected reply {:?} from gdb remote for {}",
+        String::from_utf8_lossy(reply),
+        command
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Answers each command in order with its reply, checking that the commands are the ones
+    /// expected
+    fn stub(script: &'static [(&'static str, &'static str)]) -> GdbRemote {
+        try_stub(script).unwrap()
+    }
+
+    fn try_stub(script: &'static [(&'static str, &'static str)]) -> Result<GdbRemote, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut acks = true;
+            for (command, reply) in script {
+                let mut packet = Vec::new();
+                reader.read_until(b'#', &mut packet).unwrap();
+                let mut sum = [0u8; 2];
+                reader.read_exact(&mut sum).unwrap();
+                let start = packet.iter().position(|&b| b == b'$').unwrap();
+                assert_eq!(&packet[start + 1..packet.len() - 1], command.as_bytes());
+
+                let response = format!("${}#{:02x}", reply, checksum(reply.as_bytes()));
+                let ack = if acks { "+" } else { "" };
+                write!(reader.get_mut(), "{}{}", ack, response).unwrap();
+                if *command == "QStartNoAckMode" {
+                    acks = false;
+                }
+            }
+        });
+        GdbRemote::connect(addr)
+    }
+
+    #[test]
+    fn test_decode_packet() {
+        assert_eq!(decode_packet(b"0* ").unwrap(), b"0000");
+        assert_eq!(decode_packet(b"ab}]cd").unwrap(), b"ab}cd");
+        assert!(decode_packet(b"*!").is_err());
+    }
+
+    #[test]
+    fn test_gdb_remote() {
+        let remote = stub(&[
+            ("qSupported", "PacketSize=40;QStartNoAckMode+"),
+            ("QStartNoAckMode", "OK"),
+            ("qfThreadInfo", "m1,2a"),
+            ("qsThreadInfo", "m3"),
+            ("qsThreadInfo", "l"),
+            // reads are split up to fit in the packet size
+            ("m1000,1e", "00*W"),
+            ("m101e,2", "01ff"),
+            ("m2000,4", "E14"),
+            ("Hg2a", "OK"),
+            ("g", "0100000000000000xxxxxxxxxxxxxxxx"),
+            ("g", "0200000000000000"),
+            ("qXfer:exec-file:read::0,3b", "l/bin/true"),
+            ("M3000,2:abcd", "OK"),
+            ("D", "OK"),
+        ]);
+
+        let threads = remote.threads().unwrap();
+        let ids: Vec<u64> = threads.iter().map(GdbThread::id).collect();
+        assert_eq!(ids, [1, 0x2a, 3]);
+
+        let mut data = remote.copy(0x1000, 32).unwrap();
+        assert_eq!(data.split_off(30), [1, 0xff]);
+        assert_eq!(data, [0; 30]);
+        assert!(remote.copy(0x2000, 4).is_err());
+
+        assert_eq!(
+            threads[1].read_registers().unwrap(),
+            [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        // the thread is only selected once
+        assert_eq!(
+            threads[1].read_registers().unwrap(),
+            [2, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        assert_eq!(remote.exe().unwrap(), "/bin/true");
+        remote.write_memory(0x3000, &[0xab, 0xcd]).unwrap();
+        remote.detach().unwrap();
+    }
+
+    #[test]
+    fn test_packet_size() {
+        assert!(try_stub(&[("qSupported", "PacketSize=4")]).is_err());
+        assert!(try_stub(&[("qSupported", "PacketSize=0")]).is_err());
+
+        // the smallest packets still read memory, a byte at a time
+        let remote = stub(&[
+            ("qSupported", "PacketSize=6"),
+            ("m1000,1", "ab"),
+            ("m1001,1", "cd"),
+            ("qXfer:exec-file:read::0,1", "l/"),
+        ]);
+        assert_eq!(remote.copy(0x1000, 2).unwrap(), [0xab, 0xcd]);
+        assert_eq!(remote.exe().unwrap(), "/");
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_parse_registers() {
+        // rax, rbx, then 14 unknown registers and rip
+        let mut packet = b"0100000000000000".repeat(2);
+        packet.extend(b"x".repeat(16 * 14));
+        packet.extend(b"efbeadde00000000");
+        let registers = snapshot::parse_registers(&packet);
+        assert_eq!(registers.get(0), Some(1));
+        // rbx is DWARF register 3
+        assert_eq!(registers.get(3), Some(1));
+        assert_eq!(registers.get(1), None);
+        assert_eq!(registers.sp(), None);
+        assert_eq!(registers.ip(), Some(0xdeadbeef));
+    }
+}
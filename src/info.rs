@@ -0,0 +1,217 @@
+use std::cell::OnceCell;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{Error, Pid, Process, Thread};
+
+/// Caches the metadata of a process that otherwise gets read from procfs (or queried from the
+/// OS) on every call, like its executable, working directory, memory usage and threads.
+///
+/// Each value is read the first time it is asked for, and then kept until `refresh` is called.
+/// This makes sense for samplers, which look these up far more often than they change:
+///
+/// ```rust,no_run
+/// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+/// let mut info = remoteprocess::ProcessInfo::new(pid)?;
+/// loop {
+///     // pick up new threads once a second
+///     if info.age() > std::time::Duration::from_secs(1) {
+///         info.refresh();
+///     }
+///     for thread in info.threads()? {
+///         println!("{} thread {}", info.exe()?, thread.id()?);
+///     }
+/// }
+/// # }
+/// ```
+pub struct ProcessInfo {
+    process: Process,
+    exe: OnceCell<String>,
+    cwd: OnceCell<String>,
+    cmdline: OnceCell<Vec<String>>,
+    stats: OnceCell<ProcessStats>,
+    threads: OnceCell<Vec<Thread>>,
+    refreshed_at: Instant,
+}
+
+impl ProcessInfo {
+    pub fn new(pid: Pid) -> Result<Self, Error> {
+        Ok(Self::from_process(Process::new(pid)?))
+    }
+
+    pub fn from_process(process: Process) -> Self {
+        Self {
+            process,
+            exe: OnceCell::new(),
+            cwd: OnceCell::new(),
+            cmdline: OnceCell::new(),
+            stats: OnceCell::new(),
+            threads: OnceCell::new(),
+            refreshed_at: Instant::now(),
+        }
+    }
+
+    pub fn process(&self) -> &Process {
+        &self.process
+    }
+
+    pub fn exe(&self) -> Result<&str, Error> {
+        get_or_try_init(&self.exe, || self.process.exe()).map(String::as_str)
+    }
+
+    pub fn cwd(&self) -> Result<&str, Error> {
+        get_or_try_init(&self.cwd, || self.process.cwd()).map(String::as_str)
+    }
+
+    pub fn cmdline(&self) -> Result<&[String], Error> {
+        get_or_try_init(&self.cmdline, || self.process.cmdline()).map(Vec::as_slice)
+    }
+
+    /// Returns the start time, owner, memory usage and thread count of the process, as of the
+    /// last refresh
+    pub fn stats(&self) -> Result<&ProcessStats, Error> {
+        get_or_try_init(&self.stats, || self.process.stats())
+    }
+
+    /// Returns the threads of the process as of the last refresh. Threads that have exited
+    /// since then are still returned, and fail when they are used.
+    pub fn threads(&self) -> Result<&[Thread], Error> {
+        get_or_try_init(&self.threads, || self.process.threads()).map(Vec::as_slice)
+    }
+
+    /// Drops all cached values, so that they are read again the next time they are used
+    pub fn refresh(&mut self) {
+        self.exe.take();
+        self.cwd.take();
+        self.cmdline.take();
+        self.stats.take();
+        self.threads.take();
+        self.refreshed_at = Instant::now();
+    }
+
+    /// How long it has been since the cache was created or last refreshed
+    pub fn age(&self) -> Duration {
+        self.refreshed_at.elapsed()
+    }
+}
+
+impl Process {
+    /// Returns the executable, working directory, command line, start time, owner, memory
+    /// usage and thread count of the process in one call, for agents that export process
+    /// metadata alongside their samples. The numbers come from `stats`, which reads them all
+    /// at once, and the strings that can't be read (like for processes of other users) are
+    /// left out rather than failing the whole call.
+    ///
+    /// ```rust,no_run
+    /// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+    /// let info = remoteprocess::Process::new(pid)?.info()?;
+    /// println!(
+    ///     "{} ({:?}) uses {} bytes in {} threads",
+    ///     info.pid, info.exe, info.stats.memory.resident, info.stats.thread_count
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn info(&self) -> Result<ProcessSummary, Error> {
+        Ok(ProcessSummary {
+            pid: self.pid,
+            exe: self.exe().ok(),
+            cwd: self.cwd().ok(),
+            cmdline: self.cmdline().unwrap_or_default(),
+            stats: self.stats()?,
+        })
+    }
+}
+
+/// The metadata of a process at one point in time, as returned by `Process::info`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessSummary {
+    pub pid: Pid,
+    /// The executable, or None if it can't be read (like for processes of other users)
+    pub exe: Option<String>,
+    /// The working directory, or None if it can't be read
+    pub cwd: Option<String>,
+    /// The command line, which is empty if it can't be read. Windows doesn't split the command
+    /// line into arguments, so it has a single entry there.
+    pub cmdline: Vec<String>,
+    pub stats: ProcessStats,
+}
+
+/// The numbers that describe a process at one point in time, as returned by `Process::stats`.
+/// These are gathered together with as few reads as the OS allows, for exporting alongside
+/// samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub start_time: SystemTime,
+    /// The user id the process runs as. This is None on Windows, where owners are SIDs.
+    pub uid: Option<u32>,
+    pub memory: MemoryStats,
+    pub thread_count: usize,
+}
+
+/// How much memory a process uses, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+    /// The memory that is in RAM (the working set on Windows)
+    pub resident: u64,
+    /// The address space that is mapped, whether or not it's in RAM (the committed private
+    /// memory on Windows)
+    pub virtual_size: u64,
+}
+
+/// Returns the value of the cell, loading it first if it's empty. Errors aren't cached, so
+/// loading is tried again on the next call.
+fn get_or_try_init<T>(
+    cell: &OnceCell<T>,
+    load: impl FnOnce() -> Result<T, Error>,
+) -> Result<&T, Error> {
+    if let Some(value) = cell.get() {
+        return Ok(value);
+    }
+    let value = load()?;
+    Ok(cell.get_or_init(|| value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh() {
+        let mut info = ProcessInfo::new(std::process::id() as Pid).unwrap();
+        let exe = info.exe().unwrap().to_string();
+        assert!(!info.threads().unwrap().is_empty());
+        // cached values are returned without reading them again
+        assert!(std::ptr::eq(info.exe().unwrap(), info.exe().unwrap()));
+
+        info.refresh();
+        assert!(info.exe.get().is_none() && info.threads.get().is_none());
+        assert_eq!(info.exe().unwrap(), exe);
+    }
+
+    #[test]
+    fn test_stats() {
+        let info = ProcessInfo::new(std::process::id() as Pid).unwrap();
+        let stats = info.stats().unwrap();
+        assert!(stats.thread_count >= 1);
+        assert!(stats.memory.resident > 0);
+        assert!(stats.memory.virtual_size >= stats.memory.resident);
+        let started = stats.start_time.elapsed().unwrap();
+        assert!(started < Duration::from_secs(24 * 60 * 60));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_info() {
+        let child = crate::tests::TestChild::sleep();
+        let process = Process::new(child.pid()).unwrap();
+        let info = process.info().unwrap();
+        assert_eq!(info.pid, child.pid());
+        assert!(info.exe.as_deref().unwrap().ends_with("sleep"));
+        assert_eq!(info.cwd, Some(process.cwd().unwrap()));
+        assert_eq!(info.cmdline, ["sleep", "10"]);
+        assert_eq!(info.stats.thread_count, 1);
+        assert_eq!(info.stats.uid, Some(unsafe { libc::getuid() }));
+        assert!(info.stats.memory.resident > 0);
+        assert!(info.stats.start_time.elapsed().unwrap() < Duration::from_secs(60));
+    }
+}
@@ -29,28 +29,52 @@
 //!     let unwinder = process.unwinder()?;
 //!     let symbolicator = process.symbolicator()?;
 //!     for thread in process.threads()?.iter() {
-//!         println!("Thread {} - {}", thread.id()?, if thread.active()? { "running" } else { "idle" });
-//!
 //!         // lock the thread to get a consistent snapshot (unwinding will fail otherwise)
-//!         // Note: the thread will appear idle when locked, so we are calling
-//!         // thread.active() before this
-//!         let _lock = thread.lock()?;
+//!         // Note: the thread will appear idle when locked, so we ask the lock whether it
+//!         // was running right before it was stopped
+//!         let lock = thread.lock()?;
+//!         let running = lock.was_active().unwrap_or(false);
+//!         println!("Thread {} - {}", thread.id()?, if running { "running" } else { "idle" });
 //!
-//!         // Iterate over the callstack for the current thread
-//!         for ip in unwinder.cursor(&thread)? {
-//!             let ip = ip?;
+//!         // Get the callstack for the current thread
+//!         let ips = unwinder.cursor(&thread)?.collect::<Result<Vec<_>, _>>()?;
 //!
-//!             // Lookup the current stack frame containing a filename/function/linenumber etc
-//!             // for the current address
-//!             symbolicator.symbolicate(ip, true, &mut |sf| {
+//!         // Lookup the stack frames containing a filename/function/linenumber etc
+//!         // for each address
+//!         for frames in symbolicator.symbolicate_all(&ips, true) {
+//!             for sf in frames? {
 //!                 println!("\t{}", sf);
-//!             })?;
+//!             }
 //!         }
 //!     }
 //!     Ok(())
 //! }
 //! ```
 
+// only used by the examples
+#[cfg(test)]
+use env_logger as _;
+
+mod address;
+mod gdb;
+mod info;
+mod pause;
+mod sampler;
+mod session;
+mod strings;
+mod threads;
+pub use address::{AddressClass, AddressMap};
+pub use gdb::{GdbRemote, GdbThread};
+pub use info::{MemoryStats, ProcessInfo, ProcessStats, ProcessSummary};
+pub use pause::{pause_metrics, PauseMetrics, PauseStats};
+pub use sampler::{Governor, Sampler, SamplingMode, TickStats};
+pub use session::{Session, SessionEvent};
+pub use strings::{FoundString, StringEncoding, StringScanner};
+pub use threads::ThreadChanges;
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+mod bsd;
+
 #[cfg(target_os = "macos")]
 mod osx;
 #[cfg(target_os = "macos")]
@@ -77,10 +101,120 @@ pub enum Error {
     GoblinError(goblin::error::Error),
     IOError(std::io::Error),
     Other(String),
+    ZombieProcess(Pid),
+    KernelThread(Pid),
+    TranslatedProcess(Pid),
+    /// The OS refused access to the process. The hint explains what usually causes this, like
+    /// a ptrace restriction or a missing entitlement, and how to get around it.
+    PermissionDenied {
+        pid: Pid,
+        hint: String,
+    },
+    /// Another debugger or profiler is already tracing the thread, and only one can at a time
+    AlreadyTraced {
+        tid: Tid,
+        tracer: Option<Pid>,
+    },
+    /// The process exited while it was being accessed
+    ProcessExited(Pid),
+    /// The thread exited while it was being accessed
+    ThreadExited(Tid),
     #[cfg(use_libunwind)]
     LibunwindError(libunwind::Error),
     #[cfg(target_os = "linux")]
     NixError(nix::Error),
+    /// An error from an operation on a process or thread, along with which process, thread
+    /// and addresses it was for
+    Context(ErrorContext, Box<Self>),
+}
+
+impl Error {
+    /// Attaches what was being done when the error happened
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        Self::Context(context, Box::new(self))
+    }
+
+    /// Returns the context of the error, if it has any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context(context, _) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the underlying error, without any context that was attached to it. Match on
+    /// this to check for a specific failure, like a process that has exited.
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context(_, error) => error.root(),
+            error => error,
+        }
+    }
+}
+
+/// Which process, thread and addresses an operation that failed was for, so that errors like
+/// ESRCH can be traced back to their target in a profiler sampling many processes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// What was being done, like "reading memory"
+    pub operation: &'static str,
+    pub pid: Option<Pid>,
+    pub tid: Option<Tid>,
+    pub address: Option<std::ops::Range<u64>>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            pid: None,
+            tid: None,
+            address: None,
+        }
+    }
+
+    pub fn pid(mut self, pid: Pid) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    pub fn tid(mut self, tid: Tid) -> Self {
+        self.tid = Some(tid);
+        self
+    }
+
+    /// Sets the range of memory the operation was for, from its start address and length
+    pub fn address(mut self, addr: usize, length: usize) -> Self {
+        self.address = Some(addr as u64..addr as u64 + length as u64);
+        self
+    }
+}
+
+/// Attaches an `ErrorContext` to the error of a result
+pub(crate) trait ResultExt<T> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, Error> {
+        self.map_err(|e| e.into().with_context(context()))
+    }
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if let Some(pid) = self.pid {
+            write!(f, " for pid {}", pid)?;
+        }
+        if let Some(tid) = self.tid {
+            write!(f, " for tid {}", tid)?;
+        }
+        if let Some(address) = self.address.as_ref() {
+            write!(f, " at 0x{:x}..0x{:x}", address.start, address.end)?;
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -96,10 +230,45 @@ impl std::fmt::Display for Error {
             Self::GoblinError(ref e) => e.fmt(f),
             Self::IOError(ref e) => e.fmt(f),
             Self::Other(ref e) => write!(f, "{}", e),
+            Self::ZombieProcess(pid) => write!(
+                f,
+                "Process {} has exited and is waiting to be reaped by its parent",
+                pid
+            ),
+            Self::KernelThread(pid) => write!(
+                f,
+                "Process {} is a kernel thread, and has no user space memory to inspect",
+                pid
+            ),
+            Self::TranslatedProcess(pid) => write!(
+                f,
+                "Process {} is an x86_64 binary running under Rosetta, and its translated \
+                 thread state can't be read",
+                pid
+            ),
+            Self::PermissionDenied { pid, ref hint } => {
+                write!(f, "Permission denied for process {}: {}", pid, hint)
+            }
+            Self::AlreadyTraced {
+                tid,
+                tracer: Some(tracer),
+            } => write!(
+                f,
+                "Thread {} is already being traced by process {}. Detach that debugger first",
+                tid, tracer
+            ),
+            Self::AlreadyTraced { tid, tracer: None } => write!(
+                f,
+                "Thread {} is already being traced by another process. Detach that debugger first",
+                tid
+            ),
+            Self::ProcessExited(pid) => write!(f, "Process {} has exited", pid),
+            Self::ThreadExited(tid) => write!(f, "Thread {} has exited", tid),
             #[cfg(use_libunwind)]
             Self::LibunwindError(ref e) => e.fmt(f),
             #[cfg(target_os = "linux")]
             Self::NixError(ref e) => e.fmt(f),
+            Self::Context(ref context, ref e) => write!(f, "Failed {}: {}", context, e),
         }
     }
 }
@@ -113,6 +282,7 @@ impl std::error::Error for Error {
             Self::LibunwindError(ref e) => Some(e),
             #[cfg(target_os = "linux")]
             Self::NixError(ref e) => Some(e),
+            Self::Context(_, ref e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -147,8 +317,16 @@ impl From<libunwind::Error> for Error {
 #[derive(Debug, Clone)]
 pub struct StackFrame {
     pub line: Option<u64>,
+    /// The column on `line`, where the debug info has it (it's usually only emitted by clang,
+    /// and by gcc from version 8)
+    pub column: Option<u64>,
     pub filename: Option<String>,
     pub function: Option<String>,
+    /// The address of the start of the function `addr` is in. For inlined frames this is the
+    /// function they were inlined into, since that is where the code is.
+    pub function_start: Option<u64>,
+    /// The size of the function in bytes, if the symbol table has it
+    pub function_size: Option<u64>,
     pub module: String,
     pub addr: u64,
 }
@@ -159,18 +337,53 @@ impl std::fmt::Display for StackFrame {
         if let Some(filename) = self.filename.as_ref() {
             write!(
                 f,
-                "0x{:016x} {} ({}:{})",
+                "0x{:016x} {} ({}:{}",
                 self.addr,
                 function,
                 filename,
                 self.line.unwrap_or(0)
-            )
+            )?;
+            match self.column {
+                Some(column) => write!(f, ":{})", column),
+                None => write!(f, ")"),
+            }
         } else {
             write!(f, "0x{:016x} {} ({})", self.addr, function, self.module)
         }
     }
 }
 
+/// What can be done with a process on this system, as returned by `Process::capabilities`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Memory can be read through `ProcessMemory`
+    pub can_read_mem: bool,
+    /// The process can be suspended with `lock`
+    pub can_suspend: bool,
+    /// The stacks of the threads can be unwound. This needs the unwind feature, and access to
+    /// the registers of the threads
+    pub can_unwind: bool,
+    /// Addresses can be symbolicated, which needs the unwind feature
+    pub can_symbolicate: bool,
+    /// Memory could be written (where the mapping allows it) with the platform APIs. The only
+    /// part of this crate that writes to a target is the gdb remote client, and only when its
+    /// `write_memory` or `write_registers` is called.
+    pub can_write: bool,
+}
+
+/// Returns true if memory can be read from the process, by reading a byte of its first
+/// readable mapping
+fn can_read_memory<P: ProcessMemory>(process: &P, pid: Pid) -> bool {
+    let maps = match proc_maps::get_process_maps(pid) {
+        Ok(maps) => maps,
+        Err(_) => return false,
+    };
+    match maps.iter().find(|m| m.is_read() && m.size() > 0) {
+        Some(m) => process.read(m.start(), &mut [0u8]).is_ok(),
+        None => false,
+    }
+}
+
 pub trait ProcessMemory {
     /// Copies memory from another process into an already allocated
     /// byte buffer
@@ -252,6 +465,53 @@ fn filter_child_pids(
 pub mod tests {
     use super::*;
 
+    /// A child process for tests to inspect, which is killed when this is dropped so that a
+    /// failing test doesn't leave it running
+    #[cfg(unix)]
+    pub struct TestChild(std::process::Child);
+
+    #[cfg(unix)]
+    impl TestChild {
+        pub fn spawn(command: &mut std::process::Command) -> Self {
+            Self(command.spawn().unwrap())
+        }
+
+        /// Starts `sleep 10`, and waits for it to finish starting up so that it's idle
+        pub fn sleep() -> Self {
+            let child = Self::spawn(std::process::Command::new("sleep").arg("10"));
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            child
+        }
+
+        pub fn pid(&self) -> Pid {
+            self.0.id() as Pid
+        }
+    }
+
+    #[cfg(unix)]
+    impl std::ops::Deref for TestChild {
+        type Target = std::process::Child;
+
+        fn deref(&self) -> &std::process::Child {
+            &self.0
+        }
+    }
+
+    #[cfg(unix)]
+    impl std::ops::DerefMut for TestChild {
+        fn deref_mut(&mut self) -> &mut std::process::Child {
+            &mut self.0
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for TestChild {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
     #[derive(Copy, Clone)]
     struct Point {
         x: i32,
@@ -275,4 +535,34 @@ pub mod tests {
         assert_eq!(original.x, copy.x);
         assert_eq!(original.y, copy.y);
     }
+
+    #[test]
+    fn test_error_context() {
+        let error = Error::from(std::io::Error::from_raw_os_error(3))
+            .with_context(ErrorContext::new("reading memory").address(0x1000, 8))
+            .with_context(ErrorContext::new("unwinding").pid(12).tid(13));
+        assert!(error.to_string().starts_with(
+            "Failed unwinding for pid 12 for tid 13: Failed reading memory at 0x1000..0x1008: "
+        ));
+        assert_eq!(error.context().unwrap().operation, "unwinding");
+        assert!(matches!(error.root(), Error::IOError(e) if e.raw_os_error() == Some(3)));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn test_display_stack_frame() {
+        let mut frame = StackFrame {
+            line: Some(12),
+            column: Some(5),
+            filename: Some("main.rs".to_string()),
+            function: Some("main".to_string()),
+            function_start: Some(0xf00),
+            function_size: Some(0x200),
+            module: "/bin/app".to_string(),
+            addr: 0x1000,
+        };
+        assert_eq!(frame.to_string(), "0x0000000000001000 main (main.rs:12:5)");
+        frame.column = None;
+        assert_eq!(frame.to_string(), "0x0000000000001000 main (main.rs:12)");
+    }
 }
@@ -0,0 +1,107 @@
+//! Parses the layout of the ELF binaries loaded into a process, which is needed to map addresses
+//! in the process back to addresses in the binary. This uses goblin by default, or the `object`
+//! crate (which is also used for the symbols and unwind info) with the `object-parser` feature.
+
+use crate::Error;
+
+/// Where a binary expects its executable code to be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct BinaryLayout {
+    /// The preferred virtual address of the first executable segment
+    pub exec_address: u64,
+    /// The alignment of the first executable segment
+    pub exec_align: u64,
+    /// Whether the binary is position independent, and so relocated when it's loaded
+    pub relocatable: bool,
+}
+
+impl BinaryLayout {
+    /// Returns how far the binary was shifted from its preferred address, given the start of
+    /// the mapping of its executable segment
+    pub fn load_bias(&self, start: u64) -> Result<u64, Error> {
+        if !self.relocatable {
+            return Ok(0);
+        }
+        // Don't panic if v_addr/start is messed up
+        // (https://github.com/benfred/py-spy/issues/183)
+        if self.exec_address > start {
+            return Err(Error::Other(format!(
+                "v_addr {} is past start {}",
+                self.exec_address, start
+            )));
+        }
+        Ok(start - self.exec_address + self.exec_address.checked_rem(self.exec_align).unwrap_or(0))
+    }
+}
+
+#[cfg(not(feature = "object-parser"))]
+pub(super) fn parse_layout(buffer: &[u8]) -> Result<BinaryLayout, Error> {
+    use goblin::elf::header::ET_DYN;
+    use goblin::elf::program_header::{PF_X, PT_LOAD};
+
+    let elf = match goblin::Object::parse(buffer)? {
+        goblin::Object::Elf(elf) => elf,
+        _ => return Err(Error::Other("unknown binary type".to_string())),
+    };
+    let header = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == PT_LOAD && header.p_flags & PF_X != 0)
+        .ok_or_else(|| Error::Other("Failed to find executable PT_LOAD header".to_string()))?;
+    Ok(BinaryLayout {
+        exec_address: header.p_vaddr,
+        exec_align: header.p_align,
+        relocatable: elf.header.e_type == ET_DYN,
+    })
+}
+
+#[cfg(feature = "object-parser")]
+pub(super) fn parse_layout(buffer: &[u8]) -> Result<BinaryLayout, Error> {
+    use object::{Object, ObjectKind, ObjectSegment, SegmentFlags};
+
+    let file = object::File::parse(buffer).map_err(|e| Error::Other(e.to_string()))?;
+    let segment = file
+        .segments()
+        .find(|segment| match segment.flags() {
+            SegmentFlags::Elf { p_flags } => p_flags & object::elf::PF_X != 0,
+            SegmentFlags::MachO { initprot, .. } => initprot & object::macho::VM_PROT_EXECUTE != 0,
+            SegmentFlags::Coff { characteristics } => {
+                characteristics & object::pe::IMAGE_SCN_MEM_EXECUTE != 0
+            }
+            _ => false,
+        })
+        .ok_or_else(|| Error::Other("Failed to find executable segment".to_string()))?;
+    Ok(BinaryLayout {
+        exec_address: segment.address(),
+        exec_align: segment.align(),
+        relocatable: file.kind() == ObjectKind::Dynamic,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_layout() {
+        // our own test binary is an ELF file with executable code
+        let exe = std::fs::read("/proc/self/exe").unwrap();
+        let layout = parse_layout(&exe).unwrap();
+        assert!(layout.exec_align > 0);
+
+        assert!(parse_layout(b"not a binary").is_err());
+
+        let layout = BinaryLayout {
+            exec_address: 0x1000,
+            exec_align: 0x1000,
+            relocatable: true,
+        };
+        assert_eq!(layout.load_bias(0x7f0000001000).unwrap(), 0x7f0000000000);
+        assert!(layout.load_bias(0x10).is_err());
+        let layout = BinaryLayout {
+            relocatable: false,
+            ..layout
+        };
+        assert_eq!(layout.load_bias(0x401000).unwrap(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::{Cell, Ref, RefCell};
+
+use log::debug;
+
+/// Data that is loaded lazily for a binary (like its symbols or unwind tables), and which can
+/// be evicted again when the cache it belongs to goes over its memory budget
+pub(crate) struct CachedData<T> {
+    data: RefCell<Option<T>>,
+    size: Cell<usize>,
+    last_used: Cell<u64>,
+}
+
+impl<T> CachedData<T> {
+    pub fn new() -> Self {
+        Self {
+            data: RefCell::new(None),
+            size: Cell::new(0),
+            last_used: Cell::new(0),
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.data.borrow().is_some()
+    }
+
+    /// Returns the data, calling `load` to load it if it isn't cached. `load` returns the data
+    /// along with an estimate of how many bytes of memory it uses.
+    pub fn get_or_load(
+        &self,
+        budget: &CacheBudget,
+        load: impl FnOnce() -> (T, usize),
+    ) -> Ref<'_, T> {
+        self.last_used.set(budget.tick());
+        if !self.is_loaded() {
+            let (data, size) = load();
+            *self.data.borrow_mut() = Some(data);
+            self.size.set(size);
+        }
+        Ref::map(self.data.borrow(), |data| {
+            data.as_ref().expect("cached data was just loaded")
+        })
+    }
+
+    fn evict(&self) {
+        *self.data.borrow_mut() = None;
+        self.size.set(0);
+    }
+}
+
+/// Limits how much memory the data cached for the binaries of a process can use, by evicting
+/// the least recently used entries once the limit is exceeded
+pub(crate) struct CacheBudget {
+    limit: Option<usize>,
+    clock: Cell<u64>,
+}
+
+impl CacheBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            clock: Cell::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.set(self.clock.get() + 1);
+        self.clock.get()
+    }
+
+    /// Returns the estimated number of bytes used by the entries
+    pub fn size<'a, T: 'a>(&self, entries: impl Iterator<Item = &'a CachedData<T>>) -> usize {
+        entries.map(|entry| entry.size.get()).sum()
+    }
+
+    /// Evicts the least recently used entries until the entries fit in the budget. The most
+    /// recently used entry is never evicted, since it's the one that is being looked at.
+    /// Entries that are currently borrowed are skipped.
+    pub fn enforce<'a, T: 'a>(&self, entries: impl Iterator<Item = &'a CachedData<T>> + Clone) {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let mut size = self.size(entries.clone());
+        if size <= limit {
+            return;
+        }
+
+        let mut loaded: Vec<&CachedData<T>> = entries
+            .filter(|entry| entry.is_loaded() && entry.last_used.get() != self.clock.get())
+            .collect();
+        loaded.sort_unstable_by_key(|entry| entry.last_used.get());
+        for entry in loaded {
+            if size <= limit {
+                break;
+            }
+            if entry.data.try_borrow_mut().is_err() {
+                continue;
+            }
+            debug!("evicting {} bytes of cached data", entry.size.get());
+            size -= entry.size.get();
+            entry.evict();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let budget = CacheBudget::new(Some(250));
+        let entries: Vec<CachedData<u32>> = (0..3).map(|_| CachedData::new()).collect();
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(*entry.get_or_load(&budget, || (i as u32, 100)), i as u32);
+            budget.enforce(entries.iter());
+        }
+        // loading the third entry pushed us over the limit, evicting the oldest one
+        assert!(!entries[0].is_loaded());
+        assert!(entries[1].is_loaded() && entries[2].is_loaded());
+        assert_eq!(budget.size(entries.iter()), 200);
+
+        // touching an entry makes it the most recently used
+        assert_eq!(*entries[1].get_or_load(&budget, || unreachable!()), 1);
+        assert_eq!(*entries[0].get_or_load(&budget, || (0, 100)), 0);
+        budget.enforce(entries.iter());
+        assert!(entries[0].is_loaded() && entries[1].is_loaded());
+        assert!(!entries[2].is_loaded());
+    }
+}
@@ -0,0 +1,305 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{channel, Receiver, SendError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::dwarf_unwind::SharedTables;
+use super::snapshot::DEFAULT_MAX_STACK_SIZE;
+use super::{Pid, Process, SnapshotUnwinder, StackSnapshot, Tid};
+use crate::Error;
+
+/// The stacks of every thread of a process, as returned by `StackCollector::collect`
+#[derive(Debug)]
+pub struct CollectedStacks {
+    /// The instruction pointers of the callstack of each thread, or why it couldn't be
+    /// copied or unwound
+    pub stacks: Vec<(Tid, Result<Vec<u64>, Error>)>,
+    /// How long the process was held stopped while the stacks were copied
+    pub paused_for: Duration,
+}
+
+/// Collects the stacks of every thread of a process at once, while keeping the process stopped
+/// for as short a time as possible.
+///
+/// Each collection stops the whole process, copies the registers and stacks of its threads, and
+/// resumes it again before any unwinding happens. The copies are then unwound concurrently on a
+/// pool of worker threads. For processes with hundreds of threads (like a JVM), this means the
+/// time the process is stopped only depends on how long the stacks take to copy, and not on
+/// unwinding them one after another.
+///
+/// Every worker has a `SnapshotUnwinder` of its own, but the unwind tables are shared between
+/// them, so each binary is only parsed once no matter which workers unwind through it. The
+/// tables stay cached between collections.
+///
+/// ```rust,no_run
+/// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+/// let mut collector = remoteprocess::StackCollector::new(pid, 4)?;
+/// let stacks = collector.collect()?;
+/// println!("process was paused for {:?}", stacks.paused_for);
+/// for (tid, frames) in stacks.stacks {
+///     println!("thread {}: {} frames", tid, frames?.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct StackCollector {
+    process: Process,
+    workers: Vec<Worker>,
+    results: Receiver<(usize, Result<Vec<u64>, Error>)>,
+    max_stack_size: usize,
+}
+
+struct Worker {
+    jobs: Option<Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+enum Job {
+    /// Unwind the snapshot, and send the frames back along with the index of the snapshot
+    Unwind(usize, StackSnapshot),
+    Reload,
+}
+
+impl StackCollector {
+    /// Creates a collector for a process that unwinds on `workers` threads
+    pub fn new(pid: Pid, workers: usize) -> Result<Self, Error> {
+        let process = Process::new(pid)?;
+        let (results_tx, results) = channel();
+        let (ready_tx, ready) = channel();
+        let shared = Arc::new(SharedTables::default());
+        let workers = (0..workers.max(1))
+            .map(|i| {
+                let (jobs, jobs_rx) = channel();
+                let results = results_tx.clone();
+                let ready = ready_tx.clone();
+                let shared = shared.clone();
+                let handle = std::thread::Builder::new()
+                    .name(format!("stack-collector-{}", i))
+                    .spawn(move || {
+                        // the unwinder has to be created on the worker, since frame providers
+                        // can't be sent across threads
+                        let mut unwinder = match SnapshotUnwinder::new(pid) {
+                            Ok(unwinder) => unwinder,
+                            Err(e) => {
+                                let _ = ready.send(Err(e));
+                                return;
+                            }
+                        };
+                        unwinder.share_tables(shared);
+                        let _ = ready.send(Ok(()));
+                        drop(ready);
+                        run_worker(&mut unwinder, jobs_rx, results);
+                    })?;
+                Ok(Worker {
+                    jobs: Some(jobs),
+                    handle: Some(handle),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let collector = Self {
+            process,
+            workers,
+            results,
+            max_stack_size: DEFAULT_MAX_STACK_SIZE,
+        };
+        // if a worker failed to start, the others are stopped when the collector is dropped
+        for _ in 0..collector.workers.len() {
+            ready
+                .recv()
+                .map_err(|_| Error::Other("Stack collector worker exited".to_string()))??;
+        }
+        Ok(collector)
+    }
+
+    /// Copies at most `max_stack_size` bytes of the stack of each thread
+    pub fn with_max_stack_size(mut self, max_stack_size: usize) -> Self {
+        self.max_stack_size = max_stack_size;
+        self
+    }
+
+    pub fn process(&self) -> &Process {
+        &self.process
+    }
+
+    /// Reloads the binaries loaded into the process on every worker, for after a binary has
+    /// been loaded or unloaded. The reload happens before the next stacks are unwound.
+    pub fn reload(&self) {
+        for worker in &self.workers {
+            if let Some(jobs) = &worker.jobs {
+                let _ = jobs.send(Job::Reload);
+            }
+        }
+    }
+
+    /// Stops the process, copies the stacks of all of its threads and resumes it, and then
+    /// unwinds the copies on the workers. The stacks are returned in order of thread id.
+    pub fn collect(&mut self) -> Result<CollectedStacks, Error> {
+        let start = Instant::now();
+        let (threads, snapshots) = {
+            let _lock = self.process.lock()?;
+            let mut threads = self.process.threads()?;
+            threads.sort_by_key(|thread| thread.tid.as_raw());
+            // read the maps once for all threads, rather than once per thread
+            let maps = proc_maps::get_process_maps(self.process.pid)?;
+            let snapshots: Vec<_> = threads
+                .iter()
+                .map(|thread| thread.snapshot_with_maps(&maps, self.max_stack_size))
+                .collect();
+            (threads, snapshots)
+        };
+        let paused_for = start.elapsed();
+
+        // every job that was sent has to be received before returning, or its result would be
+        // taken for a stack of the next collection
+        let mut stacks: Vec<(Tid, Result<Vec<u64>, Error>)> = Vec::with_capacity(threads.len());
+        let mut pending = 0;
+        for (i, (thread, snapshot)) in threads.iter().zip(snapshots).enumerate() {
+            let tid = thread.tid.as_raw();
+            let sent = snapshot.and_then(|snapshot| self.send(i, Job::Unwind(i, snapshot)));
+            match sent {
+                Ok(()) => {
+                    stacks.push((tid, Ok(Vec::new())));
+                    pending += 1;
+                }
+                Err(e) => stacks.push((tid, Err(e))),
+            }
+        }
+
+        for _ in 0..pending {
+            let (i, frames) = self
+                .results
+                .recv()
+                .map_err(|_| Error::Other("Stack collector workers exited".to_string()))?;
+            stacks[i].1 = frames;
+        }
+        Ok(CollectedStacks { stacks, paused_for })
+    }
+
+    /// Sends a job to a worker, spreading jobs round robin across the workers. Workers that
+    /// have exited are skipped, so this only fails once all of them have.
+    fn send(&self, index: usize, mut job: Job) -> Result<(), Error> {
+        for offset in 0..self.workers.len() {
+            let worker = &self.workers[(index + offset) % self.workers.len()];
+            let Some(jobs) = worker.jobs.as_ref() else {
+                continue;
+            };
+            match jobs.send(job) {
+                Ok(()) => return Ok(()),
+                Err(SendError(unsent)) => job = unsent,
+            }
+        }
+        Err(Error::Other("Stack collector workers exited".to_string()))
+    }
+}
+
+impl Drop for StackCollector {
+    fn drop(&mut self) {
+        // closing the job channels stops the workers
+        for worker in &mut self.workers {
+            worker.jobs.take();
+        }
+        for worker in &mut self.workers {
+            if let Some(handle) = worker.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+fn run_worker(
+    unwinder: &mut SnapshotUnwinder,
+    jobs: Receiver<Job>,
+    results: Sender<(usize, Result<Vec<u64>, Error>)>,
+) {
+    for job in jobs {
+        match job {
+            Job::Unwind(index, snapshot) => {
+                // a panic has to be turned into a result, or `collect` would wait forever for
+                // the stack that this worker was unwinding
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    let mut frames = Vec::new();
+                    unwinder.unwind_into(&snapshot, &mut frames).map(|_| frames)
+                }))
+                .unwrap_or_else(|panic| {
+                    Err(Error::Other(format!(
+                        "Unwinding thread {} panicked: {}",
+                        snapshot.tid,
+                        panic_message(&*panic)
+                    )))
+                });
+                if results.send((index, result)).is_err() {
+                    return;
+                }
+            }
+            Job::Reload => match std::panic::catch_unwind(AssertUnwindSafe(|| unwinder.reload())) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Failed to reload unwind info: {}", e),
+                Err(panic) => warn!("Reloading unwind info panicked: {}", panic_message(&*panic)),
+            },
+        }
+    }
+}
+
+/// Returns the message a panic was started with, for the usual `panic!` payloads
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let child = crate::tests::TestChild::sleep();
+        let mut collector = StackCollector::new(child.pid(), 2).unwrap();
+        let collected = collector.collect().unwrap();
+        assert_eq!(collected.stacks.len(), 1);
+        let (tid, frames) = &collected.stacks[0];
+        assert_eq!(*tid, child.pid());
+        assert!(!frames.as_ref().unwrap().is_empty());
+
+        // the unwinders are reused between collections
+        collector.reload();
+        assert_eq!(collector.collect().unwrap().stacks.len(), 1);
+    }
+
+    #[test]
+    fn test_dead_worker() {
+        let child = crate::tests::TestChild::sleep();
+        let mut collector = StackCollector::new(child.pid(), 2).unwrap();
+        // a job channel whose worker is gone, like after the worker thread died
+        let dead_worker = || {
+            let (jobs, _) = channel();
+            Some(jobs)
+        };
+
+        // the only stack goes to the first worker, so it's unwound by the other one instead
+        collector.workers[0].jobs = dead_worker();
+        let collected = collector.collect().unwrap();
+        assert!(!collected.stacks[0].1.as_ref().unwrap().is_empty());
+
+        // without any workers the stacks fail, and nothing is left over for the next collection
+        collector.workers[1].jobs = dead_worker();
+        let collected = collector.collect().unwrap();
+        assert!(collected.stacks[0].1.is_err());
+        assert!(collector.results.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let panic = std::panic::catch_unwind(|| panic!("failed at {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*panic), "failed at 1");
+        assert_eq!(panic_message(&"oops"), "oops");
+        assert_eq!(panic_message(&1), "unknown panic");
+    }
+}
@@ -0,0 +1,667 @@
+//! Reads the images that CRIU writes when checkpointing a process, so that a checkpointed
+//! process (or container) can be unwound and symbolicated like a live one.
+//!
+//! Only the images describing the memory and threads of the process are read: `pstree.img` for
+//! the threads, `core-<tid>.img` for their registers, `mm-<pid>.img` and `files.img` for the
+//! memory mappings, and `pagemap-<pid>.img` with its `pages-<id>.img` for the memory contents.
+//! Incremental dumps, where pages are stored in a parent checkpoint, aren't supported.
+//!
+//! Every image is a sequence of protobuf messages, each prefixed by its size as a u32. Apart from
+//! the pages, which are stored raw, the images start with one or two u32 magic numbers.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use log::info;
+
+use super::snapshot::RED_ZONE;
+use super::{Pid, Registers, StackSnapshot, Tid, DEFAULT_MAX_STACK_SIZE};
+use crate::{Error, ProcessMemory};
+
+const IMG_COMMON_MAGIC: u32 = 0x54564319;
+const IMG_SERVICE_MAGIC: u32 = 0x55105940;
+
+/// core_entry.mtype values
+#[cfg(target_arch = "x86_64")]
+const MARCH: u64 = 1;
+#[cfg(target_arch = "aarch64")]
+const MARCH: u64 = 3;
+
+/// The field of core_entry holding the registers for this architecture
+#[cfg(target_arch = "x86_64")]
+const THREAD_INFO_FIELD: u32 = 2;
+#[cfg(target_arch = "aarch64")]
+const THREAD_INFO_FIELD: u32 = 8;
+
+/// pagemap_entry flags
+const PE_PARENT: u64 = 1 << 0;
+#[cfg(test)]
+const PE_LAZY: u64 = 1 << 1;
+const PE_PRESENT: u64 = 1 << 2;
+
+/// vma_entry status bits for mappings of regular files, whose shmid is the id of the file
+const VMA_FILE_PRIVATE: u64 = 1 << 6;
+const VMA_FILE_SHARED: u64 = 1 << 7;
+
+/// fd_types value of regular files in files.img
+const FD_TYPES_REG: u64 = 1;
+
+/// The threads and memory of a process in a CRIU checkpoint
+pub struct CriuImage {
+    pid: Pid,
+    threads: Vec<CriuThread>,
+    mappings: Vec<CriuMapping>,
+    pagemap: Vec<PagemapEntry>,
+    pages: File,
+}
+
+/// A thread of a checkpointed process, with the registers it had when it was dumped
+#[derive(Debug, Clone)]
+pub struct CriuThread {
+    pub tid: Tid,
+    pub registers: Registers,
+}
+
+/// A memory mapping of a checkpointed process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriuMapping {
+    pub start: u64,
+    pub end: u64,
+    /// The offset into the mapped file
+    pub offset: u64,
+    /// PROT_READ/PROT_WRITE/PROT_EXEC flags
+    pub prot: u32,
+    /// The path of the mapped file, or None for anonymous memory
+    pub filename: Option<String>,
+}
+
+/// A range of pages that was dumped, and where its contents are in the pages image
+struct PagemapEntry {
+    start: u64,
+    end: u64,
+    contents: PageContents,
+}
+
+/// Where the contents of the pages in a pagemap entry are
+enum PageContents {
+    /// In the pages image, at this offset
+    Image(u64),
+    /// In a parent checkpoint of an incremental dump
+    Parent,
+    /// Nowhere in the checkpoint, since they were left for the lazy pages daemon to serve
+    Lazy,
+}
+
+impl CriuImage {
+    /// Opens the root process of the process tree in a checkpoint directory
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        let pstree = read_image(&dir.join("pstree.img"))?;
+        let root = pstree
+            .first()
+            .ok_or_else(|| Error::Other(format!("No processes in {}", dir.display())))?;
+        let pid = get_varint(root, 1)?.unwrap_or_default() as Pid;
+        Self::open_pid(dir, pid)
+    }
+
+    /// Opens one of the processes in a checkpoint directory
+    pub fn open_pid(dir: impl AsRef<Path>, pid: Pid) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        info!("reading CRIU images for {} from {}", pid, dir.display());
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+        let mut tids = None;
+        for entry in read_image(&dir.join("pstree.img"))? {
+            if get_varint(&entry, 1)? == Some(pid as u64) {
+                tids = Some(get_varints(&entry, 5)?);
+                break;
+            }
+        }
+        let tids = tids
+            .ok_or_else(|| Error::Other(format!("Process {} isn't in {}", pid, dir.display())))?;
+        let mut threads = Vec::new();
+        for tid in tids {
+            let core = read_image(&dir.join(format!("core-{}.img", tid)))?;
+            let core = core
+                .first()
+                .ok_or_else(|| Error::Other(format!("Empty core image for thread {}", tid)))?;
+            threads.push(CriuThread {
+                tid: tid as Tid,
+                registers: parse_core(core)?,
+            });
+        }
+
+        let files = read_files(dir)?;
+        let mm = read_image(&dir.join(format!("mm-{}.img", pid)))?;
+        let mut mappings = Vec::new();
+        for vma in get_repeated(mm.first().map_or(&[][..], Vec::as_slice), 14)? {
+            let status = get_varint(vma, 7)?.unwrap_or_default();
+            let filename = if status & (VMA_FILE_PRIVATE | VMA_FILE_SHARED) != 0 {
+                let id = get_varint(vma, 4)?.unwrap_or_default();
+                files.get(&id).cloned()
+            } else {
+                None
+            };
+            mappings.push(CriuMapping {
+                start: get_varint(vma, 1)?.unwrap_or_default(),
+                end: get_varint(vma, 2)?.unwrap_or_default(),
+                offset: get_varint(vma, 3)?.unwrap_or_default(),
+                prot: get_varint(vma, 5)?.unwrap_or_default() as u32,
+                filename,
+            });
+        }
+        mappings.sort_by_key(|m| m.start);
+
+        let mut entries = read_image(&dir.join(format!("pagemap-{}.img", pid)))?.into_iter();
+        let head = entries
+            .next()
+            .ok_or_else(|| Error::Other(format!("Empty pagemap image for {}", pid)))?;
+        let pages_id = get_varint(&head, 1)?.unwrap_or_default();
+        let mut pagemap = Vec::new();
+        let mut offset = 0;
+        for entry in entries {
+            let start = get_varint(&entry, 1)?.unwrap_or_default();
+            let size = get_varint(&entry, 2)?.unwrap_or_default() * page_size;
+            // older images only have the in_parent field instead of the flags
+            let contents = match get_varint(&entry, 4)? {
+                Some(flags) if flags & PE_PRESENT != 0 => PageContents::Image(offset),
+                Some(flags) if flags & PE_PARENT != 0 => PageContents::Parent,
+                Some(_) => PageContents::Lazy,
+                None if get_varint(&entry, 3)? == Some(1) => PageContents::Parent,
+                None => PageContents::Image(offset),
+            };
+            if let PageContents::Image(_) = contents {
+                offset += size;
+            }
+            pagemap.push(PagemapEntry {
+                start,
+                end: start + size,
+                contents,
+            });
+        }
+        pagemap.sort_by_key(|e| e.start);
+        let pages = File::open(dir.join(format!("pages-{}.img", pages_id)))?;
+
+        Ok(Self {
+            pid,
+            threads,
+            mappings,
+            pagemap,
+            pages,
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn threads(&self) -> &[CriuThread] {
+        &self.threads
+    }
+
+    /// The memory mappings of the process, sorted by address. Mapped files are read from this
+    /// machine when they are needed, so they should be the same files the process had mapped.
+    pub fn mappings(&self) -> &[CriuMapping] {
+        &self.mappings
+    }
+
+    /// Copies the registers and the live part of the stack of a thread, so that it can be
+    /// unwound with a `SnapshotUnwinder` created with `SnapshotUnwinder::with_memory`
+    pub fn snapshot(&self, thread: &CriuThread) -> Result<StackSnapshot, Error> {
+        let sp = thread.registers.sp().ok_or_else(|| {
+            Error::Other(format!("Failed to get stack pointer for {}", thread.tid))
+        })?;
+        let stack_map = self.find_mapping(sp).ok_or_else(|| {
+            Error::Other(format!(
+                "Failed to find stack mapping for thread {} (sp 0x{:016x})",
+                thread.tid, sp
+            ))
+        })?;
+        let stack_start = sp.saturating_sub(RED_ZONE).max(stack_map.start);
+        let length = ((stack_map.end - stack_start) as usize).min(DEFAULT_MAX_STACK_SIZE);
+        let stack = self.copy(stack_start as usize, length)?;
+        Ok(StackSnapshot::new(
+            thread.tid,
+            thread.registers,
+            stack_start,
+            stack,
+        ))
+    }
+
+    fn find_mapping(&self, addr: u64) -> Option<&CriuMapping> {
+        let i = self.mappings.partition_point(|m| m.start <= addr);
+        let mapping = self.mappings.get(i.checked_sub(1)?)?;
+        (addr < mapping.end).then_some(mapping)
+    }
+
+    /// Reads memory that is all within one dumped page range or one mapping
+    fn read_part(&self, addr: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let i = self.pagemap.partition_point(|e| e.start <= addr);
+        if let Some(entry) = i.checked_sub(1).map(|i| &self.pagemap[i]) {
+            if addr < entry.end {
+                let offset = match entry.contents {
+                    PageContents::Image(offset) => offset,
+                    PageContents::Parent => {
+                        return Err(Error::Other(format!(
+                            "Memory at 0x{:016x} is in a parent checkpoint, which isn't supported",
+                            addr
+                        )))
+                    }
+                    PageContents::Lazy => {
+                        return Err(Error::Other(format!(
+                            "Memory at 0x{:016x} was left for the lazy pages daemon",
+                            addr
+                        )))
+                    }
+                };
+                let len = buf.len().min((entry.end - addr) as usize);
+                self.pages
+                    .read_exact_at(&mut buf[..len], offset + addr - entry.start)?;
+                return Ok(len);
+            }
+        }
+
+        // pages that weren't dumped are either untouched anonymous memory, or unmodified pages
+        // of a mapped file
+        let mapping = self.find_mapping(addr).ok_or_else(|| {
+            Error::Other(format!(
+                "Address 0x{:016x} isn't mapped in the checkpoint of {}",
+                addr, self.pid
+            ))
+        })?;
+        let mut len = buf.len().min((mapping.end - addr) as usize);
+        if let Some(next) = self.pagemap.get(i) {
+            len = len.min((next.start - addr) as usize);
+        }
+        match &mapping.filename {
+            Some(filename) => {
+                let file = File::open(filename)?;
+                file.read_exact_at(&mut buf[..len], mapping.offset + addr - mapping.start)?;
+            }
+            None => buf[..len].fill(0),
+        }
+        Ok(len)
+    }
+}
+
+impl ProcessMemory for CriuImage {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            done += self.read_part((addr + done) as u64, &mut buf[done..])?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the registers from a core_entry
+fn parse_core(core: &[u8]) -> Result<Registers, Error> {
+    let march = get_varint(core, 1)?.unwrap_or_default();
+    if march != MARCH {
+        return Err(Error::Other(format!(
+            "Checkpoint is for a different architecture ({})",
+            march
+        )));
+    }
+    let thread_info = get_bytes(core, THREAD_INFO_FIELD)?
+        .ok_or_else(|| Error::Other("Missing thread info in core image".to_string()))?;
+    let mut registers = Registers::default();
+
+    // user_x86_regs_entry has the registers in the order of user_regs_struct
+    #[cfg(target_arch = "x86_64")]
+    {
+        const DWARF_NUMBERS: [(u32, u16); 17] = [
+            (11, 0),
+            (13, 1),
+            (12, 2),
+            (6, 3),
+            (14, 4),
+            (15, 5),
+            (5, 6),
+            (20, 7),
+            (10, 8),
+            (9, 9),
+            (8, 10),
+            (7, 11),
+            (4, 12),
+            (3, 13),
+            (2, 14),
+            (1, 15),
+            (17, 16),
+        ];
+        let gpregs = get_bytes(thread_info, 2)?.unwrap_or_default();
+        for (field, register) in DWARF_NUMBERS {
+            if let Some(value) = get_varint(gpregs, field)? {
+                registers.set(register, value);
+            }
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let gpregs = get_bytes(thread_info, 3)?.unwrap_or_default();
+        for (register, value) in get_varints(gpregs, 1)?.into_iter().enumerate() {
+            registers.set(register as u16, value);
+        }
+        if let Some(sp) = get_varint(gpregs, 2)? {
+            registers.set(Registers::SP, sp);
+        }
+        if let Some(pc) = get_varint(gpregs, 3)? {
+            registers.set(Registers::IP, pc);
+        }
+    }
+
+    Ok(registers)
+}
+
+/// Returns the paths of the regular files in the checkpoint by their id
+fn read_files(dir: &Path) -> Result<HashMap<u64, String>, Error> {
+    let mut files = HashMap::new();
+    let path = dir.join("files.img");
+    if path.exists() {
+        for entry in read_image(&path)? {
+            if get_varint(&entry, 1)? != Some(FD_TYPES_REG) {
+                continue;
+            }
+            if let Some(reg) = get_bytes(&entry, 3)? {
+                insert_reg_file(&mut files, reg)?;
+            }
+        }
+    } else {
+        // before CRIU 3.x, the regular files were in their own image
+        for entry in read_image(&dir.join("reg-files.img"))? {
+            insert_reg_file(&mut files, &entry)?;
+        }
+    }
+    Ok(files)
+}
+
+fn insert_reg_file(files: &mut HashMap<u64, String>, reg: &[u8]) -> Result<(), Error> {
+    if let (Some(id), Some(name)) = (get_varint(reg, 1)?, get_bytes(reg, 6)?) {
+        files.insert(id, String::from_utf8_lossy(name).into_owned());
+    }
+    Ok(())
+}
+
+/// Returns the messages in an image file
+fn read_image(path: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let data = std::fs::read(path)
+        .map_err(|e| Error::Other(format!("Failed to read {}: {}", path.display(), e)))?;
+    let mut data = &data[..];
+    let read_u32 = |data: &mut &[u8]| -> Option<u32> {
+        let (value, rest) = data.split_first_chunk::<4>()?;
+        *data = rest;
+        Some(u32::from_le_bytes(*value))
+    };
+
+    let truncated = || Error::Other(format!("Truncated image {}", path.display()));
+    let magic = read_u32(&mut data).ok_or_else(truncated)?;
+    if magic == IMG_COMMON_MAGIC || magic == IMG_SERVICE_MAGIC {
+        read_u32(&mut data).ok_or_else(truncated)?;
+    }
+
+    let mut messages = Vec::new();
+    while let Some(size) = read_u32(&mut data) {
+        let size = size as usize;
+        if data.len() < size {
+            return Err(truncated());
+        }
+        let (message, rest) = data.split_at(size);
+        messages.push(message.to_vec());
+        data = rest;
+    }
+    Ok(messages)
+}
+
+/// The value of a protobuf field
+enum Value<'a> {
+    Varint(u64),
+    Fixed(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Calls `f` with the number and value of each field in a protobuf message
+fn for_each_field<'a>(
+    mut message: &'a [u8],
+    mut f: impl FnMut(u32, Value<'a>),
+) -> Result<(), Error> {
+    let invalid = || Error::Other("Invalid protobuf message in CRIU image".to_string());
+    while !message.is_empty() {
+        let key = read_varint(&mut message).ok_or_else(invalid)?;
+        let value = match key & 7 {
+            0 => Value::Varint(read_varint(&mut message).ok_or_else(invalid)?),
+            1 => {
+                let (value, rest) = message.split_first_chunk::<8>().ok_or_else(invalid)?;
+                message = rest;
+                Value::Fixed(u64::from_le_bytes(*value))
+            }
+            2 => {
+                let len = read_varint(&mut message).ok_or_else(invalid)? as usize;
+                if message.len() < len {
+                    return Err(invalid());
+                }
+                let (value, rest) = message.split_at(len);
+                message = rest;
+                Value::Bytes(value)
+            }
+            5 => {
+                let (value, rest) = message.split_first_chunk::<4>().ok_or_else(invalid)?;
+                message = rest;
+                Value::Fixed(u32::from_le_bytes(*value) as u64)
+            }
+            _ => return Err(invalid()),
+        };
+        f((key >> 3) as u32, value);
+    }
+    Ok(())
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Returns the last value of an integer field
+fn get_varint(message: &[u8], field: u32) -> Result<Option<u64>, Error> {
+    let mut ret = None;
+    for_each_field(message, |number, value| match value {
+        Value::Varint(v) | Value::Fixed(v) if number == field => ret = Some(v),
+        _ => {}
+    })?;
+    Ok(ret)
+}
+
+/// Returns all the values of a repeated integer field, which can be packed or not
+fn get_varints(message: &[u8], field: u32) -> Result<Vec<u64>, Error> {
+    let mut ret = Vec::new();
+    let mut packed = Vec::new();
+    for_each_field(message, |number, value| match value {
+        Value::Varint(v) | Value::Fixed(v) if number == field => ret.push(v),
+        Value::Bytes(b) if number == field => packed.push(b),
+        _ => {}
+    })?;
+    for mut data in packed {
+        while !data.is_empty() {
+            let value = read_varint(&mut data)
+                .ok_or_else(|| Error::Other("Invalid packed field in CRIU image".to_string()))?;
+            ret.push(value);
+        }
+    }
+    Ok(ret)
+}
+
+fn get_bytes(message: &[u8], field: u32) -> Result<Option<&[u8]>, Error> {
+    Ok(get_repeated(message, field)?.pop())
+}
+
+/// Returns all the values of a repeated message or string field
+fn get_repeated(message: &[u8], field: u32) -> Result<Vec<&[u8]>, Error> {
+    let mut ret = Vec::new();
+    for_each_field(message, |number, value| {
+        if let Value::Bytes(b) = value {
+            if number == field {
+                ret.push(b);
+            }
+        }
+    })?;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    /// Encodes a message from (field, value) pairs
+    fn message(ints: &[(u32, u64)], nested: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &(field, value) in ints {
+            varint((field as u64) << 3, &mut out);
+            varint(value, &mut out);
+        }
+        for &(field, value) in nested {
+            varint(((field as u64) << 3) | 2, &mut out);
+            varint(value.len() as u64, &mut out);
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    fn write_image(dir: &Path, name: &str, messages: &[Vec<u8>]) {
+        let mut file = File::create(dir.join(name)).unwrap();
+        file.write_all(&IMG_COMMON_MAGIC.to_le_bytes()).unwrap();
+        file.write_all(&0x12345678u32.to_le_bytes()).unwrap();
+        for m in messages {
+            file.write_all(&(m.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(m).unwrap();
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn core(sp: u64, ip: u64) -> Vec<u8> {
+        let gpregs = message(&[(20, sp), (17, ip), (5, 0x1234)], &[]);
+        let thread_info = message(&[(1, 0)], &[(2, &gpregs)]);
+        message(&[(1, MARCH)], &[(THREAD_INFO_FIELD, &thread_info)])
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn core(sp: u64, ip: u64) -> Vec<u8> {
+        let gpregs = message(&[(2, sp), (3, ip)], &[]);
+        let thread_info = message(&[(1, 0)], &[(3, &gpregs)]);
+        message(&[(1, MARCH)], &[(THREAD_INFO_FIELD, &thread_info)])
+    }
+
+    #[test]
+    fn test_criu_image() {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let dir = std::env::temp_dir().join(format!("remoteprocess-criu-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mapped = dir.join("mapped");
+        std::fs::write(&mapped, vec![0xaa; 2 * page_size as usize]).unwrap();
+        let stack = 0x10 * page_size;
+
+        write_image(
+            &dir,
+            "pstree.img",
+            &[message(&[(1, 10), (5, 10), (5, 11)], &[])],
+        );
+        write_image(
+            &dir,
+            "core-10.img",
+            &[core(stack + page_size + 0x100, 0xdead)],
+        );
+        write_image(&dir, "core-11.img", &[core(stack + 8, 0xbeef)]);
+        let reg = message(&[(1, 5)], &[(6, mapped.to_str().unwrap().as_bytes())]);
+        write_image(
+            &dir,
+            "files.img",
+            &[message(&[(1, 1), (2, 5)], &[(3, &reg)])],
+        );
+        let file_vma = message(
+            &[
+                (1, page_size),
+                (2, 2 * page_size),
+                (3, page_size),
+                (4, 5),
+                (5, 5),
+                (7, VMA_FILE_PRIVATE | 1),
+            ],
+            &[],
+        );
+        let stack_vma = message(
+            &[(1, stack - page_size), (2, stack + 2 * page_size), (5, 3)],
+            &[],
+        );
+        write_image(
+            &dir,
+            "mm-10.img",
+            &[message(&[(1, 0)], &[(14, &file_vma), (14, &stack_vma)])],
+        );
+        write_image(
+            &dir,
+            "pagemap-10.img",
+            &[
+                message(&[(1, 3)], &[]),
+                message(&[(1, stack + page_size), (2, 1), (4, PE_PRESENT)], &[]),
+                message(&[(1, stack - page_size), (2, 1), (4, PE_LAZY)], &[]),
+            ],
+        );
+        let mut pages = vec![0; page_size as usize];
+        pages[0x100..0x104].copy_from_slice(&[1, 2, 3, 4]);
+        std::fs::write(dir.join("pages-3.img"), pages).unwrap();
+
+        let image = CriuImage::open(&dir).unwrap();
+        assert_eq!(image.pid(), 10);
+        let tids: Vec<Tid> = image.threads().iter().map(|t| t.tid).collect();
+        assert_eq!(tids, [10, 11]);
+        assert_eq!(image.threads()[1].registers.ip(), Some(0xbeef));
+        assert_eq!(
+            image.mappings()[0].filename,
+            mapped.to_str().map(String::from)
+        );
+
+        // dumped pages, untouched anonymous memory and mapped files
+        assert_eq!(
+            image.copy((stack + page_size + 0xfe) as usize, 4).unwrap(),
+            [0, 0, 1, 2]
+        );
+        assert_eq!(
+            image.copy((stack + page_size - 2) as usize, 4).unwrap(),
+            [0; 4]
+        );
+        assert_eq!(image.copy(page_size as usize, 2).unwrap(), [0xaa; 2]);
+        assert!(image.copy(0x100, 4).is_err());
+        // lazy pages aren't in the checkpoint, and aren't zero like untouched memory
+        assert!(image.copy((stack - 2) as usize, 4).is_err());
+        assert!(image.copy(stack as usize, 4).is_ok());
+
+        let snapshot = image.snapshot(&image.threads()[0]).unwrap();
+        assert_eq!(
+            snapshot.stack_range().end,
+            stack + 2 * page_size,
+            "the stack is copied up to the end of its mapping"
+        );
+        assert!(snapshot.contains(stack + page_size + 0x100, 4));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,884 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::{Deref, Range};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use gimli::{AttributeValue, Reader as _, RunTimeEndian, UnitOffset};
+use log::info;
+use memmap2::Mmap;
+use object::{CompressionFormat, Object, ObjectSection};
+
+use super::symbol_search::SymbolSearchPath;
+use crate::{Error, ProcessMemory};
+
+pub(super) type Reader = gimli::EndianReader<RunTimeEndian, SectionData>;
+
+/// The contents of a section of the debug info. Uncompressed sections are read straight from
+/// the memory mapped file, which stays mapped for as long as a reader refers to it, rather than
+/// being copied out of it.
+#[derive(Debug, Clone)]
+pub(super) enum SectionData {
+    Mapped(Rc<Mmap>, Range<usize>),
+    /// A compressed section, which had to be decompressed into memory
+    Owned(Rc<[u8]>),
+}
+
+impl Deref for SectionData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map, range) => &map[range.clone()],
+            Self::Owned(data) => data,
+        }
+    }
+}
+
+// Safety: the data of both variants is behind an Rc, so it doesn't move when the section data
+// is moved or cloned
+unsafe impl gimli::StableDeref for SectionData {}
+unsafe impl gimli::CloneStableDeref for SectionData {}
+
+impl From<&[u8]> for SectionData {
+    fn from(data: &[u8]) -> Self {
+        Self::Owned(Rc::from(data))
+    }
+}
+
+/// Give up on types nested deeper than this, in case the debug info has a cycle
+const MAX_TYPE_DEPTH: usize = 32;
+
+/// What kind of type a value has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeKind {
+    /// An integer, float, bool or character
+    Base,
+    Pointer,
+    Struct,
+    Union,
+    Array,
+    Enum,
+    /// Anything else, like a function or a type without a definition
+    Other,
+}
+
+/// A type from the debug info, with typedefs and qualifiers like `const` resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeInfo {
+    /// The name of the type, which is the name of the outermost typedef for typedefs. Pointers
+    /// and arrays without a name of their own are named after what they contain, like `int *`.
+    pub name: Option<String>,
+    pub kind: TypeKind,
+    /// The size of the type in bytes, or None if it has no size (like `void`)
+    pub size: Option<u64>,
+}
+
+/// A value read from the memory of another process, as returned by `DebugInfo::remote_value`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteValue {
+    /// The address the value was read from, or 0 for values that weren't in memory (like
+    /// variables kept in registers)
+    pub address: u64,
+    pub ty: TypeInfo,
+    /// The bytes of the value, as they are in the memory of the process
+    pub data: Vec<u8>,
+    /// Whether the process stores integers big endian, from the binary the debug info is for
+    pub(super) big_endian: bool,
+}
+
+impl RemoteValue {
+    /// Returns the value as an unsigned integer, for integers and pointers of up to 8 bytes
+    pub fn as_u64(&self) -> Option<u64> {
+        if self.data.is_empty() || self.data.len() > 8 {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        if self.big_endian {
+            bytes[8 - self.data.len()..].copy_from_slice(&self.data);
+            Some(u64::from_be_bytes(bytes))
+        } else {
+            bytes[..self.data.len()].copy_from_slice(&self.data);
+            Some(u64::from_le_bytes(bytes))
+        }
+    }
+
+    /// Returns the value as a signed integer, for integers of up to 8 bytes
+    pub fn as_i64(&self) -> Option<i64> {
+        let bits = self.data.len() as u32 * 8;
+        let value = self.as_u64()?;
+        // sign extend values smaller than 8 bytes
+        Some(((value << (64 - bits)) as i64) >> (64 - bits))
+    }
+}
+
+/// What a type name in the index refers to
+#[derive(Debug, Clone)]
+enum TypeName {
+    /// A type, along with its qualified name
+    Type(DieRef, String),
+    /// The plain name of types in different namespaces, which are listed by their qualified
+    /// names
+    Ambiguous(Vec<String>),
+}
+
+/// Where an entry is in the debug info: the index of its unit, and its offset in the unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct DieRef {
+    pub unit: usize,
+    pub offset: UnitOffset,
+}
+
+/// The types described by the DWARF debug info of a binary, for reading structs from the memory
+/// of another process by the names of their fields.
+///
+/// Offsets of fields come from the debug info, rather than from layouts hard coded into the
+/// profiler, so they stay correct across versions of the target that change its structs:
+///
+/// ```rust,no_run
+/// # fn run(pid: remoteprocess::Pid, addr: u64) -> Result<(), remoteprocess::Error> {
+/// use remoteprocess::DebugInfo;
+///
+/// let process = remoteprocess::Process::new(pid)?;
+/// let debug_info = DebugInfo::load("/usr/lib/libpython3.12.so.1.0")?;
+/// // pointers along the way are followed, so this reads `tstate->interp->id`
+/// let id = debug_info.remote_value(&process, addr, "PyThreadState.interp.id")?;
+/// println!("interpreter {:?}", id.as_i64());
+/// # Ok(())
+/// # }
+/// ```
+pub struct DebugInfo {
+    pub(super) dwarf: gimli::Dwarf<Reader>,
+    pub(super) units: Vec<gimli::Unit<Reader>>,
+    /// The named types, by both their plain and their qualified (`a::b::Type`) names
+    types: HashMap<String, TypeName>,
+    /// The global and static variables, named the same way as the types. Statics declared
+    /// inside a function are qualified with the name of the function.
+    pub(super) variables: HashMap<String, DieRef>,
+    /// The address ranges of the functions with code, sorted by their start
+    functions: Vec<(Range<u64>, DieRef)>,
+    endian: RunTimeEndian,
+}
+
+impl DebugInfo {
+    /// Loads the debug info of a binary, which is either in the binary itself or in a separate
+    /// debug file the binary links to
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        Self::load_with(filename, &SymbolSearchPath::default())
+    }
+
+    /// Loads the debug info of a binary from the first place in `search_path` that has it
+    pub fn load_with(filename: &str, search_path: &SymbolSearchPath) -> Result<Self, Error> {
+        let file = File::open(filename)?;
+        let map = unsafe { Mmap::map(&file)? };
+        let binary = parse_object(&map, filename)?;
+        let debug_file = search_path
+            .find(&binary, filename, &[])
+            .unwrap_or_else(|| PathBuf::from(filename));
+
+        info!("loading debug info from {}", debug_file.display());
+        let file = File::open(&debug_file)?;
+        let map = Rc::new(unsafe { Mmap::map(&file)? });
+        let object = parse_object(&map, filename)?;
+        let endian = if object.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let dwarf = gimli::Dwarf::load(|id| -> Result<Reader, Error> {
+            let data = match object.section_by_name(id.name()) {
+                Some(section) => section_data(&map, &section).map_err(|e| {
+                    Error::Other(format!(
+                        "Failed to read {} from {}: {}",
+                        id.name(),
+                        filename,
+                        e
+                    ))
+                })?,
+                None => SectionData::from(&[][..]),
+            };
+            Ok(Reader::new(data, endian))
+        })?;
+
+        let mut ret = Self {
+            dwarf,
+            units: Vec::new(),
+            types: HashMap::new(),
+            variables: HashMap::new(),
+            functions: Vec::new(),
+            endian,
+        };
+        let mut headers = ret.dwarf.units();
+        while let Some(header) = headers.next().map_err(dwarf_error)? {
+            let unit = ret.dwarf.unit(header).map_err(dwarf_error)?;
+            ret.index(&unit, ret.units.len())?;
+            ret.units.push(unit);
+        }
+        ret.functions.sort_by_key(|(range, _)| range.start);
+        if ret.types.is_empty() {
+            return Err(Error::Other(format!(
+                "No type information found for {}",
+                filename
+            )));
+        }
+        Ok(ret)
+    }
+
+    /// Returns the type with the given name, like `PyThreadState` or `std::string`
+    pub fn find_type(&self, name: &str) -> Result<TypeInfo, Error> {
+        self.type_info(self.named_type(name)?, 0)
+    }
+
+    /// Returns the offset of a field from the start of its struct, and the type of the field.
+    /// The path starts with the name of the struct, followed by the names of the fields
+    /// separated by dots (like `PyThreadState.cframe`), which can go into nested structs but
+    /// not through pointers. Fields of anonymous structs and unions, and of base classes, are
+    /// found as if they were fields of the struct containing them.
+    pub fn field_offset(&self, path: &str) -> Result<(u64, TypeInfo), Error> {
+        let (type_name, fields) = split_path(path)?;
+        let mut die = self.named_type(type_name)?;
+        let mut offset = 0;
+        for field in fields {
+            let ty = self.strip(die)?;
+            if self.entry_tag(ty)? == gimli::DW_TAG_pointer_type {
+                return Err(Error::Other(format!(
+                    "Field {} in {} is behind a pointer, so it doesn't have an offset",
+                    field, path
+                )));
+            }
+            let (field_offset, field_type) = self.field(ty, field, path)?;
+            offset += field_offset;
+            die = field_type;
+        }
+        Ok((offset, self.type_info(die, 0)?))
+    }
+
+    /// Reads a field of a struct at `addr` in the memory of another process. The path is like
+    /// the one for `field_offset`, except that fields which are pointers to structs are
+    /// followed, so `ThreadState.frame.code` reads `state->frame->code`.
+    pub fn remote_value(
+        &self,
+        memory: &impl ProcessMemory,
+        addr: u64,
+        path: &str,
+    ) -> Result<RemoteValue, Error> {
+        let (type_name, fields) = split_path(path)?;
+        let mut die = self.named_type(type_name)?;
+        let mut address = addr;
+        for field in fields {
+            let mut ty = self.strip(die)?;
+            if self.entry_tag(ty)? == gimli::DW_TAG_pointer_type {
+                address = self.read_pointer(memory, ty, address)?;
+                ty = self
+                    .type_attr(ty)?
+                    .map(|pointee| self.strip(pointee))
+                    .transpose()?
+                    .ok_or_else(|| {
+                        Error::Other(format!(
+                            "Can't follow a void pointer to {} in {}",
+                            field, path
+                        ))
+                    })?;
+            }
+            let (offset, field_type) = self.field(ty, field, path)?;
+            address += offset;
+            die = field_type;
+        }
+
+        let ty = self.type_info(die, 0)?;
+        let size = ty.size.ok_or_else(|| {
+            Error::Other(format!("The value of {} doesn't have a known size", path))
+        })?;
+        let data = memory.copy(address as usize, size as usize)?;
+        Ok(self.remote(address, ty, data))
+    }
+
+    /// Creates a value of type `ty` from bytes in the byte order of the process
+    pub(super) fn remote(&self, address: u64, ty: TypeInfo, data: Vec<u8>) -> RemoteValue {
+        RemoteValue {
+            address,
+            ty,
+            data,
+            big_endian: self.endian == RunTimeEndian::Big,
+        }
+    }
+
+    /// Adds the named types and variables of a unit to the index
+    fn index(&mut self, unit: &gimli::Unit<Reader>, index: usize) -> Result<(), Error> {
+        // the namespaces, types and functions that the current entry is nested in, with their
+        // depth and whether they are a function
+        let mut scopes: Vec<(isize, String, bool)> = Vec::new();
+        let mut depth = 0;
+        let mut entries = unit.entries();
+        while let Some((delta, entry)) = entries.next_dfs().map_err(dwarf_error)? {
+            depth += delta;
+            while scopes.last().is_some_and(|(scope, _, _)| *scope >= depth) {
+                scopes.pop();
+            }
+            let tag = entry.tag();
+            if tag == gimli::DW_TAG_subprogram {
+                let die = DieRef {
+                    unit: index,
+                    offset: entry.offset(),
+                };
+                let mut ranges = self.dwarf.die_ranges(unit, entry).map_err(dwarf_error)?;
+                while let Some(range) = ranges.next().map_err(dwarf_error)? {
+                    // linkers point functions that were removed as dead code at address 0
+                    if range.begin != 0 && range.begin < range.end {
+                        self.functions.push((range.begin..range.end, die));
+                    }
+                }
+            }
+            let is_scope = matches!(
+                tag,
+                gimli::DW_TAG_namespace
+                    | gimli::DW_TAG_structure_type
+                    | gimli::DW_TAG_class_type
+                    | gimli::DW_TAG_union_type
+                    | gimli::DW_TAG_subprogram
+            );
+            let is_type = is_scope
+                && !matches!(tag, gimli::DW_TAG_namespace | gimli::DW_TAG_subprogram)
+                || matches!(
+                    tag,
+                    gimli::DW_TAG_typedef
+                        | gimli::DW_TAG_base_type
+                        | gimli::DW_TAG_enumeration_type
+                );
+            let is_variable = tag == gimli::DW_TAG_variable;
+            if !is_scope && !is_type && !is_variable {
+                continue;
+            }
+            let name = match self.name(unit, entry)? {
+                Some(name) => Some(name),
+                // definitions of static members only have a name on their declaration
+                None if is_variable => match entry.attr_value(gimli::DW_AT_specification) {
+                    Ok(Some(AttributeValue::UnitRef(offset))) => {
+                        self.name(unit, &unit_entry(unit, offset)?)?
+                    }
+                    _ => None,
+                },
+                None => None,
+            };
+            let Some(name) = name else {
+                if tag == gimli::DW_TAG_subprogram {
+                    // still keep track of anonymous functions, so that their locals aren't
+                    // mistaken for globals
+                    scopes.push((depth, String::new(), true));
+                }
+                continue;
+            };
+            let in_function = scopes.iter().any(|(_, _, function)| *function);
+            let indexed = if is_variable {
+                // locals of a function can't be read without a frame, so only index statics
+                match entry.attr_value(gimli::DW_AT_location) {
+                    Ok(Some(AttributeValue::Exprloc(expression))) => {
+                        !in_function || is_static_location(&expression)
+                    }
+                    _ => false,
+                }
+            } else {
+                is_type && !matches!(entry.attr_value(gimli::DW_AT_declaration), Ok(Some(_)))
+            };
+            if indexed {
+                let die = DieRef {
+                    unit: index,
+                    offset: entry.offset(),
+                };
+                let qualified: Vec<&str> = scopes
+                    .iter()
+                    .map(|(_, scope, _)| scope.as_str())
+                    .filter(|scope| !scope.is_empty())
+                    .chain([name.as_str()])
+                    .collect();
+                let qualified = qualified.join("::");
+                if is_variable {
+                    self.variables.entry(qualified).or_insert(die);
+                    self.variables.entry(name.clone()).or_insert(die);
+                } else {
+                    self.add_type(qualified, &name, die);
+                }
+            }
+            if is_scope {
+                scopes.push((depth, name, tag == gimli::DW_TAG_subprogram));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a type to the index by its qualified and plain names. Definitions with the same
+    /// qualified name are taken to be the same type (like a struct from a header that every
+    /// unit has a copy of), but types in different namespaces that share a plain name make
+    /// that name ambiguous. A qualified name always refers to its own type, even when it's
+    /// the plain name of other types too.
+    fn add_type(&mut self, qualified: String, name: &str, die: DieRef) {
+        match self.types.get(&qualified) {
+            Some(TypeName::Type(_, existing)) if *existing == qualified => {}
+            _ => {
+                self.types
+                    .insert(qualified.clone(), TypeName::Type(die, qualified.clone()));
+            }
+        }
+        if qualified == name {
+            return;
+        }
+        match self.types.entry(name.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert(TypeName::Type(die, qualified));
+            }
+            Entry::Occupied(mut entry) => match entry.get_mut() {
+                TypeName::Type(_, existing) if *existing == name || *existing == qualified => {}
+                TypeName::Type(_, existing) => {
+                    let existing = existing.clone();
+                    entry.insert(TypeName::Ambiguous(vec![existing, qualified]));
+                }
+                TypeName::Ambiguous(names) => {
+                    if !names.contains(&qualified) {
+                        names.push(qualified);
+                    }
+                }
+            },
+        }
+    }
+
+    fn named_type(&self, name: &str) -> Result<DieRef, Error> {
+        match self.types.get(name) {
+            Some(TypeName::Type(die, _)) => Ok(*die),
+            Some(TypeName::Ambiguous(names)) => Err(Error::Other(format!(
+                "The type name {} is ambiguous, use one of {} instead",
+                name,
+                names.join(", ")
+            ))),
+            None => Err(Error::Other(format!(
+                "No type named {} in the debug info",
+                name
+            ))),
+        }
+    }
+
+    /// Finds the field `name` in the struct or union `die`, returning its offset and type
+    fn field(&self, die: DieRef, name: &str, path: &str) -> Result<(u64, DieRef), Error> {
+        self.find_field(die, name, 0)?.ok_or_else(|| {
+            Error::Other(format!(
+                "No field named {} in {} for {}",
+                name,
+                self.type_info(die, 0)
+                    .ok()
+                    .and_then(|ty| ty.name)
+                    .unwrap_or_else(|| "an anonymous type".to_string()),
+                path
+            ))
+        })
+    }
+
+    fn find_field(
+        &self,
+        die: DieRef,
+        name: &str,
+        depth: usize,
+    ) -> Result<Option<(u64, DieRef)>, Error> {
+        if depth > MAX_TYPE_DEPTH {
+            return Ok(None);
+        }
+        let unit = &self.units[die.unit];
+        let mut tree = unit.entries_tree(Some(die.offset)).map_err(dwarf_error)?;
+        let mut children = tree.root().map_err(dwarf_error)?.children();
+        while let Some(child) = children.next().map_err(dwarf_error)? {
+            let entry = child.entry();
+            if !matches!(
+                entry.tag(),
+                gimli::DW_TAG_member | gimli::DW_TAG_inheritance
+            ) {
+                continue;
+            }
+            let Some(ty) = self.type_attr(DieRef {
+                unit: die.unit,
+                offset: entry.offset(),
+            })?
+            else {
+                continue;
+            };
+            let offset = member_offset(entry)?;
+            match self.name(unit, entry)? {
+                Some(member) if member == name => return Ok(Some((offset, ty))),
+                // look inside base classes, and anonymous structs and unions
+                None => {
+                    let inner = self.strip(ty)?;
+                    if let Some((inner_offset, ty)) = self.find_field(inner, name, depth + 1)? {
+                        return Ok(Some((offset + inner_offset, ty)));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Follows typedefs and qualifiers like `const` to the type they refer to
+    pub(super) fn strip(&self, mut die: DieRef) -> Result<DieRef, Error> {
+        for _ in 0..MAX_TYPE_DEPTH {
+            match self.entry_tag(die)? {
+                gimli::DW_TAG_typedef
+                | gimli::DW_TAG_const_type
+                | gimli::DW_TAG_volatile_type
+                | gimli::DW_TAG_restrict_type
+                | gimli::DW_TAG_atomic_type => match self.type_attr(die)? {
+                    Some(ty) => die = ty,
+                    None => return Ok(die),
+                },
+                _ => return Ok(die),
+            }
+        }
+        Err(Error::Other(
+            "Typedefs in the debug info nest too deeply".to_string(),
+        ))
+    }
+
+    pub(super) fn type_info(&self, die: DieRef, depth: usize) -> Result<TypeInfo, Error> {
+        if depth > MAX_TYPE_DEPTH {
+            return Err(Error::Other(
+                "Types in the debug info nest too deeply".to_string(),
+            ));
+        }
+        let unit = &self.units[die.unit];
+        let name = self.name(unit, &unit.entry(die.offset).map_err(dwarf_error)?)?;
+        let stripped = self.strip(die)?;
+        let entry = unit_entry(&self.units[stripped.unit], stripped.offset)?;
+        let kind = match entry.tag() {
+            gimli::DW_TAG_base_type => TypeKind::Base,
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => TypeKind::Pointer,
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type => TypeKind::Struct,
+            gimli::DW_TAG_union_type => TypeKind::Union,
+            gimli::DW_TAG_array_type => TypeKind::Array,
+            gimli::DW_TAG_enumeration_type => TypeKind::Enum,
+            _ => TypeKind::Other,
+        };
+        let size = match entry.attr_value(gimli::DW_AT_byte_size) {
+            Ok(Some(value)) => value.udata_value(),
+            _ => None,
+        };
+        let inner = match kind {
+            TypeKind::Pointer | TypeKind::Array => self
+                .type_attr(stripped)?
+                .map(|ty| self.type_info(ty, depth + 1))
+                .transpose()?,
+            _ => None,
+        };
+        let inner_name = inner
+            .as_ref()
+            .and_then(|ty| ty.name.as_deref())
+            .unwrap_or("void");
+        let (name, size) = match kind {
+            TypeKind::Pointer => {
+                let address_size = self.units[stripped.unit].header.address_size() as u64;
+                let name = name.unwrap_or_else(|| format!("{} *", inner_name));
+                (Some(name), Some(size.unwrap_or(address_size)))
+            }
+            TypeKind::Array => {
+                let count = self.array_count(stripped)?;
+                let element_size = inner.as_ref().and_then(|ty| ty.size);
+                let name = name.unwrap_or_else(|| match count {
+                    Some(count) => format!("{}[{}]", inner_name, count),
+                    None => format!("{}[]", inner_name),
+                });
+                let size = size.or(count.zip(element_size).map(|(count, size)| count * size));
+                (Some(name), size)
+            }
+            _ => match name {
+                Some(name) => (Some(name), size),
+                None => (self.name(&self.units[stripped.unit], &entry)?, size),
+            },
+        };
+        Ok(TypeInfo { name, kind, size })
+    }
+
+    /// Returns the number of elements in an array type, from its first dimension
+    fn array_count(&self, die: DieRef) -> Result<Option<u64>, Error> {
+        let unit = &self.units[die.unit];
+        let mut tree = unit.entries_tree(Some(die.offset)).map_err(dwarf_error)?;
+        let mut children = tree.root().map_err(dwarf_error)?.children();
+        while let Some(child) = children.next().map_err(dwarf_error)? {
+            let entry = child.entry();
+            if entry.tag() != gimli::DW_TAG_subrange_type {
+                continue;
+            }
+            if let Ok(Some(count)) = entry.attr_value(gimli::DW_AT_count) {
+                return Ok(count.udata_value());
+            }
+            if let Ok(Some(upper)) = entry.attr_value(gimli::DW_AT_upper_bound) {
+                return Ok(upper.udata_value().map(|upper| upper + 1));
+            }
+            return Ok(None);
+        }
+        Ok(None)
+    }
+
+    fn read_pointer(
+        &self,
+        memory: &impl ProcessMemory,
+        die: DieRef,
+        address: u64,
+    ) -> Result<u64, Error> {
+        let size = self.units[die.unit].header.address_size() as usize;
+        let ty = TypeInfo {
+            name: None,
+            kind: TypeKind::Pointer,
+            size: Some(size as u64),
+        };
+        let value = self.remote(address, ty, memory.copy(address as usize, size)?);
+        match value.as_u64() {
+            Some(0) | None => Err(Error::Other(format!("Null pointer at 0x{:016x}", address))),
+            Some(pointer) => Ok(pointer),
+        }
+    }
+
+    /// Returns the function whose code contains `address`, which is relative to the addresses
+    /// in the debug info
+    pub(super) fn function_at(&self, address: u64) -> Option<DieRef> {
+        let index = self
+            .functions
+            .partition_point(|(range, _)| range.start <= address);
+        let (range, die) = self.functions.get(index.checked_sub(1)?)?;
+        range.contains(&address).then_some(*die)
+    }
+
+    /// Returns the type referred to by the DW_AT_type attribute of an entry
+    pub(super) fn type_attr(&self, die: DieRef) -> Result<Option<DieRef>, Error> {
+        self.ref_attr(die, gimli::DW_AT_type)
+    }
+
+    /// Returns the entry an attribute of another entry refers to
+    pub(super) fn ref_attr(&self, die: DieRef, attr: gimli::DwAt) -> Result<Option<DieRef>, Error> {
+        let unit = &self.units[die.unit];
+        let entry = unit_entry(unit, die.offset)?;
+        match entry.attr_value(attr).map_err(dwarf_error)? {
+            Some(AttributeValue::UnitRef(offset)) => Ok(Some(DieRef {
+                unit: die.unit,
+                offset,
+            })),
+            Some(AttributeValue::DebugInfoRef(offset)) => {
+                Ok(self.units.iter().enumerate().find_map(|(index, unit)| {
+                    let offset = offset.to_unit_offset(&unit.header)?;
+                    Some(DieRef {
+                        unit: index,
+                        offset,
+                    })
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub(super) fn entry_tag(&self, die: DieRef) -> Result<gimli::DwTag, Error> {
+        Ok(unit_entry(&self.units[die.unit], die.offset)?.tag())
+    }
+
+    pub(super) fn name(
+        &self,
+        unit: &gimli::Unit<Reader>,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, Reader>,
+    ) -> Result<Option<String>, Error> {
+        let Some(name) = entry.attr_value(gimli::DW_AT_name).map_err(dwarf_error)? else {
+            return Ok(None);
+        };
+        let name = self.dwarf.attr_string(unit, name).map_err(dwarf_error)?;
+        Ok(Some(
+            name.to_string_lossy().map_err(dwarf_error)?.into_owned(),
+        ))
+    }
+}
+
+pub(super) fn unit_entry(
+    unit: &gimli::Unit<Reader>,
+    offset: UnitOffset,
+) -> Result<gimli::DebuggingInformationEntry<'_, '_, Reader>, Error> {
+    unit.entry(offset).map_err(dwarf_error)
+}
+
+/// Returns the offset of a member from the start of the struct containing it. Members of
+/// unions don't have an offset, since they all start at 0.
+fn member_offset(entry: &gimli::DebuggingInformationEntry<'_, '_, Reader>) -> Result<u64, Error> {
+    match entry
+        .attr_value(gimli::DW_AT_data_member_location)
+        .map_err(dwarf_error)?
+    {
+        None => Ok(0),
+        Some(value) => value.udata_value().ok_or_else(|| {
+            Error::Other(
+                "Fields with computed offsets, like virtual base classes, aren't supported"
+                    .to_string(),
+            )
+        }),
+    }
+}
+
+/// Returns whether a location expression is a fixed address, like the location of a static
+fn is_static_location(expression: &gimli::Expression<Reader>) -> bool {
+    matches!(
+        expression.0.clone().read_u8().map(gimli::DwOp),
+        Ok(gimli::DW_OP_addr | gimli::DW_OP_addrx | gimli::DW_OP_GNU_addr_index)
+    )
+}
+
+/// Splits a path like `Type.field.field` into the type name and the fields
+fn split_path(path: &str) -> Result<(&str, std::str::Split<'_, char>), Error> {
+    let mut parts = path.split('.');
+    match parts.next() {
+        Some(type_name) if !type_name.is_empty() => Ok((type_name, parts)),
+        _ => Err(Error::Other(format!("Invalid field path {:?}", path))),
+    }
+}
+
+/// Returns the contents of a section, which refers to the mapped file unless the section is
+/// compressed
+fn section_data(
+    map: &Rc<Mmap>,
+    section: &object::Section<'_, '_>,
+) -> Result<SectionData, object::Error> {
+    let range = section.compressed_file_range()?;
+    if range.format == CompressionFormat::None {
+        let start = range.offset as usize;
+        let end = start + range.uncompressed_size as usize;
+        if end <= map.len() {
+            return Ok(SectionData::Mapped(map.clone(), start..end));
+        }
+    }
+    Ok(SectionData::from(&*section.uncompressed_data()?))
+}
+
+fn parse_object<'a>(data: &'a [u8], filename: &str) -> Result<object::File<'a>, Error> {
+    object::File::parse(data)
+        .map_err(|e| Error::Other(format!("Failed to parse {}: {}", filename, e)))
+}
+
+pub(super) fn dwarf_error(e: gimli::Error) -> Error {
+    Error::Other(format!("Failed to parse debug info: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::symbol_search::current_exe_has_debug_info;
+    use crate::{Pid, Process};
+
+    #[repr(C)]
+    struct DebugInfoInner {
+        value: i32,
+        next: *const Self,
+    }
+
+    #[repr(C)]
+    struct DebugInfoOuter {
+        flags: u16,
+        inner: DebugInfoInner,
+        items: [u64; 4],
+        pointer: *const DebugInfoInner,
+    }
+
+    mod first {
+        pub struct DebugInfoTwin {
+            pub value: u32,
+        }
+    }
+
+    mod second {
+        pub struct DebugInfoTwin {
+            pub value: u64,
+        }
+    }
+
+    #[test]
+    fn test_as_u64() {
+        let value = |data: &[u8], big_endian| RemoteValue {
+            address: 0,
+            ty: TypeInfo {
+                name: None,
+                kind: TypeKind::Base,
+                size: Some(data.len() as u64),
+            },
+            data: data.to_vec(),
+            big_endian,
+        };
+        assert_eq!(value(&[1, 2], false).as_u64(), Some(0x0201));
+        assert_eq!(value(&[1, 2], true).as_u64(), Some(0x0102));
+        assert_eq!(value(&[0xff, 0xfe], true).as_i64(), Some(-2));
+        assert_eq!(value(&[0; 9], false).as_u64(), None);
+    }
+
+    #[test]
+    fn test_ambiguous_type() {
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let twins = std::hint::black_box((
+            first::DebugInfoTwin { value: 1 },
+            second::DebugInfoTwin { value: 2 },
+        ));
+        assert_eq!(twins.0.value as u64 + twins.1.value, 3);
+
+        let exe = std::env::current_exe().unwrap();
+        let debug_info = DebugInfo::load(&exe.display().to_string()).unwrap();
+        let error = debug_info.find_type("DebugInfoTwin").unwrap_err();
+        assert!(error.to_string().contains("ambiguous"));
+        let qualified = "remoteprocess::linux::debug_info::tests::first::DebugInfoTwin";
+        assert!(error.to_string().contains(qualified));
+        assert_eq!(debug_info.find_type(qualified).unwrap().size, Some(4));
+    }
+
+    #[test]
+    fn test_remote_value() {
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let inner = DebugInfoInner {
+            value: -42,
+            next: std::ptr::null(),
+        };
+        let outer = std::hint::black_box(DebugInfoOuter {
+            flags: 7,
+            inner: DebugInfoInner {
+                value: 1,
+                next: &inner,
+            },
+            items: [1, 2, 3, 4],
+            pointer: &inner,
+        });
+
+        let exe = std::env::current_exe().unwrap();
+        let debug_info = DebugInfo::load(&exe.display().to_string()).unwrap();
+        let outer_type = debug_info.find_type("DebugInfoOuter").unwrap();
+        assert_eq!(outer_type.kind, TypeKind::Struct);
+        assert_eq!(outer_type.size, Some(size_of::<DebugInfoOuter>() as u64));
+
+        let (offset, ty) = debug_info
+            .field_offset("DebugInfoOuter.inner.next")
+            .unwrap();
+        assert_eq!(
+            offset,
+            std::mem::offset_of!(DebugInfoOuter, inner.next) as u64
+        );
+        assert_eq!(ty.kind, TypeKind::Pointer);
+        assert!(debug_info
+            .field_offset("DebugInfoOuter.pointer.value")
+            .is_err());
+        assert!(debug_info.field_offset("DebugInfoOuter.missing").is_err());
+
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let addr = &outer as *const _ as u64;
+        let read = |path| debug_info.remote_value(&process, addr, path).unwrap();
+        assert_eq!(read("DebugInfoOuter.flags").as_u64(), Some(7));
+        assert_eq!(read("DebugInfoOuter.inner.value").as_i64(), Some(1));
+        // pointers are followed on the way to the field
+        assert_eq!(read("DebugInfoOuter.pointer.value").as_i64(), Some(-42));
+        assert_eq!(read("DebugInfoOuter.inner.next.value").as_i64(), Some(-42));
+        let items = read("DebugInfoOuter.items");
+        assert_eq!(items.ty.kind, TypeKind::Array);
+        assert_eq!(items.data.len(), 32);
+        assert!(debug_info
+            .remote_value(&process, addr, "DebugInfoOuter.inner.next.next.value")
+            .is_err());
+    }
+}
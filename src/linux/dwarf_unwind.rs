@@ -0,0 +1,1451 @@
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+
+use addr2line::gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EhFrameHdr, EndianSlice, LittleEndian,
+    ParsedEhFrameHdr, RegisterRule, UnwindContext, UnwindSection, UnwindTableRow,
+};
+use log::{debug, info, warn};
+use memmap2::Mmap;
+use object::{CompressionFormat, Object, ObjectSection, ObjectSegment, SegmentFlags};
+
+use super::cache::{CacheBudget, CachedData};
+use super::debug_info::DebugInfo;
+use super::frame_provider::{FrameCursor, FrameProvider};
+use super::platform_info;
+use super::snapshot::{Registers, StackSnapshot};
+use super::variables::FrameVariable;
+use crate::{Error, Pid, Process, ProcessMemory};
+
+/// Stop unwinding after this many frames, in case we end up following a corrupted stack
+const MAX_FRAMES: usize = 4096;
+
+/// When stack scanning is enabled and neither the CFI nor the frame pointer can unwind a frame,
+/// scan this many words up the stack for something that looks like a return address
+const MAX_SCAN_WORDS: u64 = 256;
+
+/// How the registers of a frame were recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameSource {
+    /// The innermost frame, whose registers were read from the thread itself
+    Context,
+    /// The frame was recovered using DWARF call frame information
+    Cfi,
+    /// The frame was recovered by following the frame pointer chain, which is reliable for
+    /// code compiled with frame pointers but can skip frames or produce garbage otherwise
+    FramePointer,
+    /// The frame was found by scanning the stack for something that looks like a return
+    /// address. This is a heuristic, and frames found this way may not be real. Scanning is
+    /// off unless it's enabled with `SnapshotUnwinder::set_stack_scanning`.
+    Scan,
+}
+
+/// Why a `SnapshotCursor` stopped unwinding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnwindStop {
+    /// The outermost frame was reached, which is marked by the CFI or by a null return address
+    Complete,
+    /// None of the unwinding methods could find the caller of the last frame
+    NoUnwindInfo,
+    /// The caller of the last frame wasn't further up the stack, so the stack is corrupted (or
+    /// was unwound incorrectly)
+    NoProgress,
+    /// Reading memory failed while unwinding the last frame, and the error was returned
+    MemoryError,
+    /// The cursor returned the maximum number of frames
+    MaxFrames,
+}
+
+/// How an unwind went, from `SnapshotCursor::diagnostics`. Aggregating these across stacks
+/// shows how complete the stacks are, and which binaries are missing unwind info.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnwindDiagnostics {
+    /// The number of frames returned so far
+    pub frames: usize,
+    /// The number of frames recovered with CFI
+    pub cfi_frames: usize,
+    /// The number of frames recovered by following the frame pointer
+    pub frame_pointer_frames: usize,
+    /// The number of frames found by scanning the stack
+    pub scanned_frames: usize,
+    /// Why the unwind stopped, or None if the cursor hasn't reached the end of the stack yet
+    pub stop: Option<UnwindStop>,
+    /// The binaries that frames were in, but that had no usable CFI for those frames
+    pub missing_cfi: Vec<String>,
+    /// The number of memory reads that failed while unwinding
+    pub memory_errors: usize,
+}
+
+/// Unwinds stacks that were copied with `Thread::snapshot`, using the DWARF call frame information
+/// from the binaries loaded into the target process.
+///
+/// Since all the unwinding happens against the copied stack, the target thread only needs to be
+/// stopped while the snapshot is taken - and not for the entire duration of the unwind.
+///
+/// Memory that isn't in the snapshot is read from `M`, which is the live process by default.
+/// Any other `ProcessMemory` (like a core dump) can be unwound by creating the unwinder with
+/// `with_memory`, and describing the binaries that were loaded with `add_module`. A thread
+/// can also be unwound without copying its stack, from the registers of any `RegisterSource`
+/// with `StackSnapshot::from_registers`.
+pub struct SnapshotUnwinder<M: ProcessMemory = Process> {
+    modules: BTreeMap<u64, ModuleUnwindInfo>,
+    providers: Vec<Box<dyn FrameProvider<M>>>,
+    memory: M,
+    cache: CacheBudget,
+    stack_scanning: bool,
+    /// Where tables loaded by other unwinders for the same process are looked up
+    shared: Option<Arc<SharedTables>>,
+    /// Unwind contexts returned by cursors that have been dropped, so that creating a cursor
+    /// doesn't need to allocate a new one
+    contexts: RefCell<Vec<UnwindContext<usize>>>,
+}
+
+impl SnapshotUnwinder {
+    pub fn new(pid: Pid) -> Result<Self, Error> {
+        let mut ret = Self::with_memory(Process::new(pid)?);
+        ret.reload()?;
+        Ok(ret)
+    }
+
+    /// Reloads the list of binaries loaded into the target process. This only updates the
+    /// modules that were loaded or unloaded since the last reload (like with dlopen and
+    /// dlclose), and keeps the unwind tables cached for the rest, so it's cheap enough to call
+    /// whenever an address can't be unwound.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let pid = self.memory.pid;
+        info!("reloading unwind info for process {}", pid);
+        let maps = proc_maps::get_process_maps(pid)?;
+        let mapped: HashSet<Mapping> = maps
+            .iter()
+            .filter(|m| m.is_exec() && m.is_read())
+            .filter_map(|m| {
+                let filename = m.filename()?.display().to_string();
+                let address = m.start() as u64;
+                Some((
+                    address,
+                    address + m.size() as u64,
+                    m.offset as u64,
+                    filename,
+                ))
+            })
+            .collect();
+
+        // forget about modules that have been unloaded, or whose range has been reused by
+        // another mapping
+        self.modules.retain(|_, module| {
+            let keep = mapped.contains(&module.mapping());
+            if !keep {
+                debug!("{} has been unloaded", module.filename);
+            }
+            keep
+        });
+
+        for (address, end, file_offset, filename) in mapped {
+            if self.modules.contains_key(&end) {
+                continue;
+            }
+            debug!("{} has been loaded at 0x{:x}", filename, address);
+            self.add_module(address, end - address, file_offset, &filename);
+        }
+        Ok(())
+    }
+}
+
+impl<M: ProcessMemory> SnapshotUnwinder<M> {
+    /// Creates an unwinder that reads memory missing from the snapshots from `memory`. This
+    /// doesn't know about any binaries until they are added with `add_module`.
+    pub fn with_memory(memory: M) -> Self {
+        Self {
+            modules: BTreeMap::new(),
+            providers: Vec::new(),
+            memory,
+            cache: CacheBudget::new(None),
+            stack_scanning: false,
+            shared: None,
+            contexts: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Adds the unwind info for an executable mapping of `filename`, which is mapped at
+    /// `address..address + size` from `file_offset` in the file. Replaces the module that was
+    /// added for the same range before, and any others that overlap it.
+    pub fn add_module(&mut self, address: u64, size: u64, file_offset: u64, filename: &str) {
+        self.remove_modules(address..address + size);
+        // the key is the end address of the module, which lets us do range based lookups
+        self.modules.insert(
+            address + size,
+            ModuleUnwindInfo {
+                address,
+                size,
+                file_offset,
+                filename: filename.to_string(),
+                tables: CachedData::new(),
+            },
+        );
+    }
+
+    /// Removes the unwind info of the module containing `address`, for when a binary is
+    /// unloaded. Returns false if no module contains the address.
+    pub fn remove_module(&mut self, address: u64) -> bool {
+        self.remove_modules(address..address + 1) > 0
+    }
+
+    /// Removes the modules that overlap `range`, and returns how many were removed
+    fn remove_modules(&mut self, range: Range<u64>) -> usize {
+        let overlapping: Vec<u64> = self
+            .modules
+            .range(range.start + 1..)
+            .take_while(|(_, module)| module.address < range.end)
+            .map(|(&key, _)| key)
+            .collect();
+        for key in &overlapping {
+            self.modules.remove(key);
+        }
+        overlapping.len()
+    }
+
+    /// The memory that is read for addresses outside of the snapshots
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    /// Returns the filename of the binary loaded at `address`, and how far the binary was
+    /// shifted from the addresses in its debug info when it was loaded. This is the bias
+    /// `DebugInfo::locals` and `DebugInfo::arguments` need for frames in the binary.
+    pub fn module_bias(&self, address: u64) -> Result<(&str, u64), Error> {
+        let module = self
+            .get_module(address)
+            .ok_or_else(|| Error::Other(format!("No binary is loaded at 0x{:016x}", address)))?;
+        let bias = match self.tables(module).as_ref() {
+            Ok(tables) => tables.bias,
+            Err(e) => return Err(Error::Other(e.to_string())),
+        };
+        self.enforce_cache_limit();
+        Ok((&module.filename, bias))
+    }
+
+    /// Returns an iterator over the instruction pointers in the callstack of a snapshot
+    pub fn cursor<'a>(
+        &'a self,
+        snapshot: &'a StackSnapshot,
+    ) -> Result<SnapshotCursor<'a, M>, Error> {
+        if snapshot.registers.ip().is_none() || snapshot.registers.sp().is_none() {
+            return Err(Error::Other(format!(
+                "Snapshot for thread {} is missing its instruction or stack pointer",
+                snapshot.tid
+            )));
+        }
+        Ok(SnapshotCursor {
+            unwinder: self,
+            snapshot,
+            registers: snapshot.registers,
+            next: Some((snapshot.registers, FrameSource::Context)),
+            error: None,
+            ctx: Some(self.contexts.borrow_mut().pop().unwrap_or_default()),
+            return_address: false,
+            frames: 0,
+            diagnostics: UnwindDiagnostics::default(),
+            debug_info: HashMap::new(),
+        })
+    }
+
+    /// Unwinds a snapshot into `frames`, replacing its contents with the instruction pointers
+    /// of the callstack. Once the unwind tables are loaded this doesn't allocate, as long as
+    /// `frames` is reused between calls and already has room for the stack.
+    pub fn unwind_into(
+        &self,
+        snapshot: &StackSnapshot,
+        frames: &mut Vec<u64>,
+    ) -> Result<(), Error> {
+        frames.clear();
+        for ip in self.cursor(snapshot)? {
+            frames.push(ip?);
+        }
+        Ok(())
+    }
+
+    /// Shares the unwind tables this loads with the other unwinders using `shared`, and reuses
+    /// the ones they have loaded, instead of every unwinder parsing each binary itself
+    pub(crate) fn share_tables(&mut self, shared: Arc<SharedTables>) {
+        self.shared = Some(shared);
+    }
+
+    /// Enables scanning the stack for return addresses when a frame can't be unwound with CFI
+    /// or frame pointers. This can recover stacks through code without unwind info, but the
+    /// frames it finds are guesses that may not be real, so they are marked with
+    /// `FrameSource::Scan`. This is off by default.
+    pub fn set_stack_scanning(&mut self, enabled: bool) {
+        self.stack_scanning = enabled;
+    }
+
+    pub fn stack_scanning(&self) -> bool {
+        self.stack_scanning
+    }
+
+    /// Limits the memory used by the unwind tables cached for each module to roughly `limit`
+    /// bytes, evicting the tables of the least recently used modules once it is exceeded.
+    /// None, the default, keeps the tables of every module.
+    ///
+    /// The limit is enforced when a cursor is dropped rather than after every frame, so a
+    /// single unwind through many modules can briefly go over it.
+    pub fn set_cache_limit(&mut self, limit: Option<usize>) {
+        self.cache.set_limit(limit);
+        self.enforce_cache_limit();
+    }
+
+    pub fn cache_limit(&self) -> Option<usize> {
+        self.cache.limit()
+    }
+
+    /// Returns the estimated number of bytes used by the cached unwind tables
+    pub fn cache_size(&self) -> usize {
+        self.cache
+            .size(self.modules.values().map(|module| &module.tables))
+    }
+
+    /// Registers a provider that can add runtime frames to the stacks returned by `frames`.
+    /// Providers are consulted in the order they were added, and the first one to recognize
+    /// a native frame wins.
+    pub fn add_frame_provider(&mut self, provider: Box<dyn FrameProvider<M>>) {
+        self.providers.push(provider);
+    }
+
+    /// Returns an iterator over the native frames in the callstack of a snapshot, with the
+    /// frames from any registered `FrameProvider` mixed in
+    pub fn frames<'a>(&'a self, snapshot: &'a StackSnapshot) -> Result<FrameCursor<'a, M>, Error> {
+        let cursor = self.cursor(snapshot)?;
+        let memory = SnapshotMemory {
+            snapshot,
+            process: &self.memory,
+        };
+        Ok(FrameCursor::new(cursor, &self.providers, memory))
+    }
+
+    /// Loads the unwind tables of every module that overlaps one of `ranges` ahead of time, so
+    /// that the first unwinds through those modules don't stall while their binaries are
+    /// parsed. Use `0..u64::MAX` to load the tables of every module. The tables still count
+    /// against the cache limit, so warming more than fits evicts the least recently used
+    /// ones. Returns the number of modules whose unwind tables were loaded.
+    pub fn prewarm(&self, ranges: &[Range<u64>]) -> usize {
+        self.warm(self.modules.values().filter(|module| {
+            ranges.iter().any(|range| {
+                range.start < module.address + module.size && module.address < range.end
+            })
+        }))
+    }
+
+    /// Loads the unwind tables of each mapping of the binary `filename` ahead of time, like
+    /// `prewarm`. Returns the number of mappings whose unwind tables were loaded.
+    pub fn prewarm_module(&self, filename: &str) -> usize {
+        self.warm(
+            self.modules
+                .values()
+                .filter(|module| module.filename == filename),
+        )
+    }
+
+    fn warm<'a>(&'a self, modules: impl Iterator<Item = &'a ModuleUnwindInfo>) -> usize {
+        modules
+            .filter(|module| {
+                let loaded = match self.tables(module).as_ref() {
+                    Ok(tables) => {
+                        tables.prefault();
+                        true
+                    }
+                    Err(e) => {
+                        debug!(
+                            "failed to prewarm unwind info for {}: {}",
+                            module.filename, e
+                        );
+                        false
+                    }
+                };
+                self.enforce_cache_limit();
+                loaded
+            })
+            .count()
+    }
+
+    /// Returns the unwind tables of a module, loading them if they aren't cached. This doesn't
+    /// evict anything, callers call `enforce_cache_limit` once they are done with the tables.
+    fn tables<'a>(
+        &'a self,
+        module: &'a ModuleUnwindInfo,
+    ) -> Ref<'a, Result<Arc<UnwindTables>, Error>> {
+        module.tables.get_or_load(&self.cache, || {
+            let load = || UnwindTables::new(module, &self.memory).map(Arc::new);
+            let tables = match &self.shared {
+                Some(shared) => shared.get_or_load(module, load),
+                None => load(),
+            };
+            let size = tables.as_ref().map_or(0, |tables| tables.memory_size());
+            (tables, size)
+        })
+    }
+
+    /// Evicts the least recently used unwind tables until the cache fits in its limit
+    fn enforce_cache_limit(&self) {
+        self.cache
+            .enforce(self.modules.values().map(|module| &module.tables));
+    }
+
+    fn get_module(&self, addr: u64) -> Option<&ModuleUnwindInfo> {
+        match self.modules.range(addr + 1..).next() {
+            Some((_, module)) if module.contains(addr) => Some(module),
+            _ => None,
+        }
+    }
+
+    /// Computes the registers of the calling frame using the CFI of the module containing `pc`,
+    /// where `return_address` is true if `pc` was recovered from a calling frame.
+    /// Returns Ok(None) if there is no unwind information for `pc`.
+    fn unwind_frame(
+        &self,
+        ctx: &mut UnwindContext<usize>,
+        pc: u64,
+        return_address: bool,
+        registers: &Registers,
+        memory: &SnapshotMemory<'_, M>,
+    ) -> Result<Option<Registers>, Error> {
+        let module = match self.get_module(pc) {
+            Some(module) => module,
+            None => return Ok(None),
+        };
+
+        let tables = self.tables(module);
+        let tables = match tables.as_ref() {
+            Ok(tables) => tables,
+            Err(_) => return Ok(None),
+        };
+
+        let svma = pc.wrapping_sub(tables.bias);
+        tables
+            .find_row(ctx, svma, |row| {
+                apply_row(row, return_address, registers, memory)
+            })
+            .unwrap_or(Ok(None))
+    }
+}
+
+/// Computes the registers of the calling frame from the unwind table row for `registers`
+fn apply_row<M: ProcessMemory>(
+    row: &UnwindTableRow<usize>,
+    return_address: bool,
+    registers: &Registers,
+    memory: &SnapshotMemory<'_, M>,
+) -> Result<Option<Registers>, Error> {
+    let cfa = match *row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => match registers.get(register.0) {
+            Some(value) => value.wrapping_add(offset as u64),
+            None => return Ok(None),
+        },
+        CfaRule::Expression(_) => return Ok(None),
+    };
+
+    // registers without any rule are assumed to be unchanged from the current frame
+    let mut caller = *registers;
+    for (register, rule) in row.registers() {
+        match *rule {
+            RegisterRule::Undefined => caller.clear(register.0),
+            RegisterRule::SameValue => {}
+            RegisterRule::Offset(offset) => caller.set(
+                register.0,
+                memory.read_u64(cfa.wrapping_add(offset as u64))?,
+            ),
+            RegisterRule::ValOffset(offset) => {
+                caller.set(register.0, cfa.wrapping_add(offset as u64))
+            }
+            RegisterRule::Register(other) => match registers.get(other.0) {
+                Some(value) => caller.set(register.0, value),
+                None => caller.clear(register.0),
+            },
+            RegisterRule::Constant(value) => caller.set(register.0, value),
+            _ => caller.clear(register.0),
+        }
+    }
+    caller.set(Registers::SP, cfa);
+
+    // gimli doesn't distinguish between registers without a rule and registers explicitly
+    // marked as undefined (which is how the outermost frame is marked). The return address
+    // of a leaf function on aarch64 is still in the link register though, so it is only
+    // missing there if this isn't the innermost frame
+    let has_return_address = row
+        .registers()
+        .any(|(register, _)| register.0 == Registers::RA)
+        || (cfg!(target_arch = "aarch64") && !return_address);
+    match caller.get(Registers::RA) {
+        Some(ra) if has_return_address => caller.set(Registers::IP, ra),
+        _ => caller.clear(Registers::IP),
+    }
+    Ok(Some(caller))
+}
+
+/// A frame recovered by a `SnapshotCursor`
+#[derive(Debug, Clone, Copy)]
+pub struct UnwoundFrame {
+    pub ip: u64,
+    /// The stack pointer in this frame
+    pub sp: u64,
+    /// The frame pointer in this frame, if it could be recovered
+    pub fp: Option<u64>,
+    /// The canonical frame address: the value of the stack pointer in the calling frame right
+    /// before the call into this frame. This is None if the frame couldn't be unwound.
+    pub cfa: Option<u64>,
+    /// The registers recovered for this frame. For every frame except the innermost one this
+    /// only contains the callee saved registers, since the others aren't preserved across calls.
+    pub registers: Registers,
+    /// How this frame was recovered
+    pub source: FrameSource,
+}
+
+impl UnwoundFrame {
+    /// The size of the stack frame, which is the distance between the stack pointer and CFA
+    pub fn size(&self) -> Option<u64> {
+        self.cfa.map(|cfa| cfa.wrapping_sub(self.sp))
+    }
+
+    /// True if this frame was guessed by scanning the stack, and might not be real
+    pub fn is_heuristic(&self) -> bool {
+        self.source == FrameSource::Scan
+    }
+}
+
+/// Iterates over the instruction pointers in a stack snapshot
+pub struct SnapshotCursor<'a, M: ProcessMemory = Process> {
+    unwinder: &'a SnapshotUnwinder<M>,
+    snapshot: &'a StackSnapshot,
+    // the registers of the frame that was returned last
+    registers: Registers,
+    // the registers of the next frame to return. Each frame is unwound as it is returned, so
+    // that we know its CFA
+    next: Option<(Registers, FrameSource)>,
+    error: Option<Error>,
+    // only None once the cursor is dropped, and the context is returned to the unwinder
+    ctx: Option<UnwindContext<usize>>,
+    return_address: bool,
+    frames: usize,
+    diagnostics: UnwindDiagnostics,
+    // the debug info of the binaries that locals were asked for, by filename
+    debug_info: HashMap<String, Result<DebugInfo, Error>>,
+}
+
+impl<M: ProcessMemory> SnapshotCursor<'_, M> {
+    /// The registers recovered for the current frame
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn snapshot(&self) -> &StackSnapshot {
+        self.snapshot
+    }
+
+    /// Returns what happened while unwinding the frames returned so far
+    pub fn diagnostics(&self) -> &UnwindDiagnostics {
+        &self.diagnostics
+    }
+
+    /// Returns the local variables that are in scope at a frame returned by this cursor, from
+    /// the debug info of the binary the frame is in. This includes the variables (and
+    /// parameters) of functions inlined into the frame, which have the name of the inlined
+    /// function in `FrameVariable::inlined`.
+    ///
+    /// The debug info of a binary is loaded the first time the locals of one of its frames are
+    /// asked for, and kept until the cursor is dropped. Values are read from the copied stack
+    /// where they can be, so they are the ones from when the snapshot was taken.
+    ///
+    /// ```rust,no_run
+    /// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+    /// use remoteprocess::{Process, SnapshotUnwinder};
+    ///
+    /// let process = Process::new(pid)?;
+    /// let unwinder = SnapshotUnwinder::new(pid)?;
+    /// let snapshot = process.threads()?[0].snapshot()?;
+    /// let mut cursor = unwinder.cursor(&snapshot)?;
+    /// while let Some(frame) = cursor.next_frame() {
+    ///     let frame = frame?;
+    ///     println!("0x{:016x}", frame.ip);
+    ///     for local in cursor.locals(&frame).unwrap_or_default() {
+    ///         println!("    {} = {:?}", local.name, local.value.map(|value| value.data));
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn locals(&mut self, frame: &UnwoundFrame) -> Result<Vec<FrameVariable>, Error> {
+        // the ip of calling frames is the return address, which can be just past the end of
+        // the binary
+        let lookup = match frame.source {
+            FrameSource::Context => frame.ip,
+            _ => frame.ip.wrapping_sub(1),
+        };
+        let (filename, bias) = self.unwinder.module_bias(lookup)?;
+        let debug_info = self
+            .debug_info
+            .entry(filename.to_string())
+            .or_insert_with(|| DebugInfo::load(filename));
+        let debug_info = match debug_info {
+            Ok(debug_info) => debug_info,
+            Err(e) => return Err(Error::Other(e.to_string())),
+        };
+        let memory = SnapshotMemory {
+            snapshot: self.snapshot,
+            process: &self.unwinder.memory,
+        };
+        debug_info.locals(&memory, frame, bias)
+    }
+
+    /// Returns the next frame in the callstack along with its registers and stack addresses.
+    /// This advances the same way as `next`, so the two can be mixed.
+    pub fn next_frame(&mut self) -> Option<Result<UnwoundFrame, Error>> {
+        if self.frames >= MAX_FRAMES {
+            if self.next.is_some() {
+                self.diagnostics.stop = Some(UnwindStop::MaxFrames);
+            }
+            return None;
+        }
+        let (registers, source) = match self.next.take() {
+            Some(next) => next,
+            None => return self.error.take().map(Err),
+        };
+        let ip = registers.ip()?;
+        let sp = registers.sp().unwrap_or(0);
+
+        // unwind the calling frame now, since its stack pointer is the CFA of this frame
+        let mut cfa = None;
+        let stop = match self.step(&registers) {
+            Ok(Some((caller, caller_source))) => {
+                cfa = caller.sp();
+                // make sure we are making progress up the stack, so that a corrupted frame
+                // can't cause us to loop forever
+                match (caller.ip(), caller.sp()) {
+                    (Some(caller_ip), Some(caller_sp)) if caller_ip != 0 && caller_sp > sp => {
+                        self.next = Some((caller, caller_source));
+                        None
+                    }
+                    (Some(caller_ip), Some(_)) if caller_ip != 0 => Some(UnwindStop::NoProgress),
+                    _ => Some(UnwindStop::Complete),
+                }
+            }
+            Ok(None) => Some(UnwindStop::NoUnwindInfo),
+            Err(e) => {
+                self.error = Some(e);
+                self.diagnostics.memory_errors += 1;
+                Some(UnwindStop::MemoryError)
+            }
+        };
+        self.diagnostics.stop = stop;
+        self.diagnostics.frames += 1;
+        match source {
+            FrameSource::Context => {}
+            FrameSource::Cfi => self.diagnostics.cfi_frames += 1,
+            FrameSource::FramePointer => self.diagnostics.frame_pointer_frames += 1,
+            FrameSource::Scan => self.diagnostics.scanned_frames += 1,
+        }
+
+        let initial = self.frames == 0;
+        self.frames += 1;
+        self.registers = registers;
+        self.return_address = true;
+        Some(Ok(UnwoundFrame {
+            ip,
+            sp,
+            fp: registers.fp(),
+            cfa,
+            registers: if initial {
+                registers
+            } else {
+                registers.callee_saved()
+            },
+            source,
+        }))
+    }
+
+    /// Computes the registers of the frame that called the one with `registers`
+    fn step(&mut self, registers: &Registers) -> Result<Option<(Registers, FrameSource)>, Error> {
+        let memory = SnapshotMemory {
+            snapshot: self.snapshot,
+            process: &self.unwinder.memory,
+        };
+
+        let ip = match registers.ip() {
+            Some(ip) => ip,
+            None => return Ok(None),
+        };
+
+        // the ip of the calling frames is the return address, which can point past the end of
+        // the calling function if it ends with a call to a noreturn function
+        let lookup = if self.return_address { ip - 1 } else { ip };
+
+        let ctx = self
+            .ctx
+            .as_mut()
+            .expect("unwind context is only taken on drop");
+        match self
+            .unwinder
+            .unwind_frame(ctx, lookup, self.return_address, registers, &memory)?
+        {
+            Some(caller) => Ok(Some((strip_return_address(caller), FrameSource::Cfi))),
+            None => {
+                if let Some(module) = self.unwinder.get_module(lookup) {
+                    if !self.diagnostics.missing_cfi.contains(&module.filename) {
+                        self.diagnostics.missing_cfi.push(module.filename.clone());
+                    }
+                }
+                match frame_pointer_unwind(registers, &memory) {
+                    Ok(caller) => Ok(Some((
+                        strip_return_address(caller),
+                        FrameSource::FramePointer,
+                    ))),
+                    Err(e) => {
+                        debug!("failed to unwind 0x{:016x} using frame pointers: {}", ip, e);
+                        // without a frame pointer nothing was read
+                        if registers.fp().is_some_and(|fp| fp != 0) {
+                            self.diagnostics.memory_errors += 1;
+                        }
+                        Ok(self
+                            .scan_unwind(registers)
+                            .map(|caller| (caller, FrameSource::Scan)))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Unwinds a frame by looking for the first value on the stack that points into a loaded
+    /// binary, and assuming that it is the return address. This only scans if it's enabled.
+    fn scan_unwind(&self, registers: &Registers) -> Option<Registers> {
+        if !self.unwinder.stack_scanning {
+            return None;
+        }
+        let sp = registers.sp()?;
+        for addr in (0..MAX_SCAN_WORDS).map_while(|i| sp.checked_add(i * 8)) {
+            let mut buf = [0u8; 8];
+            // only scan the copied stack, reading the rest from the target is too slow
+            if self.snapshot.read(addr as usize, &mut buf).is_err() {
+                break;
+            }
+            let value = platform_info().strip_pointer_auth(u64::from_ne_bytes(buf));
+            if value != 0 && self.unwinder.get_module(value - 1).is_some() {
+                let mut caller = registers.callee_saved();
+                caller.set(Registers::IP, value);
+                caller.set(Registers::SP, addr + 8);
+                return Some(caller);
+            }
+        }
+        None
+    }
+}
+
+impl<M: ProcessMemory> Drop for SnapshotCursor<'_, M> {
+    fn drop(&mut self) {
+        if let Some(ctx) = self.ctx.take() {
+            self.unwinder.contexts.borrow_mut().push(ctx);
+        }
+        self.unwinder.enforce_cache_limit();
+    }
+}
+
+impl<M: ProcessMemory> Iterator for SnapshotCursor<'_, M> {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Result<u64, Error>> {
+        self.next_frame().map(|frame| frame.map(|frame| frame.ip))
+    }
+}
+
+/// Strips the pointer authentication signature from the return address of a calling frame,
+/// which is saved signed on the stack by code built with -mbranch-protection
+fn strip_return_address(mut caller: Registers) -> Registers {
+    if let Some(ip) = caller.ip() {
+        caller.set(Registers::IP, platform_info().strip_pointer_auth(ip));
+    }
+    caller
+}
+
+/// Unwinds a single frame by following the frame pointer chain, for code without CFI
+fn frame_pointer_unwind<M: ProcessMemory>(
+    registers: &Registers,
+    memory: &SnapshotMemory<'_, M>,
+) -> Result<Registers, Error> {
+    let fp = registers
+        .fp()
+        .filter(|fp| *fp != 0)
+        .ok_or_else(|| Error::Other("No frame pointer".to_string()))?;
+
+    // a garbage frame pointer near the top of the address space can't be a real frame
+    let (Some(return_address), Some(sp)) = (fp.checked_add(8), fp.checked_add(16)) else {
+        return Err(Error::Other(format!("Invalid frame pointer 0x{:016x}", fp)));
+    };
+
+    let mut caller = *registers;
+    caller.set(Registers::FP, memory.read_u64(fp)?);
+    caller.set(Registers::IP, memory.read_u64(return_address)?);
+    caller.set(Registers::SP, sp);
+    Ok(caller)
+}
+
+/// Reads memory from a stack snapshot, falling back to the live process for addresses that
+/// weren't copied (like global data referenced by the unwind tables)
+pub struct SnapshotMemory<'a, M: ProcessMemory = Process> {
+    snapshot: &'a StackSnapshot,
+    process: &'a M,
+}
+
+impl<M: ProcessMemory> ProcessMemory for SnapshotMemory<'_, M> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        if self.snapshot.contains(addr as u64, buf.len()) {
+            self.snapshot.read(addr, buf)
+        } else {
+            self.process.read(addr, buf)
+        }
+    }
+}
+
+impl<M: ProcessMemory> SnapshotMemory<'_, M> {
+    fn read_u64(&self, addr: u64) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        self.read(addr as usize, &mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+}
+
+// Contains the address range of a binary, and lazily loaded unwind tables for it
+struct ModuleUnwindInfo {
+    address: u64,
+    size: u64,
+    file_offset: u64,
+    filename: String,
+    tables: CachedData<Result<Arc<UnwindTables>, Error>>,
+}
+
+impl ModuleUnwindInfo {
+    /// The mapping the module was added for
+    fn mapping(&self) -> Mapping {
+        (
+            self.address,
+            self.address + self.size,
+            self.file_offset,
+            self.filename.clone(),
+        )
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.address && addr < (self.address + self.size)
+    }
+}
+
+/// Returns the address and file offset of the segment that a mapping starting at `file_offset`
+/// was mapped from, out of the `(address, file offset, file size)` of each executable segment.
+/// Mappings start on a page boundary, which for linkers that don't page align segments in the
+/// file (like lld) can be before the start of the segment. So the segment whose file range
+/// contains the offset wins, and otherwise the last one that starts in the same page.
+fn find_segment(
+    segments: &[(u64, u64, u64)],
+    file_offset: u64,
+    page_size: u64,
+) -> Option<(u64, u64)> {
+    let contains = |&&(_, offset, size): &&(u64, u64, u64)| {
+        file_offset >= offset && file_offset < offset + size
+    };
+    let in_page = |&&(_, offset, size): &&(u64, u64, u64)| {
+        let start = offset - offset % page_size;
+        file_offset >= start && file_offset < offset + size
+    };
+    segments
+        .iter()
+        .find(contains)
+        .or_else(|| segments.iter().rfind(in_page))
+        .map(|&(address, offset, _)| (address, offset))
+}
+
+fn page_size() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+/// Unwind tables that are shared between the unwinders of a process, like the ones on the
+/// workers of a `StackCollector`. This only holds weak references, so tables are freed once
+/// every unwinder has evicted them.
+#[derive(Default)]
+pub(crate) struct SharedTables {
+    tables: Mutex<HashMap<Mapping, Weak<UnwindTables>>>,
+}
+
+/// The start, end, file offset and filename of an executable mapping
+type Mapping = (u64, u64, u64, String);
+
+impl SharedTables {
+    /// Returns the tables of a module that another unwinder has loaded, or loads them with
+    /// `load`. Loading happens without holding the lock, so that workers can load different
+    /// binaries at the same time.
+    fn get_or_load(
+        &self,
+        module: &ModuleUnwindInfo,
+        load: impl FnOnce() -> Result<Arc<UnwindTables>, Error>,
+    ) -> Result<Arc<UnwindTables>, Error> {
+        let key = module.mapping();
+        if let Some(tables) = self.lock().get(&key).and_then(Weak::upgrade) {
+            return Ok(tables);
+        }
+        let tables = load()?;
+        let mut shared = self.lock();
+        shared.retain(|_, tables| tables.strong_count() > 0);
+        // another unwinder may have loaded the same tables in the meantime, keep theirs
+        match shared.get(&key).and_then(Weak::upgrade) {
+            Some(existing) => Ok(existing),
+            None => {
+                shared.insert(key, Arc::downgrade(&tables));
+                Ok(tables)
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Mapping, Weak<UnwindTables>>> {
+        // the map stays consistent even if a thread panicked while holding the lock
+        self.tables
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The contents of a binary. Files are memory mapped rather than read, so that only the pages
+/// holding the unwind info for addresses we actually unwind through are paged in.
+enum BinaryData {
+    Mapped(Mmap),
+    Copied(Vec<u8>),
+}
+
+impl std::ops::Deref for BinaryData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Copied(data) => data,
+        }
+    }
+}
+
+enum SectionData {
+    /// The range of the section in the binary
+    Range(Range<usize>),
+    /// The decompressed contents of a compressed section
+    Decompressed(Vec<u8>),
+}
+
+struct Section {
+    data: SectionData,
+    address: u64,
+}
+
+struct UnwindTables {
+    binary: BinaryData,
+    bias: u64,
+    bases: BaseAddresses,
+    eh_frame: Option<Section>,
+    /// The binary search table for the FDEs in `eh_frame`, which saves scanning the whole
+    /// section for every lookup
+    eh_frame_hdr: Option<Section>,
+    debug_frame: Option<Section>,
+}
+
+impl UnwindTables {
+    fn new(module: &ModuleUnwindInfo, process: &impl ProcessMemory) -> Result<Self, Error> {
+        info!("loading unwind info from {}", module.filename);
+
+        let binary = if Path::new(&module.filename).exists() {
+            let file = File::open(&module.filename)?;
+            BinaryData::Mapped(unsafe { Mmap::map(&file)? })
+        } else if module.filename == "[vdso]" {
+            BinaryData::Copied(process.copy(module.address as usize, module.size as usize)?)
+        } else {
+            return Err(Error::Other(format!(
+                "No binary found for {}",
+                module.filename
+            )));
+        };
+
+        let file = object::File::parse(&*binary).map_err(|e| {
+            Error::Other(format!(
+                "Failed to parse {} for unwinding: {}",
+                module.filename, e
+            ))
+        })?;
+
+        // figure out how far the module was shifted from its preferred address, using the
+        // segment that contains the file offset of this mapping
+        // modules are only added for executable mappings, so only executable segments count
+        let segments: Vec<(u64, u64, u64)> = file
+            .segments()
+            .filter(|s| match s.flags() {
+                SegmentFlags::Elf { p_flags } => p_flags & object::elf::PF_X != 0,
+                _ => true,
+            })
+            .map(|s| {
+                let (offset, size) = s.file_range();
+                (s.address(), offset, size)
+            })
+            .collect();
+        let (segment_address, segment_offset) =
+            find_segment(&segments, module.file_offset, page_size()).ok_or_else(|| {
+                Error::Other(format!(
+                    "Failed to find segment for offset 0x{:x} in {}",
+                    module.file_offset, module.filename
+                ))
+            })?;
+        let bias = module
+            .address
+            .wrapping_sub(segment_address)
+            .wrapping_sub(module.file_offset)
+            .wrapping_add(segment_offset);
+
+        let section = |name: &str| -> Option<Section> {
+            let section = file.section_by_name(name)?;
+            let data = match section.compressed_file_range() {
+                // refer to uncompressed sections by their position in the mapped file, rather
+                // than copying them out of it
+                Ok(range) if range.format == CompressionFormat::None => {
+                    let start = range.offset as usize;
+                    let end = start + range.uncompressed_size as usize;
+                    (end > start && end <= binary.len()).then_some(SectionData::Range(start..end))
+                }
+                Ok(_) => match section.uncompressed_data() {
+                    Ok(data) if !data.is_empty() => {
+                        Some(SectionData::Decompressed(data.into_owned()))
+                    }
+                    Ok(_) => None,
+                    Err(e) => {
+                        warn!("Failed to read {} from {}: {}", name, module.filename, e);
+                        None
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to read {} from {}: {}", name, module.filename, e);
+                    None
+                }
+            };
+            Some(Section {
+                data: data?,
+                address: section.address(),
+            })
+        };
+        let address = |name: &str| file.section_by_name(name).map(|s| s.address());
+
+        let eh_frame = section(".eh_frame");
+        let eh_frame_hdr = section(".eh_frame_hdr");
+        let debug_frame = section(".debug_frame");
+        if eh_frame.is_none() && debug_frame.is_none() {
+            warn!("No unwind info found in {}", module.filename);
+        }
+
+        let mut bases = BaseAddresses::default();
+        if let Some(eh_frame) = eh_frame.as_ref() {
+            bases = bases.set_eh_frame(eh_frame.address);
+        }
+        if let Some(eh_frame_hdr) = eh_frame_hdr.as_ref() {
+            bases = bases.set_eh_frame_hdr(eh_frame_hdr.address);
+        }
+        if let Some(text) = address(".text") {
+            bases = bases.set_text(text);
+        }
+        if let Some(got) = address(".got") {
+            bases = bases.set_got(got);
+        }
+
+        Ok(Self {
+            binary,
+            bias,
+            bases,
+            eh_frame,
+            eh_frame_hdr,
+            debug_frame,
+        })
+    }
+
+    /// Returns an estimate of the number of bytes used by the tables, not counting the memory
+    /// mapped binary
+    fn memory_size(&self) -> usize {
+        let copied = match &self.binary {
+            BinaryData::Mapped(_) => 0,
+            BinaryData::Copied(data) => data.len(),
+        };
+        let decompressed = [&self.eh_frame, &self.eh_frame_hdr, &self.debug_frame]
+            .into_iter()
+            .flatten()
+            .map(|section| match &section.data {
+                SectionData::Range(_) => 0,
+                SectionData::Decompressed(data) => data.len(),
+            })
+            .sum::<usize>();
+        size_of::<Self>() + copied + decompressed
+    }
+
+    fn section_data<'a>(&'a self, section: &'a Section) -> &'a [u8] {
+        match &section.data {
+            SectionData::Range(range) => &self.binary[range.clone()],
+            SectionData::Decompressed(data) => data,
+        }
+    }
+
+    /// Reads a byte from each page of the unwind sections, so that the memory mapped binary is
+    /// paged in before the first lookups rather than during them
+    fn prefault(&self) {
+        for section in [&self.eh_frame_hdr, &self.eh_frame, &self.debug_frame]
+            .into_iter()
+            .flatten()
+        {
+            let data = self.section_data(section);
+            for page in data.iter().step_by(4096) {
+                std::hint::black_box(*page);
+            }
+        }
+    }
+
+    /// Parses the header of `.eh_frame_hdr`, which is small enough to do on every lookup
+    fn eh_frame_hdr(&self) -> Option<ParsedEhFrameHdr<EndianSlice<'_, LittleEndian>>> {
+        let section = EhFrameHdr::new(self.section_data(self.eh_frame_hdr.as_ref()?), LittleEndian);
+        match section.parse(&self.bases, size_of::<usize>() as u8) {
+            Ok(hdr) => Some(hdr),
+            Err(e) => {
+                debug!("failed to parse eh_frame_hdr: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Finds the unwind table row for an address, relative to the binary, and passes it to
+    /// `apply`. The row borrows from `ctx`, which is reused between lookups so that unwinding
+    /// doesn't allocate.
+    fn find_row<T>(
+        &self,
+        ctx: &mut UnwindContext<usize>,
+        svma: u64,
+        apply: impl FnOnce(&UnwindTableRow<usize>) -> T,
+    ) -> Option<T> {
+        if let Some(eh_frame) = self.eh_frame.as_ref() {
+            let section = EhFrame::new(self.section_data(eh_frame), LittleEndian);
+
+            // binary search the FDEs with the table in eh_frame_hdr if there is one, and only
+            // scan eh_frame if the table is missing or broken
+            let hdr = self.eh_frame_hdr();
+            let result = match hdr.as_ref().and_then(|hdr| hdr.table()) {
+                Some(table) => match table.unwind_info_for_address(
+                    &section,
+                    &self.bases,
+                    ctx,
+                    svma,
+                    EhFrame::cie_from_offset,
+                ) {
+                    Err(e) if e != gimli::Error::NoUnwindInfoForAddress => {
+                        debug!("failed to search eh_frame_hdr for 0x{:x}: {}", svma, e);
+                        section.unwind_info_for_address(
+                            &self.bases,
+                            ctx,
+                            svma,
+                            EhFrame::cie_from_offset,
+                        )
+                    }
+                    result => result,
+                },
+                None => section.unwind_info_for_address(
+                    &self.bases,
+                    ctx,
+                    svma,
+                    EhFrame::cie_from_offset,
+                ),
+            };
+            match result {
+                Ok(row) => return Some(apply(row)),
+                Err(gimli::Error::NoUnwindInfoForAddress) => {}
+                Err(e) => debug!("failed to get eh_frame info for 0x{:x}: {}", svma, e),
+            }
+        }
+
+        if let Some(debug_frame) = self.debug_frame.as_ref() {
+            let section = DebugFrame::new(self.section_data(debug_frame), LittleEndian);
+            match section.unwind_info_for_address(
+                &self.bases,
+                ctx,
+                svma,
+                DebugFrame::cie_from_offset,
+            ) {
+                Ok(row) => return Some(apply(row)),
+                Err(gimli::Error::NoUnwindInfoForAddress) => {}
+                Err(e) => debug!("failed to get debug_frame info for 0x{:x}: {}", svma, e),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Memory where every address can be read, and is zero
+    struct ZeroMemory;
+
+    impl ProcessMemory for ZeroMemory {
+        fn read(&self, _addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+            buf.fill(0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_unwind_diagnostics() {
+        // a frame pointer chain of two frames, where the outer frame has a null return address
+        let mut stack = vec![0u8; 64];
+        stack[0x10..0x18].copy_from_slice(&0x1020u64.to_ne_bytes());
+        stack[0x18..0x20].copy_from_slice(&0x6000u64.to_ne_bytes());
+        let mut registers = Registers::default();
+        registers.set(Registers::IP, 0x5000);
+        registers.set(Registers::SP, 0x1000);
+        registers.set(Registers::FP, 0x1010);
+        let snapshot = StackSnapshot::new(1, registers, 0x1000, stack);
+
+        // the unwinder can't read any memory outside of the snapshot
+        let memory = StackSnapshot::new(1, Registers::default(), 0, Vec::new());
+        let mut unwinder = SnapshotUnwinder::with_memory(memory);
+        unwinder.add_module(0x5000, 0x1000, 0, "/nonexistent/libfoo.so");
+
+        let mut cursor = unwinder.cursor(&snapshot).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap(), 0x5000);
+        assert_eq!(cursor.diagnostics().stop, None);
+        assert_eq!(cursor.next().unwrap().unwrap(), 0x6000);
+        assert!(cursor.next().is_none());
+        assert_eq!(
+            cursor.diagnostics(),
+            &UnwindDiagnostics {
+                frames: 2,
+                frame_pointer_frames: 1,
+                stop: Some(UnwindStop::Complete),
+                missing_cfi: vec!["/nonexistent/libfoo.so".to_string()],
+                ..Default::default()
+            }
+        );
+
+        // a frame pointer outside of the snapshot can't be followed
+        registers.set(Registers::FP, 0x9000);
+        let snapshot = StackSnapshot::new(1, registers, 0x1000, vec![0u8; 64]);
+        let mut cursor = unwinder.cursor(&snapshot).unwrap();
+        assert_eq!(cursor.by_ref().count(), 1);
+        assert_eq!(cursor.diagnostics().stop, Some(UnwindStop::NoUnwindInfo));
+        assert_eq!(cursor.diagnostics().memory_errors, 1);
+
+        // and neither can one where the return address would be past the end of memory, even
+        // when the memory can be read
+        let unwinder = SnapshotUnwinder::with_memory(ZeroMemory);
+        registers.set(Registers::FP, u64::MAX - 4);
+        let snapshot = StackSnapshot::new(1, registers, 0x1000, vec![0u8; 64]);
+        let mut cursor = unwinder.cursor(&snapshot).unwrap();
+        assert_eq!(cursor.by_ref().count(), 1);
+        assert_eq!(cursor.diagnostics().stop, Some(UnwindStop::NoUnwindInfo));
+    }
+
+    #[test]
+    fn test_stack_scanning() {
+        // a frame without a frame pointer, with a return address a few words up the stack
+        let mut stack = vec![0u8; 64];
+        stack[0x18..0x20].copy_from_slice(&0x6010u64.to_ne_bytes());
+        let mut registers = Registers::default();
+        registers.set(Registers::IP, 0x5000);
+        registers.set(Registers::SP, 0x1000);
+        let snapshot = StackSnapshot::new(1, registers, 0x1000, stack);
+
+        let memory = StackSnapshot::new(1, Registers::default(), 0, Vec::new());
+        let mut unwinder = SnapshotUnwinder::with_memory(memory);
+        unwinder.add_module(0x5000, 0x2000, 0, "/nonexistent/libfoo.so");
+
+        // without scanning, the unwind stops at the frame that can't be unwound
+        assert!(!unwinder.stack_scanning());
+        let mut cursor = unwinder.cursor(&snapshot).unwrap();
+        assert_eq!(cursor.by_ref().count(), 1);
+        assert_eq!(cursor.diagnostics().stop, Some(UnwindStop::NoUnwindInfo));
+        assert_eq!(cursor.diagnostics().scanned_frames, 0);
+        drop(cursor);
+
+        unwinder.set_stack_scanning(true);
+        let mut cursor = unwinder.cursor(&snapshot).unwrap();
+        let frame = cursor.next_frame().unwrap().unwrap();
+        assert!(!frame.is_heuristic());
+        let frame = cursor.next_frame().unwrap().unwrap();
+        assert_eq!(frame.ip, 0x6010);
+        assert_eq!(frame.sp, 0x1020);
+        assert_eq!(frame.source, FrameSource::Scan);
+        assert!(frame.is_heuristic());
+        assert_eq!(cursor.diagnostics().scanned_frames, 1);
+    }
+
+    #[test]
+    fn test_register_source() {
+        let child = crate::tests::TestChild::sleep();
+        let process = Process::new(child.pid()).unwrap();
+        let _lock = process.lock().unwrap();
+        let thread = &process.threads().unwrap()[0];
+        let unwinder = SnapshotUnwinder::new(child.pid()).unwrap();
+
+        // without a copy of the stack, it's read from the process while unwinding
+        let snapshot = StackSnapshot::from_registers(thread).unwrap();
+        assert!(snapshot.stack().is_empty());
+        let frames: Vec<u64> = unwinder.cursor(&snapshot).unwrap().flatten().collect();
+        assert!(frames.len() > 1);
+
+        let copied = thread.snapshot().unwrap();
+        let expected: Vec<u64> = unwinder.cursor(&copied).unwrap().flatten().collect();
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn test_module_changes() {
+        let memory = StackSnapshot::new(1, Registers::default(), 0, Vec::new());
+        let mut unwinder = SnapshotUnwinder::with_memory(memory);
+        unwinder.add_module(0x1000, 0x1000, 0, "liba.so");
+        unwinder.add_module(0x3000, 0x1000, 0, "libb.so");
+        unwinder.add_module(0x5000, 0x1000, 0, "libc.so");
+        let filename = |unwinder: &SnapshotUnwinder<_>, addr| {
+            unwinder
+                .get_module(addr)
+                .map(|module| module.filename.clone())
+        };
+        assert_eq!(filename(&unwinder, 0x1800).as_deref(), Some("liba.so"));
+
+        // a new mapping replaces the ones it overlaps
+        unwinder.add_module(0x1800, 0x2000, 0, "libd.so");
+        assert_eq!(filename(&unwinder, 0x1000), None);
+        assert_eq!(filename(&unwinder, 0x3900), None);
+        assert_eq!(filename(&unwinder, 0x3000).as_deref(), Some("libd.so"));
+        assert_eq!(filename(&unwinder, 0x5000).as_deref(), Some("libc.so"));
+
+        assert!(unwinder.remove_module(0x5fff));
+        assert!(!unwinder.remove_module(0x5000));
+        assert_eq!(filename(&unwinder, 0x5000), None);
+        assert_eq!(filename(&unwinder, 0x2000).as_deref(), Some("libd.so"));
+    }
+
+    #[test]
+    fn test_reload_keeps_tables() {
+        let mut unwinder = SnapshotUnwinder::new(std::process::id() as Pid).unwrap();
+        let code = test_reload_keeps_tables as *const () as u64;
+        assert_eq!(unwinder.prewarm(&[code..code + 1, 0..1]), 1);
+        let size = unwinder.cache_size();
+        unwinder.reload().unwrap();
+        assert_eq!(unwinder.cache_size(), size);
+        assert!(unwinder.get_module(code).unwrap().tables.is_loaded());
+    }
+
+    #[test]
+    fn test_find_segment() {
+        // the executable segments of a binary linked by lld, where the code starts right after
+        // the read only data in the file rather than on a page of its own
+        let segments = [(0x15a8, 0x5a8, 0x1000), (0x45a8, 0x25a8, 0x200)];
+        // the code is mapped from the start of the page the read only data is in
+        assert_eq!(find_segment(&segments, 0x0, 0x1000), Some((0x15a8, 0x5a8)));
+        assert_eq!(
+            find_segment(&segments, 0x1000, 0x1000),
+            Some((0x15a8, 0x5a8))
+        );
+        // with larger pages both segments start in the same page, and the one whose file
+        // range contains the offset wins over the last one in the page
+        assert_eq!(
+            find_segment(&segments, 0x2000, 0x10000),
+            Some((0x45a8, 0x25a8))
+        );
+        assert_eq!(
+            find_segment(&segments, 0x1000, 0x10000),
+            Some((0x15a8, 0x5a8))
+        );
+        assert_eq!(find_segment(&segments, 0x3000, 0x1000), None);
+    }
+
+    #[test]
+    fn test_shared_tables() {
+        let shared = Arc::new(SharedTables::default());
+        let code = test_shared_tables as *const () as u64;
+        let unwinders: Vec<_> = (0..2)
+            .map(|_| {
+                let mut unwinder = SnapshotUnwinder::new(std::process::id() as Pid).unwrap();
+                unwinder.share_tables(shared.clone());
+                assert_eq!(unwinder.prewarm(&[code..code + 1, 0..1]), 1);
+                unwinder
+            })
+            .collect();
+        let tables = |unwinder: &SnapshotUnwinder| {
+            let module = unwinder.get_module(code).unwrap();
+            unwinder.tables(module).as_ref().unwrap().clone()
+        };
+        assert!(Arc::ptr_eq(&tables(&unwinders[0]), &tables(&unwinders[1])));
+
+        // the tables are freed once no unwinder uses them anymore
+        let weak = Arc::downgrade(&tables(&unwinders[0]));
+        drop(unwinders);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_prewarm() {
+        let unwinder = SnapshotUnwinder::new(std::process::id() as Pid).unwrap();
+        assert_eq!(unwinder.cache_size(), 0);
+        let code = test_prewarm as *const () as u64;
+        assert_eq!(unwinder.prewarm(&[0..1, code..code + 1]), 1);
+        let size = unwinder.cache_size();
+        assert!(size > 0);
+
+        // warming cached tables doesn't load them again
+        assert_eq!(unwinder.prewarm(&[code..code + 1, code..code + 2]), 1);
+        assert_eq!(unwinder.cache_size(), size);
+        assert_eq!(unwinder.prewarm(&[0..1, 1..2]), 0);
+        assert_eq!(unwinder.prewarm_module("/nonexistent/libfoo.so"), 0);
+    }
+
+    #[test]
+    fn test_eh_frame_hdr() {
+        let code = test_eh_frame_hdr as *const () as u64;
+        let maps = proc_maps::get_process_maps(std::process::id() as Pid).unwrap();
+        let map = maps
+            .iter()
+            .find(|m| {
+                m.is_exec() && (m.start() as u64..(m.start() + m.size()) as u64).contains(&code)
+            })
+            .unwrap();
+        let module = ModuleUnwindInfo {
+            address: map.start() as u64,
+            size: map.size() as u64,
+            file_offset: map.offset as u64,
+            filename: map.filename().unwrap().display().to_string(),
+            tables: CachedData::new(),
+        };
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let mut tables = UnwindTables::new(&module, &process).unwrap();
+        assert!(tables
+            .eh_frame_hdr()
+            .is_some_and(|hdr| hdr.table().is_some()));
+
+        // the binary search has to find the same rows as scanning the whole of eh_frame
+        let mut ctx = UnwindContext::new();
+        let row = |row: &UnwindTableRow<usize>| {
+            (row.start_address(), row.end_address(), row.cfa().clone())
+        };
+        let addresses: Vec<u64> = (0..module.size)
+            .step_by((module.size / 64).max(1) as usize)
+            .map(|offset| module.address + offset - tables.bias)
+            .collect();
+        let searched: Vec<_> = addresses
+            .iter()
+            .map(|&svma| tables.find_row(&mut ctx, svma, row))
+            .collect();
+        assert!(searched.iter().any(|row| row.is_some()));
+        tables.eh_frame_hdr = None;
+        let scanned: Vec<_> = addresses
+            .iter()
+            .map(|&svma| tables.find_row(&mut ctx, svma, row))
+            .collect();
+        assert_eq!(searched, scanned);
+    }
+}
@@ -0,0 +1,129 @@
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use super::{get_start_time, platform_info, Pid, Process};
+use crate::Error;
+
+/// A pollable handle that becomes readable when a process exits.
+///
+/// This wraps a pidfd, which can be registered with an event loop through `AsRawFd`, or
+/// checked directly with `has_exited` and `wait`.
+pub struct ExitNotification {
+    pid: Pid,
+    pidfd: OwnedFd,
+}
+
+impl ExitNotification {
+    fn new(process: &Process) -> Result<Self, Error> {
+        if !platform_info().pidfd {
+            return Err(Error::Other(
+                "Exit notifications need pidfd support, which was added in Linux 5.3".to_string(),
+            ));
+        }
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, process.pid, 0) };
+        if fd < 0 {
+            return Err(Error::NixError(nix::errno::Errno::last()));
+        }
+        let pidfd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        // the pid might have been reused since the process was opened, in which case the pidfd
+        // refers to some other process. If we can't read the start time anymore the original
+        // process has already exited, and the pidfd is reporting that.
+        if let (Some(expected), Ok(start_time)) = (process.start_time, get_start_time(process.pid))
+        {
+            if expected != start_time {
+                return Err(Error::Other(format!(
+                    "Process {} has exited and its pid has been reused",
+                    process.pid
+                )));
+            }
+        }
+
+        Ok(Self {
+            pid: process.pid,
+            pidfd,
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns true if the process has exited, without blocking
+    pub fn has_exited(&self) -> Result<bool, Error> {
+        self.wait(Some(Duration::ZERO))
+    }
+
+    /// Blocks until the process exits or the timeout elapses, returning true if the process
+    /// has exited. A timeout of None waits forever.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        let timeout = match timeout {
+            // round up, so that sub millisecond timeouts don't turn into a non-blocking poll
+            Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+        let mut fds = libc::pollfd {
+            fd: self.pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            let ret = unsafe { libc::poll(&mut fds, 1, timeout) };
+            if ret >= 0 {
+                return Ok(ret > 0);
+            }
+            let errno = nix::errno::Errno::last();
+            if errno != nix::errno::Errno::EINTR {
+                return Err(Error::NixError(errno));
+            }
+        }
+    }
+}
+
+impl AsRawFd for ExitNotification {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pidfd.as_raw_fd()
+    }
+}
+
+impl AsFd for ExitNotification {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.pidfd.as_fd()
+    }
+}
+
+impl Process {
+    /// Returns a handle that can be polled to find out when this process exits.
+    ///
+    /// This requires pidfd support, which was added in Linux 5.3.
+    pub fn exit_notification(&self) -> Result<ExitNotification, Error> {
+        ExitNotification::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_exit_notification() {
+        if !platform_info().pidfd {
+            return;
+        }
+        let mut child = crate::tests::TestChild::sleep();
+        let process = Process::new(child.pid()).unwrap();
+        let notification = process.exit_notification().unwrap();
+        assert_eq!(notification.pid(), child.pid());
+        assert!(!notification.has_exited().unwrap());
+
+        // short timeouts still block for at least as long as asked
+        let start = Instant::now();
+        assert!(!notification.wait(Some(Duration::from_micros(500))).unwrap());
+        assert!(start.elapsed() >= Duration::from_micros(500));
+
+        child.kill().unwrap();
+        assert!(notification.wait(Some(Duration::from_secs(5))).unwrap());
+        assert!(notification.has_exited().unwrap());
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+
+use super::dwarf_unwind::{SnapshotCursor, SnapshotMemory, UnwindDiagnostics};
+use super::snapshot::{Registers, StackSnapshot};
+use crate::{Error, Process, ProcessMemory, StackFrame};
+
+/// A frame in a mixed-mode callstack
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// A native frame, with the instruction pointer of the frame
+    Native(u64),
+    /// A frame synthesized by a `FrameProvider`, like a function in an interpreted language
+    Runtime(StackFrame),
+}
+
+/// What a `FrameProvider` found for a native frame
+#[derive(Debug, Clone, Default)]
+pub enum RuntimeFrames {
+    /// The native frame doesn't belong to the runtime, and should be kept as is
+    #[default]
+    None,
+    /// Insert these frames (innermost first) before the native frame
+    Insert(Vec<StackFrame>),
+    /// Replace the native frame with these frames (innermost first), for instance to replace
+    /// an interpreter loop with the functions it was evaluating
+    Replace(Vec<StackFrame>),
+}
+
+/// Recognizes frames belonging to a language runtime while a native stack is being unwound,
+/// so that interpreter frames can be reported inline with the native frames that run them.
+pub trait FrameProvider<M: ProcessMemory = Process> {
+    /// Called for every native frame, with the registers recovered for that frame and a
+    /// reader for the memory of the target process (which reads from the copied stack where
+    /// it can)
+    fn frames(
+        &self,
+        ip: u64,
+        registers: &Registers,
+        memory: &SnapshotMemory<'_, M>,
+    ) -> Result<RuntimeFrames, Error>;
+}
+
+/// Iterates over a callstack with both native frames and the frames added by the providers
+/// registered with `SnapshotUnwinder::add_frame_provider`
+pub struct FrameCursor<'a, M: ProcessMemory = Process> {
+    cursor: SnapshotCursor<'a, M>,
+    providers: &'a [Box<dyn FrameProvider<M>>],
+    memory: SnapshotMemory<'a, M>,
+    pending: VecDeque<Frame>,
+}
+
+impl<'a, M: ProcessMemory> FrameCursor<'a, M> {
+    pub(super) fn new(
+        cursor: SnapshotCursor<'a, M>,
+        providers: &'a [Box<dyn FrameProvider<M>>],
+        memory: SnapshotMemory<'a, M>,
+    ) -> Self {
+        Self {
+            cursor,
+            providers,
+            memory,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> &StackSnapshot {
+        self.cursor.snapshot()
+    }
+
+    /// Returns what happened while unwinding the native frames returned so far
+    pub fn diagnostics(&self) -> &UnwindDiagnostics {
+        self.cursor.diagnostics()
+    }
+
+    fn runtime_frames(&self, ip: u64) -> Result<RuntimeFrames, Error> {
+        for provider in self.providers {
+            match provider.frames(ip, self.cursor.registers(), &self.memory)? {
+                RuntimeFrames::None => continue,
+                frames => return Ok(frames),
+            }
+        }
+        Ok(RuntimeFrames::None)
+    }
+}
+
+impl<M: ProcessMemory> Iterator for FrameCursor<'_, M> {
+    type Item = Result<Frame, Error>;
+
+    fn next(&mut self) -> Option<Result<Frame, Error>> {
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Some(Ok(frame));
+            }
+
+            let ip = match self.cursor.next()? {
+                Ok(ip) => ip,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.runtime_frames(ip) {
+                Ok(RuntimeFrames::None) => return Some(Ok(Frame::Native(ip))),
+                Ok(RuntimeFrames::Insert(frames)) => {
+                    self.pending.extend(frames.into_iter().map(Frame::Runtime));
+                    self.pending.push_back(Frame::Native(ip));
+                }
+                Ok(RuntimeFrames::Replace(frames)) => {
+                    self.pending.extend(frames.into_iter().map(Frame::Runtime));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use super::Pid;
+use crate::Error;
+
+/// Freezes every process in a cgroup using the cgroup v2 freezer, and thaws them again when
+/// dropped
+pub struct Freezer {
+    path: PathBuf,
+    thaw_on_drop: bool,
+}
+
+impl Freezer {
+    /// Freezes the cgroup containing `pid`, waiting up to `timeout` for it to be frozen
+    pub fn new(pid: Pid, timeout: Duration) -> Result<Self, Error> {
+        let path = cgroup_path(pid)?;
+
+        // freezing the cgroup we are running in, or one above it, would also freeze us, and
+        // we'd never thaw it
+        if let Ok(own) = cgroup_path(std::process::id() as Pid) {
+            if freezes_cgroup(&path, &own) {
+                return Err(Error::Other(format!(
+                    "Process {} is in a cgroup containing this process, and can't be frozen",
+                    pid
+                )));
+            }
+        }
+
+        // if the cgroup was already frozen by someone else, leave it frozen when we're done
+        let already_frozen = std::fs::read_to_string(path.join("cgroup.freeze"))?.trim() == "1";
+        std::fs::write(path.join("cgroup.freeze"), "1")?;
+        let freezer = Self {
+            path,
+            thaw_on_drop: !already_frozen,
+        };
+
+        // freezing is asynchronous, cgroup.events is updated once every process has stopped.
+        // if this fails the freezer is dropped, which thaws the cgroup again
+        let start = Instant::now();
+        while !freezer.is_frozen()? {
+            if start.elapsed() > timeout {
+                return Err(Error::Other(format!(
+                    "Timed out waiting for cgroup {} to freeze",
+                    freezer.path.display()
+                )));
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        debug!("froze cgroup {}", freezer.path.display());
+        Ok(freezer)
+    }
+
+    fn is_frozen(&self) -> Result<bool, Error> {
+        let events = std::fs::read_to_string(self.path.join("cgroup.events"))?;
+        Ok(events.lines().any(|line| line.trim() == "frozen 1"))
+    }
+}
+
+impl Drop for Freezer {
+    fn drop(&mut self) {
+        if !self.thaw_on_drop {
+            return;
+        }
+        if let Err(e) = std::fs::write(self.path.join("cgroup.freeze"), "0") {
+            warn!("Failed to thaw cgroup {} : {}", self.path.display(), e);
+        }
+        debug!("thawed cgroup {}", self.path.display());
+    }
+}
+
+/// Returns the directory of the cgroup v2 hierarchy that contains a process
+fn cgroup_path(pid: Pid) -> Result<PathBuf, Error> {
+    let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+    let relative = parse_unified_cgroup(&cgroups)
+        .ok_or_else(|| Error::Other(format!("Process {} isn't in a cgroup v2 hierarchy", pid)))?;
+    if relative == "/" {
+        return Err(Error::Other(format!(
+            "Process {} is in the root cgroup, which can't be frozen",
+            pid
+        )));
+    }
+
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    let (root, mount_point) = parse_cgroup2_mount(&mountinfo)
+        .ok_or_else(|| Error::Other("Failed to find the cgroup2 filesystem".to_string()))?;
+
+    // the cgroup path is relative to the root of the mount, which might not be the root of
+    // the hierarchy inside of a container
+    let relative = match Path::new(relative).strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => Path::new(relative.trim_start_matches('/')),
+    };
+    Ok(Path::new(mount_point).join(relative))
+}
+
+/// Returns true if freezing the cgroup at `path` would freeze the cgroup at `other`, which is
+/// the case when it's the same cgroup or one of its ancestors
+fn freezes_cgroup(path: &Path, other: &Path) -> bool {
+    // this compares whole components, so /a/b isn't an ancestor of /a/bc
+    other.starts_with(path)
+}
+
+/// Returns the path of the cgroup v2 entry in the contents of /proc/<pid>/cgroup
+fn parse_unified_cgroup(cgroups: &str) -> Option<&str> {
+    cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.trim())
+}
+
+/// Returns the root and mount point of the cgroup2 filesystem from /proc/self/mountinfo
+fn parse_cgroup2_mount(mountinfo: &str) -> Option<(&str, &str)> {
+    mountinfo.lines().find_map(|line| {
+        // the filesystem type comes after a '-' separator, since the number of optional fields
+        // before it varies
+        let (mount, fs) = line.split_once(" - ")?;
+        if fs.split_whitespace().next()? != "cgroup2" {
+            return None;
+        }
+        let mut fields = mount.split_whitespace();
+        let root = fields.nth(3)?;
+        let mount_point = fields.next()?;
+        Some((root, mount_point))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroups() {
+        let cgroups = "4:memory:/user.slice\n0::/user.slice/session-2.scope\n";
+        assert_eq!(
+            parse_unified_cgroup(cgroups),
+            Some("/user.slice/session-2.scope")
+        );
+        assert_eq!(parse_unified_cgroup("4:memory:/user.slice\n"), None);
+
+        let mountinfo = "32 24 0:28 / /sys/fs/cgroup rw,relatime - tmpfs tmpfs rw,mode=755\n\
+            42 32 0:38 / /sys/fs/cgroup/unified rw,relatime shared:5 - cgroup2 cgroup2 rw\n";
+        assert_eq!(
+            parse_cgroup2_mount(mountinfo),
+            Some(("/", "/sys/fs/cgroup/unified"))
+        );
+        assert_eq!(
+            parse_cgroup2_mount("32 24 0:28 / /sys rw - tmpfs tmpfs rw"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_freezes_cgroup() {
+        let own = Path::new("/sys/fs/cgroup/user.slice/app.scope");
+        assert!(freezes_cgroup(own, own));
+        assert!(freezes_cgroup(Path::new("/sys/fs/cgroup/user.slice"), own));
+        assert!(!freezes_cgroup(
+            Path::new("/sys/fs/cgroup/user.slice/app.scope/worker"),
+            own
+        ));
+        assert!(!freezes_cgroup(Path::new("/sys/fs/cgroup/user.sl"), own));
+        assert!(!freezes_cgroup(
+            Path::new("/sys/fs/cgroup/other.slice"),
+            own
+        ));
+    }
+}
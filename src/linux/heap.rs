@@ -0,0 +1,409 @@
+//! Enumerates the chunks of glibc's malloc heap in the target process, for tools like leak
+//! detectors that want to know what is allocated without reimplementing the allocator.
+//!
+//! This depends on the layout of glibc's internal structures, and supports glibc 2.27 or later
+//! on 64 bit targets. Chunks allocated directly with mmap (large allocations) aren't part of any
+//! arena, and so aren't returned. The target should be locked while its heap is walked, since
+//! the walk follows the heap's own bookkeeping and can fail if that changes under it.
+
+use std::path::Path;
+
+use log::{debug, info};
+
+use super::Process;
+use crate::{Error, ProcessMemory};
+
+/// Offsets into struct malloc_state
+const ARENA_TOP: u64 = 0x60;
+const ARENA_NEXT: u64 = 0x870;
+const ARENA_SYSTEM_MEM: u64 = 0x888;
+const ARENA_SIZE: u64 = 0x898;
+
+/// Thread arenas are allocated in heaps aligned to this size, which start with a heap_info
+const HEAP_MAX_SIZE: u64 = 64 * 1024 * 1024;
+/// Offsets into struct heap_info
+const HEAP_PREV: u64 = 0x8;
+const HEAP_SIZE: u64 = 0x10;
+
+const MALLOC_ALIGNMENT: u64 = 16;
+/// The size of the chunk header, which is also the offset of the pointer returned by malloc
+const CHUNK_HEADER: u64 = 16;
+const PREV_INUSE: u64 = 0x1;
+const SIZE_BITS: u64 = 0x7;
+
+/// How much of the heap to copy from the target at a time while walking it
+const WINDOW_SIZE: usize = 256 * 1024;
+
+/// An arena of glibc's malloc. The main arena manages the heap grown with brk, and each thread
+/// arena manages one or more heaps created with mmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arena {
+    /// The address of the arena's malloc_state
+    pub address: u64,
+    /// Whether this is main_arena
+    pub main: bool,
+    /// The top chunk, which is the free space at the end of the arena's current heap
+    pub top: u64,
+    pub top_size: u64,
+    /// The memory the arena has taken from the system
+    pub system_mem: u64,
+}
+
+/// A chunk of memory in a malloc arena
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapChunk {
+    /// The address of the chunk header. The pointer returned by malloc is `mem()`.
+    pub address: u64,
+    /// The size of the chunk, including its header
+    pub size: u64,
+    /// Whether the chunk is allocated. Chunks held in the tcache and fastbins are counted as
+    /// allocated, since malloc doesn't mark them free either.
+    pub in_use: bool,
+}
+
+impl HeapChunk {
+    /// The address that malloc returned for this chunk
+    pub fn mem(&self) -> u64 {
+        self.address + CHUNK_HEADER
+    }
+}
+
+/// Walks the arenas and chunks of glibc's malloc in a process
+pub struct HeapWalker<'a> {
+    process: &'a Process,
+    main_arena: u64,
+}
+
+impl<'a> HeapWalker<'a> {
+    /// Finds main_arena from the symbols of the process's libc, or its separate debug info in
+    /// /usr/lib/debug. Distributions usually strip main_arena from libc itself, so when neither
+    /// has it, this falls back to scanning libc's data for a structure that looks like it.
+    pub fn new(process: &'a Process) -> Result<Self, Error> {
+        let main_arena = find_main_arena(process)?;
+        info!("found main_arena at 0x{:016x}", main_arena);
+        Ok(Self::with_main_arena(process, main_arena))
+    }
+
+    /// Uses main_arena at a known address, like one looked up with a debugger
+    pub fn with_main_arena(process: &'a Process, main_arena: u64) -> Self {
+        Self {
+            process,
+            main_arena,
+        }
+    }
+
+    pub fn main_arena(&self) -> u64 {
+        self.main_arena
+    }
+
+    /// Returns all the arenas, starting with main_arena
+    pub fn arenas(&self) -> Result<Vec<Arena>, Error> {
+        let mut arenas = Vec::new();
+        let mut address = self.main_arena;
+        loop {
+            let top: u64 = self.process.copy_struct((address + ARENA_TOP) as usize)?;
+            let top_size: u64 = self.process.copy_struct((top + 8) as usize)?;
+            arenas.push(Arena {
+                address,
+                main: address == self.main_arena,
+                top,
+                top_size: top_size & !SIZE_BITS,
+                system_mem: self
+                    .process
+                    .copy_struct((address + ARENA_SYSTEM_MEM) as usize)?,
+            });
+
+            address = self.process.copy_struct((address + ARENA_NEXT) as usize)?;
+            if address == self.main_arena {
+                return Ok(arenas);
+            }
+            if arenas.len() > 1024 {
+                return Err(Error::Other(
+                    "The list of malloc arenas doesn't loop back to main_arena".to_string(),
+                ));
+            }
+        }
+    }
+
+    /// Returns the chunks in an arena, ordered by address. The top chunk isn't included.
+    pub fn chunks(&self, arena: &Arena) -> Result<Vec<HeapChunk>, Error> {
+        let mut chunks = Vec::new();
+        let mut window = Window::new(self.process);
+        if arena.main {
+            // the main arena's chunks start at the beginning of the brk heap
+            let maps = proc_maps::get_process_maps(self.process.pid)?;
+            let heap = maps
+                .iter()
+                .find(|m| {
+                    m.start() as u64 <= arena.top && arena.top < (m.start() + m.size()) as u64
+                })
+                .ok_or_else(|| {
+                    Error::Other("Failed to find the mapping of the heap".to_string())
+                })?;
+            let start = align_up(heap.start() as u64 + CHUNK_HEADER) - CHUNK_HEADER;
+            walk_chunks(&mut window, start, arena.top, &mut chunks)?;
+            return Ok(chunks);
+        }
+
+        // thread arenas live at the start of their first heap, just after its heap_info, and
+        // each heap links to the one before it
+        let header_size = arena.address - heap_for_ptr(arena.address);
+        let mut heaps = Vec::new();
+        let mut heap = heap_for_ptr(arena.top);
+        loop {
+            heaps.push(heap);
+            if heap == heap_for_ptr(arena.address) || heaps.len() > 1024 {
+                break;
+            }
+            heap = self.process.copy_struct((heap + HEAP_PREV) as usize)?;
+        }
+        for &heap in heaps.iter().rev() {
+            let start = if heap == heap_for_ptr(arena.address) {
+                align_up(arena.address + ARENA_SIZE + CHUNK_HEADER) - CHUNK_HEADER
+            } else {
+                align_up(heap + header_size + CHUNK_HEADER) - CHUNK_HEADER
+            };
+            let end = if heap == heap_for_ptr(arena.top) {
+                arena.top
+            } else {
+                heap + self
+                    .process
+                    .copy_struct::<u64>((heap + HEAP_SIZE) as usize)?
+            };
+            walk_chunks(&mut window, start, end, &mut chunks)?;
+        }
+        Ok(chunks)
+    }
+}
+
+/// Adds the chunks from start up to end
+fn walk_chunks(
+    window: &mut Window<'_>,
+    start: u64,
+    end: u64,
+    chunks: &mut Vec<HeapChunk>,
+) -> Result<(), Error> {
+    let mut address = start;
+    while address < end {
+        let size = window.read_u64(address + 8)? & !SIZE_BITS;
+        // heaps that were replaced by a new one end with fenceposts, which are smaller than
+        // any real chunk
+        if size <= CHUNK_HEADER {
+            break;
+        }
+        if address + size > end {
+            return Err(Error::Other(format!(
+                "Invalid malloc chunk at 0x{:016x} with size 0x{:x}",
+                address, size
+            )));
+        }
+        // a chunk is in use if the chunk after it says so
+        let next_size = window.read_u64(address + size + 8)?;
+        chunks.push(HeapChunk {
+            address,
+            size,
+            in_use: next_size & PREV_INUSE != 0,
+        });
+        address += size;
+    }
+    Ok(())
+}
+
+/// Copies the target's memory a block at a time, so that walking a heap doesn't take a read
+/// for every chunk
+struct Window<'a> {
+    process: &'a Process,
+    start: u64,
+    data: Vec<u8>,
+}
+
+impl<'a> Window<'a> {
+    fn new(process: &'a Process) -> Self {
+        Self {
+            process,
+            start: 0,
+            data: Vec::new(),
+        }
+    }
+
+    fn read_u64(&mut self, addr: u64) -> Result<u64, Error> {
+        if addr < self.start || addr + 8 > self.start + self.data.len() as u64 {
+            self.data.resize(WINDOW_SIZE, 0);
+            // the window can't go past the end of the mapping, so read as much as we can
+            let mut len = WINDOW_SIZE;
+            while self
+                .process
+                .read(addr as usize, &mut self.data[..len])
+                .is_err()
+            {
+                if len <= 4096 {
+                    return self.process.copy_struct(addr as usize);
+                }
+                len /= 2;
+            }
+            self.data.truncate(len);
+            self.start = addr;
+        }
+        let offset = (addr - self.start) as usize;
+        Ok(u64::from_le_bytes(
+            self.data[offset..offset + 8].try_into().unwrap(),
+        ))
+    }
+}
+
+fn align_up(addr: u64) -> u64 {
+    (addr + MALLOC_ALIGNMENT - 1) & !(MALLOC_ALIGNMENT - 1)
+}
+
+fn heap_for_ptr(addr: u64) -> u64 {
+    addr & !(HEAP_MAX_SIZE - 1)
+}
+
+fn is_libc(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("libc.so") || name.starts_with("libc-2."))
+}
+
+fn find_main_arena(process: &Process) -> Result<u64, Error> {
+    let pid = process.pid;
+    let maps = proc_maps::get_process_maps(pid)?;
+    let libc_maps: Vec<_> = maps
+        .iter()
+        .filter(|m| m.filename().is_some_and(is_libc))
+        .collect();
+    let libc = libc_maps
+        .iter()
+        .find(|m| m.offset == 0)
+        .ok_or_else(|| Error::Other(format!("Failed to find libc in process {}", pid)))?;
+    let filename = libc.filename().unwrap();
+
+    if let Some(offset) = find_libc_symbol(filename, "main_arena")? {
+        return Ok(libc.start() as u64 + offset);
+    }
+    debug!(
+        "{} doesn't have a main_arena symbol, searching for it",
+        filename.display()
+    );
+
+    // main_arena is in libc's data, and is the start of a circular list of arenas
+    let mut candidates = Vec::new();
+    for m in libc_maps.iter().filter(|m| m.is_write()) {
+        let data = process.copy(m.start(), m.size())?;
+        let words: Vec<u64> = data
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        let arena_words = (ARENA_SIZE / 8) as usize;
+        for i in 0..words.len().saturating_sub(arena_words) {
+            let top = words[i + (ARENA_TOP / 8) as usize];
+            if top == 0 || !top.is_multiple_of(MALLOC_ALIGNMENT) {
+                continue;
+            }
+            let address = (m.start() + i * 8) as u64;
+            if is_arena_list(process, address, words[i + (ARENA_NEXT / 8) as usize]) {
+                candidates.push(address);
+            }
+        }
+    }
+    match candidates[..] {
+        [address] => Ok(address),
+        [] => Err(Error::Other(format!(
+            "Failed to find main_arena in {}",
+            filename.display()
+        ))),
+        _ => Err(Error::Other(format!(
+            "Found {} possible main_arenas in {}",
+            candidates.len(),
+            filename.display()
+        ))),
+    }
+}
+
+/// Returns true if following the next pointers from an arena leads back to it
+fn is_arena_list(process: &Process, address: u64, mut next: u64) -> bool {
+    for _ in 0..1024 {
+        if next == address {
+            return true;
+        }
+        // thread arenas are just after the heap_info at the start of their heap
+        if next - heap_for_ptr(next) > 0x100 {
+            return false;
+        }
+        next = match process.copy_struct((next + ARENA_NEXT) as usize) {
+            Ok(next) => next,
+            Err(_) => return false,
+        };
+    }
+    false
+}
+
+/// Looks up a symbol in libc, or in its separate debug info in /usr/lib/debug
+fn find_libc_symbol(filename: &Path, name: &str) -> Result<Option<u64>, Error> {
+    let data = std::fs::read(filename)?;
+    let elf = goblin::elf::Elf::parse(&data)?;
+    if let Some(address) = find_symbol(&elf, name) {
+        return Ok(Some(address));
+    }
+
+    let build_id = elf
+        .iter_note_sections(&data, Some(".note.gnu.build-id"))
+        .and_then(|mut notes| {
+            notes
+                .find_map(|note| note.ok())
+                .filter(|note| note.n_type == goblin::elf::note::NT_GNU_BUILD_ID)
+                .map(|note| note.desc.to_vec())
+        });
+    let build_id = match build_id {
+        Some(build_id) if build_id.len() > 1 => build_id,
+        _ => return Ok(None),
+    };
+    let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+    let debug_file = format!("/usr/lib/debug/.build-id/{}/{}.debug", &hex[..2], &hex[2..]);
+    let data = match std::fs::read(&debug_file) {
+        Ok(data) => data,
+        Err(_) => return Ok(None),
+    };
+    debug!("looking up {} in {}", name, debug_file);
+    Ok(find_symbol(&goblin::elf::Elf::parse(&data)?, name))
+}
+
+fn find_symbol(elf: &goblin::elf::Elf<'_>, name: &str) -> Option<u64> {
+    elf.syms
+        .iter()
+        .find(|sym| elf.strtab.get_at(sym.st_name) == Some(name))
+        .map(|sym| sym.st_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heap_walker() {
+        // sleep allocates a little while starting up, and then leaves its heap alone
+        let child = crate::tests::TestChild::sleep();
+        let process = Process::new(child.pid()).unwrap();
+
+        // the walker only understands glibc's malloc, which sleep won't use on musl systems
+        let maps = proc_maps::get_process_maps(child.pid()).unwrap();
+        if !maps.iter().any(|m| {
+            m.filename()
+                .and_then(|f| f.file_name())
+                .is_some_and(|f| f == "libc.so.6")
+        }) {
+            return;
+        }
+
+        let walker = HeapWalker::new(&process).unwrap();
+        let arenas = walker.arenas().unwrap();
+        assert_eq!(arenas.len(), 1);
+        assert!(arenas[0].main);
+
+        // the chunks and the top chunk cover all the memory the arena got from the system
+        let chunks = walker.chunks(&arenas[0]).unwrap();
+        assert!(chunks.iter().any(|chunk| chunk.in_use));
+        let total: u64 = chunks.iter().map(|chunk| chunk.size).sum();
+        assert_eq!(total + arenas[0].top_size, arenas[0].system_mem);
+    }
+}
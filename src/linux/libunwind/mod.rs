@@ -3,6 +3,7 @@ use libc::{c_char, c_int, c_void, pid_t, size_t};
 #[cfg_attr(target_arch = "x86_64", path = "bindings_x86_64.rs")]
 #[cfg_attr(target_arch = "arm", path = "bindings_arm.rs")]
 #[cfg_attr(target_arch = "aarch64", path = "bindings_aarch64.rs")]
+#[allow(clippy::use_self)]
 mod bindings;
 
 use self::bindings::{
@@ -74,6 +75,9 @@ pub struct Cursor {
 }
 
 impl Cursor {
+    /// # Safety
+    /// The register number must be valid for libunwind on the current architecture
+    #[allow(clippy::unnecessary_cast)]
     pub unsafe fn register(&self, register: i32) -> Result<u64> {
         let mut value = 0;
         let cursor = &self.cursor as *const _ as *mut _;
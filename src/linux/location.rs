@@ -0,0 +1,488 @@
+//! Evaluates the DWARF location expressions that describe where variables are stored, against
+//! the registers of a frame and the memory of the process.
+
+use gimli::{EvaluationResult, Reader as _, RunTimeEndian, UnitOffset, Value, ValueType};
+
+use super::debug_info::{
+    dwarf_error, unit_entry, DebugInfo, DieRef, Reader, RemoteValue, SectionData,
+};
+use super::Registers;
+use crate::{Error, ProcessMemory};
+
+/// Where a variable is stored, as computed from its location in the debug info
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableLocation {
+    /// In the memory of the process, at an address
+    Address(u64),
+    /// In a register, by its DWARF register number
+    Register(u16),
+    /// Nowhere, since the debug info computes the value itself. The bytes are little endian.
+    Value(Vec<u8>),
+    /// Split into pieces stored in different places, along with the size of each piece in bytes
+    Pieces(Vec<(Self, u64)>),
+    /// The compiler didn't keep the variable around at this point of the program
+    OptimizedOut,
+}
+
+impl VariableLocation {
+    /// Returns the address of the variable, if it's stored in memory as a whole
+    pub fn address(&self) -> Option<u64> {
+        match self {
+            Self::Address(address) => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Reads `size` bytes of the variable from wherever it's stored. The registers are those of
+    /// the frame the location was evaluated for.
+    pub fn read(
+        &self,
+        memory: &impl ProcessMemory,
+        registers: Option<&Registers>,
+        size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let mut data = match self {
+            Self::Address(address) => return memory.copy(*address as usize, size),
+            Self::Register(register) => registers
+                .and_then(|registers| registers.get(*register))
+                .ok_or_else(|| register_error(*register))?
+                .to_le_bytes()
+                .to_vec(),
+            Self::Value(value) => value.clone(),
+            Self::Pieces(pieces) => {
+                let mut data = Vec::with_capacity(size);
+                for (piece, piece_size) in pieces {
+                    data.extend(piece.read(memory, registers, *piece_size as usize)?);
+                }
+                data
+            }
+            Self::OptimizedOut => {
+                return Err(Error::Other("The variable was optimized out".to_string()))
+            }
+        };
+        if data.len() < size {
+            return Err(Error::Other(format!(
+                "The variable only has {} bytes, rather than {}",
+                data.len(),
+                size
+            )));
+        }
+        data.truncate(size);
+        Ok(data)
+    }
+}
+
+/// The frame a location expression is evaluated in. Global variables don't need anything from
+/// a frame other than the bias, so the default context (with a bias of 0) is enough for them in
+/// binaries that aren't position independent.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameContext<'a> {
+    /// The registers of the frame, like those recovered by `SnapshotCursor::registers`
+    pub registers: Option<&'a Registers>,
+    /// The canonical frame address of the frame, which is the stack pointer of its caller
+    pub cfa: Option<u64>,
+    /// The frame base of the function, which is what the locations of its locals are relative to
+    pub frame_base: Option<u64>,
+    /// How far the binary was shifted from the addresses in its debug info when it was loaded
+    pub bias: u64,
+}
+
+impl DebugInfo {
+    /// Returns where a global or static variable is stored. Variables are named like types,
+    /// with statics declared inside a function qualified by the name of the function.
+    pub fn variable_location(
+        &self,
+        memory: &impl ProcessMemory,
+        name: &str,
+        frame: &FrameContext<'_>,
+    ) -> Result<VariableLocation, Error> {
+        let die = self.named_variable(name)?;
+        self.location_attr(die, gimli::DW_AT_location, memory, frame, None)
+    }
+
+    /// Reads a global or static variable from the memory of another process. The bias is how far
+    /// the binary was shifted from the addresses in its debug info when it was loaded, which is 0
+    /// unless it's position independent.
+    ///
+    /// ```rust,no_run
+    /// # fn run(pid: remoteprocess::Pid, bias: u64) -> Result<(), remoteprocess::Error> {
+    /// let process = remoteprocess::Process::new(pid)?;
+    /// let debug_info = remoteprocess::DebugInfo::load("/usr/lib/libpython3.12.so.1.0")?;
+    /// let runtime = debug_info.global_value(&process, "_PyRuntime", bias)?;
+    /// println!("_PyRuntime is at 0x{:016x}", runtime.address);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn global_value(
+        &self,
+        memory: &impl ProcessMemory,
+        name: &str,
+        bias: u64,
+    ) -> Result<RemoteValue, Error> {
+        let frame = FrameContext {
+            bias,
+            ..Default::default()
+        };
+        let die = self.named_variable(name)?;
+        let location = self.location_attr(die, gimli::DW_AT_location, memory, &frame, None)?;
+        self.read_variable(die, &location, memory, None)
+    }
+
+    fn named_variable(&self, name: &str) -> Result<DieRef, Error> {
+        self.variables
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::Other(format!("No variable named {} in the debug info", name)))
+    }
+
+    /// Reads the value of the variable `die` from where it's stored
+    pub(super) fn read_variable(
+        &self,
+        die: DieRef,
+        location: &VariableLocation,
+        memory: &impl ProcessMemory,
+        registers: Option<&Registers>,
+    ) -> Result<RemoteValue, Error> {
+        let ty = self
+            .type_attr(die)?
+            .map(|ty| self.type_info(ty, 0))
+            .transpose()?
+            .ok_or_else(|| Error::Other("The variable doesn't have a type".to_string()))?;
+        let size = ty
+            .size
+            .ok_or_else(|| Error::Other("The variable doesn't have a known size".to_string()))?;
+        let data = location.read(memory, registers, size as usize)?;
+        Ok(self.remote(location.address().unwrap_or(0), ty, data))
+    }
+
+    /// Evaluates a location attribute of an entry, like the DW_AT_location of a variable. For
+    /// locations that change throughout a function, `pc` picks the one at that instruction
+    /// (relative to the addresses in the debug info), which defaults to the instruction pointer
+    /// of the frame.
+    pub(super) fn location_attr(
+        &self,
+        die: DieRef,
+        attr: gimli::DwAt,
+        memory: &impl ProcessMemory,
+        frame: &FrameContext<'_>,
+        pc: Option<u64>,
+    ) -> Result<VariableLocation, Error> {
+        let unit = &self.units[die.unit];
+        let entry = unit_entry(unit, die.offset)?;
+        match entry.attr_value(attr).map_err(dwarf_error)? {
+            Some(gimli::AttributeValue::Exprloc(expression)) => {
+                self.evaluate(die.unit, expression, memory, frame)
+            }
+            Some(value) => {
+                // a location list, which has a different expression for each range of
+                // instructions the variable lives in
+                let pc = match pc {
+                    Some(pc) => pc,
+                    None => frame
+                        .registers
+                        .and_then(|registers| registers.ip())
+                        .ok_or_else(|| register_error(Registers::IP))?
+                        .wrapping_sub(frame.bias),
+                };
+                let Some(offset) = self
+                    .dwarf
+                    .attr_locations_offset(unit, value)
+                    .map_err(dwarf_error)?
+                else {
+                    return Ok(VariableLocation::OptimizedOut);
+                };
+                let mut locations = self.dwarf.locations(unit, offset).map_err(dwarf_error)?;
+                while let Some(location) = locations.next().map_err(dwarf_error)? {
+                    if location.range.begin <= pc && pc < location.range.end {
+                        return self.evaluate(die.unit, location.data, memory, frame);
+                    }
+                }
+                Ok(VariableLocation::OptimizedOut)
+            }
+            None => Ok(VariableLocation::OptimizedOut),
+        }
+    }
+
+    /// Evaluates a location expression from a unit of the debug info
+    pub(super) fn evaluate(
+        &self,
+        unit: usize,
+        expression: gimli::Expression<Reader>,
+        memory: &impl ProcessMemory,
+        frame: &FrameContext<'_>,
+    ) -> Result<VariableLocation, Error> {
+        // an empty expression means the compiler didn't keep the variable around
+        if expression.0.is_empty() {
+            return Ok(VariableLocation::OptimizedOut);
+        }
+        let unit_ref = &self.units[unit];
+        let mut evaluation = expression.evaluation(unit_ref.encoding());
+        let mut result = evaluation.evaluate().map_err(dwarf_error)?;
+        loop {
+            result = match result {
+                EvaluationResult::Complete => break,
+                EvaluationResult::RequiresMemory {
+                    address,
+                    size,
+                    base_type,
+                    ..
+                } => {
+                    let data = memory.copy(address as usize, size as usize)?;
+                    let value = self.value(unit, base_type, &data)?;
+                    evaluation.resume_with_memory(value)
+                }
+                EvaluationResult::RequiresRegister {
+                    register,
+                    base_type,
+                } => {
+                    let value = frame
+                        .registers
+                        .and_then(|registers| registers.get(register.0))
+                        .ok_or_else(|| register_error(register.0))?;
+                    let value = self.value(unit, base_type, &value.to_le_bytes())?;
+                    evaluation.resume_with_register(value)
+                }
+                EvaluationResult::RequiresFrameBase => {
+                    let frame_base = frame.frame_base.ok_or_else(|| {
+                        Error::Other("The frame base of the function isn't known".to_string())
+                    })?;
+                    evaluation.resume_with_frame_base(frame_base)
+                }
+                EvaluationResult::RequiresCallFrameCfa => {
+                    let cfa = frame.cfa.ok_or_else(|| {
+                        Error::Other("The canonical frame address isn't known".to_string())
+                    })?;
+                    evaluation.resume_with_call_frame_cfa(cfa)
+                }
+                EvaluationResult::RequiresRelocatedAddress(address) => {
+                    evaluation.resume_with_relocated_address(address.wrapping_add(frame.bias))
+                }
+                EvaluationResult::RequiresIndexedAddress { index, relocate } => {
+                    let mut address = self.dwarf.address(unit_ref, index).map_err(dwarf_error)?;
+                    if relocate {
+                        address = address.wrapping_add(frame.bias);
+                    }
+                    evaluation.resume_with_indexed_address(address)
+                }
+                EvaluationResult::RequiresBaseType(offset) => {
+                    evaluation.resume_with_base_type(self.base_type(unit, offset)?)
+                }
+                EvaluationResult::RequiresTls(_) => {
+                    return Err(Error::Other(
+                        "Thread local variables aren't supported".to_string(),
+                    ))
+                }
+                EvaluationResult::RequiresEntryValue(_)
+                | EvaluationResult::RequiresParameterRef(_) => {
+                    return Err(Error::Other(
+                        "The variable depends on values from the calling frame".to_string(),
+                    ))
+                }
+                EvaluationResult::RequiresAtLocation(_) => {
+                    return Err(Error::Other(
+                        "Locations that call other entries aren't supported".to_string(),
+                    ))
+                }
+            }
+            .map_err(dwarf_error)?;
+        }
+
+        let mut pieces = evaluation.result();
+        if pieces.len() == 1 && pieces[0].size_in_bits.is_none() {
+            return piece_location(pieces.remove(0).location);
+        }
+        if pieces.is_empty() {
+            return Ok(VariableLocation::OptimizedOut);
+        }
+        let pieces = pieces
+            .into_iter()
+            .map(|piece| match (piece.size_in_bits, piece.bit_offset) {
+                (Some(bits), None | Some(0)) if bits % 8 == 0 => {
+                    Ok((piece_location(piece.location)?, bits / 8))
+                }
+                _ => Err(Error::Other(
+                    "Variables split into pieces of bits aren't supported".to_string(),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(VariableLocation::Pieces(pieces))
+    }
+
+    /// Returns the type of values for an expression, where offset 0 is the generic type
+    fn base_type(&self, unit: usize, offset: UnitOffset) -> Result<ValueType, Error> {
+        if offset.0 == 0 {
+            return Ok(ValueType::Generic);
+        }
+        let entry = unit_entry(&self.units[unit], offset)?;
+        ValueType::from_entry(&entry)
+            .map_err(dwarf_error)?
+            .ok_or_else(|| Error::Other("Invalid base type in location expression".to_string()))
+    }
+
+    /// Converts little endian bytes to a value of the base type
+    fn value(&self, unit: usize, base_type: UnitOffset, data: &[u8]) -> Result<Value, Error> {
+        match self.base_type(unit, base_type)? {
+            ValueType::Generic => {
+                let mut bytes = [0u8; 8];
+                let len = data.len().min(8);
+                bytes[..len].copy_from_slice(&data[..len]);
+                Ok(Value::Generic(u64::from_le_bytes(bytes)))
+            }
+            value_type => Value::parse(
+                value_type,
+                Reader::new(SectionData::from(data), RunTimeEndian::Little),
+            )
+            .map_err(dwarf_error),
+        }
+    }
+}
+
+fn piece_location(location: gimli::Location<Reader>) -> Result<VariableLocation, Error> {
+    Ok(match location {
+        gimli::Location::Empty => VariableLocation::OptimizedOut,
+        gimli::Location::Register { register } => VariableLocation::Register(register.0),
+        gimli::Location::Address { address } => VariableLocation::Address(address),
+        gimli::Location::Value { value } => VariableLocation::Value(value_bytes(value)),
+        gimli::Location::Bytes { value } => {
+            VariableLocation::Value(value.to_slice().map_err(dwarf_error)?.into_owned())
+        }
+        gimli::Location::ImplicitPointer { .. } => {
+            return Err(Error::Other(
+                "Pointers to optimized out variables aren't supported".to_string(),
+            ))
+        }
+    })
+}
+
+fn value_bytes(value: Value) -> Vec<u8> {
+    match value {
+        Value::Generic(value) | Value::U64(value) => value.to_le_bytes().to_vec(),
+        Value::I8(value) => value.to_le_bytes().to_vec(),
+        Value::U8(value) => value.to_le_bytes().to_vec(),
+        Value::I16(value) => value.to_le_bytes().to_vec(),
+        Value::U16(value) => value.to_le_bytes().to_vec(),
+        Value::I32(value) => value.to_le_bytes().to_vec(),
+        Value::U32(value) => value.to_le_bytes().to_vec(),
+        Value::I64(value) => value.to_le_bytes().to_vec(),
+        Value::F32(value) => value.to_le_bytes().to_vec(),
+        Value::F64(value) => value.to_le_bytes().to_vec(),
+    }
+}
+
+pub(super) fn register_error(register: u16) -> Error {
+    Error::Other(format!("Register {} isn't known in this frame", register))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::symbol_search::current_exe_has_debug_info;
+    use crate::{Pid, Process};
+
+    static LOCATION_TEST_VALUE: u64 = 0x1234_5678_9abc_def0;
+
+    fn load() -> DebugInfo {
+        let exe = std::env::current_exe().unwrap();
+        DebugInfo::load(&exe.display().to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_global_value() {
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let expected = std::hint::black_box(&LOCATION_TEST_VALUE) as *const u64 as u64;
+        let debug_info = load();
+
+        // the test binary is position independent, and its first segment is at address 0
+        let exe = std::env::current_exe().unwrap();
+        let bias = proc_maps::get_process_maps(std::process::id() as Pid)
+            .unwrap()
+            .iter()
+            .filter(|map| map.filename() == Some(exe.as_path()))
+            .map(|map| map.start() as u64)
+            .min()
+            .unwrap();
+
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let value = debug_info
+            .global_value(&process, "LOCATION_TEST_VALUE", bias)
+            .unwrap();
+        assert_eq!(value.address, expected);
+        assert_eq!(value.as_u64(), Some(LOCATION_TEST_VALUE));
+        assert!(debug_info
+            .global_value(&process, "LOCATION_MISSING_VALUE", bias)
+            .is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let debug_info = load();
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let target = std::hint::black_box(0xfeed_u64);
+        let stack = std::hint::black_box([0, &target as *const u64 as u64]);
+
+        let mut registers = Registers::default();
+        registers.set(Registers::SP, stack.as_ptr() as u64);
+        registers.set(0, 0x1111_2222);
+        registers.set(1, 0x3333_4444);
+        let frame = FrameContext {
+            registers: Some(&registers),
+            cfa: Some(0x2000),
+            frame_base: Some(0x1000),
+            bias: 0,
+        };
+        let evaluate = |bytes: &[u8]| {
+            let expression =
+                gimli::Expression(Reader::new(SectionData::from(bytes), RunTimeEndian::Little));
+            debug_info.evaluate(0, expression, &process, &frame)
+        };
+
+        // a pointer stored 8 bytes above the stack pointer: DW_OP_breg 8, DW_OP_deref
+        let sp = gimli::DW_OP_breg0.0 + Registers::SP as u8;
+        let location = evaluate(&[sp, 8, gimli::DW_OP_deref.0]).unwrap();
+        assert_eq!(location.address(), Some(&target as *const u64 as u64));
+        assert_eq!(
+            location.read(&process, Some(&registers), 8).unwrap(),
+            0xfeed_u64.to_le_bytes()
+        );
+
+        // relative to the frame base and the cfa: DW_OP_fbreg 16, DW_OP_call_frame_cfa
+        assert_eq!(
+            evaluate(&[gimli::DW_OP_fbreg.0, 16]).unwrap(),
+            VariableLocation::Address(0x1010)
+        );
+        assert_eq!(
+            evaluate(&[gimli::DW_OP_call_frame_cfa.0]).unwrap(),
+            VariableLocation::Address(0x2000)
+        );
+
+        // a value in a register, and one split across two registers
+        let location = evaluate(&[gimli::DW_OP_reg0.0]).unwrap();
+        assert_eq!(location, VariableLocation::Register(0));
+        assert_eq!(
+            location.read(&process, Some(&registers), 4).unwrap(),
+            0x1111_2222_u32.to_le_bytes()
+        );
+        let piece = gimli::DW_OP_piece.0;
+        let location =
+            evaluate(&[gimli::DW_OP_reg0.0, piece, 4, gimli::DW_OP_reg1.0, piece, 4]).unwrap();
+        assert_eq!(
+            location.read(&process, Some(&registers), 8).unwrap(),
+            0x3333_4444_1111_2222_u64.to_le_bytes()
+        );
+
+        // a value computed by the expression: DW_OP_lit5, DW_OP_stack_value
+        let location = evaluate(&[gimli::DW_OP_lit5.0, gimli::DW_OP_stack_value.0]).unwrap();
+        assert_eq!(location.read(&process, None, 4).unwrap(), [5, 0, 0, 0]);
+
+        // an empty expression means the value was optimized out
+        assert_eq!(evaluate(&[]).unwrap(), VariableLocation::OptimizedOut);
+        // registers that weren't recovered for the frame can't be read
+        let location = evaluate(&[gimli::DW_OP_reg2.0]).unwrap();
+        assert!(location.read(&process, Some(&registers), 8).is_err());
+    }
+}
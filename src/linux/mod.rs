@@ -1,7 +1,42 @@
 #[cfg(use_libunwind)]
+mod binary;
+#[cfg(use_libunwind)]
+mod cache;
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod collector;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod criu;
+#[cfg(use_libunwind)]
+mod debug_info;
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod dwarf_unwind;
+mod exit;
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod frame_provider;
+mod freezer;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod heap;
+#[cfg(use_libunwind)]
 pub mod libunwind;
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod location;
+mod platform;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod pthread;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod snapshot;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod stack;
+#[cfg(use_libunwind)]
+mod symbol_index;
+#[cfg(use_libunwind)]
+mod symbol_search;
+#[cfg(use_libunwind)]
+mod symbol_source;
 #[cfg(use_libunwind)]
 mod symbolication;
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod variables;
 
 use lazy_static::lazy_static;
 use libc::pid_t;
@@ -15,16 +50,51 @@ use nix::{
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant, SystemTime};
 
-use super::Error;
+use super::{Error, ErrorContext, ResultExt};
 
+#[cfg(use_libunwind)]
+pub use self::debug_info::{DebugInfo, RemoteValue, TypeInfo, TypeKind};
+#[cfg(use_libunwind)]
+pub use self::symbol_index::SymbolIndex;
+#[cfg(use_libunwind)]
+pub use self::symbol_search::{SymbolSearchLocation, SymbolSearchPath};
+#[cfg(use_libunwind)]
+pub use self::symbol_source::SymbolSource;
 #[cfg(use_libunwind)]
 pub use self::symbolication::*;
 
 #[cfg(use_libunwind)]
 pub use self::libunwind::Unwinder;
 
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::criu::{CriuImage, CriuMapping, CriuThread};
+pub use self::exit::ExitNotification;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::heap::{Arena, HeapChunk, HeapWalker};
+pub use self::platform::{platform_info, PlatformInfo};
+
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::collector::{CollectedStacks, StackCollector};
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::dwarf_unwind::{
+    FrameSource, SnapshotCursor, SnapshotMemory, SnapshotUnwinder, UnwindDiagnostics, UnwindStop,
+    UnwoundFrame,
+};
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::frame_provider::{Frame, FrameCursor, FrameProvider, RuntimeFrames};
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::location::{FrameContext, VariableLocation};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::snapshot::{RegisterSource, Registers, StackSnapshot, DEFAULT_MAX_STACK_SIZE};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::stack::{StackBounds, StackHeadroom, StackUsage, StackWatermarks};
+#[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use self::variables::FrameVariable;
+
 use read_process_memory::{CopyAddress, ProcessHandle};
 
 pub type Pid = pid_t;
@@ -32,30 +102,201 @@ pub type Tid = pid_t;
 
 pub struct Process {
     pub pid: Pid,
+    start_time: Option<u64>,
+    access: ProcessAccess,
 }
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+/// A thread in the target process. Threads are compared by their tid.
+#[derive(Copy, Clone)]
 pub struct Thread {
     tid: nix::unistd::Pid,
+    read_only: bool,
+}
+
+impl PartialEq for Thread {
+    fn eq(&self, other: &Self) -> bool {
+        self.tid == other.tid
+    }
+}
+
+impl Eq for Thread {}
+
+impl std::hash::Hash for Thread {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.tid.hash(state);
+    }
+}
+
+/// How a process is allowed to be accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProcessAccess {
+    /// Attach to threads with ptrace when they are locked. This is the default.
+    #[default]
+    Full,
+    /// Never use ptrace, only reading memory with process_vm_readv and information from
+    /// procfs. This works where ptrace is forbidden (by YAMA, seccomp or because another
+    /// debugger is attached) but memory reads are allowed.
+    ///
+    /// Locking the process or its threads with ptrace is downgraded to a best-effort lock that
+    /// doesn't stop anything, so reads can see the process while it's changing. Reading
+    /// registers (and so taking snapshots and unwinding) isn't possible.
+    ReadOnly,
 }
 
 impl Process {
     pub fn new(pid: Pid) -> Result<Self, Error> {
-        Ok(Self { pid })
+        Self::with_access(pid, ProcessAccess::Full)
+    }
+
+    /// Opens a process, only accessing it in the ways allowed by `access`
+    pub fn with_access(pid: Pid, access: ProcessAccess) -> Result<Self, Error> {
+        if is_kernel_thread(pid) {
+            return Err(Error::KernelThread(pid));
+        }
+        // remember when the process started, so that we can tell if the pid gets reused
+        let start_time = get_start_time(pid).ok();
+        Ok(Self {
+            pid,
+            start_time,
+            access,
+        })
+    }
+
+    pub fn access(&self) -> ProcessAccess {
+        self.access
+    }
+
+    /// Returns what can be done with this process, by checking the permissions we have for it
+    /// without attaching to it
+    pub fn capabilities(&self) -> crate::Capabilities {
+        let can_read_mem = crate::can_read_memory(self, self.pid);
+        // attaching with ptrace and process_vm_writev need the same permissions as
+        // process_vm_readv, but attaching also fails if someone else is tracing the process
+        let can_write = self.access == ProcessAccess::Full && can_read_mem;
+        let traced = self
+            .thread(self.pid)
+            .tracer()
+            .map_or(true, |tracer| tracer.is_some());
+        let can_suspend = can_write && !traced && !self.is_zombie();
+        crate::Capabilities {
+            can_read_mem,
+            can_suspend,
+            can_unwind: cfg!(use_libunwind) && can_suspend,
+            can_symbolicate: cfg!(use_libunwind) && proc_maps::get_process_maps(self.pid).is_ok(),
+            can_write,
+        }
+    }
+
+    /// Returns a lock that doesn't stop anything, for when the process can't be ptraced
+    fn best_effort_lock(&self) -> Lock {
+        debug!(
+            "process {} is read only, not attaching to its threads",
+            self.pid
+        );
+        Lock {
+            locks: Vec::new(),
+            continue_on_drop: false,
+            freezer: None,
+            pid: self.pid,
+            locked_at: Instant::now(),
+        }
+    }
+
+    /// Returns a thread of this process, which inherits the access allowed for the process
+    fn thread(&self, tid: Tid) -> Thread {
+        Thread {
+            tid: nix::unistd::Pid::from_raw(tid),
+            read_only: self.access == ProcessAccess::ReadOnly,
+        }
+    }
+
+    /// Returns true if the process still exists and is the same process this was created
+    /// for, rather than a new process that has been given the same pid
+    pub fn exists(&self) -> bool {
+        match nix::sys::signal::kill(nix::unistd::Pid::from_raw(self.pid), None) {
+            // EPERM means the process exists, but we aren't allowed to signal it
+            Ok(()) | Err(nix::errno::Errno::EPERM) => {}
+            Err(_) => return false,
+        }
+        match self.start_time {
+            Some(start_time) => get_start_time(self.pid).ok() == Some(start_time),
+            None => true,
+        }
+    }
+
+    /// Sends a signal to the process.
+    ///
+    /// This fails with `ProcessExited` if the process has exited, including when its pid has
+    /// since been reused by another process.
+    pub fn signal(&self, signal: i32) -> Result<(), Error> {
+        let signal = nix::sys::signal::Signal::try_from(signal)?;
+        if !self.exists() {
+            return Err(Error::ProcessExited(self.pid));
+        }
+        match nix::sys::signal::kill(nix::unistd::Pid::from_raw(self.pid), signal) {
+            Ok(()) => Ok(()),
+            Err(nix::errno::Errno::ESRCH) => Err(Error::ProcessExited(self.pid)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Kills the process with SIGKILL
+    pub fn kill(&self) -> Result<(), Error> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Returns true if the process exists and hasn't exited. Unlike `exists`, this is false
+    /// for processes that have exited but haven't yet been reaped by their parent.
+    pub fn is_alive(&self) -> bool {
+        self.exists() && !self.is_zombie()
+    }
+
+    /// Returns true if the process has exited, but hasn't yet been reaped by its parent
+    pub fn is_zombie(&self) -> bool {
+        match std::fs::read(format!("/proc/{}/stat", self.pid)) {
+            Ok(stat) => matches!(get_active_status(&stat), Some(b'Z' | b'X')),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns an error if the process is a zombie, since those can't be attached to
+    fn check_not_zombie(&self) -> Result<(), Error> {
+        if self.is_zombie() {
+            return Err(Error::ZombieProcess(self.pid));
+        }
+        Ok(())
     }
 
     pub fn exe(&self) -> Result<String, Error> {
-        let path = std::fs::read_link(format!("/proc/{}/exe", self.pid))?;
+        let path = std::fs::read_link(format!("/proc/{}/exe", self.pid))
+            .context(|| ErrorContext::new("reading the executable").pid(self.pid))?;
         Ok(path.to_string_lossy().to_string())
     }
 
     pub fn cwd(&self) -> Result<String, Error> {
-        let path = std::fs::read_link(format!("/proc/{}/cwd", self.pid))?;
+        let path = std::fs::read_link(format!("/proc/{}/cwd", self.pid))
+            .context(|| ErrorContext::new("reading the working directory").pid(self.pid))?;
         Ok(path.to_string_lossy().to_string())
     }
 
+    /// Returns the id of the process group the process is in, which is the pid of the group's
+    /// leader. Shells put each job in its own group, so this is how to find the other
+    /// processes in the same pipeline.
+    pub fn process_group(&self) -> Result<Pid, Error> {
+        // the process group is the 5th field, and the 3rd after the comm field
+        get_stat_id(self.pid, 2)
+    }
+
+    /// Returns the id of the session the process is in, which is the pid of the session's
+    /// leader (usually the shell of the terminal the process was started from)
+    pub fn session_id(&self) -> Result<Pid, Error> {
+        // the session is the 6th field, and the 4th after the comm field
+        get_stat_id(self.pid, 3)
+    }
+
     pub fn cmdline(&self) -> Result<Vec<String>, Error> {
-        let mut f = File::open(format!("/proc/{}/cmdline", self.pid))?;
+        let mut f = File::open(format!("/proc/{}/cmdline", self.pid))
+            .context(|| ErrorContext::new("reading the command line").pid(self.pid))?;
         let mut buffer = Vec::new();
         f.read_to_end(&mut buffer)?;
 
@@ -69,7 +310,55 @@ impl Process {
         Ok(ret)
     }
 
+    /// Returns the start time, owner, memory usage and thread count of the process. These all
+    /// come from a single read of /proc/<pid>/stat, along with a stat of /proc/<pid>.
+    pub fn stats(&self) -> Result<crate::ProcessStats, Error> {
+        let context = || ErrorContext::new("reading the process stats").pid(self.pid);
+        let stat = std::fs::read(format!("/proc/{}/stat", self.pid)).context(context)?;
+        let uid = std::fs::metadata(format!("/proc/{}", self.pid))
+            .context(context)?
+            .uid();
+        let field =
+            |index| get_stat_field(&stat, index).and_then(|field| field.parse::<u64>().ok());
+        // num_threads, vsize and rss are the 20th, 23rd and 24th fields
+        let (Some(threads), Some(virtual_size), Some(resident), Some(start_time)) = (
+            field(17),
+            field(20),
+            field(21),
+            get_start_time_status(&stat).and_then(boot_relative_time),
+        ) else {
+            return Err(Error::Other(format!(
+                "Failed to parse /proc/{}/stat",
+                self.pid
+            )));
+        };
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        Ok(crate::ProcessStats {
+            start_time,
+            uid: Some(uid),
+            memory: crate::MemoryStats {
+                resident: resident * page_size,
+                virtual_size,
+            },
+            thread_count: threads as usize,
+        })
+    }
+
+    /// Suspends every thread of the process by attaching to them with ptrace, keeping them
+    /// stopped while the returned lock is alive. For processes opened with
+    /// `ProcessAccess::ReadOnly` this doesn't stop anything.
     pub fn lock(&self) -> Result<Lock, Error> {
+        self.ptrace_lock()
+            .context(|| ErrorContext::new("locking process").pid(self.pid))
+    }
+
+    fn ptrace_lock(&self) -> Result<Lock, Error> {
+        self.check_not_zombie()?;
+        if self.access == ProcessAccess::ReadOnly {
+            return Ok(self.best_effort_lock());
+        }
+        // the process is paused from when the first thread is stopped, not once they all are
+        let locked_at = Instant::now();
         let mut locks = Vec::new();
         let mut locked = std::collections::HashSet::new();
         let mut done = false;
@@ -81,32 +370,18 @@ impl Process {
         // created
         while !done {
             done = true;
-            for thread in self.threads()? {
+            let mut threads = self.threads()?;
+            threads.sort_by_key(|thread| thread.tid.as_raw());
+            for thread in threads {
                 let threadid = thread.id()?;
                 if !locked.contains(&threadid) {
-                    match thread.lock() {
-                        Ok(lock) => {
-                            locks.push(lock);
-                            locked.insert(threadid);
-                            done = false;
-                            all_locks_failed = false;
-                        }
-                        Err(Error::NixError(nix::errno::Errno::ESRCH)) => {
-                            // the thread probably exited before we could get a lock
-                            continue;
-                        }
-                        Err(e @ Error::NixError(nix::errno::Errno::EPERM)) => {
-                            if !thread.exists() {
-                                // The thread was probably in the "exiting" state, which returns
-                                // EPERM to the caller. This thread is dead, we can not ptrace
-                                // it and we should just ignore it.
-                                // See https://elixir.bootlin.com/linux/v6.15.3/source/kernel/ptrace.c#L458
-                                continue;
-                            }
-                            // We likely really have no permission, propagate the error
-                            return Err(e);
-                        }
-                        Err(e) => return Err(e),
+                    // if this fails, the locks we've already acquired are released when
+                    // `locks` is dropped
+                    if let Some(lock) = thread.try_lock()? {
+                        locks.push(lock);
+                        locked.insert(threadid);
+                        done = false;
+                        all_locks_failed = false;
                     }
                 }
             }
@@ -116,28 +391,137 @@ impl Process {
             return Err(Error::Other("All threads failed to lock".to_string()));
         }
 
-        Ok(Lock { locks })
+        Ok(Lock {
+            locks,
+            continue_on_drop: false,
+            freezer: None,
+            pid: self.pid,
+            locked_at,
+        })
     }
 
-    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
-        let mut ret = Vec::new();
-        let path = format!("/proc/{}/task", self.pid);
-        let tasks = std::fs::read_dir(path)?;
-        for entry in tasks {
-            let entry = entry?;
-            let filename = entry.file_name();
-            let thread = match filename.to_str() {
-                Some(thread) => thread,
-                None => continue,
-            };
+    /// Suspends the process using the given method, keeping it stopped while the returned lock
+    /// is alive. `lock()` is the same as `lock_with(LockMethod::Ptrace)`.
+    pub fn lock_with(&self, method: LockMethod) -> Result<Lock, Error> {
+        let lock = match method {
+            LockMethod::Ptrace => self.ptrace_lock(),
+            LockMethod::Signal => self.lock_with_signal(),
+            LockMethod::Freezer => self.lock_with_freezer(),
+        };
+        lock.context(|| ErrorContext::new("locking process").pid(self.pid))
+    }
 
-            if let Ok(threadid) = thread.parse::<i32>() {
-                ret.push(Thread {
-                    tid: nix::unistd::Pid::from_raw(threadid),
-                });
+    fn lock_with_freezer(&self) -> Result<Lock, Error> {
+        self.check_not_zombie()?;
+        let locked_at = Instant::now();
+        let freezer = freezer::Freezer::new(self.pid, STOP_TIMEOUT)?;
+        Ok(Lock {
+            locks: Vec::new(),
+            continue_on_drop: false,
+            freezer: Some(freezer),
+            pid: self.pid,
+            locked_at,
+        })
+    }
+
+    fn lock_with_signal(&self) -> Result<Lock, Error> {
+        self.check_not_zombie()?;
+        // if the process was already stopped by someone else, leave it stopped when we're done
+        let stat = std::fs::read(format!("/proc/{}/stat", self.pid))?;
+        let already_stopped = matches!(get_active_status(&stat), Some(b'T'));
+
+        let locked_at = Instant::now();
+        self.signal(libc::SIGSTOP)?;
+        let lock = Lock {
+            locks: Vec::new(),
+            continue_on_drop: !already_stopped,
+            freezer: None,
+            pid: self.pid,
+            locked_at,
+        };
+
+        // SIGSTOP is delivered asynchronously, so wait for every thread to actually stop.
+        // If this fails the lock is dropped, which sends SIGCONT to resume the process again
+        let start = Instant::now();
+        loop {
+            let mut running = 0;
+            for thread in self.threads()? {
+                match thread.active_status() {
+                    // 't' is a stop from another tracer, which is also a stop for our purposes
+                    Ok(b'T' | b't' | b'Z' | b'X') => {}
+                    Ok(_) => running += 1,
+                    // the thread has exited
+                    Err(_) => {}
+                }
+            }
+            if running == 0 {
+                break;
             }
+            if start.elapsed() > STOP_TIMEOUT {
+                return Err(Error::Other(format!(
+                    "Timed out waiting for {} threads of process {} to stop",
+                    running, self.pid
+                )));
+            }
+            std::thread::sleep(Duration::from_micros(100));
         }
-        Ok(ret)
+
+        Ok(lock)
+    }
+
+    /// Locks a subset of the threads of this process.
+    ///
+    /// Threads are always locked in ascending order of thread id, so that callers locking
+    /// overlapping sets of threads can't deadlock against each other. Threads that exit before
+    /// they can be locked are skipped, and if any other error occurs the threads that were
+    /// already locked are released before returning. Like `lock`, this fails if no thread
+    /// could be locked, including when `threads` is empty.
+    pub fn lock_threads(&self, threads: &[Thread]) -> Result<Lock, Error> {
+        self.check_not_zombie()?;
+        if threads.is_empty() {
+            return Err(Error::Other("No threads to lock".to_string()));
+        }
+        if self.access == ProcessAccess::ReadOnly {
+            return Ok(self.best_effort_lock());
+        }
+        let mut threads = threads.to_vec();
+        threads.sort_by_key(|thread| thread.tid.as_raw());
+        threads.dedup();
+
+        let locked_at = Instant::now();
+        let mut locks = Vec::with_capacity(threads.len());
+        for thread in threads {
+            if let Some(lock) = thread.try_lock()? {
+                locks.push(lock);
+            }
+        }
+        if locks.is_empty() {
+            return Err(Error::Other("All threads failed to lock".to_string()));
+        }
+
+        Ok(Lock {
+            locks,
+            continue_on_drop: false,
+            freezer: None,
+            pid: self.pid,
+            locked_at,
+        })
+    }
+
+    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
+        self.iter_threads()?.collect()
+    }
+
+    /// Returns an iterator over the threads of the process, which reads /proc/<pid>/task as it
+    /// goes instead of listing every thread up front. This is cheaper than `threads` for
+    /// processes with many threads, when only some of them are needed.
+    pub fn iter_threads(&self) -> Result<ThreadIter<'_>, Error> {
+        let path = format!("/proc/{}/task", self.pid);
+        Ok(ThreadIter {
+            process: self,
+            tasks: std::fs::read_dir(path)
+                .context(|| ErrorContext::new("listing threads").pid(self.pid))?,
+        })
     }
 
     pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
@@ -147,19 +531,91 @@ impl Process {
 
     #[cfg(use_libunwind)]
     pub fn unwinder(&self) -> Result<Unwinder, Error> {
+        // libunwind reads the registers of each thread with ptrace
+        if self.access == ProcessAccess::ReadOnly {
+            return Err(Error::Other(format!(
+                "Process {} is read only, and can't be unwound with libunwind",
+                self.pid
+            )));
+        }
         Unwinder::new()
     }
 
+    /// Returns an unwinder for stacks copied with `Thread::snapshot`, which lets threads be
+    /// resumed before they are unwound
+    #[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn snapshot_unwinder(&self) -> Result<SnapshotUnwinder, Error> {
+        SnapshotUnwinder::new(self.pid)
+    }
+
+    /// Returns a collector that copies the stacks of every thread at once, and unwinds the
+    /// copies on `workers` threads after the process has been resumed
+    #[cfg(all(use_libunwind, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn stack_collector(&self, workers: usize) -> Result<StackCollector, Error> {
+        StackCollector::new(self.pid, workers)
+    }
+
     #[cfg(use_libunwind)]
     pub fn symbolicator(&self) -> Result<Symbolicator, Error> {
         Symbolicator::new(self.pid)
     }
+
+    /// Returns a symbolicator that loads the symbols of each binary as configured by `loading`
+    #[cfg(use_libunwind)]
+    pub fn symbolicator_with(&self, loading: SymbolLoading) -> Result<Symbolicator, Error> {
+        Symbolicator::with_loading(self.pid, loading)
+    }
 }
 
 impl super::ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
-        let handle: ProcessHandle = self.pid.try_into()?;
-        Ok(handle.copy_address(addr, buf)?)
+        copy_memory(self.pid, addr, buf).map_err(|e| {
+            // zombies have already released their memory, which is reported as a generic error
+            let error = if self.is_zombie() {
+                Error::ZombieProcess(self.pid)
+            } else {
+                e.into()
+            };
+            error.with_context(
+                ErrorContext::new("reading memory")
+                    .pid(self.pid)
+                    .address(addr, buf.len()),
+            )
+        })
+    }
+}
+
+/// Reads memory from a process with process_vm_readv, or through /proc/<pid>/mem on kernels
+/// where that isn't available
+fn copy_memory(pid: Pid, addr: usize, buf: &mut [u8]) -> std::io::Result<()> {
+    if platform_info().process_vm_readv {
+        let handle: ProcessHandle = pid.try_into()?;
+        return handle.copy_address(addr, buf);
+    }
+    use std::os::unix::fs::FileExt;
+    File::open(format!("/proc/{}/mem", pid))?.read_exact_at(buf, addr as u64)
+}
+
+/// Iterates over the threads of a process, as returned by `Process::iter_threads`
+pub struct ThreadIter<'a> {
+    process: &'a Process,
+    tasks: std::fs::ReadDir,
+}
+
+impl Iterator for ThreadIter<'_> {
+    type Item = Result<Thread, Error>;
+
+    fn next(&mut self) -> Option<Result<Thread, Error>> {
+        loop {
+            let entry = match self.tasks.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let filename = entry.file_name();
+            if let Some(threadid) = filename.to_str().and_then(|tid| tid.parse::<i32>().ok()) {
+                return Some(Ok(self.process.thread(threadid)));
+            }
+        }
     }
 }
 
@@ -167,46 +623,129 @@ impl Thread {
     pub fn new(threadid: i32) -> Result<Self, Error> {
         Ok(Self {
             tid: nix::unistd::Pid::from_raw(threadid),
+            read_only: false,
         })
     }
 
+    /// Stops this thread by attaching to it with ptrace, until the returned lock is dropped.
+    /// For threads of a process opened with `ProcessAccess::ReadOnly` this doesn't stop the
+    /// thread.
     pub fn lock(&self) -> Result<ThreadLock, Error> {
+        if self.read_only {
+            return Ok(ThreadLock {
+                tid: self.tid,
+                stopped_at: Instant::now(),
+                attached: false,
+                was_active: read_active_status(self.tid.as_raw())
+                    .ok()
+                    .map(|s| s == b'R'),
+            });
+        }
         ThreadLock::new(self.tid)
+            .context(|| ErrorContext::new("locking thread").tid(self.tid.as_raw()))
+    }
+
+    /// Returns an error if this thread can't be ptraced
+    pub(super) fn check_not_read_only(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::Other(format!(
+                "Thread {} belongs to a read only process, and can't be accessed with ptrace",
+                self.tid
+            )));
+        }
+        Ok(())
+    }
+
+    /// Locks this thread, returning None if it exited before it could be locked
+    fn try_lock(&self) -> Result<Option<ThreadLock>, Error> {
+        let error = match self.lock() {
+            Ok(lock) => return Ok(Some(lock)),
+            Err(e) => e,
+        };
+        match error.root() {
+            Error::ThreadExited(_) => {
+                // the thread probably exited before we could get a lock
+                Ok(None)
+            }
+            Error::PermissionDenied { .. } if !self.exists() => {
+                // The thread was probably in the "exiting" state, which returns
+                // EPERM to the caller. This thread is dead, we can not ptrace
+                // it and we should just ignore it.
+                // See https://elixir.bootlin.com/linux/v6.15.3/source/kernel/ptrace.c#L458
+                Ok(None)
+            }
+            // We likely really have no permission, propagate the error
+            _ => Err(error),
+        }
     }
 
     pub fn id(&self) -> Result<Tid, Error> {
         Ok(self.tid.as_raw())
     }
 
+    /// The tid and start time of the thread, since tids are reused after a thread exits
+    pub(crate) fn thread_key(&self) -> Result<crate::threads::ThreadKey, Error> {
+        let start_time = get_start_time(self.tid.as_raw()).map_err(|e| {
+            if self.exists() {
+                e
+            } else {
+                Error::ThreadExited(self.tid.as_raw())
+            }
+        })?;
+        Ok((self.tid.as_raw() as u64, start_time))
+    }
+
+    /// Returns the tid of this thread, for passing to `nix::sys::ptrace` calls this crate
+    /// doesn't wrap. The thread needs to be locked for most ptrace calls to succeed.
+    pub fn tid(&self) -> nix::unistd::Pid {
+        self.tid
+    }
+
+    /// Returns the id of the thread that is ptrace attached to this thread, if any
+    pub fn tracer(&self) -> Result<Option<Tid>, Error> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.tid))?;
+        let tracer = get_tracer_status(&status)
+            .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/status", self.tid)))?;
+        Ok(if tracer == 0 { None } else { Some(tracer) })
+    }
+
+    /// Returns true if this thread is ptrace attached to a thread in this process, like when
+    /// it has been locked with `lock`
+    pub fn is_traced(&self) -> Result<bool, Error> {
+        Ok(match self.tracer()? {
+            Some(tracer) => std::path::Path::new(&format!("/proc/self/task/{}", tracer)).exists(),
+            None => false,
+        })
+    }
+
     /// True if this thread still exists and has not yet exited.
     fn exists(&self) -> bool {
         std::path::Path::new(&format!("/proc/{}/stat", self.tid)).exists()
     }
 
+    /// Returns true if the thread is running. Locking a thread stops it, so this is always
+    /// false while the thread is locked - use `ThreadLock::was_active` for its state from
+    /// right before it was stopped.
     pub fn active(&self) -> Result<bool, Error> {
-        let mut file = File::open(format!("/proc/{}/stat", self.tid))?;
-        let mut buf = [0u8; 512];
-        let _ = file.read(&mut buf)?;
-        match get_active_status(&buf) {
-            Some(stat) => Ok(stat == b'R'),
-            None => Err(Error::Other(format!(
-                "Failed to parse /proc/{}/stat",
-                self.tid
-            ))),
-        }
+        Ok(self.active_status()? == b'R')
     }
 
     pub fn active_status(&self) -> Result<u8, Error> {
-        let mut file = File::open(format!("/proc/{}/stat", self.tid))?;
-        let mut buf = [0u8; 512];
-        let _ = file.read(&mut buf)?;
-        match get_active_status(&buf) {
-            Some(stat) => Ok(stat),
-            None => Err(Error::Other(format!(
-                "Failed to parse /proc/{}/stat",
-                self.tid
-            ))),
-        }
+        read_active_status(self.tid.as_raw())
+    }
+
+    /// Returns the CPU time (user and system) the thread has used. This is read in nanoseconds
+    /// from /proc/<tid>/schedstat, or on kernels without scheduler statistics in clock ticks
+    /// (usually 10ms) from /proc/<tid>/stat.
+    pub fn cpu_time(&self) -> Result<Duration, Error> {
+        read_cpu_time(self.tid.as_raw())
+    }
+
+    /// Returns true if this thread is stopped, either by a signal like SIGSTOP or by a
+    /// debugger that has it ptrace stopped. A thread that stays stopped while nothing in this
+    /// process has it locked usually means that another debugger is attached.
+    pub fn is_stopped(&self) -> Result<bool, Error> {
+        Ok(matches!(self.active_status()?, b't' | b'T'))
     }
 
     pub fn thread_name(&self) -> Result<Option<String>, Error> {
@@ -241,15 +780,75 @@ fn get_process_tree() -> Result<HashMap<Pid, Pid>, Error> {
     Ok(ret)
 }
 
-/// This locks a target process using ptrace, and prevents it from running while this
-/// struct is alive
+/// How long to wait for all threads to stop after sending SIGSTOP or freezing the cgroup
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The ways a process can be suspended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockMethod {
+    /// Attach to every thread with ptrace. This is the default, and is required for anything
+    /// that reads registers, like unwinding
+    #[default]
+    Ptrace,
+    /// Send SIGSTOP to the process and SIGCONT when it is released. This works when ptrace is
+    /// prohibited, for instance by YAMA or by another debugger being attached, but is visible
+    /// to the parent of the target and only allows for memory reads.
+    Signal,
+    /// Freeze the cgroup v2 hierarchy that contains the process. This pauses every process in
+    /// the cgroup at once without sending any signals, which makes it possible to snapshot a
+    /// multi-process application atomically. Like `Signal` this only allows for memory reads.
+    Freezer,
+}
+
+/// Prevents a target process from running while this struct is alive. Depending on the
+/// `LockMethod` it was created with, this holds a ptrace attachment to each thread, a SIGSTOP
+/// that is continued on drop or a frozen cgroup. Locks for processes opened with
+/// `ProcessAccess::ReadOnly` don't stop anything.
 pub struct Lock {
-    #[allow(dead_code)]
     locks: Vec<ThreadLock>,
+    continue_on_drop: bool,
+    freezer: Option<freezer::Freezer>,
+    pid: Pid,
+    locked_at: Instant,
+}
+
+impl Lock {
+    /// How long the process has been held stopped by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.locked_at.elapsed()
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // best-effort locks, and locks on threads that all exited, didn't pause anything
+        let paused = !self.locks.is_empty() || self.continue_on_drop || self.freezer.is_some();
+        // release the threads in the reverse of the order they were locked in, and before
+        // measuring so that the pause time includes detaching
+        while let Some(lock) = self.locks.pop() {
+            drop(lock);
+        }
+        self.freezer = None;
+        if self.continue_on_drop {
+            if let Err(e) = nix::sys::signal::kill(
+                nix::unistd::Pid::from_raw(self.pid),
+                nix::sys::signal::Signal::SIGCONT,
+            ) {
+                warn!("Failed to continue process {} : {}", self.pid, e);
+            }
+        }
+        if paused {
+            crate::pause_metrics().record_process(self.pid, self.locked_at.elapsed());
+        }
+    }
 }
 
 pub struct ThreadLock {
     tid: nix::unistd::Pid,
+    stopped_at: Instant,
+    /// False for the best-effort locks of read only threads
+    attached: bool,
+    was_active: Option<bool>,
 }
 
 impl ThreadLock {
@@ -260,24 +859,64 @@ impl ThreadLock {
             // Without this, it *appears* that the tracee can get stuck in the
             // zombie state and our `waitpid` below will just hang.
             ptrace::Options::PTRACE_O_TRACEEXIT,
-        )?;
+        )
+        .map_err(|e| ptrace_error(tid, e))?;
 
         // Pause the process using `interrupt`.  Unlike `attach`, this doesn't
         // use `SIGSTOP` or cause execve to send a `SIGTRAP` and so avoids races
         // with signals from foreign processes.
+        let stopped_at = Instant::now();
         if let Err(e) = ptrace::interrupt(tid) {
             if let Err(e) = ptrace::detach(tid, None) {
                 warn!("Failed to detach from thread {} for cleanup: {}", tid, e);
             }
-            return Err(Error::NixError(e));
+            return Err(ptrace_error(tid, e));
+        }
+
+        // Verify that the thread has stopped. If anything goes wrong here we have to detach
+        // again, otherwise the thread would be left stopped with nothing to resume it
+        if let Err(e) = Self::wait_for_stop(tid) {
+            if let Err(e) = ptrace::detach(tid, None) {
+                debug!("Failed to detach from thread {} for cleanup: {}", tid, e);
+            }
+            return Err(e);
         }
 
-        // Verify that the thread has stopped.
+        debug!("attached to thread {}", tid);
+        Ok(Self {
+            tid,
+            stopped_at,
+            attached: true,
+            was_active: read_stopped_in_user_code(tid.as_raw()).ok(),
+        })
+    }
+
+    /// Whether the thread was running right before it was stopped by this lock, or None if its
+    /// state couldn't be read. This comes from where the thread was stopped: threads that were
+    /// stopped while running their own code were active, and threads that were stopped inside
+    /// a syscall were blocked in it. Unlike calling `Thread::active` before locking, this can't
+    /// race with the thread changing state before it's stopped.
+    pub fn was_active(&self) -> Option<bool> {
+        self.was_active
+    }
+
+    /// How long the thread has been held stopped by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.stopped_at.elapsed()
+    }
+
+    fn wait_for_stop(tid: nix::unistd::Pid) -> Result<(), Error> {
         loop {
-            match wait::waitpid(
+            let status = match wait::waitpid(
                 tid,
                 Some(wait::WaitPidFlag::WSTOPPED | wait::WaitPidFlag::__WALL),
-            )? {
+            ) {
+                Ok(status) => status,
+                // the thread exited and was reaped before we could wait on it
+                Err(nix::errno::Errno::ECHILD) => return Err(Error::ThreadExited(tid.as_raw())),
+                Err(e) => return Err(e.into()),
+            };
+            match status {
                 // We only really expect to see a `PTRACE_EVENT_STOP`.
                 wait::WaitStatus::PtraceEvent(
                     _,
@@ -286,11 +925,13 @@ impl ThreadLock {
                 ) if event == ptrace::Event::PTRACE_EVENT_STOP as i32
                     || event == ptrace::Event::PTRACE_EVENT_EXIT as i32 =>
                 {
-                    break
+                    return Ok(())
                 }
                 // However, experimentally, it appears we see an exit status when
-                // a process is dying.
-                wait::WaitStatus::Exited(_, _) => break,
+                // a process is dying. The thread is gone, so there is nothing to hold.
+                wait::WaitStatus::Exited(_, _) | wait::WaitStatus::Signaled(_, _, _) => {
+                    return Err(Error::ThreadExited(tid.as_raw()))
+                }
                 // Just re-injecting other signals that aren't ours.
                 wait::WaitStatus::Stopped(_, sig) => {
                     info!("reinjecting non-SIGSTOP signal {} to {}", sig, tid);
@@ -305,18 +946,19 @@ impl ThreadLock {
                 }
             }
         }
-
-        debug!("attached to thread {}", tid);
-        Ok(Self { tid })
     }
 }
 
 impl Drop for ThreadLock {
     fn drop(&mut self) {
+        if !self.attached {
+            return;
+        }
         if let Err(e) = ptrace::detach(self.tid, None) {
             warn!("Failed to detach from thread {} : {}", self.tid, e);
         }
         debug!("detached from thread {}", self.tid);
+        crate::pause_metrics().record_thread(self.tid.as_raw(), self.stopped_at.elapsed());
     }
 }
 
@@ -370,6 +1012,42 @@ fn get_active_status(stat: &[u8]) -> Option<u8> {
     Some(caps.get(1)?.as_bytes()[0])
 }
 
+/// Returns the state field of /proc/<tid>/stat, like b'R' for running or b'S' for sleeping
+fn read_active_status(tid: Tid) -> Result<u8, Error> {
+    let mut file = File::open(format!("/proc/{}/stat", tid))?;
+    let mut buf = [0u8; 512];
+    let _ = file.read(&mut buf)?;
+    get_active_status(&buf)
+        .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/stat", tid)))
+}
+
+/// Returns whether a stopped thread was stopped while running user code, rather than while
+/// blocked in a syscall, from /proc/<tid>/syscall
+fn read_stopped_in_user_code(tid: Tid) -> Result<bool, Error> {
+    let syscall = std::fs::read_to_string(format!("/proc/{}/syscall", tid))?;
+    get_stopped_in_user_code(&syscall)
+        .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/syscall", tid)))
+}
+
+/// Parses /proc/<tid>/syscall, which starts with the number of the syscall the thread is
+/// blocked in, -1 if it is in user code, or "running" if it isn't stopped
+fn get_stopped_in_user_code(syscall: &str) -> Option<bool> {
+    match syscall.split_whitespace().next()? {
+        "running" | "-1" => Some(true),
+        number => number.parse::<u64>().ok().map(|_| false),
+    }
+}
+
+fn read_cpu_time(tid: Tid) -> Result<Duration, Error> {
+    let schedstat = std::fs::read_to_string(format!("/proc/{}/schedstat", tid));
+    if let Some(time) = schedstat.ok().and_then(|s| get_cpu_time_schedstat(&s)) {
+        return Ok(time);
+    }
+    let stat = std::fs::read(format!("/proc/{}/stat", tid))?;
+    get_cpu_time_status(&stat)
+        .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/stat", tid)))
+}
+
 fn get_parent_pid(pid: Pid) -> Result<Pid, Error> {
     let mut file = File::open(format!("/proc/{}/stat", pid))?;
     let mut buf = [0u8; 512];
@@ -377,6 +1055,93 @@ fn get_parent_pid(pid: Pid) -> Result<Pid, Error> {
     get_ppid_status(&buf).ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/stat", pid)))
 }
 
+fn get_start_time(pid: Pid) -> Result<u64, Error> {
+    let stat = std::fs::read(format!("/proc/{}/stat", pid))?;
+    get_start_time_status(&stat)
+        .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/stat", pid)))
+}
+
+fn get_stat_id(pid: Pid, index: usize) -> Result<Pid, Error> {
+    let stat = std::fs::read(format!("/proc/{}/stat", pid))?;
+    get_stat_field(&stat, index)
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| Error::Other(format!("Failed to parse /proc/{}/stat", pid)))
+}
+
+/// Returns a field of /proc/<pid>/stat, counting from the state field that follows the comm
+fn get_stat_field(stat: &[u8], index: usize) -> Option<&str> {
+    // the comm field can contain spaces and ')', so split on the last ')' in the line
+    let end = stat.iter().rposition(|b| *b == b')')?;
+    let fields = std::str::from_utf8(&stat[end + 1..]).ok()?;
+    fields.split_whitespace().nth(index)
+}
+
+/// Converts a time in clock ticks since boot, like the start time in /proc/<pid>/stat, to the
+/// time of day
+fn boot_relative_time(ticks: u64) -> Option<SystemTime> {
+    lazy_static! {
+        static ref BOOT_TIME: Option<u64> = std::fs::read_to_string("/proc/stat")
+            .ok()
+            .and_then(|stat| get_boot_time(&stat));
+    }
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs((*BOOT_TIME)?) + ticks_to_duration(ticks)?)
+}
+
+/// Converts a number of clock ticks, the unit of the times in /proc/<pid>/stat, to a duration
+fn ticks_to_duration(ticks: u64) -> Option<Duration> {
+    let ticks_per_second = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) })
+        .ok()
+        .filter(|&ticks| ticks > 0)?;
+    Some(
+        Duration::from_secs(ticks / ticks_per_second)
+            + Duration::from_nanos(ticks % ticks_per_second * 1_000_000_000 / ticks_per_second),
+    )
+}
+
+/// Returns the btime field (when the system booted, in seconds since the epoch) from the
+/// contents of /proc/stat
+fn get_boot_time(stat: &str) -> Option<u64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn get_cpu_time_status(stat: &[u8]) -> Option<Duration> {
+    // utime and stime are the 14th and 15th fields, and the 12th and 13th after the comm field
+    let utime: u64 = get_stat_field(stat, 11)?.parse().ok()?;
+    let stime: u64 = get_stat_field(stat, 12)?.parse().ok()?;
+    ticks_to_duration(utime + stime)
+}
+
+/// Returns the time spent on the cpu from /proc/<tid>/schedstat, which is its first field
+fn get_cpu_time_schedstat(schedstat: &str) -> Option<Duration> {
+    let nanos = schedstat.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_nanos(nanos))
+}
+
+fn get_start_time_status(stat: &[u8]) -> Option<u64> {
+    // the start time is the 22nd field, and the 20th after the comm field
+    get_stat_field(stat, 19)?.parse().ok()
+}
+
+/// Set in the flags of /proc/<pid>/stat for kernel threads
+const PF_KTHREAD: u64 = 0x00200000;
+
+fn get_kernel_thread_status(stat: &[u8]) -> Option<bool> {
+    // the flags are the 9th field, and the 7th after the comm field
+    let flags: u64 = get_stat_field(stat, 6)?.parse().ok()?;
+    Some(flags & PF_KTHREAD != 0)
+}
+
+fn is_kernel_thread(pid: Pid) -> bool {
+    match std::fs::read(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => get_kernel_thread_status(&stat).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
 fn get_ppid_status(stat: &[u8]) -> Option<Pid> {
     lazy_static! {
         static ref RE: regex::bytes::Regex =
@@ -389,6 +1154,75 @@ fn get_ppid_status(stat: &[u8]) -> Option<Pid> {
         .ok()
 }
 
+/// Turns the errno from a failed ptrace call on a thread into an error that says what usually
+/// causes it, and how to fix it
+fn ptrace_error(tid: nix::unistd::Pid, errno: nix::errno::Errno) -> Error {
+    use nix::errno::Errno;
+
+    if errno == Errno::ESRCH {
+        return Error::ThreadExited(tid.as_raw());
+    }
+    let status = std::fs::read_to_string(format!("/proc/{}/status", tid)).unwrap_or_default();
+    let tracer = get_tracer_status(&status).filter(|&tracer| tracer != 0);
+    // the thread being traced by us isn't another debugger getting in the way
+    let traced_by_us = tracer.is_some_and(|tracer| {
+        std::path::Path::new(&format!("/proc/self/task/{}", tracer)).exists()
+    });
+    match errno {
+        _ if traced_by_us => Error::NixError(errno),
+        Errno::EBUSY => Error::AlreadyTraced {
+            tid: tid.as_raw(),
+            tracer,
+        },
+        Errno::EPERM if tracer.is_some() => Error::AlreadyTraced {
+            tid: tid.as_raw(),
+            tracer,
+        },
+        Errno::EPERM => Error::PermissionDenied {
+            pid: get_tgid_status(&status).unwrap_or(tid.as_raw()),
+            hint: ptrace_hint(
+                &std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope").unwrap_or_default(),
+            ),
+        },
+        errno => Error::NixError(errno),
+    }
+}
+
+/// Explains why ptrace was refused, given the contents of /proc/sys/kernel/yama/ptrace_scope
+fn ptrace_hint(ptrace_scope: &str) -> String {
+    match ptrace_scope.trim() {
+        "1" => "kernel.yama.ptrace_scope is 1, which only lets processes trace their own \
+                children. Run as root or with CAP_SYS_PTRACE, or set it to 0"
+            .to_string(),
+        "2" => "kernel.yama.ptrace_scope is 2, which only lets processes with CAP_SYS_PTRACE \
+                use ptrace. Run as root or with CAP_SYS_PTRACE"
+            .to_string(),
+        "3" => {
+            "kernel.yama.ptrace_scope is 3, which disables ptrace until the next reboot".to_string()
+        }
+        _ => "The process belongs to another user, or isn't dumpable. Run as root or with \
+              CAP_SYS_PTRACE (in a container, add --cap-add SYS_PTRACE)"
+            .to_string(),
+    }
+}
+
+/// Returns the Tgid field (the pid of the process) from the contents of /proc/<tid>/status
+fn get_tgid_status(status: &str) -> Option<Pid> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+}
+
+/// Returns the TracerPid field from the contents of /proc/<tid>/status, which is 0 if the
+/// thread isn't being traced
+fn get_tracer_status(status: &str) -> Option<Tid> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))
+        .and_then(|tracer| tracer.trim().parse().ok())
+}
+
 #[test]
 fn test_parse_active_stat() {
     assert_eq!(get_active_status(b"1234 (bash) S 1233"), Some(b'S'));
@@ -429,3 +1263,197 @@ fn test_parse_ppid_stat() {
     // Invalid UTF-8 and whitespace:
     assert_eq!(get_ppid_status(b"83 (\xc3\x28)) S ) R 1 19"), Some(1));
 }
+
+#[test]
+fn test_parse_start_time_stat() {
+    let stat =
+        b"13447 (cat) R 13401 13447 13401 0 -1 4194304 82 0 0 0 0 0 0 0 20 0 1 0 161946 2703360";
+    assert_eq!(get_start_time_status(stat), Some(161946));
+    let stat = b"83 (Thread.(<la mbda>)) S 1 19 19 0 -1 4194304 82 0 0 0 0 0 0 0 20 0 1 0 42 0";
+    assert_eq!(get_start_time_status(stat), Some(42));
+    assert_eq!(get_start_time_status(b"1234 (bash) S 1233"), None);
+    assert_eq!(get_start_time_status(b"1234"), None);
+}
+
+#[test]
+fn test_parse_cpu_time_stat() {
+    let stat =
+        b"13447 (cat) R 13401 13447 13401 0 -1 4194304 82 0 0 0 150 50 0 0 20 0 1 0 161946 2703360";
+    assert_eq!(get_cpu_time_status(stat), ticks_to_duration(200));
+    assert_eq!(get_cpu_time_status(b"1234 (bash) S 1233"), None);
+    assert_eq!(
+        get_cpu_time_schedstat("123456789 2000 15\n"),
+        Some(Duration::from_nanos(123456789))
+    );
+    assert_eq!(get_cpu_time_schedstat(""), None);
+
+    // the time spent spinning shows up for this thread
+    let thread = Thread::new(nix::unistd::gettid().as_raw()).unwrap();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(50) {
+        std::hint::black_box(start.elapsed());
+    }
+    assert!(thread.cpu_time().unwrap() >= Duration::from_millis(10));
+}
+
+#[test]
+fn test_parse_kernel_thread_stat() {
+    let stat = b"2 (kthreadd) S 0 0 0 0 -1 2129984 0 0 0 0 0 0 0 0 20 0 1 0 7 0";
+    assert_eq!(get_kernel_thread_status(stat), Some(true));
+    let stat = b"13447 (cat) R 13401 13447 13401 0 -1 4194304 82 0 0 0 0 0 0 0 20 0 1 0 161946";
+    assert_eq!(get_kernel_thread_status(stat), Some(false));
+    assert_eq!(get_kernel_thread_status(b"1234 (bash) S 1233"), None);
+}
+
+#[test]
+fn test_parse_tracer_status() {
+    let status = "Name:\tcat\nState:\tt (tracing stop)\nTgid:\t13447\nTracerPid:\t13401\n";
+    assert_eq!(get_tracer_status(status), Some(13401));
+    assert_eq!(get_tgid_status(status), Some(13447));
+    assert_eq!(get_tracer_status("Name:\tcat\nTracerPid:\t0\n"), Some(0));
+    assert_eq!(get_tracer_status("Name:\tcat\n"), None);
+}
+
+#[test]
+fn test_boot_time() {
+    let stat = "cpu  1 2 3 4\nintr 5\nctxt 6\nbtime 1760000000\nprocesses 7\n";
+    assert_eq!(get_boot_time(stat), Some(1760000000));
+    assert_eq!(get_boot_time("cpu  1 2 3 4\n"), None);
+}
+
+#[test]
+fn test_ptrace_error() {
+    use nix::errno::Errno;
+
+    let tid = nix::unistd::Pid::from_raw(i32::MAX);
+    assert!(matches!(
+        ptrace_error(tid, Errno::ESRCH),
+        Error::ThreadExited(i32::MAX)
+    ));
+    assert!(matches!(
+        ptrace_error(tid, Errno::EBUSY),
+        Error::AlreadyTraced { tracer: None, .. }
+    ));
+    match ptrace_error(tid, Errno::EPERM) {
+        Error::PermissionDenied { pid, hint } => {
+            assert_eq!(pid, i32::MAX);
+            assert!(!hint.is_empty());
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+    assert!(matches!(
+        ptrace_error(tid, Errno::EIO),
+        Error::NixError(Errno::EIO)
+    ));
+
+    // a thread we have locked ourselves isn't reported as traced by another debugger
+    let child = crate::tests::TestChild::sleep();
+    let process = Process::new(child.pid()).unwrap();
+    let _lock = process.lock().unwrap();
+    let tid = nix::unistd::Pid::from_raw(child.pid());
+    assert!(matches!(
+        ptrace_error(tid, Errno::EPERM),
+        Error::NixError(Errno::EPERM)
+    ));
+
+    assert!(ptrace_hint("1\n").contains("own children"));
+    assert!(ptrace_hint("3\n").contains("disables ptrace"));
+    assert!(ptrace_hint("").contains("CAP_SYS_PTRACE"));
+}
+
+#[test]
+fn test_thread_is_stopped() {
+    let child = crate::tests::TestChild::sleep();
+    let process = Process::new(child.pid()).unwrap();
+    let thread = &process.threads().unwrap()[0];
+    assert!(!thread.is_stopped().unwrap());
+    {
+        let _lock = process.lock().unwrap();
+        assert!(thread.is_stopped().unwrap());
+    }
+    assert!(!thread.is_stopped().unwrap());
+}
+
+#[test]
+fn test_lock_threads() {
+    let child = crate::tests::TestChild::sleep();
+    let process = Process::new(child.pid()).unwrap();
+    assert!(process.lock_threads(&[]).is_err());
+
+    // locking the same thread twice only locks it once
+    let thread = process.threads().unwrap().remove(0);
+    {
+        let lock = process.lock_threads(&[thread, thread]).unwrap();
+        assert_eq!(lock.locks.len(), 1);
+        assert!(thread.is_stopped().unwrap());
+    }
+    assert!(!thread.is_stopped().unwrap());
+
+    // threads that have exited are skipped, and locking nothing but those fails
+    let mut exited = crate::tests::TestChild::spawn(&mut std::process::Command::new("true"));
+    let exited_tid = exited.pid();
+    exited.wait().unwrap();
+    let exited = process.thread(exited_tid);
+    let lock = process.lock_threads(&[exited, thread]).unwrap();
+    assert_eq!(lock.locks.len(), 1);
+    drop(lock);
+    assert!(process.lock_threads(&[exited]).is_err());
+}
+
+#[test]
+fn test_stopped_in_user_code() {
+    assert_eq!(
+        get_stopped_in_user_code("-1 0x7ffd1a827a28 0x55d0c2b1c2ad\n"),
+        Some(true)
+    );
+    assert_eq!(
+        get_stopped_in_user_code("230 0x0 0x0 0x7ffe 0x7ffe 0x0 0x0 0x7ffe 0x7fe4\n"),
+        Some(false)
+    );
+    assert_eq!(get_stopped_in_user_code("running\n"), Some(true));
+    assert_eq!(get_stopped_in_user_code(""), None);
+}
+
+#[test]
+fn test_lock_was_active() {
+    let idle = crate::tests::TestChild::sleep();
+    let busy = crate::tests::TestChild::spawn(
+        std::process::Command::new("sh").args(["-c", "while :; do :; done"]),
+    );
+    std::thread::sleep(Duration::from_millis(50));
+
+    for (child, active) in [(idle, false), (busy, true)] {
+        let process = Process::new(child.pid()).unwrap();
+        let thread = &process.threads().unwrap()[0];
+        let lock = thread.lock().unwrap();
+        assert_eq!(lock.was_active(), Some(active));
+        // the thread never looks active while it's locked
+        assert!(!thread.active().unwrap());
+    }
+}
+
+#[test]
+fn test_process_group() {
+    let process = Process::new(std::process::id() as Pid).unwrap();
+    assert_eq!(process.process_group().unwrap(), unsafe {
+        libc::getpgid(0)
+    });
+    assert_eq!(process.session_id().unwrap(), unsafe { libc::getsid(0) });
+
+    // the comm field can contain spaces and ')'
+    let stat = b"13447 (a) b) R 13401 13447 13390 0 -1 4194304 82 0 0 0 0 0 0 0 20 0 1 0 161946";
+    assert_eq!(get_stat_field(stat, 2), Some("13447"));
+    assert_eq!(get_stat_field(stat, 3), Some("13390"));
+}
+
+#[test]
+fn test_read_error_context() {
+    use crate::ProcessMemory;
+
+    let process = Process::new(std::process::id() as Pid).unwrap();
+    let error = process.copy(8, 16).unwrap_err();
+    let context = error.context().unwrap();
+    assert_eq!(context.pid, Some(process.pid));
+    assert_eq!(context.address, Some(8..24));
+    assert!(error.to_string().contains("at 0x8..0x18"));
+}
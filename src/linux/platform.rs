@@ -0,0 +1,144 @@
+use std::sync::OnceLock;
+
+use log::info;
+
+/// The features of the running kernel that this crate depends on, probed once at first use so
+/// that code paths can be picked up front instead of failing at first use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformInfo {
+    /// The kernel release from uname, like (6, 1, 0), or (0, 0, 0) if it couldn't be read
+    pub kernel_version: (u32, u32, u32),
+    /// process_vm_readv can be used to read memory. This was added in Linux 3.2, and can also
+    /// be blocked by seccomp, in which case memory is read through /proc/<pid>/mem instead.
+    pub process_vm_readv: bool,
+    /// PTRACE_GETREGSET is supported, which was added in Linux 2.6.34. Otherwise the registers
+    /// are read with PTRACE_GETREGS, and only the general purpose registers are available.
+    pub ptrace_getregset: bool,
+    /// pidfd_open is available, which was added in Linux 5.3 and is needed for
+    /// `Process::exit_notification`
+    pub pidfd: bool,
+    /// The CPU supports pointer authentication, so return addresses saved on the stack can
+    /// have a signature in their upper bits. This is only ever true on aarch64.
+    pub pointer_auth: bool,
+}
+
+impl PlatformInfo {
+    fn probe() -> Self {
+        let kernel_version = kernel_version();
+        let info = Self {
+            kernel_version: kernel_version.unwrap_or_default(),
+            process_vm_readv: probe_process_vm_readv(),
+            // aarch64 never had PTRACE_GETREGS, and got PTRACE_GETREGSET from the start. If
+            // the release can't be read (like in a sandbox without /proc/sys), assume a kernel
+            // from this century rather than one older than 2.6.34
+            ptrace_getregset: cfg!(target_arch = "aarch64")
+                || kernel_version.is_none_or(|version| version >= (2, 6, 34)),
+            pidfd: probe_pidfd(),
+            pointer_auth: probe_pointer_auth(),
+        };
+        info!("probed platform {:?}", info);
+        info
+    }
+}
+
+impl PlatformInfo {
+    /// Removes the pointer authentication signature from a code address, leaving the address
+    /// that was signed. Addresses without a signature are returned as is.
+    pub fn strip_pointer_auth(&self, addr: u64) -> u64 {
+        // user space addresses are limited to 48 bits unless a process explicitly asks for
+        // larger ones with mmap hints, and the signature is stored in the bits above that
+        const ADDRESS_MASK: u64 = (1 << 48) - 1;
+        if self.pointer_auth {
+            addr & ADDRESS_MASK
+        } else {
+            addr
+        }
+    }
+}
+
+/// Returns the features of the running kernel
+pub fn platform_info() -> &'static PlatformInfo {
+    static INFO: OnceLock<PlatformInfo> = OnceLock::new();
+    INFO.get_or_init(PlatformInfo::probe)
+}
+
+fn kernel_version() -> Option<(u32, u32, u32)> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    parse_kernel_version(release.trim())
+}
+
+/// Parses a kernel release like "6.1.0-18-amd64" or "5.15.0"
+fn parse_kernel_version(release: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts
+        .next()
+        .and_then(|patch| patch.parse().ok())
+        .unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/// Reads a value from our own memory with process_vm_readv
+fn probe_process_vm_readv() -> bool {
+    let value = 0x1234_5678u32;
+    let mut copy = 0u32;
+    let local = libc::iovec {
+        iov_base: &mut copy as *mut u32 as *mut libc::c_void,
+        iov_len: size_of::<u32>(),
+    };
+    let remote = libc::iovec {
+        iov_base: &value as *const u32 as *mut libc::c_void,
+        iov_len: size_of::<u32>(),
+    };
+    let ret = unsafe { libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0) };
+    ret == size_of::<u32>() as isize && copy == value
+}
+
+/// Opens a pidfd for our own process
+fn probe_pidfd() -> bool {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) };
+    if fd < 0 {
+        return false;
+    }
+    unsafe { libc::close(fd as libc::c_int) };
+    true
+}
+
+#[cfg(target_arch = "aarch64")]
+fn probe_pointer_auth() -> bool {
+    unsafe { libc::getauxval(libc::AT_HWCAP) & libc::HWCAP_PACA != 0 }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn probe_pointer_auth() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("6.1.0-18-amd64"), Some((6, 1, 0)));
+        assert_eq!(parse_kernel_version("5.15.0"), Some((5, 15, 0)));
+        assert_eq!(parse_kernel_version("4.9"), Some((4, 9, 0)));
+        assert_eq!(parse_kernel_version("6.18.44-fc-v130"), Some((6, 18, 44)));
+        assert_eq!(parse_kernel_version("garbage"), None);
+
+        let info = platform_info();
+        assert!(info.kernel_version >= (2, 6, 0));
+        assert!(info.process_vm_readv);
+
+        let info = PlatformInfo {
+            pointer_auth: true,
+            ..info.clone()
+        };
+        assert_eq!(
+            info.strip_pointer_auth(0x002d_ffff_8a5c_1234),
+            0xffff_8a5c_1234
+        );
+        assert_eq!(info.strip_pointer_auth(0xaaaa_d000_1000), 0xaaaa_d000_1000);
+    }
+}
@@ -0,0 +1,260 @@
+use super::{Process, Thread, Tid};
+use crate::{Error, ProcessMemory};
+
+/// Offset of the `tid` field in glibc's `struct pthread`
+#[cfg(target_arch = "x86_64")]
+const GLIBC_TID_OFFSET: u64 = 0x2d0;
+#[cfg(target_arch = "aarch64")]
+const GLIBC_TID_OFFSET: u64 = 0xd0;
+
+/// Offset of the `tid` field in musl's `struct pthread`
+#[cfg(target_arch = "x86_64")]
+const MUSL_TID_OFFSET: u64 = 48;
+#[cfg(target_arch = "aarch64")]
+const MUSL_TID_OFFSET: u64 = 32;
+
+/// On aarch64 the `struct pthread` is stored directly below the thread pointer, so we search
+/// this far below it to find the start of the structure
+#[cfg(target_arch = "aarch64")]
+const MAX_PTHREAD_SIZE: u64 = 4096;
+
+/// Which libc a `pthread_t` belongs to, detected from the layout of the structure it points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Libc {
+    Glibc,
+    Musl,
+}
+
+impl Libc {
+    fn tid_offset(self) -> u64 {
+        match self {
+            Self::Glibc => GLIBC_TID_OFFSET,
+            Self::Musl => MUSL_TID_OFFSET,
+        }
+    }
+}
+
+impl Thread {
+    /// Returns the thread pointer of this thread (fs_base on x86_64 and tpidr_el0 on
+    /// aarch64), which thread local storage is addressed from. The thread must be locked.
+    #[cfg(target_arch = "x86_64")]
+    pub fn thread_pointer(&self) -> Result<u64, Error> {
+        let regs: libc::user_regs_struct = self.get_regset(libc::NT_PRSTATUS)?;
+        Ok(regs.fs_base)
+    }
+
+    /// Returns the thread pointer of this thread (fs_base on x86_64 and tpidr_el0 on
+    /// aarch64), which thread local storage is addressed from. The thread must be locked.
+    #[cfg(target_arch = "aarch64")]
+    pub fn thread_pointer(&self) -> Result<u64, Error> {
+        const NT_ARM_TLS: libc::c_int = 0x401;
+        self.get_regset::<u64>(NT_ARM_TLS)
+    }
+
+    /// Returns the `pthread_t` of this thread in the target process, as returned by
+    /// `pthread_self`. This supports glibc and musl, and the thread must be locked.
+    pub fn pthread_id(&self, process: &Process) -> Result<u64, Error> {
+        let tp = self.thread_pointer()?;
+        if tp == 0 {
+            return Err(Error::Other(format!(
+                "Thread {} has no thread pointer",
+                self.tid
+            )));
+        }
+        find_pthread(process, tp, self.tid.as_raw())
+    }
+}
+
+/// On x86_64 the thread pointer points at the `struct pthread` itself, which starts with a
+/// pointer to itself in both glibc and musl
+#[cfg(target_arch = "x86_64")]
+fn find_pthread(process: &Process, tp: u64, _tid: Tid) -> Result<u64, Error> {
+    let header: u64 = process.copy_struct(tp as usize)?;
+    if header != tp {
+        return Err(Error::Other(format!(
+            "0x{:016x} doesn't point to a struct pthread",
+            tp
+        )));
+    }
+    Ok(tp)
+}
+
+/// On aarch64 the thread pointer points just past the `struct pthread`, whose size varies
+/// between libc versions. Search backwards for something that looks like the start of it.
+#[cfg(target_arch = "aarch64")]
+fn find_pthread(process: &Process, tp: u64, tid: Tid) -> Result<u64, Error> {
+    let base = tp.saturating_sub(MAX_PTHREAD_SIZE);
+    let data = process.copy(base as usize, (tp - base) as usize)?;
+    let read_u64 = |addr: u64| -> Option<u64> {
+        let offset = addr.checked_sub(base)? as usize;
+        let bytes = data.get(offset..offset + 8)?;
+        Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+    };
+
+    // try the closest candidates first, since the struct ends right at the thread pointer
+    for candidate in (1..=(tp - base) / 8).map(|i| (tp & !7) - i * 8) {
+        // musl's struct pthread starts with a pointer to itself
+        if read_u64(candidate) == Some(candidate) {
+            return Ok(candidate);
+        }
+        // glibc aligns struct pthread to 64 bytes, and has no self pointer to check against
+        if candidate % 64 == 0
+            && read_u64(candidate + GLIBC_TID_OFFSET).map(|value| value as u32 as Tid) == Some(tid)
+        {
+            return Ok(candidate);
+        }
+    }
+    Err(Error::Other(format!(
+        "Failed to find struct pthread for thread {}",
+        tid
+    )))
+}
+
+/// Detects which libc the process uses, from the libc it has loaded. Statically linked
+/// processes have no libc module, so for those it's guessed from the layout of `pthread`.
+fn detect_libc(process: &Process, pthread: u64) -> Result<Libc, Error> {
+    let maps = proc_maps::get_process_maps(process.pid)?;
+    let loaded = maps
+        .iter()
+        .filter_map(|m| m.filename()?.file_name()?.to_str())
+        .find_map(libc_for_module);
+    match loaded {
+        Some(libc) => Ok(libc),
+        None => libc_for_layout(process, pthread),
+    }
+}
+
+/// Returns which libc a shared library belongs to, if it is one
+fn libc_for_module(filename: &str) -> Option<Libc> {
+    if filename.starts_with("ld-musl-") || filename.starts_with("libc.musl-") {
+        Some(Libc::Musl)
+    } else if filename == "libc.so.6" {
+        Some(Libc::Glibc)
+    } else {
+        None
+    }
+}
+
+/// Guesses the libc from the start of the `struct pthread`. Both begin with a pointer to
+/// themselves (glibc's tcbhead_t.tcb and musl's self), and glibc has another at offset 16
+/// (tcbhead_t.self). That is where musl keeps the previous thread in its list of threads,
+/// which is also the thread itself when there's only one - but then the next thread at
+/// offset 24 is too, while glibc keeps two small integer flags there.
+#[cfg(target_arch = "x86_64")]
+fn libc_for_layout(process: &Process, pthread: u64) -> Result<Libc, Error> {
+    let header: [u64; 4] = process.copy_struct(pthread as usize)?;
+    libc_for_header(&header, pthread).ok_or_else(|| {
+        Error::Other(format!(
+            "0x{:016x} doesn't point to a struct pthread",
+            pthread
+        ))
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+fn libc_for_header(header: &[u64; 4], pthread: u64) -> Option<Libc> {
+    if header[0] != pthread {
+        None
+    } else if header[2] == pthread && header[3] != pthread {
+        Some(Libc::Glibc)
+    } else {
+        Some(Libc::Musl)
+    }
+}
+
+/// Guesses the libc from the start of the `struct pthread`, which is a pointer to itself in
+/// musl and isn't in glibc
+#[cfg(target_arch = "aarch64")]
+fn libc_for_layout(process: &Process, pthread: u64) -> Result<Libc, Error> {
+    let header: u64 = process.copy_struct(pthread as usize)?;
+    Ok(if header == pthread {
+        Libc::Musl
+    } else {
+        Libc::Glibc
+    })
+}
+
+impl Process {
+    /// Returns the kernel thread id for a `pthread_t` value from the target process, which
+    /// lets data read from runtime internal structures be matched up with `Thread` objects.
+    /// This reads the tid out of the glibc or musl thread structure, and doesn't require the
+    /// thread to be locked.
+    pub fn pthread_tid(&self, pthread: u64) -> Result<Tid, Error> {
+        let libc = detect_libc(self, pthread)?;
+        let tid: Tid = self.copy_struct((pthread + libc.tid_offset()) as usize)?;
+
+        // make sure this is actually one of our threads, rather than garbage from a bad guess
+        // at the layout or from a thread that has exited
+        if !self
+            .threads()?
+            .iter()
+            .any(|thread| thread.tid.as_raw() == tid)
+        {
+            return Err(Error::Other(format!(
+                "pthread 0x{:016x} doesn't belong to a thread of process {}",
+                pthread, self.pid
+            )));
+        }
+        Ok(tid)
+    }
+
+    /// Returns the thread for a `pthread_t` value from the target process
+    pub fn thread_for_pthread(&self, pthread: u64) -> Result<Thread, Error> {
+        Ok(self.thread(self.pthread_tid(pthread)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pid;
+
+    #[test]
+    fn test_libc_for_module() {
+        assert_eq!(libc_for_module("libc.so.6"), Some(Libc::Glibc));
+        assert_eq!(libc_for_module("ld-musl-x86_64.so.1"), Some(Libc::Musl));
+        assert_eq!(libc_for_module("libc.musl-aarch64.so.1"), Some(Libc::Musl));
+        assert_eq!(libc_for_module("libcrypto.so.3"), None);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_libc_for_header() {
+        let pthread = 0x7f00_0000_1000;
+        // a single threaded glibc process, and one with multiple_threads set
+        assert_eq!(
+            libc_for_header(&[pthread, 0x5000, pthread, 0], pthread),
+            Some(Libc::Glibc)
+        );
+        assert_eq!(
+            libc_for_header(&[pthread, 0x5000, pthread, 1], pthread),
+            Some(Libc::Glibc)
+        );
+        // a single threaded musl process, whose list of threads only holds itself
+        assert_eq!(
+            libc_for_header(&[pthread, 0x5000, pthread, pthread], pthread),
+            Some(Libc::Musl)
+        );
+        assert_eq!(
+            libc_for_header(
+                &[pthread, 0x5000, 0x7f00_0000_9000, 0x7f00_0000_9000],
+                pthread
+            ),
+            Some(Libc::Musl)
+        );
+        assert_eq!(libc_for_header(&[0, 0, pthread, 0], pthread), None);
+    }
+
+    #[test]
+    fn test_pthread_tid() {
+        let process = Process::new(std::process::id() as Pid).unwrap();
+        let pthread = unsafe { libc::pthread_self() } as u64;
+        let tid = nix::unistd::gettid().as_raw();
+        assert_eq!(process.pthread_tid(pthread).unwrap(), tid);
+        assert_eq!(
+            process.thread_for_pthread(pthread).unwrap().id().unwrap(),
+            tid
+        );
+        assert!(process.pthread_tid(0x1000).is_err());
+    }
+}
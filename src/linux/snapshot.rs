@@ -0,0 +1,371 @@
+use std::ops::Range;
+
+use super::{copy_memory, platform_info, Thread, Tid};
+use crate::{Error, ProcessMemory};
+use libc::c_void;
+
+/// By default we copy at most this many bytes of stack for each thread
+pub const DEFAULT_MAX_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// The x86_64 ABI lets leaf functions use 128 bytes below the stack pointer without adjusting it,
+/// so we copy that red zone along with the live part of the stack
+#[cfg(target_arch = "x86_64")]
+pub(super) const RED_ZONE: u64 = 128;
+#[cfg(target_arch = "aarch64")]
+pub(super) const RED_ZONE: u64 = 0;
+
+#[cfg(target_arch = "x86_64")]
+const REGISTER_COUNT: usize = 17;
+#[cfg(target_arch = "aarch64")]
+const REGISTER_COUNT: usize = 33;
+
+/// Bitmask of the registers that are preserved across calls: rbx, rbp, rsp, r12-r15 and rip
+#[cfg(target_arch = "x86_64")]
+const CALLEE_SAVED: u64 = (1 << 3) | (1 << 6) | (1 << 7) | (0xf << 12) | (1 << 16);
+/// Bitmask of the registers that are preserved across calls: x19-x29, x30 (which holds the
+/// return address when unwinding), sp and pc
+#[cfg(target_arch = "aarch64")]
+const CALLEE_SAVED: u64 = (0xfff << 19) | (1 << 31) | (1 << 32);
+
+/// General purpose registers of a thread, indexed by their DWARF register number
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Registers {
+    values: [u64; REGISTER_COUNT],
+    valid: u64,
+}
+
+impl Registers {
+    #[cfg(target_arch = "x86_64")]
+    pub const IP: u16 = 16;
+    #[cfg(target_arch = "x86_64")]
+    pub const SP: u16 = 7;
+    #[cfg(target_arch = "x86_64")]
+    pub const FP: u16 = 6;
+    #[cfg(target_arch = "x86_64")]
+    pub const RA: u16 = 16;
+
+    // aarch64 doesn't have a DWARF register number for the pc, so we store it after sp
+    #[cfg(target_arch = "aarch64")]
+    pub const IP: u16 = 32;
+    #[cfg(target_arch = "aarch64")]
+    pub const SP: u16 = 31;
+    #[cfg(target_arch = "aarch64")]
+    pub const FP: u16 = 29;
+    #[cfg(target_arch = "aarch64")]
+    pub const RA: u16 = 30;
+
+    /// Returns the value of a register, or None if it isn't known
+    pub fn get(&self, register: u16) -> Option<u64> {
+        let register = register as usize;
+        if register < REGISTER_COUNT && self.valid & (1 << register) != 0 {
+            Some(self.values[register])
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, register: u16, value: u64) {
+        let register = register as usize;
+        if register < REGISTER_COUNT {
+            self.values[register] = value;
+            self.valid |= 1 << register;
+        }
+    }
+
+    pub fn clear(&mut self, register: u16) {
+        let register = register as usize;
+        if register < REGISTER_COUNT {
+            self.valid &= !(1 << register);
+        }
+    }
+
+    pub fn ip(&self) -> Option<u64> {
+        self.get(Self::IP)
+    }
+
+    pub fn sp(&self) -> Option<u64> {
+        self.get(Self::SP)
+    }
+
+    pub fn fp(&self) -> Option<u64> {
+        self.get(Self::FP)
+    }
+
+    /// Returns a copy of these registers with only the callee saved registers (and the stack
+    /// and instruction pointers) set. Other registers can be clobbered by a call, so their
+    /// values can't be recovered for any frame but the innermost one.
+    pub fn callee_saved(&self) -> Self {
+        let mut ret = *self;
+        ret.valid &= CALLEE_SAVED;
+        ret
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn from_user_regs(regs: &libc::user_regs_struct) -> Self {
+        let mut ret = Self::default();
+        let ordered = [
+            regs.rax, regs.rdx, regs.rcx, regs.rbx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+            regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+        ];
+        for (register, value) in ordered.iter().enumerate() {
+            ret.set(register as u16, *value);
+        }
+        ret
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn from_user_regs(regs: &libc::user_regs_struct) -> Self {
+        let mut ret = Self::default();
+        for (register, value) in regs.regs.iter().enumerate() {
+            ret.set(register as u16, *value);
+        }
+        ret.set(Self::SP, regs.sp);
+        ret.set(Self::IP, regs.pc);
+        ret
+    }
+}
+
+/// A copy of a stopped thread's registers and the live part of its stack.
+///
+/// Taking a snapshot only requires the thread to be locked for a single bulk memory read - the
+/// thread can be resumed as soon as this is returned, and unwound afterwards.
+pub struct StackSnapshot {
+    pub tid: Tid,
+    pub registers: Registers,
+    stack_start: u64,
+    stack: Vec<u8>,
+}
+
+impl StackSnapshot {
+    /// Creates a snapshot from registers and stack memory that were captured elsewhere, like
+    /// from a core dump, so that it can be unwound with a `SnapshotUnwinder`
+    pub fn new(tid: Tid, registers: Registers, stack_start: u64, stack: Vec<u8>) -> Self {
+        Self {
+            tid,
+            registers,
+            stack_start,
+            stack,
+        }
+    }
+
+    /// Creates a snapshot with the registers of a thread but no copy of its stack. Unwinding
+    /// this reads the stack from the memory of the `SnapshotUnwinder` instead, so the thread
+    /// has to stay stopped until it's unwound (or the memory has to be a copy, like a core
+    /// dump).
+    pub fn from_registers(source: &impl RegisterSource) -> Result<Self, Error> {
+        Ok(Self::new(source.tid(), source.registers()?, 0, Vec::new()))
+    }
+
+    /// The copied stack memory, starting at `stack_range().start`
+    pub fn stack(&self) -> &[u8] {
+        &self.stack
+    }
+
+    /// The range of addresses in the target process that were copied
+    pub fn stack_range(&self) -> Range<u64> {
+        self.stack_start..self.stack_start + self.stack.len() as u64
+    }
+
+    /// Returns true if the memory at addr..addr+len was copied in this snapshot
+    pub fn contains(&self, addr: u64, len: usize) -> bool {
+        let range = self.stack_range();
+        addr >= range.start
+            && addr
+                .checked_add(len as u64)
+                .is_some_and(|end| end <= range.end)
+    }
+}
+
+impl ProcessMemory for StackSnapshot {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        if !self.contains(addr as u64, buf.len()) {
+            return Err(Error::Other(format!(
+                "Address 0x{:016x} is outside of the copied stack for thread {}",
+                addr, self.tid
+            )));
+        }
+        let offset = (addr as u64 - self.stack_start) as usize;
+        buf.copy_from_slice(&self.stack[offset..offset + buf.len()]);
+        Ok(())
+    }
+}
+
+/// Where the registers of a stopped thread come from, like a locked thread of a live process or
+/// a thread saved in a core dump. Combined with a `ProcessMemory` for the rest of the address
+/// space, this is all a `SnapshotUnwinder` needs to unwind a thread.
+pub trait RegisterSource {
+    /// The thread the registers belong to
+    fn tid(&self) -> Tid;
+
+    /// Returns the general purpose registers of the thread
+    fn registers(&self) -> Result<Registers, Error>;
+}
+
+impl RegisterSource for Thread {
+    fn tid(&self) -> Tid {
+        self.tid.as_raw()
+    }
+
+    fn registers(&self) -> Result<Registers, Error> {
+        Self::registers(self)
+    }
+}
+
+impl RegisterSource for StackSnapshot {
+    fn tid(&self) -> Tid {
+        self.tid
+    }
+
+    fn registers(&self) -> Result<Registers, Error> {
+        Ok(self.registers)
+    }
+}
+
+impl Thread {
+    /// Returns the general purpose registers of this thread. The thread must be locked.
+    pub fn registers(&self) -> Result<Registers, Error> {
+        let regs: libc::user_regs_struct = self.get_regset(libc::NT_PRSTATUS)?;
+        Ok(Registers::from_user_regs(&regs))
+    }
+
+    /// Reads a register set of this thread with PTRACE_GETREGSET. The thread must be locked.
+    pub(super) fn get_regset<T: Copy>(&self, set: libc::c_int) -> Result<T, Error> {
+        self.check_not_read_only()?;
+        #[cfg(target_arch = "x86_64")]
+        if !platform_info().ptrace_getregset && set == libc::NT_PRSTATUS {
+            return self.get_regs();
+        }
+        let mut regs: T = unsafe { std::mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: &mut regs as *mut _ as *mut c_void,
+            iov_len: size_of::<T>(),
+        };
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETREGSET,
+                self.tid.as_raw(),
+                set as usize as *mut c_void,
+                &mut iov as *mut _ as *mut c_void,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::NixError(nix::errno::Errno::last()));
+        }
+        Ok(regs)
+    }
+
+    /// Reads the general purpose registers with PTRACE_GETREGS, for kernels that don't support
+    /// PTRACE_GETREGSET
+    #[cfg(target_arch = "x86_64")]
+    fn get_regs<T: Copy>(&self) -> Result<T, Error> {
+        if size_of::<T>() != size_of::<libc::user_regs_struct>() {
+            return Err(Error::Other(
+                "PTRACE_GETREGS only returns the general purpose registers".to_string(),
+            ));
+        }
+        let mut regs: T = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETREGS,
+                self.tid.as_raw(),
+                std::ptr::null_mut::<c_void>(),
+                &mut regs as *mut _ as *mut c_void,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::NixError(nix::errno::Errno::last()));
+        }
+        Ok(regs)
+    }
+
+    /// Copies the registers and the live part of the stack of this thread, so that it can be
+    /// unwound after the thread has been resumed. The thread must be locked.
+    pub fn snapshot(&self) -> Result<StackSnapshot, Error> {
+        self.snapshot_with_limit(DEFAULT_MAX_STACK_SIZE)
+    }
+
+    /// Like `snapshot`, but copies at most `max_stack_size` bytes of the stack
+    pub fn snapshot_with_limit(&self, max_stack_size: usize) -> Result<StackSnapshot, Error> {
+        let maps = proc_maps::get_process_maps(self.tid.as_raw())?;
+        self.snapshot_with_maps(&maps, max_stack_size)
+    }
+
+    /// Like `snapshot_with_limit`, but finds the stack in `maps` instead of reading the memory
+    /// maps of the process again, for when many threads are copied at once
+    pub(super) fn snapshot_with_maps(
+        &self,
+        maps: &[proc_maps::MapRange],
+        max_stack_size: usize,
+    ) -> Result<StackSnapshot, Error> {
+        let tid = self.tid.as_raw();
+        let registers = self.registers()?;
+        let sp = registers
+            .sp()
+            .ok_or_else(|| Error::Other(format!("Failed to get stack pointer for {}", tid)))?;
+
+        // the live part of the stack goes from the stack pointer to the end of its mapping
+        let stack_map = maps
+            .iter()
+            .find(|m| sp >= m.start() as u64 && sp < (m.start() + m.size()) as u64)
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "Failed to find stack mapping for thread {} (sp 0x{:016x})",
+                    tid, sp
+                ))
+            })?;
+
+        let stack_start = sp.saturating_sub(RED_ZONE).max(stack_map.start() as u64);
+        let stack_end = (stack_map.start() + stack_map.size()) as u64;
+        let length = ((stack_end - stack_start) as usize).min(max_stack_size);
+
+        let mut stack = vec![0; length];
+        copy_memory(tid, stack_start as usize, &mut stack)?;
+
+        Ok(StackSnapshot {
+            tid,
+            registers,
+            stack_start,
+            stack,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut regs = Registers::default();
+        assert_eq!(regs.ip(), None);
+        regs.set(Registers::IP, 0x1234);
+        regs.set(Registers::SP, 0x7fff0000);
+        assert_eq!(regs.ip(), Some(0x1234));
+        assert_eq!(regs.sp(), Some(0x7fff0000));
+        assert_eq!(regs.fp(), None);
+        regs.clear(Registers::IP);
+        assert_eq!(regs.ip(), None);
+        // out of range registers are ignored
+        regs.set(1000, 1);
+        assert_eq!(regs.get(1000), None);
+
+        // only callee saved registers survive into the calling frames
+        regs.set(0, 1);
+        regs.set(Registers::FP, 2);
+        let saved = regs.callee_saved();
+        assert_eq!(saved.get(0), None);
+        assert_eq!(saved.fp(), Some(2));
+        assert_eq!(saved.sp(), Some(0x7fff0000));
+    }
+
+    #[test]
+    fn test_snapshot_read() {
+        let snapshot = StackSnapshot::new(1, Registers::default(), 0x1000, (0..32).collect());
+        let mut buf = [0u8; 4];
+        snapshot.read(0x1004, &mut buf).unwrap();
+        assert_eq!(buf, [4, 5, 6, 7]);
+        assert!(snapshot.read(0x101e, &mut buf).is_err());
+        assert!(snapshot.read(0xfff, &mut buf).is_err());
+        assert_eq!(snapshot.stack_range(), 0x1000..0x1020);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::{copy_memory, Thread, Tid};
+use crate::Error;
+
+/// The kernel won't grow the main thread's stack to within this many bytes of the mapping
+/// below it (the `stack_guard_gap` boot parameter, 256 pages by default)
+const STACK_GUARD_GAP: u64 = 256 * 4096;
+
+/// Where a thread's stack is, and how far it can grow before it overflows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackBounds {
+    /// The lowest address the stack can grow down to
+    pub limit: u64,
+    /// The start of the memory currently mapped for the stack. This is the same as `limit` for
+    /// threads started with pthreads, but the main thread's stack is grown on demand by the
+    /// kernel until it reaches `limit`.
+    pub start: u64,
+    /// The end (highest address) of the stack
+    pub end: u64,
+    /// The memory below `limit` that faults when the stack overflows into it: the PROT_NONE
+    /// guard pages below a pthread stack, or for the main thread the unmapped memory between
+    /// `limit` and the mapping below. None if the stack has no guard, in which case an overflow
+    /// silently corrupts whatever is mapped below it.
+    pub guard: Option<Range<u64>>,
+    /// Whether this is the main thread's stack, which grows on demand
+    pub main_thread: bool,
+}
+
+impl StackBounds {
+    /// The largest size the stack can grow to
+    pub fn size(&self) -> u64 {
+        self.end - self.limit
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.limit && addr < self.end
+    }
+}
+
+/// How much of its stack a thread is using, from `Thread::stack_headroom`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackHeadroom {
+    pub bounds: StackBounds,
+    /// The stack pointer of the thread
+    pub sp: u64,
+    /// How many bytes of stack the thread is using, from the stack pointer to the end
+    pub used: u64,
+    /// How many bytes the stack pointer can still move down before the stack overflows
+    pub remaining: u64,
+}
+
+impl StackHeadroom {
+    /// Returns whether the stack pointer is within `margin` bytes of overflowing the stack
+    pub fn is_near_overflow(&self, margin: u64) -> bool {
+        self.remaining < margin
+    }
+
+    /// The fraction of the stack in use, from 0 to 1
+    pub fn used_fraction(&self) -> f64 {
+        self.used as f64 / self.bounds.size().max(1) as f64
+    }
+}
+
+/// The most stack a thread has used, from `Thread::stack_usage`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackUsage {
+    pub bounds: StackBounds,
+    /// The lowest address on the stack that has been written to
+    pub deepest: u64,
+    /// The most bytes of stack the thread has used, from `deepest` to the end of the stack
+    pub peak: u64,
+}
+
+/// Keeps the most stack each thread has used across samples, from the lowest stack pointer
+/// seen for it.
+///
+/// This is cheap enough to update on every sample, but can miss short lived deep calls that
+/// happen between samples. `Thread::stack_usage` finds the high water mark directly instead.
+#[derive(Debug, Clone, Default)]
+pub struct StackWatermarks {
+    peaks: HashMap<Tid, u64>,
+}
+
+impl StackWatermarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a sample of a thread's stack, returning the most stack it has used so far
+    pub fn record(&mut self, tid: Tid, headroom: &StackHeadroom) -> u64 {
+        let peak = self.peaks.entry(tid).or_insert(0);
+        *peak = (*peak).max(headroom.used);
+        *peak
+    }
+
+    /// Returns the most stack used by a thread in the recorded samples
+    pub fn peak(&self, tid: Tid) -> Option<u64> {
+        self.peaks.get(&tid).copied()
+    }
+
+    /// Forgets a thread, like after it has exited (since the tid can be reused)
+    pub fn remove(&mut self, tid: Tid) -> Option<u64> {
+        self.peaks.remove(&tid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Tid, u64)> + '_ {
+        self.peaks.iter().map(|(&tid, &peak)| (tid, peak))
+    }
+}
+
+/// The parts of a memory mapping that matter for finding the bounds of a stack
+#[derive(Debug, Clone)]
+struct Region {
+    range: Range<u64>,
+    /// Whether the mapping can't be accessed at all, like a guard page
+    inaccessible: bool,
+    /// Whether this is the `[stack]` mapping of the main thread
+    main_stack: bool,
+}
+
+impl Thread {
+    /// Returns the bounds of this thread's stack, found from the memory mapping its stack
+    /// pointer is in. The thread must be locked.
+    pub fn stack_bounds(&self) -> Result<StackBounds, Error> {
+        Ok(self.stack_headroom()?.bounds)
+    }
+
+    /// Returns how close this thread is to overflowing its stack, which is how far its stack
+    /// pointer is above the guard below the stack. The thread must be locked.
+    pub fn stack_headroom(&self) -> Result<StackHeadroom, Error> {
+        let tid = self.tid.as_raw();
+        let sp = self
+            .registers()?
+            .sp()
+            .ok_or_else(|| Error::Other(format!("Failed to get stack pointer for {}", tid)))?;
+
+        let regions: Vec<Region> = proc_maps::get_process_maps(tid)?
+            .iter()
+            .map(|m| Region {
+                range: m.start() as u64..(m.start() + m.size()) as u64,
+                inaccessible: m.flags.starts_with("---"),
+                main_stack: m.filename().is_some_and(|f| f.as_os_str() == "[stack]"),
+            })
+            .collect();
+        let bounds = find_stack_bounds(&regions, sp, || stack_rlimit(tid)).ok_or_else(|| {
+            Error::Other(format!(
+                "Failed to find stack mapping for thread {} (sp 0x{:016x})",
+                tid, sp
+            ))
+        })?;
+
+        Ok(StackHeadroom {
+            sp,
+            used: bounds.end - sp,
+            remaining: sp.saturating_sub(bounds.limit),
+            bounds,
+        })
+    }
+
+    /// Measures the most stack this thread has used since it started, by scanning up from the
+    /// bottom of its stack for the first memory that has been written to. Stack memory starts
+    /// out zeroed, so this is exact unless the deepest frames only wrote zeros (and it can't
+    /// tell that a pthread stack reused from an exited thread was written by that thread). The
+    /// thread must be locked.
+    pub fn stack_usage(&self) -> Result<StackUsage, Error> {
+        let tid = self.tid.as_raw();
+        let headroom = self.stack_headroom()?;
+        let bounds = headroom.bounds;
+        let deepest = find_deepest_write(bounds.start..headroom.sp, |addr, buf| {
+            Ok(copy_memory(tid, addr as usize, buf)?)
+        })?
+        .unwrap_or(headroom.sp);
+        Ok(StackUsage {
+            peak: bounds.end - deepest,
+            deepest,
+            bounds,
+        })
+    }
+}
+
+/// Returns the address of the first non zero byte in `range`
+fn find_deepest_write(
+    range: Range<u64>,
+    mut read: impl FnMut(u64, &mut [u8]) -> Result<(), Error>,
+) -> Result<Option<u64>, Error> {
+    const CHUNK_SIZE: u64 = 64 * 1024;
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    let mut addr = range.start;
+    while addr < range.end {
+        let chunk = &mut buf[..(range.end - addr).min(CHUNK_SIZE) as usize];
+        read(addr, chunk)?;
+        if let Some(offset) = chunk.iter().position(|&b| b != 0) {
+            return Ok(Some(addr + offset as u64));
+        }
+        addr += chunk.len() as u64;
+    }
+    Ok(None)
+}
+
+/// Finds the bounds of the stack containing `sp` in the sorted list of mappings. `rlimit`
+/// returns the maximum size of the main thread's stack, or None if it's unlimited.
+fn find_stack_bounds(
+    regions: &[Region],
+    sp: u64,
+    rlimit: impl FnOnce() -> Option<u64>,
+) -> Option<StackBounds> {
+    let index = regions.iter().position(|r| r.range.contains(&sp))?;
+    let stack = &regions[index];
+    let below = index.checked_sub(1).map(|i| &regions[i]);
+
+    if stack.main_stack {
+        // the kernel grows the stack down until it hits the rlimit, or until it would come
+        // within the guard gap of the mapping below it
+        let rlimit_limit = rlimit().map_or(0, |size| stack.range.end.saturating_sub(size));
+        let gap_limit = below.map_or(0, |r| r.range.end + STACK_GUARD_GAP);
+        let limit = rlimit_limit.max(gap_limit).min(stack.range.start);
+        return Some(StackBounds {
+            limit,
+            start: stack.range.start,
+            end: stack.range.end,
+            guard: below.map(|r| r.range.end..limit),
+            main_thread: true,
+        });
+    }
+
+    // pthreads maps the guard pages directly below the stack
+    let guard = below
+        .filter(|r| r.inaccessible && r.range.end == stack.range.start)
+        .map(|r| r.range.clone());
+    Some(StackBounds {
+        limit: stack.range.start,
+        start: stack.range.start,
+        end: stack.range.end,
+        guard,
+        main_thread: false,
+    })
+}
+
+/// Returns the soft limit on the main thread's stack size, from /proc/<tid>/limits
+fn stack_rlimit(tid: i32) -> Option<u64> {
+    let limits = std::fs::read_to_string(format!("/proc/{}/limits", tid)).ok()?;
+    let line = limits.lines().find(|l| l.starts_with("Max stack size"))?;
+    line["Max stack size".len()..]
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LocalProcess, Process, ProcessMemory};
+
+    fn region(range: Range<u64>, inaccessible: bool, main_stack: bool) -> Region {
+        Region {
+            range,
+            inaccessible,
+            main_stack,
+        }
+    }
+
+    #[test]
+    fn test_find_stack_bounds() {
+        let regions = [
+            region(0x10000..0x20000, false, false),
+            region(0x20000..0x21000, true, false),
+            region(0x21000..0x40000, false, false),
+            region(0x1000000..0x1100000, false, false),
+            region(0x2000000..0x2100000, false, true),
+        ];
+
+        let bounds = find_stack_bounds(&regions, 0x30000, || None).unwrap();
+        assert_eq!(bounds.limit, 0x21000);
+        assert_eq!(bounds.guard, Some(0x20000..0x21000));
+        assert!(!bounds.main_thread);
+
+        // no guard page when the mapping below is accessible
+        let bounds = find_stack_bounds(&regions, 0x18000, || None).unwrap();
+        assert_eq!(bounds.guard, None);
+
+        // the main thread's stack is limited by its rlimit...
+        let bounds = find_stack_bounds(&regions, 0x20ff000, || Some(0x800000)).unwrap();
+        assert_eq!(bounds.limit, 0x1900000);
+        assert_eq!(bounds.start, 0x2000000);
+        assert_eq!(bounds.guard, Some(0x1100000..0x1900000));
+        assert_eq!(bounds.size(), 0x800000);
+        // ... and by the guard gap below it
+        let bounds = find_stack_bounds(&regions, 0x20ff000, || None).unwrap();
+        assert_eq!(bounds.limit, 0x1100000 + STACK_GUARD_GAP);
+
+        assert_eq!(find_stack_bounds(&regions, 0x50000, || None), None);
+    }
+
+    #[test]
+    fn test_find_deepest_write() {
+        let mut stack = vec![0u8; 200 * 1024];
+        stack[150 * 1024 + 3] = 1;
+        let start = stack.as_ptr() as u64;
+        let read = |addr: u64, buf: &mut [u8]| LocalProcess.read(addr as usize, buf);
+        let deepest = find_deepest_write(start..start + stack.len() as u64, read).unwrap();
+        assert_eq!(deepest, Some(start + 150 * 1024 + 3));
+        let deepest = find_deepest_write(start..start + 150 * 1024, read).unwrap();
+        assert_eq!(deepest, None);
+    }
+
+    #[test]
+    fn test_stack_watermarks() {
+        let bounds = StackBounds {
+            limit: 0x1000,
+            start: 0x1000,
+            end: 0x9000,
+            guard: None,
+            main_thread: false,
+        };
+        let sample = |sp: u64| StackHeadroom {
+            bounds: bounds.clone(),
+            sp,
+            used: bounds.end - sp,
+            remaining: sp - bounds.limit,
+        };
+        let mut watermarks = StackWatermarks::new();
+        assert_eq!(watermarks.record(1, &sample(0x8000)), 0x1000);
+        assert_eq!(watermarks.record(1, &sample(0x4000)), 0x5000);
+        assert_eq!(watermarks.record(1, &sample(0x8800)), 0x5000);
+        assert_eq!(watermarks.record(2, &sample(0x8800)), 0x800);
+        assert_eq!(watermarks.peak(1), Some(0x5000));
+        assert_eq!(watermarks.remove(1), Some(0x5000));
+        assert_eq!(watermarks.peak(1), None);
+    }
+
+    #[test]
+    fn test_stack_headroom() {
+        let child = crate::tests::TestChild::sleep();
+        let process = Process::new(child.pid()).unwrap();
+        let _lock = process.lock().unwrap();
+
+        let thread = &process.threads().unwrap()[0];
+        let headroom = thread.stack_headroom().unwrap();
+        assert!(headroom.bounds.main_thread);
+        assert!(headroom.bounds.contains(headroom.sp));
+        assert!(headroom.used > 0 && headroom.remaining > headroom.used);
+        assert!(!headroom.is_near_overflow(64 * 1024));
+        let usage = thread.stack_usage().unwrap();
+        assert!(usage.peak >= headroom.used && usage.deepest >= usage.bounds.start);
+    }
+}
@@ -0,0 +1,325 @@
+//! A precomputed index of the symbols and line table of a binary, which can be built ahead of
+//! time (like when the binary is built) and saved to a file, so that symbolicating addresses in
+//! the binary doesn't need to parse its DWARF debug info. The index only has the line table,
+//! so addresses in inlined functions are reported as the function they were inlined into.
+//!
+//! The file format is little endian throughout:
+//!
+//! ```text
+//! magic      b"RPSYMIDX"
+//! version    u32
+//! build id   u32 length, followed by the bytes (an empty build id means none)
+//! symbols    u32 count, followed by (u64 address, u64 size, string name) for each symbol
+//! dynamic    the dynamic symbols, in the same layout as the symbols
+//! files      u32 count, followed by a string for each source file
+//! lines      u32 count, followed by (u64 address, u32 size, u32 file, u32 line, u32 column)
+//!            for each row, where a column of 0 means it isn't known
+//! ```
+//!
+//! Version 1 files are also read, which are the same except for not having the column.
+//!
+//! Strings are stored as a u32 length followed by the UTF-8 bytes.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use addr2line::Loader;
+use log::info;
+use memmap2::Mmap;
+use object::Object;
+
+use super::symbolication::read_symbols;
+use crate::Error;
+
+const MAGIC: &[u8; 8] = b"RPSYMIDX";
+const VERSION: u32 = 2;
+
+/// The symbols and line table of a binary, as returned by `SymbolIndex::build`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolIndex {
+    pub(super) build_id: Option<Vec<u8>>,
+    pub(super) symbols: Vec<(u64, u64, String)>,
+    pub(super) dynamic_symbols: Vec<(u64, u64, String)>,
+    pub(super) files: Vec<String>,
+    pub(super) lines: Vec<LineRow>,
+}
+
+/// A range of addresses that were generated from the same source line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct LineRow {
+    pub address: u64,
+    pub size: u32,
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl SymbolIndex {
+    /// Builds the index for a binary, parsing its symbol tables and DWARF line tables
+    pub fn build(filename: &str) -> Result<Self, Error> {
+        info!("building symbol index for {}", filename);
+        let file = File::open(filename)?;
+        let map = unsafe { Mmap::map(&file)? };
+        let object = object::File::parse(&*map).map_err(|e| {
+            Error::Other(format!("Failed to parse {} for symbols: {}", filename, e))
+        })?;
+        let build_id = object.build_id().ok().flatten().map(|id| id.to_vec());
+        let (symbols, dynamic_symbols) = read_symbols(&object);
+
+        let loader = Loader::new(filename).map_err(|e| {
+            Error::Other(format!(
+                "Failed to get symbol context for {}: {:?}",
+                filename, e
+            ))
+        })?;
+        let locations = loader
+            .find_location_range(0, u64::MAX)
+            .map_err(|e| Error::Other(format!("addr2line error: {:?}", e)))?;
+
+        let mut files: Vec<String> = Vec::new();
+        let mut file_indexes: HashMap<String, u32> = HashMap::new();
+        let mut lines = Vec::new();
+        for (address, size, location) in locations {
+            let (file, line) = match (location.file, location.line) {
+                (Some(file), Some(line)) => (file, line),
+                _ => continue,
+            };
+            let file = match file_indexes.get(file) {
+                Some(&index) => index,
+                None => {
+                    let index = files.len() as u32;
+                    files.push(file.to_string());
+                    file_indexes.insert(file.to_string(), index);
+                    index
+                }
+            };
+            lines.push(LineRow {
+                address,
+                size: size.min(u32::MAX as u64) as u32,
+                file,
+                line,
+                column: location.column.unwrap_or(0),
+            });
+        }
+        lines.sort_unstable_by_key(|row| row.address);
+
+        Ok(Self {
+            build_id,
+            symbols,
+            dynamic_symbols,
+            files,
+            lines,
+        })
+    }
+
+    /// The build id of the binary the index was built from, if it has one
+    pub fn build_id(&self) -> Option<&[u8]> {
+        self.build_id.as_deref()
+    }
+
+    /// Returns the source file and line for an address relative to the binary
+    pub fn find_line(&self, offset: u64) -> Option<(&str, u32)> {
+        let row = find_line(&self.lines, offset)?;
+        Some((self.files.get(row.file as usize)?, row.line))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        write_bytes(writer, self.build_id.as_deref().unwrap_or_default())?;
+        for symbols in [&self.symbols, &self.dynamic_symbols] {
+            write_len(writer, symbols.len())?;
+            for (address, size, name) in symbols {
+                writer.write_all(&address.to_le_bytes())?;
+                writer.write_all(&size.to_le_bytes())?;
+                write_bytes(writer, name.as_bytes())?;
+            }
+        }
+        write_len(writer, self.files.len())?;
+        for file in &self.files {
+            write_bytes(writer, file.as_bytes())?;
+        }
+        write_len(writer, self.lines.len())?;
+        for row in &self.lines {
+            writer.write_all(&row.address.to_le_bytes())?;
+            write_u32(writer, row.size)?;
+            write_u32(writer, row.file)?;
+            write_u32(writer, row.line)?;
+            write_u32(writer, row.column)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Other("Not a symbol index file".to_string()));
+        }
+        let version = read_u32(reader)?;
+        if version != 1 && version != VERSION {
+            return Err(Error::Other(format!(
+                "Unsupported symbol index version {}",
+                version
+            )));
+        }
+        let build_id = Some(read_bytes(reader)?).filter(|id| !id.is_empty());
+
+        let symbols = read_symbol_table(reader)?;
+        let dynamic_symbols = read_symbol_table(reader)?;
+
+        let count = read_u32(reader)?;
+        let mut files = Vec::new();
+        for _ in 0..count {
+            files.push(read_string(reader)?);
+        }
+        let count = read_u32(reader)?;
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            lines.push(LineRow {
+                address: read_u64(reader)?,
+                size: read_u32(reader)?,
+                file: read_u32(reader)?,
+                line: read_u32(reader)?,
+                column: if version >= 2 { read_u32(reader)? } else { 0 },
+            });
+        }
+
+        Ok(Self {
+            build_id,
+            symbols,
+            dynamic_symbols,
+            files,
+            lines,
+        })
+    }
+}
+
+/// Returns the row of a sorted line table containing offset
+pub(super) fn find_line(lines: &[LineRow], offset: u64) -> Option<&LineRow> {
+    let i = lines.partition_point(|row| row.address <= offset);
+    let row = lines.get(i.checked_sub(1)?)?;
+    (offset < row.address + row.size as u64).then_some(row)
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> Result<(), Error> {
+    Ok(writer.write_all(&value.to_le_bytes())?)
+}
+
+fn write_len(writer: &mut impl Write, len: usize) -> Result<(), Error> {
+    let len = u32::try_from(len)
+        .map_err(|_| Error::Other(format!("{} entries is too many for a symbol index", len)))?;
+    write_u32(writer, len)
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<(), Error> {
+    write_len(writer, bytes.len())?;
+    Ok(writer.write_all(bytes)?)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let len = read_u32(reader)? as usize;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(Error::Other("Truncated symbol index file".to_string()));
+    }
+    Ok(bytes)
+}
+
+fn read_symbol_table(reader: &mut impl Read) -> Result<Vec<(u64, u64, String)>, Error> {
+    let count = read_u32(reader)?;
+    let mut symbols = Vec::new();
+    for _ in 0..count {
+        let address = read_u64(reader)?;
+        let size = read_u64(reader)?;
+        symbols.push((address, size, read_string(reader)?));
+    }
+    Ok(symbols)
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, Error> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|e| Error::Other(format!("Invalid string in symbol index: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::symbol_search::current_exe_has_debug_info;
+
+    #[test]
+    fn test_symbol_index_roundtrip() {
+        // our own test binary has symbols, and debug info unless it's a release build
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let exe = std::fs::read_link("/proc/self/exe").unwrap();
+        let index = SymbolIndex::build(exe.to_str().unwrap()).unwrap();
+        assert!(!index.symbols.is_empty());
+        assert!(!index.lines.is_empty());
+
+        let row = index.lines[index.lines.len() / 2];
+        let (file, line) = index.find_line(row.address).unwrap();
+        assert_eq!(file, index.files[row.file as usize]);
+        assert_eq!(line, row.line);
+
+        let mut data = Vec::new();
+        index.write(&mut data).unwrap();
+        assert_eq!(SymbolIndex::read(&mut &data[..]).unwrap(), index);
+
+        assert!(SymbolIndex::read(&mut &data[..data.len() - 1]).is_err());
+        assert!(SymbolIndex::read(&mut &b"not an index"[..]).is_err());
+    }
+
+    #[test]
+    fn test_symbol_index_columns() {
+        let mut index = SymbolIndex {
+            build_id: None,
+            symbols: Vec::new(),
+            dynamic_symbols: Vec::new(),
+            files: vec!["main.rs".to_string()],
+            lines: vec![LineRow {
+                address: 0x1000,
+                size: 16,
+                file: 0,
+                line: 12,
+                column: 5,
+            }],
+        };
+        let mut data = Vec::new();
+        index.write(&mut data).unwrap();
+        assert_eq!(SymbolIndex::read(&mut &data[..]).unwrap(), index);
+
+        // version 1 files are the same, except for the column at the end of each row
+        data[8..12].copy_from_slice(&1u32.to_le_bytes());
+        data.truncate(data.len() - 4);
+        index.lines[0].column = 0;
+        assert_eq!(SymbolIndex::read(&mut &data[..]).unwrap(), index);
+    }
+}
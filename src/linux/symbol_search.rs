@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use memmap2::Mmap;
+use object::Object;
+
+use super::symbol_source::SymbolSource;
+
+/// A place the `Symbolicator` looks for the debug info of a binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolSearchLocation {
+    /// The binary itself, if it wasn't stripped of its debug info
+    Binary,
+    /// The file named by the `.gnu_debuglink` section of the binary, in the same directory as
+    /// the binary or in a `.debug` directory next to it
+    Adjacent,
+    /// A directory of separate debug info like `/usr/lib/debug`, which is searched by build id
+    /// (`.build-id/ab/cdef.debug`) and by the path of the binary with its debug link name
+    DebugDir(PathBuf),
+    /// A copy of the root filesystem of the target, like an extracted container image. The
+    /// binary is looked up at its path under the sysroot, and then in the sysroot's
+    /// `/usr/lib/debug`.
+    Sysroot(PathBuf),
+    /// A directory of debug files named by build id (`<hex build id>.debug`), like the cache
+    /// directory of a `SymbolSource` that downloads them
+    CacheDir(PathBuf),
+    /// The sources registered with `Symbolicator::add_symbol_source`, in the order they were
+    /// added
+    Sources,
+}
+
+/// The places to look for the debug info of each binary, in the order they are searched.
+///
+/// The first location with debug info for a binary is used, and the binary's own symbol
+/// tables are used for anything without it. The default searches the binary itself and then
+/// the symbol sources.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolSearchPath {
+    locations: Vec<SymbolSearchLocation>,
+}
+
+impl Default for SymbolSearchPath {
+    fn default() -> Self {
+        Self {
+            locations: vec![SymbolSearchLocation::Binary, SymbolSearchLocation::Sources],
+        }
+    }
+}
+
+impl SymbolSearchPath {
+    /// Creates an empty search path, which doesn't look for debug info anywhere
+    pub fn new() -> Self {
+        Self {
+            locations: Vec::new(),
+        }
+    }
+
+    /// Adds a location to search after the ones already added
+    pub fn then(mut self, location: SymbolSearchLocation) -> Self {
+        self.locations.push(location);
+        self
+    }
+
+    pub fn locations(&self) -> &[SymbolSearchLocation] {
+        &self.locations
+    }
+
+    /// Returns the path of the file with the debug info for `file`, which was opened from
+    /// `filename`
+    pub(super) fn find(
+        &self,
+        file: &object::File<'_>,
+        filename: &str,
+        sources: &[Box<dyn SymbolSource>],
+    ) -> Option<PathBuf> {
+        let build_id = file.build_id().ok().flatten();
+        let debuglink = file
+            .gnu_debuglink()
+            .ok()
+            .flatten()
+            .and_then(|(name, _)| std::str::from_utf8(name).ok());
+        let binary = Path::new(filename);
+
+        for location in &self.locations {
+            let found = match location {
+                SymbolSearchLocation::Binary => has_debug_info(file).then(|| binary.to_owned()),
+                SymbolSearchLocation::Adjacent => debuglink.and_then(|name| {
+                    let dir = binary.parent()?;
+                    [dir.join(name), dir.join(".debug").join(name)]
+                        .into_iter()
+                        .find(|path| matches_build_id(path, build_id))
+                }),
+                SymbolSearchLocation::DebugDir(dir) => {
+                    search_debug_dir(dir, binary, build_id, debuglink)
+                }
+                SymbolSearchLocation::Sysroot(root) => {
+                    let copy = root.join(binary.strip_prefix("/").unwrap_or(binary));
+                    if is_debug_file(&copy, build_id) {
+                        Some(copy)
+                    } else {
+                        let dir = root.join("usr/lib/debug");
+                        search_debug_dir(&dir, binary, build_id, debuglink)
+                    }
+                }
+                SymbolSearchLocation::CacheDir(dir) => build_id
+                    .map(|id| dir.join(format!("{}.debug", hex(id))))
+                    .filter(|path| matches_build_id(path, build_id)),
+                SymbolSearchLocation::Sources => {
+                    build_id.and_then(|id| find_in_sources(id, filename, sources))
+                }
+            };
+            if let Some(path) = found {
+                debug!(
+                    "found debug info for {} in {:?}: {}",
+                    filename,
+                    location,
+                    path.display()
+                );
+                return Some(path);
+            }
+        }
+        None
+    }
+}
+
+/// Looks for the debug info of `binary` under a directory like /usr/lib/debug
+fn search_debug_dir(
+    dir: &Path,
+    binary: &Path,
+    build_id: Option<&[u8]>,
+    debuglink: Option<&str>,
+) -> Option<PathBuf> {
+    if let Some(id) = build_id.filter(|id| id.len() > 1) {
+        let path = dir
+            .join(".build-id")
+            .join(hex(&id[..1]))
+            .join(format!("{}.debug", hex(&id[1..])));
+        if matches_build_id(&path, build_id) {
+            return Some(path);
+        }
+    }
+    let name = debuglink?;
+    let parent = binary.parent()?;
+    let path = dir
+        .join(parent.strip_prefix("/").unwrap_or(parent))
+        .join(name);
+    matches_build_id(&path, build_id).then_some(path)
+}
+
+/// Asks each source in turn for the debug info of a binary
+fn find_in_sources(
+    build_id: &[u8],
+    filename: &str,
+    sources: &[Box<dyn SymbolSource>],
+) -> Option<PathBuf> {
+    for source in sources {
+        match source.find_debug_file(build_id, filename) {
+            Ok(Some(path)) => {
+                info!("using debug info from {} for {}", path.display(), filename);
+                return Some(path);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to find debug info for {}: {}", filename, e),
+        }
+    }
+    None
+}
+
+fn has_debug_info(file: &object::File<'_>) -> bool {
+    file.section_by_name(".debug_info").is_some()
+}
+
+/// True if the test binary has DWARF debug info, which the tests that read it need. Release
+/// builds (and builds with `debug = false`) don't have it.
+#[cfg(test)]
+pub(super) fn current_exe_has_debug_info() -> bool {
+    with_object(&std::env::current_exe().unwrap(), has_debug_info)
+}
+
+/// Returns true if `path` is a binary with the build id we're looking for (or any binary, if
+/// we don't know the build id)
+fn matches_build_id(path: &Path, build_id: Option<&[u8]>) -> bool {
+    with_object(path, |file| same_build_id(file, build_id))
+}
+
+/// Returns true if `path` has debug info and the build id we're looking for
+fn is_debug_file(path: &Path, build_id: Option<&[u8]>) -> bool {
+    with_object(path, |file| {
+        same_build_id(file, build_id) && has_debug_info(file)
+    })
+}
+
+fn same_build_id(file: &object::File<'_>, build_id: Option<&[u8]>) -> bool {
+    build_id.is_none() || file.build_id().ok().flatten() == build_id
+}
+
+fn with_object(path: &Path, f: impl FnOnce(&object::File<'_>) -> bool) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let Ok(map) = (unsafe { Mmap::map(&file) }) else {
+        return false;
+    };
+    object::File::parse(&*map).is_ok_and(|file| f(&file))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_search_path() {
+        // sleep is stripped, but has a build id and a debug link
+        let data = std::fs::read("/bin/sleep").unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let Some(build_id) = file.build_id().unwrap() else {
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("remoteprocess-search-{}", std::process::id()));
+        let debug_dir = dir.join("debug");
+        let build_id_dir = debug_dir.join(".build-id").join(hex(&build_id[..1]));
+        std::fs::create_dir_all(&build_id_dir).unwrap();
+        let cached = dir.join(format!("{}.debug", hex(build_id)));
+        std::fs::write(&cached, &data).unwrap();
+        let by_build_id = build_id_dir.join(format!("{}.debug", hex(&build_id[1..])));
+        std::fs::write(&by_build_id, &data).unwrap();
+
+        // the binary has no debug info, so only the directories find anything
+        let search = SymbolSearchPath::default();
+        assert_eq!(search.find(&file, "/bin/sleep", &[]), None);
+        let search = SymbolSearchPath::new()
+            .then(SymbolSearchLocation::Binary)
+            .then(SymbolSearchLocation::CacheDir(dir.clone()))
+            .then(SymbolSearchLocation::DebugDir(debug_dir.clone()));
+        assert_eq!(search.find(&file, "/bin/sleep", &[]), Some(cached.clone()));
+        let search = SymbolSearchPath::new()
+            .then(SymbolSearchLocation::DebugDir(debug_dir))
+            .then(SymbolSearchLocation::CacheDir(dir.clone()));
+        assert_eq!(search.find(&file, "/bin/sleep", &[]), Some(by_build_id));
+
+        // files that aren't the binary we're looking for are skipped
+        std::fs::write(&cached, b"not a binary").unwrap();
+        let search = SymbolSearchPath::new().then(SymbolSearchLocation::CacheDir(dir.clone()));
+        assert_eq!(search.find(&file, "/bin/sleep", &[]), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
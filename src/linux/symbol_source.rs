@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use crate::Error;
+
+/// Finds the debug info for binaries that were stripped of it, like from a symbol server.
+///
+/// Sources are registered with `Symbolicator::add_symbol_source`, and are consulted in the
+/// order they were added for binaries with a build id, when the search reaches
+/// `SymbolSearchLocation::Sources` without finding any debug info. The symbol_server example
+/// has a source that downloads debug info from a debuginfod server.
+pub trait SymbolSource {
+    /// Returns the path to a local file with the debug info for the binary with `build_id`,
+    /// or None if this source doesn't have it. `filename` is the path of the binary in the
+    /// target process, for sources that aren't indexed by build id.
+    fn find_debug_file(&self, build_id: &[u8], filename: &str) -> Result<Option<PathBuf>, Error>;
+}
@@ -1,32 +1,71 @@
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::cell::Ref;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use log::{debug, error, info, trace, warn};
 use memmap2::Mmap;
 
+use super::binary::parse_layout;
+use super::cache::{CacheBudget, CachedData};
+use super::platform_info;
+use super::symbol_index::{find_line, LineRow, SymbolIndex};
+use super::symbol_search::SymbolSearchPath;
+use super::symbol_source::SymbolSource;
 use crate::{Error, Pid, Process, StackFrame};
 use addr2line::Loader;
-use goblin::elf::program_header::*;
 use object::{Object, ObjectSymbol};
 
 use crate::ProcessMemory;
 
-pub struct Symbolicator {
+/// When the symbol tables and debug info of each binary are parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymbolLoading {
+    /// Parse the symbols of a binary the first time an address in it is symbolicated, and
+    /// keep them for later lookups. This is the default, and makes creating a symbolicator
+    /// fast when only a few stacks will be symbolicated, like when dumping a process once.
+    #[default]
+    Lazy,
+    /// Parse the symbols of every binary when the symbolicator is created (and on reload),
+    /// so that symbolicating never stalls on loading a large binary. This is a better fit for
+    /// samplers, which want predictable latency for each sample.
+    Eager,
+}
+
+/// The binary an address belongs to, as returned by `Symbolicator::locate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleLocation<'a> {
+    /// The filename of the binary
+    pub module: &'a str,
+    /// The address relative to the binary before it was relocated, which is what its symbol
+    /// tables and debug info are indexed by
+    pub offset: u64,
+}
+
+/// Symbolicates addresses in the binaries loaded into a process.
+///
+/// By default the binaries are found from the memory maps of a live process. Other sources,
+/// like a core dump, can be symbolicated by creating the symbolicator with `with_memory` and
+/// adding each binary with `add_module`. The memory is only read for binaries that aren't on
+/// disk, like the vdso.
+pub struct Symbolicator<M: ProcessMemory = Process> {
     binaries: BTreeMap<u64, BinaryInfo>,
-    process: Process,
-    pid: Pid,
+    memory: M,
+    loading: SymbolLoading,
+    cache: CacheBudget,
+    indexes: HashMap<String, PathBuf>,
+    sources: Vec<Box<dyn SymbolSource>>,
+    search_path: SymbolSearchPath,
 }
 
 impl Symbolicator {
+    /// Creates a symbolicator that loads the symbols of each binary lazily
     pub fn new(pid: Pid) -> Result<Self, Error> {
-        let process = Process::new(pid)?;
-        let mut ret = Self {
-            binaries: BTreeMap::new(),
-            process,
-            pid,
-        };
+        Self::with_loading(pid, SymbolLoading::Lazy)
+    }
+
+    pub fn with_loading(pid: Pid, loading: SymbolLoading) -> Result<Self, Error> {
+        let mut ret = Self::with_memory(Process::new(pid)?, loading);
         ret.reload()?;
         Ok(ret)
     }
@@ -35,11 +74,25 @@ impl Symbolicator {
         info!("reloading process binaries");
 
         // Get shared libraries from virtual memory mapped files
-        let maps = &proc_maps::get_process_maps(self.pid)?;
+        let maps = &proc_maps::get_process_maps(self.memory.pid)?;
         let shared_maps = maps
             .iter()
             .filter(|m| m.is_exec() && !m.is_write() && m.is_read());
 
+        // forget about binaries that have been unloaded, so that their symbols don't stick
+        // around for the lifetime of the symbolicator
+        let mapped: HashSet<u64> = shared_maps
+            .clone()
+            .map(|m| (m.start() + m.size()) as u64)
+            .collect();
+        self.binaries.retain(|key, binary| {
+            let keep = mapped.contains(key);
+            if !keep {
+                debug!("{} has been unloaded", binary.filename);
+            }
+            keep
+        });
+
         // Open them up and parse etc
         for m in shared_maps {
             // Get the filename if it exists from the map
@@ -54,107 +107,169 @@ impl Symbolicator {
                 debug!("skipping {}", filename.display());
                 continue;
             }
-            info!("loading debug info from {}", filename.display());
-
-            // Memory-map the file, special casing [vdso] regions
-            let file;
-            let mmapped_file;
-            let vdso_data;
-
-            let buffer = if Path::new(filename).exists() {
-                file = File::open(Path::new(filename))?;
-                mmapped_file = unsafe { Mmap::map(&file)? };
-                &mmapped_file[..]
-            } else if filename != std::path::PathBuf::from("[vsyscall]") {
-                // if the filename doesn't exist, its' almost certainly the vdso section
-                // read from the the target processes memory
-                vdso_data = self.process.copy(m.start(), m.size())?;
-                &vdso_data
-            } else {
-                // vsyscall region, can be ignored, but lets not keep on trying to do this
-                info!("skipping {} region", filename.display());
-
-                // insert a stub for [vsyscall] so that we don't continually try to load it etc
-                self.binaries.insert(
-                    address_key,
-                    BinaryInfo {
-                        offset: 0,
-                        address: m.start() as u64,
-                        size: m.size() as u64,
-                        filename: filename.display().to_string(),
-                        symbols: RefCell::new(None),
-                    },
-                );
-                continue;
-            };
+            self.add_module(
+                m.start() as u64,
+                m.size() as u64,
+                &filename.display().to_string(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<M: ProcessMemory> Symbolicator<M> {
+    /// Creates a symbolicator that reads binaries that aren't on disk from `memory`. This
+    /// doesn't know about any binaries until they are added with `add_module`.
+    pub fn with_memory(memory: M, loading: SymbolLoading) -> Self {
+        Self {
+            binaries: BTreeMap::new(),
+            memory,
+            loading,
+            cache: CacheBudget::new(None),
+            indexes: HashMap::new(),
+            sources: Vec::new(),
+            search_path: SymbolSearchPath::default(),
+        }
+    }
+
+    pub fn loading(&self) -> SymbolLoading {
+        self.loading
+    }
+
+    /// The memory that binaries missing from disk are read from
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    /// Limits the memory used by the symbols cached for each binary to roughly `limit` bytes,
+    /// evicting the symbols of the least recently used binaries once it is exceeded (they are
+    /// loaded again if needed). None, the default, keeps the symbols of every binary.
+    ///
+    /// The limit only covers the symbol tables and the line tables loaded from a `SymbolIndex`,
+    /// whose sizes are estimated. It doesn't include the binaries themselves, since they are
+    /// memory mapped and can be paged out by the kernel, or the DWARF units that addr2line
+    /// parses on demand when looking up line info, which are freed with the rest of a binary's
+    /// symbols when it's evicted. Each symbolicator has its own limit.
+    pub fn set_cache_limit(&mut self, limit: Option<usize>) {
+        self.cache.set_limit(limit);
+        self.cache
+            .enforce(self.binaries.values().map(|binary| &binary.symbols));
+    }
+
+    pub fn cache_limit(&self) -> Option<usize> {
+        self.cache.limit()
+    }
+
+    /// Returns the estimated number of bytes used by the cached symbol tables, which is what
+    /// the cache limit applies to
+    pub fn cache_size(&self) -> usize {
+        self.cache
+            .size(self.binaries.values().map(|binary| &binary.symbols))
+    }
+
+    /// Registers a source for the debug info of binaries that were stripped of it. Sources
+    /// are consulted in the order they were added, the first time the symbols of a binary
+    /// without debug info are loaded.
+    pub fn add_symbol_source(&mut self, source: Box<dyn SymbolSource>) {
+        self.sources.push(source);
+    }
+
+    /// Sets where to look for the debug info of each binary. This applies to binaries whose
+    /// symbols haven't been loaded yet, so it should be set before symbolicating anything.
+    pub fn set_symbol_search_path(&mut self, search_path: SymbolSearchPath) {
+        self.search_path = search_path;
+    }
+
+    pub fn symbol_search_path(&self) -> &SymbolSearchPath {
+        &self.search_path
+    }
+
+    /// Uses the `SymbolIndex` saved at `path` for the symbols of `module` (the filename of a
+    /// binary in the process), instead of parsing the binary itself. The index is loaded when
+    /// the symbols of the binary are first needed, and is ignored if its build id doesn't match
+    /// the binary.
+    pub fn add_symbol_index(&mut self, module: &str, path: impl Into<PathBuf>) {
+        self.indexes.insert(module.to_string(), path.into());
+        // drop symbols that were already loaded from the binary
+        for binary in self.binaries.values_mut() {
+            if binary.filename == module {
+                binary.symbols = CachedData::new();
+            }
+        }
+    }
 
-            debug!(
-                "loading file {} 0x{:X} 0x{:X}",
-                filename.display(),
-                m.start(),
-                buffer.len()
+    /// Adds a binary whose executable code is mapped at `address..address + size`, so that
+    /// addresses in it can be symbolicated. Binaries that don't exist on disk are read from
+    /// memory instead, since that's almost certainly the vdso. Replaces the binary that was
+    /// added for the same range before, if any.
+    pub fn add_module(&mut self, address: u64, size: u64, filename: &str) -> Result<(), Error> {
+        info!("loading debug info from {}", filename);
+        let address_key = address + size;
+
+        // Memory-map the file, special casing [vdso] regions
+        let file;
+        let mmapped_file;
+        let vdso_data;
+
+        let buffer = if Path::new(filename).exists() {
+            file = File::open(Path::new(filename))?;
+            mmapped_file = unsafe { Mmap::map(&file)? };
+            &mmapped_file[..]
+        } else if filename != "[vsyscall]" {
+            // if the filename doesn't exist, its' almost certainly the vdso section
+            // read from the the target processes memory
+            vdso_data = self.memory.copy(address as usize, size as usize)?;
+            &vdso_data
+        } else {
+            // vsyscall region, can be ignored, but lets not keep on trying to do this
+            info!("skipping {} region", filename);
+
+            // insert a stub for [vsyscall] so that we don't continually try to load it etc
+            self.binaries.insert(
+                address_key,
+                BinaryInfo {
+                    offset: 0,
+                    address,
+                    size,
+                    filename: filename.to_string(),
+                    symbols: CachedData::new(),
+                },
             );
-            match goblin::Object::parse(buffer) {
-                Ok(goblin::Object::Elf(elf)) => {
-                    trace!("filename {} elf {:#?}", filename.display(), elf);
-
-                    let program_header = elf
-                        .program_headers
-                        .iter()
-                        .find(|header| header.p_type == PT_LOAD && header.p_flags & PF_X != 0);
-
-                    let obj_base = match program_header {
-                        Some(hdr) => {
-                            // Don't panic if v_addr/start is messed up
-                            // (https://github.com/benfred/py-spy/issues/183)
-                            if hdr.p_vaddr > m.start() as u64 {
-                                warn!(
-                                    "Failed to load {} for symbols: v_addr {} is past start {}",
-                                    filename.display(),
-                                    hdr.p_vaddr,
-                                    m.start()
-                                );
-                                continue;
-                            }
-                            m.start() as u64 - hdr.p_vaddr + hdr.p_vaddr % hdr.p_align
-                        }
-                        None => {
-                            warn!(
-                                "Failed to find executable PT_LOAD header in {}",
-                                filename.display()
-                            );
-                            continue;
-                        }
-                    };
-
-                    let obj_base = if elf.header.e_type == goblin::elf::header::ET_DYN {
-                        obj_base
-                    } else {
-                        0
-                    };
-
-                    // the map key is the end address of this filename, which lets us do a relatively efficient range
-                    // based lookup of the binary
-                    self.binaries.insert(
-                        address_key,
-                        BinaryInfo {
-                            offset: obj_base,
-                            address: m.start() as u64,
-                            size: m.size() as u64,
-                            filename: filename.display().to_string(),
-                            symbols: RefCell::new(None),
-                        },
-                    );
-                }
-                Ok(_) => {
-                    warn!("unknown binary type for {}", filename.display());
-                    continue;
-                }
-                Err(e) => {
-                    warn!("Failed to parse {}: {:?}", filename.display(), e);
-                    continue;
-                }
+            return Ok(());
+        };
+
+        debug!(
+            "loading file {} 0x{:X} 0x{:X}",
+            filename,
+            address,
+            buffer.len()
+        );
+        let obj_base = match parse_layout(buffer).and_then(|layout| {
+            trace!("filename {} layout {:?}", filename, layout);
+            layout.load_bias(address)
+        }) {
+            Ok(obj_base) => obj_base,
+            Err(e) => {
+                warn!("Failed to load {} for symbols: {}", filename, e);
+                return Ok(());
             }
+        };
+
+        // the map key is the end address of this filename, which lets us do a relatively efficient range
+        // based lookup of the binary
+        self.binaries.insert(
+            address_key,
+            BinaryInfo {
+                offset: obj_base,
+                address,
+                size,
+                filename: filename.to_string(),
+                symbols: CachedData::new(),
+            },
+        );
+
+        if self.loading == SymbolLoading::Eager {
+            self.symbols(&self.binaries[&address_key]);
         }
         Ok(())
     }
@@ -165,6 +280,8 @@ impl Symbolicator {
         line_info: bool,
         callback: &mut dyn FnMut(&StackFrame),
     ) -> Result<(), Error> {
+        // return addresses read from the stack can be signed with pointer authentication
+        let addr = platform_info().strip_pointer_auth(addr);
         let binary = match self.get_binary(addr) {
             Some(binary) => binary,
             None => {
@@ -172,39 +289,147 @@ impl Symbolicator {
             }
         };
         if binary.filename != "[vdso]" {
-            let mut symbols = binary.symbols.borrow_mut();
-            if symbols.is_none() {
-                info!("loading symbols from {}", binary.filename);
-                *symbols = Some(SymbolData::new(&binary.filename, binary.offset));
-            }
-            match symbols.as_ref() {
+            let symbols = self.symbols(binary);
+            match symbols.as_deref() {
                 Some(Ok(symbols)) => symbols.symbolicate(addr, line_info, callback),
                 _ => {
                     // we probably failed to load the symbols (maybe goblin v0.15 dependency causing error
                     // in gimli/object crate). Rather than fail add a stub
-                    callback(&StackFrame {
-                        line: None,
-                        addr,
-                        function: None,
-                        filename: None,
-                        module: binary.filename.clone(),
-                    });
+                    callback(&binary.stub_frame(addr));
                     Ok(())
                 }
             }
         } else {
             // TODO: allow symbolication code to access vdso data
-            callback(&StackFrame {
-                line: None,
-                addr,
-                function: None,
-                filename: None,
-                module: binary.filename.clone(),
-            });
+            callback(&binary.stub_frame(addr));
             Ok(())
         }
     }
 
+    /// Symbolicates a batch of addresses, like every frame of a stack, returning the frames for
+    /// each address in the same order as `addrs`. This is the preferred way of symbolicating
+    /// more than a single address: the addresses are sorted and grouped by binary, so that
+    /// each binary is looked up once and its symbol tables and line index are walked in a
+    /// single pass in address order. Addresses looked up in the DWARF debug info are still
+    /// searched one at a time, though addr2line keeps the units it has parsed between them.
+    pub fn symbolicate_all(
+        &self,
+        addrs: &[u64],
+        line_info: bool,
+    ) -> Vec<Result<Vec<StackFrame>, Error>> {
+        let platform = platform_info();
+        let stripped: Vec<u64> = addrs
+            .iter()
+            .map(|&addr| platform.strip_pointer_auth(addr))
+            .collect();
+        let mut order: Vec<usize> = (0..addrs.len()).collect();
+        order.sort_unstable_by_key(|&i| stripped[i]);
+
+        let mut results: Vec<Option<Result<Vec<StackFrame>, Error>>> =
+            (0..addrs.len()).map(|_| None).collect();
+        let mut remaining = &order[..];
+        while let Some(&first) = remaining.first() {
+            let binary = match self.get_binary(stripped[first]) {
+                Some(binary) => binary,
+                None => {
+                    results[first] = Some(Err(Error::NoBinaryForAddress(addrs[first])));
+                    remaining = &remaining[1..];
+                    continue;
+                }
+            };
+
+            // once sorted, all the addresses in the same binary are next to each other
+            let count = remaining
+                .iter()
+                .take_while(|&&i| binary.contains(stripped[i]))
+                .count();
+            let (group, rest) = remaining.split_at(count);
+            remaining = rest;
+
+            let symbols = self.symbols(binary);
+            let symbols = match symbols.as_deref() {
+                Some(Ok(symbols)) => symbols,
+                _ => {
+                    for &i in group {
+                        results[i] = Some(Ok(vec![binary.stub_frame(stripped[i])]));
+                    }
+                    continue;
+                }
+            };
+            let mut cursor = SymbolCursor::default();
+            let mut previous: Option<usize> = None;
+            for &i in group {
+                // repeated addresses reuse the frames of the first one, but errors aren't
+                // cloneable so those are looked up again
+                let repeated = previous
+                    .filter(|&p| stripped[p] == stripped[i])
+                    .and_then(|p| match &results[p] {
+                        Some(Ok(frames)) => Some(frames.clone()),
+                        _ => None,
+                    });
+                let result = match repeated {
+                    Some(frames) => Ok(frames),
+                    None => symbols.lookup(stripped[i], line_info, &mut cursor),
+                };
+                results[i] = Some(result);
+                previous = Some(i);
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("address was symbolicated"))
+            .collect()
+    }
+
+    /// Returns the binary containing an address and the offset of the address inside of it,
+    /// without loading any symbols. This is much cheaper than `symbolicate`, for when the
+    /// symbolication itself is done later or elsewhere. This is only available on Linux, as
+    /// dbghelp on Windows resolves the module as part of symbolicating.
+    pub fn locate(&self, addr: u64) -> Result<ModuleLocation<'_>, Error> {
+        let addr = platform_info().strip_pointer_auth(addr);
+        let binary = self
+            .get_binary(addr)
+            .ok_or(Error::NoBinaryForAddress(addr))?;
+        Ok(ModuleLocation {
+            module: &binary.filename,
+            offset: addr - binary.offset,
+        })
+    }
+
+    /// Returns the symbols for a binary, loading them if they aren't cached
+    fn symbols<'a>(&self, binary: &'a BinaryInfo) -> Option<Ref<'a, Result<SymbolData, Error>>> {
+        // the vdso isn't a file on disk, and the vsyscall stub isn't a binary at all
+        if binary.filename == "[vdso]" || binary.filename == "[vsyscall]" {
+            return None;
+        }
+        let symbols = binary.symbols.get_or_load(&self.cache, || {
+            info!("loading symbols from {}", binary.filename);
+            let symbols = match self.indexes.get(&binary.filename) {
+                Some(path) => SymbolData::from_index_file(&binary.filename, binary.offset, path)
+                    .or_else(|e| {
+                        warn!("Failed to use symbol index {}: {}", path.display(), e);
+                        self.load_symbols(binary)
+                    }),
+                None => self.load_symbols(binary),
+            };
+            let size = symbols.as_ref().map_or(0, |symbols| symbols.memory_size());
+            (symbols, size)
+        });
+        self.cache
+            .enforce(self.binaries.values().map(|binary| &binary.symbols));
+        Some(symbols)
+    }
+
+    fn load_symbols(&self, binary: &BinaryInfo) -> Result<SymbolData, Error> {
+        SymbolData::load(
+            &binary.filename,
+            binary.offset,
+            &self.search_path,
+            &self.sources,
+        )
+    }
+
     fn get_binary(&self, addr: u64) -> Option<&BinaryInfo> {
         match self.binaries.range(addr..).next() {
             Some((_, binary)) if binary.contains(addr) => Some(binary),
@@ -216,7 +441,7 @@ impl Symbolicator {
 
 pub struct SymbolData {
     // Contains symbol info for a single binary
-    address_loader: Loader,
+    line_info: LineInfo,
     offset: u64,
     symbols: Vec<(u64, u64, String)>,
     dynamic_symbols: Vec<(u64, u64, String)>,
@@ -225,6 +450,17 @@ pub struct SymbolData {
 
 impl SymbolData {
     pub fn new(filename: &str, offset: u64) -> Result<Self, Error> {
+        Self::load(filename, offset, &SymbolSearchPath::default(), &[])
+    }
+
+    /// Loads the symbols of a binary, with the debug info from the first place in `search_path`
+    /// that has it
+    fn load(
+        filename: &str,
+        offset: u64,
+        search_path: &SymbolSearchPath,
+        sources: &[Box<dyn SymbolSource>],
+    ) -> Result<Self, Error> {
         info!("opening {} for symbols", filename);
 
         let file = File::open(filename)?;
@@ -243,36 +479,53 @@ impl SymbolData {
             }
         };
 
-        let address_loader = Loader::new(filename).map_err(|e| {
+        let debug_file = search_path
+            .find(&file, filename, sources)
+            .unwrap_or_else(|| PathBuf::from(filename));
+        let address_loader = Loader::new(&debug_file).map_err(|e| {
             Error::Other(format!(
                 "Failed to get symbol context for {}: {:?}",
-                filename, e
+                debug_file.display(),
+                e
             ))
         })?;
 
-        let mut symbols = Vec::new();
-        for sym in file.symbols() {
-            if sym.size() == 0 {
-                continue;
-            }
-            if let Ok(name) = sym.name() {
-                symbols.push((sym.address(), sym.size(), name.to_string()));
-            }
-        }
-        symbols.sort_unstable();
+        let (symbols, dynamic_symbols) = read_symbols(&file);
+        Ok(Self {
+            line_info: LineInfo::Dwarf(Box::new(address_loader)),
+            offset,
+            dynamic_symbols,
+            symbols,
+            filename: filename.to_owned(),
+        })
+    }
 
-        let mut dynamic_symbols = Vec::new();
-        for sym in file.dynamic_symbols() {
-            if sym.size() == 0 {
-                continue;
-            }
-            if let Ok(name) = sym.name() {
-                dynamic_symbols.push((sym.address(), sym.size(), name.to_string()));
+    /// Loads the symbols of a binary from a `SymbolIndex` saved at `path`. If the binary is on
+    /// disk, its build id has to match the one the index was built from.
+    fn from_index_file(filename: &str, offset: u64, path: &Path) -> Result<Self, Error> {
+        info!("loading symbol index {} for {}", path.display(), filename);
+        let index = SymbolIndex::load(path)?;
+        if let (Some(expected), Ok(file)) = (index.build_id(), File::open(filename)) {
+            let map = unsafe { Mmap::map(&file)? };
+            let build_id = object::File::parse(&*map)
+                .ok()
+                .and_then(|file| file.build_id().ok().flatten());
+            if build_id.is_some_and(|build_id| build_id != expected) {
+                return Err(Error::Other(format!(
+                    "Symbol index was built from a different version of {}",
+                    filename
+                )));
             }
         }
-        dynamic_symbols.sort_unstable();
+        let SymbolIndex {
+            symbols,
+            dynamic_symbols,
+            files,
+            lines,
+            ..
+        } = index;
         Ok(Self {
-            address_loader,
+            line_info: LineInfo::Index { files, lines },
             offset,
             dynamic_symbols,
             symbols,
@@ -280,16 +533,52 @@ impl SymbolData {
         })
     }
 
+    /// Returns an estimate of the number of bytes used by the symbol tables. This doesn't count
+    /// the DWARF debug info, since addr2line parses it lazily and doesn't report its size.
+    fn memory_size(&self) -> usize {
+        let size = |symbols: &[(u64, u64, String)]| -> usize {
+            symbols
+                .iter()
+                .map(|sym| size_of_val(sym) + sym.2.capacity())
+                .sum()
+        };
+        let lines = match &self.line_info {
+            LineInfo::Dwarf(_) => 0,
+            LineInfo::Index { files, lines } => {
+                files.iter().map(|file| file.capacity()).sum::<usize>() + size_of_val(&lines[..])
+            }
+        };
+        size_of::<Self>() + size(&self.symbols) + size(&self.dynamic_symbols) + lines
+    }
+
     pub fn symbolicate(
         &self,
         addr: u64,
         line_info: bool,
         callback: &mut dyn FnMut(&StackFrame),
     ) -> Result<(), Error> {
+        for frame in self.lookup(addr, line_info, &mut SymbolCursor::default())? {
+            callback(&frame);
+        }
+        Ok(())
+    }
+
+    /// Returns the frames for an address. The cursor remembers where the previous lookup
+    /// ended up in the symbol tables, so that looking up addresses in ascending order only
+    /// searches the part of the tables past the previous address.
+    fn lookup(
+        &self,
+        addr: u64,
+        line_info: bool,
+        cursor: &mut SymbolCursor,
+    ) -> Result<Vec<StackFrame>, Error> {
         let mut ret = StackFrame {
             line: None,
+            column: None,
             filename: None,
             function: None,
+            function_start: None,
+            function_size: None,
             addr,
             module: self.filename.clone(),
         };
@@ -297,62 +586,122 @@ impl SymbolData {
         // get the address before relocations
         let offset = addr - self.offset;
 
+        // the symbol tables have the bounds of the function, even if the debug info has a
+        // better name for it
+        let symbol = find_symbol(&self.symbols, &mut cursor.symbols, offset)
+            .or_else(|| find_symbol(&self.dynamic_symbols, &mut cursor.dynamic_symbols, offset));
+        if let Some((start, size, _)) = symbol {
+            ret.function_start = Some(start + self.offset);
+            ret.function_size = Some(*size);
+        }
+
         // if we are being asked for line information, sue gimli addr2line to look up the debug info
         // (this is slow, and not necessary all the time which is why we are skipping)
-        if line_info {
-            let mut has_debug_info = false;
+        if let (true, LineInfo::Index { files, lines }) = (line_info, &self.line_info) {
+            // like the symbols, only search the line table past the previous address
+            let start = cursor.lines.min(lines.len());
+            let i = start + lines[start..].partition_point(|row| row.address <= offset);
+            cursor.lines = i.saturating_sub(1).max(start);
+            if let Some(row) = find_line(&lines[cursor.lines..], offset) {
+                ret.line = Some(row.line as u64);
+                ret.column = Some(row.column as u64).filter(|&column| column != 0);
+                ret.filename = files.get(row.file as usize).cloned();
+            }
+        }
+        if let (true, LineInfo::Dwarf(address_loader)) = (line_info, &self.line_info) {
+            let mut frames = Vec::new();
+
+            // a stripped binary can have its full symbol table in the separate debug file,
+            // which only gives us the start of the function
+            if symbol.is_none() {
+                ret.function_start = address_loader
+                    .find_symbol_info(offset)
+                    .map(|symbol| symbol.address() + self.offset);
+            }
 
             // if we have debugging info, get the appropriate stack frames for the address
-            let mut frames = self
-                .address_loader
+            let mut iter = address_loader
                 .find_frames(offset)
                 .map_err(|e| Error::Other(format!("addr2line error: {:?}", e)))?;
 
             let error_handler = |e| Error::Other(format!("addr2line error: {:?}", e));
-            while let Some(frame) = frames.next().map_err(error_handler)? {
-                has_debug_info = true;
+            while let Some(frame) = iter.next().map_err(error_handler)? {
                 if let Some(func) = frame.function {
                     ret.function = Some(func.raw_name().map_err(error_handler)?.to_string());
                 }
                 if let Some(loc) = frame.location {
                     ret.line = loc.line.map(|x| x as u64);
+                    ret.column = loc.column.map(|x| x as u64);
                     if let Some(file) = loc.file.as_ref() {
                         ret.filename = Some(file.to_string());
                     }
                 }
-                callback(&ret);
+                frames.push(ret.clone());
             }
 
-            if has_debug_info {
-                return Ok(());
+            if !frames.is_empty() {
+                return Ok(frames);
             }
         }
 
         // otherwise try getting the function name from the symbols
-        if !self.symbols.is_empty() {
-            let symbol = match self.symbols.binary_search_by(|sym| sym.0.cmp(&offset)) {
-                Ok(i) => &self.symbols[i],
-                Err(i) => &self.symbols[if i > 0 { i - 1 } else { 0 }],
-            };
-            if offset >= symbol.0 && offset < (symbol.0 + symbol.1) {
-                ret.function = Some(symbol.2.clone());
-            }
-        }
+        ret.function = symbol.map(|(_, _, name)| name.clone());
+        Ok(vec![ret])
+    }
+}
 
-        if ret.function.is_none() && !self.dynamic_symbols.is_empty() {
-            let symbol = match self
-                .dynamic_symbols
-                .binary_search_by(|sym| sym.0.cmp(&offset))
-            {
-                Ok(i) => &self.dynamic_symbols[i],
-                Err(i) => &self.dynamic_symbols[if i > 0 { i - 1 } else { 0 }],
-            };
-            if offset >= symbol.0 && offset < (symbol.0 + symbol.1) {
-                ret.function = Some(symbol.2.clone());
-            }
-        }
-        callback(&ret);
-        Ok(())
+/// Where the line info of a binary comes from
+enum LineInfo {
+    /// The DWARF debug info of the binary, which also has the inlined frames
+    Dwarf(Box<Loader>),
+    /// The line table from a `SymbolIndex`
+    Index {
+        files: Vec<String>,
+        lines: Vec<LineRow>,
+    },
+}
+
+/// Reads the symbol and dynamic symbol tables of a binary, sorted by address
+pub(super) fn read_symbols(file: &object::File<'_>) -> SymbolTables {
+    let read = |symbols: &mut dyn Iterator<Item = object::Symbol<'_, '_>>| {
+        let mut ret: Vec<(u64, u64, String)> = symbols
+            .filter(|sym| sym.size() != 0)
+            .filter_map(|sym| Some((sym.address(), sym.size(), sym.name().ok()?.to_string())))
+            .collect();
+        ret.sort_unstable();
+        ret
+    };
+    (read(&mut file.symbols()), read(&mut file.dynamic_symbols()))
+}
+
+type SymbolTables = (Vec<(u64, u64, String)>, Vec<(u64, u64, String)>);
+
+/// Position in the symbol tables of a binary, for looking up sorted addresses in one pass
+#[derive(Default)]
+struct SymbolCursor {
+    symbols: usize,
+    dynamic_symbols: usize,
+    lines: usize,
+}
+
+/// Returns the symbol containing offset. Only the symbols from `start` onwards are searched,
+/// and `start` is moved up to the last symbol starting at or before offset.
+fn find_symbol<'a>(
+    symbols: &'a [(u64, u64, String)],
+    start: &mut usize,
+    offset: u64,
+) -> Option<&'a (u64, u64, String)> {
+    let remaining = symbols.get(*start..)?;
+    let i = remaining.partition_point(|sym| sym.0 <= offset);
+    if i == 0 {
+        return None;
+    }
+    *start += i - 1;
+    let symbol = &symbols[*start];
+    if offset >= symbol.0 && offset < (symbol.0 + symbol.1) {
+        Some(symbol)
+    } else {
+        None
     }
 }
 
@@ -362,11 +711,87 @@ struct BinaryInfo {
     size: u64,
     offset: u64,
     filename: String,
-    symbols: RefCell<Option<Result<SymbolData, Error>>>,
+    symbols: CachedData<Result<SymbolData, Error>>,
 }
 
 impl BinaryInfo {
     pub fn contains(&self, addr: u64) -> bool {
         addr >= self.address && addr < (self.address + self.size)
     }
+
+    /// A frame for an address in this binary that we don't have symbols for
+    fn stub_frame(&self, addr: u64) -> StackFrame {
+        StackFrame {
+            line: None,
+            column: None,
+            addr,
+            function: None,
+            function_start: None,
+            function_size: None,
+            filename: None,
+            module: self.filename.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_symbol() {
+        let symbols = vec![
+            (0x100, 0x10, "a".to_string()),
+            (0x200, 0x20, "b".to_string()),
+            (0x300, 0x30, "c".to_string()),
+        ];
+        let mut start = 0;
+        assert_eq!(find_symbol(&symbols, &mut start, 0x50), None);
+        assert_eq!(find_symbol(&symbols, &mut start, 0x105), Some(&symbols[0]));
+        assert_eq!(find_symbol(&symbols, &mut start, 0x110), None);
+        assert_eq!(find_symbol(&symbols, &mut start, 0x21f), Some(&symbols[1]));
+        assert_eq!(start, 1);
+        assert_eq!(find_symbol(&symbols, &mut start, 0x300), Some(&symbols[2]));
+        assert_eq!(find_symbol(&symbols, &mut start, 0x1000), None);
+        assert_eq!(find_symbol(&[], &mut 0, 0x100), None);
+    }
+
+    #[inline(never)]
+    fn symbolicated_function() -> u64 {
+        std::hint::black_box(symbolicated_function as *const () as u64)
+    }
+
+    #[test]
+    fn test_function_bounds() {
+        let start = symbolicated_function();
+        let symbolicator = Symbolicator::new(std::process::id() as Pid).unwrap();
+        let mut frames = Vec::new();
+        symbolicator
+            .symbolicate(start + 1, true, &mut |frame| frames.push(frame.clone()))
+            .unwrap();
+        let frame = frames.last().unwrap();
+        assert_eq!(frame.function_start, Some(start));
+        assert!(frame.function_size.unwrap() > 1);
+    }
+    #[test]
+    fn test_symbolicate_all() {
+        let start = symbolicated_function();
+        let symbolicator = Symbolicator::new(std::process::id() as Pid).unwrap();
+        let results = symbolicator.symbolicate_all(&[start + 1, 0, start + 1, start], true);
+        assert_eq!(results.len(), 4);
+        assert!(matches!(
+            results[1].as_ref().map_err(Error::root),
+            Err(Error::NoBinaryForAddress(0))
+        ));
+
+        let frames = results[0].as_ref().unwrap();
+        assert_eq!(frames.last().unwrap().function_start, Some(start));
+        let repeated = results[2].as_ref().unwrap();
+        assert_eq!(repeated.len(), frames.len());
+        assert_eq!(
+            repeated.last().unwrap().function,
+            frames.last().unwrap().function
+        );
+        assert_eq!(results[3].as_ref().unwrap().last().unwrap().addr, start);
+    }
 }
@@ -0,0 +1,630 @@
+//! Recovers the local variables of a frame from the debug info of the function it's in
+
+use super::debug_info::{
+    dwarf_error, unit_entry, DebugInfo, DieRef, Reader, RemoteValue, TypeInfo, TypeKind,
+};
+use super::dwarf_unwind::{FrameSource, UnwoundFrame};
+use super::location::{register_error, FrameContext, VariableLocation};
+use super::Registers;
+use crate::{Error, ProcessMemory};
+
+/// The DWARF numbers of the registers the first integer arguments are passed in: rdi, rsi, rdx,
+/// rcx, r8 and r9
+#[cfg(target_arch = "x86_64")]
+const ARGUMENT_REGISTERS: [u16; 6] = [5, 4, 1, 2, 8, 9];
+/// The DWARF numbers of the registers the first integer arguments are passed in: x0 to x7
+#[cfg(target_arch = "aarch64")]
+const ARGUMENT_REGISTERS: [u16; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+/// Where the arguments that didn't fit into registers start, relative to the stack pointer at
+/// the first instruction of a function. On x86_64 the return address is in the way.
+#[cfg(target_arch = "x86_64")]
+const STACK_ARGUMENTS_OFFSET: u64 = 8;
+#[cfg(target_arch = "aarch64")]
+const STACK_ARGUMENTS_OFFSET: u64 = 0;
+
+/// Whether the pointer to the memory a struct is returned in takes up the first argument
+/// register. On aarch64 it's passed in x8 instead.
+#[cfg(target_arch = "x86_64")]
+const RETURN_POINTER_IN_ARGUMENTS: bool = true;
+#[cfg(target_arch = "aarch64")]
+const RETURN_POINTER_IN_ARGUMENTS: bool = false;
+
+/// A variable of the function a frame is in, as returned by `SnapshotCursor::locals`,
+/// `DebugInfo::locals` and `DebugInfo::arguments`
+#[derive(Debug)]
+pub struct FrameVariable {
+    pub name: String,
+    /// The name of the function inlined into the frame that the variable belongs to, or None
+    /// for the variables of the function the frame is in
+    pub inlined: Option<String>,
+    /// The type of the variable, if the debug info has it
+    pub ty: Option<TypeInfo>,
+    /// The value of the variable, or why it couldn't be read. Variables can be optimized out
+    /// at the instruction the frame is at, or kept in registers that aren't preserved across
+    /// calls (and so are only known for the innermost frame).
+    pub value: Result<RemoteValue, Error>,
+}
+
+/// The function a frame is in, and what the locations of its variables are evaluated against
+struct FrameScope<'a> {
+    function: DieRef,
+    /// The instruction the frame is at, relative to the addresses in the debug info
+    pc: u64,
+    context: FrameContext<'a>,
+}
+
+impl DebugInfo {
+    /// Returns the local variables that are in scope at the instruction a frame is at,
+    /// including those of the functions inlined at it. The bias is how far the binary was
+    /// shifted from the addresses in its debug info, as returned by
+    /// `SnapshotUnwinder::module_bias`. `SnapshotCursor::locals` does this for the binary
+    /// of each frame.
+    pub fn locals(
+        &self,
+        memory: &impl ProcessMemory,
+        frame: &UnwoundFrame,
+        bias: u64,
+    ) -> Result<Vec<FrameVariable>, Error> {
+        let scope = self.frame_scope(memory, frame, bias)?;
+        self.frame_variables(memory, &scope, gimli::DW_TAG_variable)
+    }
+
+    fn frame_scope<'a>(
+        &self,
+        memory: &impl ProcessMemory,
+        frame: &'a UnwoundFrame,
+        bias: u64,
+    ) -> Result<FrameScope<'a>, Error> {
+        // the ip of calling frames is the return address, which can be past the end of the
+        // function if it ends with a call
+        let ip = match frame.source {
+            FrameSource::Context => frame.ip,
+            _ => frame.ip.wrapping_sub(1),
+        };
+        let pc = ip.wrapping_sub(bias);
+        let function = self.function_at(pc).ok_or_else(|| {
+            Error::Other(format!(
+                "No function in the debug info contains 0x{:016x}",
+                frame.ip
+            ))
+        })?;
+
+        let mut context = FrameContext {
+            registers: Some(&frame.registers),
+            cfa: frame.cfa,
+            frame_base: None,
+            bias,
+        };
+        // variables that aren't relative to the frame base can still be read without it, so
+        // failing to find it is left to the variables that need it
+        let frame_base = self.location_attr(
+            function,
+            gimli::DW_AT_frame_base,
+            memory,
+            &context,
+            Some(pc),
+        );
+        context.frame_base = match frame_base {
+            Ok(VariableLocation::Address(address)) => Some(address),
+            // a frame base of a register means the value of the register, not where it's stored
+            Ok(VariableLocation::Register(register)) => frame.registers.get(register),
+            _ => None,
+        };
+        Ok(FrameScope {
+            function,
+            pc,
+            context,
+        })
+    }
+
+    /// Returns the arguments the function of a frame was called with, from the locations the
+    /// debug info has for its parameters, in the order they are declared.
+    ///
+    /// At the first instruction of the innermost frame (like when the thread is stopped at a
+    /// breakpoint on the function), the arguments are read from the registers and stack slots
+    /// the C calling convention passes them in instead, since unoptimized code only has them
+    /// where the debug info says once the prologue has stored them. The same goes for
+    /// arguments the debug info has no location for. Only integers, pointers and enums of up
+    /// to 8 bytes are recovered this way, and arguments after one of any other type aren't.
+    pub fn arguments(
+        &self,
+        memory: &impl ProcessMemory,
+        frame: &UnwoundFrame,
+        bias: u64,
+    ) -> Result<Vec<FrameVariable>, Error> {
+        let scope = self.frame_scope(memory, frame, bias)?;
+        let dies: Vec<DieRef> = self
+            .scope_dies(&scope, gimli::DW_TAG_formal_parameter)?
+            .into_iter()
+            .map(|(die, _)| die)
+            .collect();
+        let mut arguments = dies
+            .iter()
+            .map(|die| self.frame_variable(memory, &scope, *die, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        if frame.source == FrameSource::Context && self.entry_pc(scope.function)? == Some(scope.pc)
+        {
+            self.calling_convention_arguments(memory, &scope, &dies, &mut arguments)?;
+        }
+        Ok(arguments)
+    }
+
+    /// Reads the variables with `tag` in the function of a frame, from the lexical blocks (and
+    /// for local variables, the inlined functions) that contain the instruction the frame is at
+    fn frame_variables(
+        &self,
+        memory: &impl ProcessMemory,
+        scope: &FrameScope<'_>,
+        tag: gimli::DwTag,
+    ) -> Result<Vec<FrameVariable>, Error> {
+        self.scope_dies(scope, tag)?
+            .into_iter()
+            .map(|(die, inlined)| self.frame_variable(memory, scope, die, inlined))
+            .collect()
+    }
+
+    /// Returns the entries with `tag` that are in scope in a frame, along with the inlined
+    /// subroutine they are in
+    fn scope_dies(
+        &self,
+        scope: &FrameScope<'_>,
+        tag: gimli::DwTag,
+    ) -> Result<Vec<(DieRef, Option<DieRef>)>, Error> {
+        let unit = &self.units[scope.function.unit];
+        let mut tree = unit
+            .entries_tree(Some(scope.function.offset))
+            .map_err(dwarf_error)?;
+        let mut dies = Vec::new();
+        self.scope_variables(
+            scope.function.unit,
+            tree.root().map_err(dwarf_error)?,
+            scope.pc,
+            tag,
+            None,
+            &mut dies,
+        )?;
+        Ok(dies)
+    }
+
+    fn frame_variable(
+        &self,
+        memory: &impl ProcessMemory,
+        scope: &FrameScope<'_>,
+        die: DieRef,
+        inlined: Option<DieRef>,
+    ) -> Result<FrameVariable, Error> {
+        let inlined = match inlined {
+            Some(subroutine) => {
+                let origin = self.origin(subroutine)?;
+                let entry = unit_entry(&self.units[origin.unit], origin.offset)?;
+                self.name(&self.units[origin.unit], &entry)?
+            }
+            None => None,
+        };
+        let origin = self.origin(die)?;
+        let entry = unit_entry(&self.units[origin.unit], origin.offset)?;
+        let name = self
+            .name(&self.units[origin.unit], &entry)?
+            .unwrap_or_default();
+        let ty = self
+            .type_attr(origin)?
+            .map(|ty| self.type_info(ty, 0))
+            .transpose()
+            .ok()
+            .flatten();
+        let value = self
+            .location_attr(
+                die,
+                gimli::DW_AT_location,
+                memory,
+                &scope.context,
+                Some(scope.pc),
+            )
+            .and_then(|location| {
+                self.read_variable(origin, &location, memory, scope.context.registers)
+            });
+        Ok(FrameVariable {
+            name,
+            inlined,
+            ty,
+            value,
+        })
+    }
+
+    /// Replaces the values of arguments with those from where the calling convention passes
+    /// them, for a frame at the first instruction of its function
+    fn calling_convention_arguments(
+        &self,
+        memory: &impl ProcessMemory,
+        scope: &FrameScope<'_>,
+        dies: &[DieRef],
+        arguments: &mut [FrameVariable],
+    ) -> Result<(), Error> {
+        let registers = scope
+            .context
+            .registers
+            .ok_or_else(|| register_error(Registers::SP))?;
+        // structs returned in memory are written to a pointer passed as a hidden first argument
+        let first = usize::from(RETURN_POINTER_IN_ARGUMENTS && self.returns_in_memory(scope)?);
+        for (slot, (die, argument)) in (first..).zip(dies.iter().zip(arguments)) {
+            let Some(ty) = argument.ty.clone() else {
+                break;
+            };
+            let size = ty.size.unwrap_or(0) as usize;
+            let origin = self.origin(*die)?;
+            let is_integer = match self.type_attr(origin)? {
+                Some(ty) => self.is_integer(ty)?,
+                None => false,
+            };
+            if !is_integer || size == 0 || size > 8 {
+                break;
+            }
+            // location lists describe where arguments are at each instruction, including the
+            // first one, so those are kept
+            if argument.value.is_err() || !self.has_location_list(*die)? {
+                let value = match ARGUMENT_REGISTERS.get(slot) {
+                    Some(register) => registers
+                        .get(*register)
+                        .ok_or_else(|| register_error(*register))
+                        .map(|value| (0, value.to_le_bytes()[..size].to_vec())),
+                    None => registers
+                        .sp()
+                        .ok_or_else(|| register_error(Registers::SP))
+                        .and_then(|sp| {
+                            let index = (slot - ARGUMENT_REGISTERS.len()) as u64;
+                            let address = sp + STACK_ARGUMENTS_OFFSET + index * 8;
+                            Ok((address, memory.copy(address as usize, size)?))
+                        }),
+                };
+                argument.value = value.map(|(address, data)| self.remote(address, ty, data));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entry that a variable or function inherits its name and type from, which for
+    /// the out of line copies of inlined functions (and their variables) is the abstract entry
+    /// of the inlined function
+    fn origin(&self, die: DieRef) -> Result<DieRef, Error> {
+        Ok(self
+            .ref_attr(die, gimli::DW_AT_abstract_origin)?
+            .unwrap_or(die))
+    }
+
+    /// Returns the address of the first instruction of a function
+    fn entry_pc(&self, function: DieRef) -> Result<Option<u64>, Error> {
+        let unit = &self.units[function.unit];
+        let entry = unit_entry(unit, function.offset)?;
+        match entry.attr_value(gimli::DW_AT_low_pc).map_err(dwarf_error)? {
+            Some(value) => self.dwarf.attr_address(unit, value).map_err(dwarf_error),
+            None => Ok(None),
+        }
+    }
+
+    fn has_location_list(&self, die: DieRef) -> Result<bool, Error> {
+        let entry = unit_entry(&self.units[die.unit], die.offset)?;
+        Ok(matches!(
+            entry
+                .attr_value(gimli::DW_AT_location)
+                .map_err(dwarf_error)?,
+            Some(value) if !matches!(value, gimli::AttributeValue::Exprloc(_))
+        ))
+    }
+
+    /// Returns whether a type is passed in the general purpose registers, like an integer or a
+    /// pointer
+    fn is_integer(&self, die: DieRef) -> Result<bool, Error> {
+        let die = self.strip(die)?;
+        Ok(match self.entry_tag(die)? {
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type
+            | gimli::DW_TAG_enumeration_type => true,
+            gimli::DW_TAG_base_type => {
+                let entry = unit_entry(&self.units[die.unit], die.offset)?;
+                !matches!(
+                    entry
+                        .attr_value(gimli::DW_AT_encoding)
+                        .map_err(dwarf_error)?,
+                    Some(gimli::AttributeValue::Encoding(
+                        gimli::DW_ATE_float | gimli::DW_ATE_complex_float
+                    ))
+                )
+            }
+            _ => false,
+        })
+    }
+
+    /// Returns whether the function of a frame returns a struct too large for registers
+    fn returns_in_memory(&self, scope: &FrameScope<'_>) -> Result<bool, Error> {
+        let Some(ty) = self.type_attr(self.origin(scope.function)?)? else {
+            return Ok(false);
+        };
+        let ty = self.type_info(ty, 0)?;
+        Ok(matches!(
+            ty.kind,
+            TypeKind::Struct | TypeKind::Union | TypeKind::Array
+        ) && ty.size.is_some_and(|size| size > 16))
+    }
+
+    /// Collects the children of `node` with `tag`, descending into the lexical blocks that
+    /// contain `pc`. Local variables are also collected from the functions inlined at `pc`,
+    /// where the parameters of the inlined function count as locals of the frame too.
+    fn scope_variables(
+        &self,
+        unit: usize,
+        node: gimli::EntriesTreeNode<'_, '_, '_, Reader>,
+        pc: u64,
+        tag: gimli::DwTag,
+        inlined: Option<DieRef>,
+        dies: &mut Vec<(DieRef, Option<DieRef>)>,
+    ) -> Result<(), Error> {
+        let mut children = node.children();
+        while let Some(child) = children.next().map_err(dwarf_error)? {
+            let entry = child.entry();
+            let die = DieRef {
+                unit,
+                offset: entry.offset(),
+            };
+            if entry.tag() == tag
+                || (inlined.is_some() && entry.tag() == gimli::DW_TAG_formal_parameter)
+            {
+                dies.push((die, inlined));
+            } else if entry.tag() == gimli::DW_TAG_lexical_block
+                && self.contains(unit, entry, pc)?
+            {
+                self.scope_variables(unit, child, pc, tag, inlined, dies)?;
+            } else if tag == gimli::DW_TAG_variable
+                && entry.tag() == gimli::DW_TAG_inlined_subroutine
+                && self.contains(unit, entry, pc)?
+            {
+                self.scope_variables(unit, child, pc, tag, Some(die), dies)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the code of a lexical block or inlined subroutine contains `pc`. Entries
+    /// without any addresses are treated as covering the whole function.
+    fn contains(
+        &self,
+        unit: usize,
+        entry: &gimli::DebuggingInformationEntry<'_, '_, Reader>,
+        pc: u64,
+    ) -> Result<bool, Error> {
+        let mut ranges = self
+            .dwarf
+            .die_ranges(&self.units[unit], entry)
+            .map_err(dwarf_error)?;
+        let mut empty = true;
+        while let Some(range) = ranges.next().map_err(dwarf_error)? {
+            if range.begin <= pc && pc < range.end {
+                return Ok(true);
+            }
+            empty = false;
+        }
+        Ok(empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::symbol_search::current_exe_has_debug_info;
+    use crate::{Pid, Process, SnapshotUnwinder, StackSnapshot};
+
+    /// Returns the registers at the point in the function this is expanded into
+    macro_rules! current_registers {
+        () => {{
+            let (sp, fp, ip): (u64, u64, u64);
+            #[cfg(target_arch = "x86_64")]
+            unsafe {
+                std::arch::asm!(
+                    "mov {}, rsp",
+                    "mov {}, rbp",
+                    "lea {}, [rip]",
+                    out(reg) sp,
+                    out(reg) fp,
+                    out(reg) ip
+                );
+            }
+            #[cfg(target_arch = "aarch64")]
+            unsafe {
+                std::arch::asm!(
+                    "mov {}, sp",
+                    "mov {}, x29",
+                    "adr {}, .",
+                    out(reg) sp,
+                    out(reg) fp,
+                    out(reg) ip
+                );
+            }
+            let mut registers = Registers::default();
+            registers.set(Registers::SP, sp);
+            registers.set(Registers::FP, fp);
+            registers.set(Registers::IP, ip);
+            registers
+        }};
+    }
+
+    #[inline(never)]
+    fn arguments_frame(
+        debug_info: &DebugInfo,
+        unwinder: &SnapshotUnwinder,
+        first: u64,
+        second: i32,
+    ) -> Vec<FrameVariable> {
+        std::hint::black_box((first, second));
+        let registers = current_registers!();
+        let sp = registers.sp().unwrap();
+        let stack = unwinder.memory().copy(sp as usize, 16 * 1024).unwrap();
+        let snapshot = StackSnapshot::new(0, registers, sp, stack);
+        let frame = unwinder
+            .cursor(&snapshot)
+            .unwrap()
+            .next_frame()
+            .unwrap()
+            .unwrap();
+        let (_, bias) = unwinder.module_bias(frame.ip).unwrap();
+        debug_info
+            .arguments(unwinder.memory(), &frame, bias)
+            .unwrap()
+    }
+
+    #[inline(never)]
+    extern "C" fn many_arguments(
+        a: u64,
+        b: u64,
+        c: u64,
+        d: u64,
+        e: u64,
+        f: u64,
+        g: u64,
+        h: u64,
+        i: u64,
+    ) -> u64 {
+        a + b + c + d + e + f + g + h + i
+    }
+
+    #[test]
+    fn test_arguments() {
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let debug_info =
+            DebugInfo::load(&std::env::current_exe().unwrap().display().to_string()).unwrap();
+        let pid = std::process::id() as Pid;
+        let unwinder = SnapshotUnwinder::new(pid).unwrap();
+
+        // from the locations in the debug info, once the prologue has run
+        let arguments = arguments_frame(&debug_info, &unwinder, 0xabcd, -3);
+        let names: Vec<&str> = arguments.iter().map(|arg| arg.name.as_str()).collect();
+        assert_eq!(names, ["debug_info", "unwinder", "first", "second"]);
+        let value = |index: usize| arguments[index].value.as_ref().unwrap();
+        assert_eq!(value(0).as_u64(), Some(&debug_info as *const _ as u64));
+        assert_eq!(value(2).as_u64(), Some(0xabcd));
+        assert_eq!(value(3).as_i64(), Some(-3));
+
+        // from the calling convention, at the first instruction of the function
+        let entry = |function: u64, stack: &[u64], arguments: &[u64]| {
+            let mut registers = Registers::default();
+            registers.set(Registers::IP, function);
+            registers.set(Registers::SP, stack.as_ptr() as u64);
+            for (register, value) in ARGUMENT_REGISTERS.iter().zip(arguments) {
+                registers.set(*register, *value);
+            }
+            let frame = UnwoundFrame {
+                ip: function,
+                sp: stack.as_ptr() as u64,
+                fp: None,
+                cfa: None,
+                registers,
+                source: FrameSource::Context,
+            };
+            let (_, bias) = unwinder.module_bias(function).unwrap();
+            debug_info
+                .arguments(unwinder.memory(), &frame, bias)
+                .unwrap()
+        };
+        let function = arguments_frame as *const () as u64;
+        // the returned Vec is written to memory passed in as a hidden first argument on x86_64
+        let mut registers = vec![1, 2, 0xfeed, (-4_i32) as u32 as u64];
+        if RETURN_POINTER_IN_ARGUMENTS {
+            registers.insert(0, 0);
+        }
+        let arguments = entry(function, &[0; 4], &registers);
+        assert_eq!(arguments[2].value.as_ref().unwrap().as_u64(), Some(0xfeed));
+        assert_eq!(arguments[3].value.as_ref().unwrap().as_i64(), Some(-4));
+
+        // arguments that don't fit into registers are on the stack
+        let function = std::hint::black_box(many_arguments as *const ()) as u64;
+        let stack: Vec<u64> = (0..4).map(|i| 100 + i).collect();
+        let arguments = entry(function, &stack, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(arguments.len(), 9);
+        assert_eq!(arguments[0].value.as_ref().unwrap().as_u64(), Some(1));
+        let last = arguments[8].value.as_ref().unwrap();
+        let slot = (STACK_ARGUMENTS_OFFSET / 8) as usize + 8 - ARGUMENT_REGISTERS.len();
+        assert_eq!(last.as_u64(), Some(stack[slot]));
+        assert_eq!(last.address, &stack[slot] as *const u64 as u64);
+    }
+
+    /// Returns the locals of the frame this is inlined into, which has `inlined_value` as a
+    /// local of an inlined function
+    #[inline(always)]
+    fn inlined_locals(process: &Process, unwinder: &SnapshotUnwinder) -> Vec<FrameVariable> {
+        let inlined_value = std::hint::black_box(0x55_u8);
+        let registers = current_registers!();
+        let sp = registers.sp().unwrap();
+        let stack = process.copy(sp as usize, 16 * 1024).unwrap();
+        let snapshot = StackSnapshot::new(0, registers, sp, stack);
+        let mut cursor = unwinder.cursor(&snapshot).unwrap();
+        let frame = cursor.next_frame().unwrap().unwrap();
+        let locals = cursor.locals(&frame).unwrap();
+        std::hint::black_box(inlined_value);
+        locals
+    }
+
+    #[test]
+    fn test_locals() {
+        if !current_exe_has_debug_info() {
+            return;
+        }
+        let pid = std::process::id() as Pid;
+        let process = Process::new(pid).unwrap();
+        let unwinder = SnapshotUnwinder::new(pid).unwrap();
+
+        let local_count = std::hint::black_box(0x1234_u32);
+        let local_values = std::hint::black_box([7_i16, -7]);
+        {
+            let local_nested = std::hint::black_box(-5_i64);
+            // unwind a copy of our own stack, to get the cfa the frame base is relative to
+            let registers = current_registers!();
+            let sp = registers.sp().unwrap();
+            let stack = process.copy(sp as usize, 16 * 1024).unwrap();
+            let snapshot = StackSnapshot::new(0, registers, sp, stack);
+            let mut cursor = unwinder.cursor(&snapshot).unwrap();
+            let frame = cursor.next_frame().unwrap().unwrap();
+            let locals = cursor.locals(&frame).unwrap();
+            let find = |name: &str| {
+                locals
+                    .iter()
+                    .find(|local| local.name == name)
+                    .unwrap_or_else(|| panic!("missing local {}", name))
+            };
+
+            let count = find("local_count");
+            assert_eq!(count.ty.as_ref().unwrap().name.as_deref(), Some("u32"));
+            assert_eq!(count.inlined, None);
+            let count = count.value.as_ref().unwrap();
+            assert_eq!(count.as_u64(), Some(local_count as u64));
+            assert_eq!(count.address, &local_count as *const u32 as u64);
+            let values = find("local_values").value.as_ref().unwrap();
+            assert_eq!(values.address, local_values.as_ptr() as u64);
+            assert_eq!(values.data, [7, 0, 0xf9, 0xff]);
+            // variables of the enclosing block are in scope too
+            let nested = find("local_nested").value.as_ref().unwrap();
+            assert_eq!(nested.as_i64(), Some(local_nested));
+
+            // addresses outside of any function in the debug info have no locals
+            let debug_info =
+                DebugInfo::load(&std::env::current_exe().unwrap().display().to_string()).unwrap();
+            let mut outside = frame;
+            outside.ip = 0;
+            assert!(debug_info.locals(&process, &outside, 0).is_err());
+        }
+
+        // the variables and parameters of inlined functions are included with their function
+        let locals = inlined_locals(&process, &unwinder);
+        let inlined = locals
+            .iter()
+            .find(|local| local.name == "inlined_value")
+            .expect("missing inlined local");
+        assert_eq!(inlined.inlined.as_deref(), Some("inlined_locals"));
+        assert_eq!(inlined.value.as_ref().unwrap().as_u64(), Some(0x55));
+        assert!(locals
+            .iter()
+            .any(|local| local.name == "unwinder" && local.inlined.is_some()));
+        assert!(locals.iter().any(|local| local.name == "local_count"));
+    }
+}
@@ -0,0 +1,532 @@
+//! Stops threads through a mach exception port instead of `thread_suspend`.
+//!
+//! The thread is made to single step, and the trap this raises is sent to an exception port
+//! registered for just that thread. The kernel keeps the thread blocked until the exception
+//! message is replied to, so the thread stays stopped while the message is held, without
+//! touching the suspend count that the target (or another tool attached to it) can change
+//! behind our back. Only breakpoint exceptions are taken over, and only at the thread level,
+//! so the task and host exception ports that crash reporters and runtimes like the JVM
+//! register keep getting every other exception.
+
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+use log::{debug, error};
+use mach::exception_types::{
+    exception_behavior_t, exception_mask_t, exception_type_t, mach_exception_data_type_t,
+    EXCEPTION_DEFAULT, EXC_BREAKPOINT, EXC_MASK_BREAKPOINT, MACH_EXCEPTION_CODES,
+};
+use mach::kern_return::{kern_return_t, KERN_FAILURE, KERN_SUCCESS};
+use mach::mach_port::{
+    mach_port_allocate, mach_port_deallocate, mach_port_destroy, mach_port_insert_right,
+};
+use mach::mach_types::thread_act_t;
+use mach::message::{
+    mach_msg, mach_msg_body_t, mach_msg_destroy, mach_msg_header_t, mach_msg_port_descriptor_t,
+    mach_msg_return_t, mach_msg_trailer_t, mach_msg_type_number_t, MACH_MSGH_BITS,
+    MACH_MSGH_BITS_REMOTE_MASK, MACH_MSG_SUCCESS, MACH_MSG_TYPE_MAKE_SEND, MACH_RCV_MSG,
+    MACH_RCV_TIMEOUT, MACH_SEND_MSG,
+};
+use mach::port::{mach_port_t, MACH_PORT_NULL, MACH_PORT_RIGHT_RECEIVE};
+use mach::thread_act::thread_get_state;
+use mach::thread_status::{thread_state_flavor_t, thread_state_t};
+use mach::traps::mach_task_self;
+
+use crate::Error;
+
+/// The most exception ports a thread can have, one per exception type (EXC_TYPES_COUNT)
+const EXC_TYPES_COUNT: usize = 14;
+
+/// THREAD_STATE_NONE, for exception ports that don't get the thread state in the message
+const THREAD_STATE_NONE: thread_state_flavor_t = 13;
+
+/// The message id of `mach_exception_raise`, and of its reply
+const MACH_EXCEPTION_RAISE: i32 = 2405;
+const MACH_EXCEPTION_RAISE_REPLY: i32 = MACH_EXCEPTION_RAISE + 100;
+
+/// mach_msg returns this when nothing was received before the timeout
+const MACH_RCV_TIMED_OUT: mach_msg_return_t = 0x1000_4003;
+
+/// The exception code of a single step trap: EXC_I386_SGL on x86_64, and EXC_ARM_BREAKPOINT
+/// (with a subcode of 0, which tells it apart from a brk instruction) on arm64
+const SINGLE_STEP_CODE: mach_exception_data_type_t = 1;
+
+/// The trap flag in rflags, which raises a debug exception after the next instruction
+#[cfg(not(target_arch = "aarch64"))]
+const TRAP_FLAG: u64 = 0x100;
+
+/// The debug registers of an arm64 thread, from mach/arm/_structs.h
+#[cfg(target_arch = "aarch64")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct arm_debug_state64_t {
+    __bvr: [u64; 16],
+    __bcr: [u64; 16],
+    __wvr: [u64; 16],
+    __wcr: [u64; 16],
+    __mdscr_el1: u64,
+}
+
+#[cfg(target_arch = "aarch64")]
+const ARM_DEBUG_STATE64: thread_state_flavor_t = 15;
+
+/// The software step bit of MDSCR_EL1
+#[cfg(target_arch = "aarch64")]
+const MDSCR_SS: u64 = 1;
+
+extern "C" {
+    fn thread_set_state(
+        thread: thread_act_t,
+        flavor: thread_state_flavor_t,
+        state: thread_state_t,
+        count: mach_msg_type_number_t,
+    ) -> kern_return_t;
+
+    fn thread_swap_exception_ports(
+        thread: thread_act_t,
+        exception_mask: exception_mask_t,
+        new_port: mach_port_t,
+        behavior: exception_behavior_t,
+        new_flavor: thread_state_flavor_t,
+        masks: *mut exception_mask_t,
+        masks_count: *mut mach_msg_type_number_t,
+        old_handlers: *mut mach_port_t,
+        old_behaviors: *mut exception_behavior_t,
+        old_flavors: *mut thread_state_flavor_t,
+    ) -> kern_return_t;
+
+    fn thread_set_exception_ports(
+        thread: thread_act_t,
+        exception_mask: exception_mask_t,
+        new_port: mach_port_t,
+        behavior: exception_behavior_t,
+        new_flavor: thread_state_flavor_t,
+    ) -> kern_return_t;
+}
+
+/// The request of `mach_exception_raise`, as laid out by MIG (which packs to 4 bytes)
+#[repr(C, packed(4))]
+#[derive(Clone, Copy)]
+struct ExceptionRequest {
+    header: mach_msg_header_t,
+    body: mach_msg_body_t,
+    thread: mach_msg_port_descriptor_t,
+    task: mach_msg_port_descriptor_t,
+    ndr: [u8; 8],
+    exception: exception_type_t,
+    code_count: mach_msg_type_number_t,
+    code: [mach_exception_data_type_t; 2],
+    trailer: mach_msg_trailer_t,
+    // room for a larger trailer than the one asked for, in case the kernel sends one
+    padding: [u8; 64],
+}
+
+#[repr(C, packed(4))]
+#[derive(Clone, Copy)]
+struct ExceptionReply {
+    header: mach_msg_header_t,
+    ndr: [u8; 8],
+    ret_code: kern_return_t,
+}
+
+/// The thread level exception ports a thread had for the exceptions we take over
+struct SavedPorts {
+    count: mach_msg_type_number_t,
+    masks: [exception_mask_t; EXC_TYPES_COUNT],
+    handlers: [mach_port_t; EXC_TYPES_COUNT],
+    behaviors: [exception_behavior_t; EXC_TYPES_COUNT],
+    flavors: [thread_state_flavor_t; EXC_TYPES_COUNT],
+}
+
+/// Keeps a thread stopped in the delivery of a single step exception, and lets it continue
+/// when dropped. This is returned by `Thread::lock_with_exception_port`.
+pub struct ExceptionLock {
+    thread: thread_act_t,
+    port: mach_port_t,
+    saved: SavedPorts,
+    /// The exception message the thread is blocked on, until it's replied to
+    request: ExceptionRequest,
+    locked_at: Instant,
+}
+
+impl ExceptionLock {
+    /// Stops a thread by single stepping it into our exception port, waiting up to `timeout`
+    /// for it to execute an instruction. A thread blocked in the kernel doesn't run any
+    /// instructions until it wakes up, so this fails for those once the timeout is reached.
+    pub fn new(thread: thread_act_t, timeout: Duration) -> Result<ExceptionLock, Error> {
+        let port = allocate_port()?;
+        let saved = match swap_ports(thread, port) {
+            Ok(saved) => saved,
+            Err(e) => {
+                unsafe { mach_port_destroy(mach_task_self(), port) };
+                return Err(e);
+            }
+        };
+        let mut lock = ExceptionLock {
+            thread,
+            port,
+            saved,
+            request: unsafe { std::mem::zeroed() },
+            locked_at: Instant::now(),
+        };
+        // dropping the lock from here on restores the ports, and lets the thread go if it was
+        // stopped by the time the single step was turned off
+        set_single_step(thread, true)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !lock.receive(remaining)? {
+                return Err(Error::Other(format!(
+                    "Thread {} didn't stop within {:?}, it's probably blocked in the kernel",
+                    thread, timeout
+                )));
+            }
+            if lock.is_single_step() {
+                lock.locked_at = Instant::now();
+                return Ok(lock);
+            }
+            // a breakpoint of the target's own: failing it makes the kernel pass it on to the
+            // task and host exception ports, like it would have without us
+            debug!("forwarding breakpoint exception of thread {}", thread);
+            lock.reply(KERN_FAILURE);
+        }
+    }
+
+    /// How long the thread has been held stopped by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.locked_at.elapsed()
+    }
+
+    /// Receives the next exception message, returning false if none arrived before `timeout`
+    fn receive(&mut self, timeout: Duration) -> Result<bool, Error> {
+        self.request = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            mach_msg(
+                &mut self.request.header,
+                MACH_RCV_MSG | MACH_RCV_TIMEOUT,
+                0,
+                std::mem::size_of::<ExceptionRequest>() as u32,
+                self.port,
+                timeout.as_millis().min(u32::MAX as u128) as u32,
+                MACH_PORT_NULL,
+            )
+        };
+        match result {
+            MACH_MSG_SUCCESS if self.request.header.msgh_id == MACH_EXCEPTION_RAISE => Ok(true),
+            MACH_MSG_SUCCESS => {
+                let id = self.request.header.msgh_id;
+                unsafe { mach_msg_destroy(&mut self.request.header) };
+                self.request.header.msgh_remote_port = MACH_PORT_NULL;
+                Err(Error::Other(format!(
+                    "Unexpected message {} on the exception port of thread {}",
+                    id, self.thread
+                )))
+            }
+            MACH_RCV_TIMED_OUT => Ok(false),
+            result => Err(Error::Other(format!(
+                "Failed to receive the exception of thread {}: mach_msg returned {}",
+                self.thread, result
+            ))),
+        }
+    }
+
+    fn is_single_step(&self) -> bool {
+        let exception = self.request.exception;
+        let code = self.request.code;
+        exception == EXC_BREAKPOINT as exception_type_t
+            && code[0] == SINGLE_STEP_CODE
+            && (cfg!(not(target_arch = "aarch64")) || code[1] == 0)
+    }
+
+    /// Replies to the message the thread is blocked on, which lets it continue. This also
+    /// releases the thread and task ports that came with the message.
+    fn reply(&mut self, ret_code: kern_return_t) {
+        let request = self.request;
+        if request.header.msgh_remote_port == MACH_PORT_NULL {
+            return;
+        }
+        let mut reply = ExceptionReply {
+            header: mach_msg_header_t {
+                msgh_bits: MACH_MSGH_BITS(request.header.msgh_bits & MACH_MSGH_BITS_REMOTE_MASK, 0),
+                msgh_size: std::mem::size_of::<ExceptionReply>() as u32,
+                msgh_remote_port: request.header.msgh_remote_port,
+                msgh_local_port: MACH_PORT_NULL,
+                msgh_voucher_port: MACH_PORT_NULL,
+                msgh_id: MACH_EXCEPTION_RAISE_REPLY,
+            },
+            ndr: request.ndr,
+            ret_code,
+        };
+        let result = unsafe {
+            mach_msg(
+                &mut reply.header,
+                MACH_SEND_MSG,
+                std::mem::size_of::<ExceptionReply>() as u32,
+                0,
+                MACH_PORT_NULL,
+                0,
+                MACH_PORT_NULL,
+            )
+        };
+        if result != MACH_MSG_SUCCESS {
+            error!(
+                "Failed to reply to the exception of thread {}: mach_msg returned {}",
+                self.thread, result
+            );
+        }
+        unsafe {
+            mach_port_deallocate(mach_task_self(), request.thread.name);
+            mach_port_deallocate(mach_task_self(), request.task.name);
+        }
+        self.request.header.msgh_remote_port = MACH_PORT_NULL;
+    }
+}
+
+impl Drop for ExceptionLock {
+    fn drop(&mut self) {
+        // setting the state stops the thread while it's changed, so once this returns the
+        // thread can't raise another single step trap
+        if let Err(e) = set_single_step(self.thread, false) {
+            error!(
+                "Failed to stop single stepping thread {}: {}",
+                self.thread, e
+            );
+        }
+        let stopped = self.request.header.msgh_remote_port != MACH_PORT_NULL;
+        self.reply(KERN_SUCCESS);
+        // a trap raised right before the single step was turned off is still queued
+        while let Ok(true) = self.receive(Duration::ZERO) {
+            let ret_code = if self.is_single_step() {
+                KERN_SUCCESS
+            } else {
+                KERN_FAILURE
+            };
+            self.reply(ret_code);
+        }
+        restore_ports(self.thread, &self.saved);
+        unsafe { mach_port_destroy(mach_task_self(), self.port) };
+        if stopped {
+            crate::pause_metrics().record_thread(self.thread, self.locked_at.elapsed());
+        }
+    }
+}
+
+fn allocate_port() -> Result<mach_port_t, Error> {
+    let mut port: mach_port_t = MACH_PORT_NULL;
+    unsafe {
+        let result = mach_port_allocate(mach_task_self(), MACH_PORT_RIGHT_RECEIVE, &mut port);
+        if result != KERN_SUCCESS {
+            return Err(kern_error("allocate an exception port", result));
+        }
+        let result = mach_port_insert_right(mach_task_self(), port, port, MACH_MSG_TYPE_MAKE_SEND);
+        if result != KERN_SUCCESS {
+            mach_port_destroy(mach_task_self(), port);
+            return Err(kern_error(
+                "make a send right to the exception port",
+                result,
+            ));
+        }
+    }
+    Ok(port)
+}
+
+/// Sends the breakpoint exceptions of a thread to `port`, returning the ports it had before
+fn swap_ports(thread: thread_act_t, port: mach_port_t) -> Result<SavedPorts, Error> {
+    let mut saved = SavedPorts {
+        count: EXC_TYPES_COUNT as mach_msg_type_number_t,
+        masks: [0; EXC_TYPES_COUNT],
+        handlers: [MACH_PORT_NULL; EXC_TYPES_COUNT],
+        behaviors: [0; EXC_TYPES_COUNT],
+        flavors: [0; EXC_TYPES_COUNT],
+    };
+    let result = unsafe {
+        thread_swap_exception_ports(
+            thread,
+            EXC_MASK_BREAKPOINT,
+            port,
+            (EXCEPTION_DEFAULT | MACH_EXCEPTION_CODES) as exception_behavior_t,
+            THREAD_STATE_NONE,
+            saved.masks.as_mut_ptr(),
+            &mut saved.count,
+            saved.handlers.as_mut_ptr(),
+            saved.behaviors.as_mut_ptr(),
+            saved.flavors.as_mut_ptr(),
+        )
+    };
+    if result != KERN_SUCCESS {
+        return Err(kern_error("set the exception port", result));
+    }
+    Ok(saved)
+}
+
+/// Gives the thread back the exception ports it had before `swap_ports`
+fn restore_ports(thread: thread_act_t, saved: &SavedPorts) {
+    let mut restored = false;
+    for i in 0..saved.count as usize {
+        let result = unsafe {
+            thread_set_exception_ports(
+                thread,
+                saved.masks[i],
+                saved.handlers[i],
+                saved.behaviors[i],
+                saved.flavors[i],
+            )
+        };
+        if result != KERN_SUCCESS {
+            error!("Failed to restore the exception ports of thread {}", thread);
+        }
+        restored |= saved.masks[i] & EXC_MASK_BREAKPOINT != 0;
+        if saved.handlers[i] != MACH_PORT_NULL {
+            unsafe { mach_port_deallocate(mach_task_self(), saved.handlers[i]) };
+        }
+    }
+    // the thread had no port of its own for breakpoints, so take ours away again
+    if !restored {
+        unsafe {
+            thread_set_exception_ports(
+                thread,
+                EXC_MASK_BREAKPOINT,
+                MACH_PORT_NULL,
+                EXCEPTION_DEFAULT as exception_behavior_t,
+                THREAD_STATE_NONE,
+            )
+        };
+    }
+}
+
+/// Turns single stepping of a thread on or off
+#[cfg(not(target_arch = "aarch64"))]
+fn set_single_step(thread: thread_act_t, enabled: bool) -> Result<(), Error> {
+    use mach::structs::x86_thread_state64_t;
+    use mach::thread_status::x86_THREAD_STATE64;
+
+    let mut state = x86_thread_state64_t::new();
+    let mut count = x86_thread_state64_t::count();
+    let result = unsafe {
+        thread_get_state(
+            thread,
+            x86_THREAD_STATE64,
+            &mut state as *mut x86_thread_state64_t as thread_state_t,
+            &mut count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return Err(kern_error("read the registers", result));
+    }
+    if enabled {
+        state.__rflags |= TRAP_FLAG;
+    } else {
+        state.__rflags &= !TRAP_FLAG;
+    }
+    let result = unsafe {
+        thread_set_state(
+            thread,
+            x86_THREAD_STATE64,
+            &mut state as *mut x86_thread_state64_t as thread_state_t,
+            count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return Err(kern_error("set the trap flag", result));
+    }
+    Ok(())
+}
+
+/// Turns single stepping of a thread on or off
+#[cfg(target_arch = "aarch64")]
+fn set_single_step(thread: thread_act_t, enabled: bool) -> Result<(), Error> {
+    let mut state: arm_debug_state64_t = unsafe { std::mem::zeroed() };
+    let mut count = (std::mem::size_of::<arm_debug_state64_t>() / std::mem::size_of::<u32>())
+        as mach_msg_type_number_t;
+    let result = unsafe {
+        thread_get_state(
+            thread,
+            ARM_DEBUG_STATE64,
+            &mut state as *mut arm_debug_state64_t as thread_state_t,
+            &mut count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return Err(kern_error("read the debug registers", result));
+    }
+    if enabled {
+        state.__mdscr_el1 |= MDSCR_SS;
+    } else {
+        state.__mdscr_el1 &= !MDSCR_SS;
+    }
+    let result = unsafe {
+        thread_set_state(
+            thread,
+            ARM_DEBUG_STATE64,
+            &mut state as *mut arm_debug_state64_t as thread_state_t,
+            count,
+        )
+    };
+    if result != KERN_SUCCESS {
+        return Err(kern_error("enable single stepping", result));
+    }
+    Ok(())
+}
+
+fn kern_error(operation: &str, result: c_int) -> Error {
+    Error::Other(format!("Failed to {}: kern_return_t {}", operation, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_exception_lock() {
+        let done = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let spinner = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                sender
+                    .send(unsafe { mach::mach_init::mach_thread_self() })
+                    .unwrap();
+                let mut count = 0_u64;
+                while !done.load(Ordering::Relaxed) {
+                    count = std::hint::black_box(count + 1);
+                }
+                count
+            })
+        };
+        let thread = receiver.recv().unwrap();
+
+        // the spinning thread stops in our exception port, without being suspended
+        let lock = ExceptionLock::new(thread, Duration::from_secs(5)).unwrap();
+        let crate_thread = crate::Thread::new(thread).unwrap();
+        assert_eq!(crate_thread.suspend_count().unwrap(), 0);
+        assert!(crate_thread.registers().is_ok());
+        drop(lock);
+
+        // and runs again once it's let go
+        done.store(true, Ordering::Relaxed);
+        assert!(spinner.join().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_exception_lock_blocked() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (wake, blocked) = std::sync::mpsc::channel::<()>();
+        let sleeper = std::thread::spawn(move || {
+            sender
+                .send(unsafe { mach::mach_init::mach_thread_self() })
+                .unwrap();
+            blocked.recv().unwrap();
+        });
+        let thread = receiver.recv().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        // a thread blocked in the kernel never runs into the single step
+        let lock = ExceptionLock::new(thread, Duration::from_millis(50));
+        assert!(lock.is_err());
+        wake.send(()).unwrap();
+        sleeper.join().unwrap();
+    }
+}
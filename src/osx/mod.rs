@@ -1,11 +1,14 @@
+mod exception;
 mod mach_thread_bindings;
+mod platform;
 mod utils;
 
 use mach;
 use std;
 use std::convert::TryInto;
+use std::time::{Duration, SystemTime};
 
-use super::Error;
+use super::{Error, ErrorContext, ResultExt};
 use mach::kern_return::KERN_SUCCESS;
 use mach::port::{mach_port_name_t, mach_port_t, MACH_PORT_NULL};
 use mach::traps::{mach_task_self, task_for_pid};
@@ -15,14 +18,21 @@ use libc::{c_int, c_void, pid_t};
 
 use mach::kern_return::kern_return_t;
 use mach::mach_types::thread_act_t;
+#[cfg(not(target_arch = "aarch64"))]
 use mach::structs::x86_thread_state64_t;
 use mach::thread_act::thread_get_state;
+#[cfg(not(target_arch = "aarch64"))]
 use mach::thread_status::x86_THREAD_STATE64;
 use mach::vm_types::{mach_vm_address_t, mach_vm_size_t};
 
+pub use self::exception::ExceptionLock;
+pub use self::platform::{platform_info, PlatformInfo};
 pub use self::utils::{TaskLock, ThreadLock};
+pub use crate::bsd::ExitNotification;
 
+use libproc::libproc::bsd_info::BSDInfo;
 use libproc::libproc::proc_pid::{pidinfo, pidpath, PIDInfo, PidInfoFlavor};
+use libproc::libproc::task_info::TaskAllInfo;
 
 pub type Pid = pid_t;
 pub type Tid = u32;
@@ -30,11 +40,20 @@ pub type Tid = u32;
 pub struct Process {
     pub pid: Pid,
     pub task: mach_port_name_t,
+    start_time: Option<(u64, u64)>,
 }
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+/// A thread in the target process.
+///
+/// The `tid` is a mach port, which can be different each time the threads of a process are
+/// listed. Threads are compared using the system wide thread id from THREAD_IDENTIFIER_INFO
+/// instead, which stays the same for the lifetime of the thread.
+#[derive(Copy, Clone)]
 pub struct Thread {
     pub tid: Tid,
+    thread_id: Option<u64>,
+    /// The pid of the process, if it's being translated by Rosetta
+    translated: Option<Pid>,
 }
 
 impl Process {
@@ -42,15 +61,167 @@ impl Process {
         let mut task: mach_port_name_t = MACH_PORT_NULL;
         let result = unsafe { task_for_pid(mach_task_self(), pid as c_int, &mut task) };
         if result != KERN_SUCCESS {
+            return Err(mach_error(pid, result));
+        }
+        // remember when the process started, so that we can tell if the pid gets reused
+        let start_time = get_start_time(pid).ok();
+        Ok(Process {
+            pid,
+            task,
+            start_time,
+        })
+    }
+
+    /// Creates a process from a task port that was already obtained elsewhere, like from a
+    /// privileged helper that called `task_for_pid` on our behalf. The port is used as is,
+    /// and isn't deallocated when the process is dropped.
+    pub fn from_task_port(task: mach_port_name_t) -> Result<Process, Error> {
+        let mut pid: c_int = 0;
+        let result = unsafe { utils::pid_for_task(task, &mut pid) };
+        if result != KERN_SUCCESS {
+            return Err(Error::Other(format!(
+                "Failed to get the pid for task port {}: {}",
+                task, result
+            )));
+        }
+        let start_time = get_start_time(pid).ok();
+        Ok(Process {
+            pid,
+            task,
+            start_time,
+        })
+    }
+
+    /// Returns what can be done with this process. Having the task port of a process gives
+    /// full control over it, but stack unwinding and symbolication aren't supported on macOS.
+    pub fn capabilities(&self) -> crate::Capabilities {
+        let can_read_mem = crate::can_read_memory(self, self.pid);
+        crate::Capabilities {
+            can_read_mem,
+            can_suspend: can_read_mem,
+            can_unwind: false,
+            can_symbolicate: false,
+            can_write: can_read_mem,
+        }
+    }
+
+    /// Returns true if the process still exists and is the same process this was created
+    /// for, rather than a new process that has been given the same pid
+    pub fn exists(&self) -> bool {
+        // EPERM means the process exists, but we aren't allowed to signal it
+        if unsafe { libc::kill(self.pid, 0) } != 0
+            && std::io::Error::last_os_error().raw_os_error() != Some(libc::EPERM)
+        {
+            return false;
+        }
+        match self.start_time {
+            Some(start_time) => get_start_time(self.pid).ok() == Some(start_time),
+            None => true,
+        }
+    }
+
+    /// Sends a signal to the process.
+    ///
+    /// This fails with `Error::ProcessExited` if the process has exited, including when its
+    /// pid has since been reused by another process.
+    pub fn signal(&self, signal: i32) -> Result<(), Error> {
+        crate::bsd::signal(self.pid, self.exists(), signal)
+    }
+
+    /// Kills the process with SIGKILL
+    pub fn kill(&self) -> Result<(), Error> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Returns a handle that can be polled to find out when this process exits
+    pub fn exit_notification(&self) -> Result<ExitNotification, Error> {
+        ExitNotification::new(self.pid, self.start_time, get_start_time)
+    }
+
+    /// Returns true if the process exists and hasn't exited. Unlike `exists`, this is false
+    /// for processes that have exited but haven't yet been reaped by their parent.
+    pub fn is_alive(&self) -> bool {
+        self.exists() && !self.is_zombie()
+    }
+
+    /// Returns true if the process has exited, but hasn't yet been reaped by its parent
+    pub fn is_zombie(&self) -> bool {
+        match pidinfo::<BSDInfo>(self.pid, 0) {
+            Ok(info) => info.pbi_status == libc::SZOMB,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns true if the process is an x86_64 binary that is being translated by Rosetta 2
+    /// on Apple Silicon. The registers of its threads hold the state of the translator rather
+    /// than the x86_64 state, so `Thread::registers` fails for them.
+    pub fn is_translated(&self) -> Result<bool, Error> {
+        // libc doesn't define kinfo_proc for apple, so this reads the p_flag field of its
+        // kp_proc member from the raw buffer
+        const KINFO_PROC_SIZE: usize = 648;
+        const P_FLAG_OFFSET: usize = 32;
+        const P_TRANSLATED: i32 = 0x0002_0000;
+
+        let mut mib: [c_int; 4] = [
+            libc::CTL_KERN,
+            libc::KERN_PROC,
+            libc::KERN_PROC_PID,
+            self.pid,
+        ];
+        let mut info = [0u8; KINFO_PROC_SIZE];
+        let mut size = info.len();
+        let ret = unsafe {
+            libc::sysctl(
+                mib.as_mut_ptr(),
+                mib.len() as u32,
+                info.as_mut_ptr() as *mut c_void,
+                &mut size,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if ret < 0 {
             return Err(Error::IOError(std::io::Error::last_os_error()));
         }
-        Ok(Process { pid, task })
+        if size < P_FLAG_OFFSET + 4 {
+            return Err(Error::IOError(std::io::Error::from_raw_os_error(
+                libc::ESRCH,
+            )));
+        }
+        let mut flag = [0u8; 4];
+        flag.copy_from_slice(&info[P_FLAG_OFFSET..P_FLAG_OFFSET + 4]);
+        Ok(i32::from_ne_bytes(flag) & P_TRANSLATED != 0)
+    }
+
+    /// Returns the task port of the process, for making mach calls this crate doesn't wrap.
+    /// The port is owned by this process object, and shouldn't be deallocated.
+    pub fn task(&self) -> mach_port_name_t {
+        self.task
     }
 
     pub fn exe(&self) -> Result<String, Error> {
         pidpath(self.pid).map_err(|e| Error::Other(format!("proc_pidpath failed: {}", e)))
     }
 
+    /// Returns the id of the process group the process is in, which is the pid of the group's
+    /// leader. Shells put each job in its own group, so this is how to find the other
+    /// processes in the same pipeline.
+    pub fn process_group(&self) -> Result<Pid, Error> {
+        let info = pidinfo::<BSDInfo>(self.pid, 0)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+        Ok(info.pbi_pgid as Pid)
+    }
+
+    /// Returns the id of the session the process is in, which is the pid of the session's
+    /// leader (usually the shell of the terminal the process was started from)
+    pub fn session_id(&self) -> Result<Pid, Error> {
+        let sid = unsafe { libc::getsid(self.pid) };
+        if sid < 0 {
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        Ok(sid)
+    }
+
     pub fn cwd(&self) -> Result<String, Error> {
         let cwd = pidinfo::<proc_vnodepathinfo>(self.pid, 0)
             .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
@@ -105,7 +276,29 @@ impl Process {
         }
     }
 
+    /// Returns the start time, owner, memory usage and thread count of the process, which all
+    /// come from a single proc_pidinfo call
+    pub fn stats(&self) -> Result<crate::ProcessStats, Error> {
+        let info = pidinfo::<TaskAllInfo>(self.pid, 0)
+            .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+        let start_time = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(info.pbsd.pbi_start_tvsec)
+            + Duration::from_micros(info.pbsd.pbi_start_tvusec);
+        Ok(crate::ProcessStats {
+            start_time,
+            uid: Some(info.pbsd.pbi_uid),
+            memory: crate::MemoryStats {
+                resident: info.ptinfo.pti_resident_size,
+                virtual_size: info.ptinfo.pti_virtual_size,
+            },
+            thread_count: info.ptinfo.pti_threadnum as usize,
+        })
+    }
+
     pub fn lock(&self) -> Result<TaskLock, Error> {
+        if self.is_zombie() {
+            return Err(Error::ZombieProcess(self.pid));
+        }
         Ok(TaskLock::new(self.task)?)
     }
 
@@ -115,13 +308,16 @@ impl Process {
         let result =
             unsafe { mach::task::task_threads(self.task, &mut threads, &mut thread_count) };
         if result != KERN_SUCCESS {
-            return Err(Error::IOError(std::io::Error::last_os_error()));
+            return Err(mach_error(self.pid, result));
         }
 
+        let translated = self.is_translated().unwrap_or(false).then_some(self.pid);
         let mut ret = Vec::new();
         for i in 0..thread_count {
             let tid = unsafe { *threads.offset(i as isize) };
-            ret.push(Thread { tid });
+            let mut thread = Thread::new(tid)?;
+            thread.translated = translated;
+            ret.push(thread);
         }
 
         let memsize = thread_count as usize * std::mem::size_of::<Tid>();
@@ -135,6 +331,14 @@ impl Process {
         Ok(ret)
     }
 
+    /// Returns an iterator over the threads of the process. This matches `iter_threads` on the
+    /// other platforms, but task_threads always returns every thread at once on macOS.
+    pub fn iter_threads(&self) -> Result<ThreadIter, Error> {
+        Ok(ThreadIter {
+            threads: self.threads()?.into_iter(),
+        })
+    }
+
     pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
         fn recurse(pid: Pid, ret: &mut Vec<(Pid, Pid)>) -> Result<(), Error> {
             for child in childpids(pid)? {
@@ -149,6 +353,33 @@ impl Process {
     }
 }
 
+/// Turns the result of a failed mach call on the task of a process into an error that says
+/// what usually causes it
+fn mach_error(pid: Pid, result: kern_return_t) -> Error {
+    // task_for_pid returns KERN_FAILURE both when we aren't allowed to get the task port and
+    // when the process doesn't exist, so check which one it is
+    let alive = unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    match result {
+        mach::kern_return::KERN_FAILURE if alive => Error::PermissionDenied {
+            pid,
+            hint: "task_for_pid needs root, or the com.apple.security.cs.debugger entitlement. \
+                   Binaries protected by System Integrity Protection can't be inspected at all"
+                .to_string(),
+        },
+        mach::kern_return::KERN_FAILURE | mach::message::MACH_SEND_INVALID_DEST => {
+            Error::ProcessExited(pid)
+        }
+        result => Error::Other(format!("Mach call for pid {} failed: {}", pid, result)),
+    }
+}
+
+fn get_start_time(pid: Pid) -> Result<(u64, u64), Error> {
+    let info = pidinfo::<BSDInfo>(pid, 0)
+        .map_err(|e| Error::Other(format!("proc_pidinfo failed: {}", e)))?;
+    Ok((info.pbi_start_tvsec, info.pbi_start_tvusec))
+}
+
 fn childpids(pid: Pid) -> Result<Vec<Pid>, Error> {
     let size = unsafe { proc_listchildpids(pid, std::ptr::null_mut(), 0) };
     if size < 0 {
@@ -168,15 +399,38 @@ fn childpids(pid: Pid) -> Result<Vec<Pid>, Error> {
 impl super::ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
         let handle: ProcessHandle = self.task.try_into()?;
-        Ok(handle.copy_address(addr, buf)?)
+        handle.copy_address(addr, buf).context(|| {
+            ErrorContext::new("reading memory")
+                .pid(self.pid)
+                .address(addr, buf.len())
+        })
     }
 }
 
 use self::mach_thread_bindings::{
-    thread_basic_info, thread_identifier_info, thread_info, THREAD_BASIC_INFO,
-    THREAD_IDENTIFIER_INFO, TH_FLAGS_IDLE, TH_STATE_RUNNING,
+    thread_basic_info, thread_identifier_info, thread_info, time_value_t, THREAD_BASIC_INFO,
+    THREAD_IDENTIFIER_INFO, TH_FLAGS_IDLE, TH_STATE_RUNNING, TH_STATE_STOPPED,
 };
 
+/// The general purpose registers of an arm64 thread, from mach/arm/_structs.h. This is
+/// missing from the mach crate.
+#[cfg(target_arch = "aarch64")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct arm_thread_state64_t {
+    pub __x: [u64; 29],
+    pub __fp: u64,
+    pub __lr: u64,
+    pub __sp: u64,
+    pub __pc: u64,
+    pub __cpsr: u32,
+    pub __flags: u32,
+}
+
+#[cfg(target_arch = "aarch64")]
+const ARM_THREAD_STATE64: c_int = 6;
+
 extern "C" {
     fn vm_deallocate(
         target_task: mach_port_t,
@@ -185,15 +439,70 @@ extern "C" {
     ) -> kern_return_t;
 }
 
+/// Iterates over the threads of a process, as returned by `Process::iter_threads`
+pub struct ThreadIter {
+    threads: std::vec::IntoIter<Thread>,
+}
+
+impl Iterator for ThreadIter {
+    type Item = Result<Thread, Error>;
+
+    fn next(&mut self) -> Option<Result<Thread, Error>> {
+        self.threads.next().map(Ok)
+    }
+}
+
 impl Thread {
     pub fn new(tid: Tid) -> Result<Thread, Error> {
-        Ok(Thread { tid })
+        let mut thread = Thread {
+            tid,
+            thread_id: None,
+            translated: None,
+        };
+        // this fails if the thread has already exited, in which case we fall back to the port
+        thread.thread_id = thread
+            .get_thread_identifier_info()
+            .ok()
+            .map(|info| info.thread_id);
+        Ok(thread)
     }
 
     pub fn id(&self) -> Result<Tid, Error> {
         Ok(self.tid)
     }
 
+    /// Returns the mach port of the thread, for making thread_* calls this crate doesn't wrap
+    pub fn port(&self) -> thread_act_t {
+        self.tid
+    }
+
+    /// Returns the system wide id of this thread. Unlike `id`, this is the same every time
+    /// the threads of the process are listed, so it can be used to track a thread over time.
+    pub fn thread_id(&self) -> Result<u64, Error> {
+        match self.thread_id {
+            Some(thread_id) => Ok(thread_id),
+            None => Ok(self.get_thread_identifier_info()?.thread_id),
+        }
+    }
+
+    /// The system wide id of the thread, which is never reused. This is None when the thread
+    /// exited before the threads were listed.
+    pub(crate) fn thread_key(&self) -> Result<crate::threads::ThreadKey, Error> {
+        match self.thread_id {
+            Some(thread_id) => Ok((thread_id, 0)),
+            None => Err(Error::ThreadExited(self.tid)),
+        }
+    }
+
+    /// The value threads are compared by: the stable thread id if we have it, and the port
+    /// otherwise
+    fn identity(&self) -> (bool, u64) {
+        match self.thread_id {
+            Some(thread_id) => (true, thread_id),
+            None => (false, self.tid as u64),
+        }
+    }
+
     pub fn thread_handle(&self) -> Result<u64, Error> {
         let thread_id = self.get_thread_identifier_info()?;
         Ok(thread_id.thread_handle)
@@ -204,15 +513,64 @@ impl Thread {
         Ok(info.run_state == TH_STATE_RUNNING as i32 && info.flags & TH_FLAGS_IDLE as i32 == 0)
     }
 
+    /// Returns the CPU time (user and system) the thread has used
+    pub fn cpu_time(&self) -> Result<Duration, Error> {
+        let info = self.get_thread_basic_info()?;
+        let time = |value: time_value_t| {
+            Duration::from_secs(value.seconds as u64)
+                + Duration::from_micros(value.microseconds as u64)
+        };
+        Ok(time(info.user_time) + time(info.system_time))
+    }
+
+    /// Returns how many times the thread has been suspended without being resumed, including
+    /// by a `ThreadLock`. Suspending the whole task (like `Process::lock` does) isn't counted.
+    pub fn suspend_count(&self) -> Result<u32, Error> {
+        Ok(self.get_thread_basic_info()?.suspend_count as u32)
+    }
+
+    /// Returns true if the thread is suspended or stopped, like by a debugger
+    pub fn is_stopped(&self) -> Result<bool, Error> {
+        let info = self.get_thread_basic_info()?;
+        Ok(info.suspend_count > 0 || info.run_state == TH_STATE_STOPPED as i32)
+    }
+
     pub fn lock(&self) -> Result<ThreadLock, Error> {
-        Ok(ThreadLock::new(self.tid)?)
+        let mut lock = ThreadLock::new(self.tid)?;
+        // thread_suspend waits for the thread to stop, so its state now says how it was stopped
+        lock.was_active = self
+            .get_thread_basic_info()
+            .ok()
+            .map(|info| suspended_while_running(&info));
+        Ok(lock)
+    }
+
+    /// Stops the thread without suspending it, by single stepping it into an exception port
+    /// that only this lock listens on, and lets it continue when the lock is dropped. This
+    /// doesn't change the suspend count, so it can't race with a target (or a crash reporter
+    /// attached to it) that suspends and resumes its own threads. Breakpoint exceptions the
+    /// target raises itself while we wait are passed on to its task and host exception ports.
+    ///
+    /// A thread that is blocked in the kernel doesn't execute any instructions, so this fails
+    /// for it once `timeout` has passed. Such threads can still be stopped with `lock`.
+    pub fn lock_with_exception_port(&self, timeout: Duration) -> Result<ExceptionLock, Error> {
+        if let Some(pid) = self.translated {
+            return Err(Error::TranslatedProcess(pid));
+        }
+        ExceptionLock::new(self.tid, timeout)
     }
 
     pub fn thread_name(&self) -> Result<Option<String>, Error> {
         Ok(None)
     }
 
-    pub fn registers(&self) -> Result<x86_thread_state64_t, std::io::Error> {
+    /// Returns the registers of the thread. This fails with `Error::TranslatedProcess` for
+    /// threads of processes running under Rosetta, whose thread state belongs to the translator.
+    #[cfg(not(target_arch = "aarch64"))]
+    pub fn registers(&self) -> Result<x86_thread_state64_t, Error> {
+        if let Some(pid) = self.translated {
+            return Err(Error::TranslatedProcess(pid));
+        }
         unsafe {
             let thread_state = x86_thread_state64_t::new();
             let thread_state_size = x86_thread_state64_t::count();
@@ -223,12 +581,40 @@ impl Thread {
                 std::mem::transmute(&thread_state_size),
             );
             if result != KERN_SUCCESS {
-                return Err(std::io::Error::last_os_error());
+                return Err(Error::IOError(std::io::Error::last_os_error()));
             }
             Ok(thread_state)
         }
     }
 
+    /// Returns the registers of the thread, with the pointer authentication signature removed
+    /// from the pc and lr of arm64e threads. This fails with `Error::TranslatedProcess` for
+    /// threads of processes running under Rosetta, whose thread state belongs to the translator.
+    #[cfg(target_arch = "aarch64")]
+    pub fn registers(&self) -> Result<arm_thread_state64_t, Error> {
+        if let Some(pid) = self.translated {
+            return Err(Error::TranslatedProcess(pid));
+        }
+        let mut thread_state: arm_thread_state64_t = unsafe { std::mem::zeroed() };
+        let mut thread_state_size =
+            (std::mem::size_of::<arm_thread_state64_t>() / std::mem::size_of::<u32>()) as u32;
+        let result = unsafe {
+            thread_get_state(
+                self.tid,
+                ARM_THREAD_STATE64,
+                &mut thread_state as *mut arm_thread_state64_t as *mut u32,
+                &mut thread_state_size,
+            )
+        };
+        if result != KERN_SUCCESS {
+            return Err(Error::IOError(std::io::Error::last_os_error()));
+        }
+        let platform = platform_info();
+        thread_state.__pc = platform.strip_pointer_auth(thread_state.__pc);
+        thread_state.__lr = platform.strip_pointer_auth(thread_state.__lr);
+        Ok(thread_state)
+    }
+
     pub fn get_thread_basic_info(&self) -> Result<thread_basic_info, std::io::Error> {
         let mut info: thread_basic_info = unsafe { std::mem::zeroed() };
         let mut info_size: u32 =
@@ -267,6 +653,33 @@ impl Thread {
     }
 }
 
+/// Returns whether a thread that was just suspended was running when it was suspended. A
+/// running thread is stopped by the suspension, while a blocked thread keeps waiting until it's
+/// resumed. A stopped thread is only one we stopped if nothing else had it suspended already.
+fn suspended_while_running(info: &thread_basic_info) -> bool {
+    if info.flags & TH_FLAGS_IDLE as i32 != 0 {
+        return false;
+    }
+    if info.run_state == TH_STATE_RUNNING as i32 {
+        return true;
+    }
+    info.run_state == TH_STATE_STOPPED as i32 && info.suspend_count == 1
+}
+
+impl PartialEq for Thread {
+    fn eq(&self, other: &Self) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+impl Eq for Thread {}
+
+impl std::hash::Hash for Thread {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.identity().hash(state);
+    }
+}
+
 // extra struct definitions needed to get CWD from proc_pidinfo
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -0,0 +1,136 @@
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+use log::info;
+
+/// The version specific details of the running macOS that this crate depends on, probed once
+/// at first use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformInfo {
+    /// The product version, like (14, 2, 1), or (0, 0, 0) if it couldn't be read
+    pub os_version: (u32, u32, u32),
+    /// The CPU supports pointer authentication, so arm64e processes (which includes the system
+    /// libraries) can have a signature in the upper bits of their return addresses and of the
+    /// pc and lr registers. This is only ever true on Apple Silicon.
+    pub pointer_auth: bool,
+}
+
+impl PlatformInfo {
+    fn probe() -> Self {
+        let info = Self {
+            os_version: os_version().unwrap_or_default(),
+            pointer_auth: probe_pointer_auth(),
+        };
+        info!("probed platform {:?}", info);
+        info
+    }
+
+    /// Removes the pointer authentication signature from a code address, leaving the address
+    /// that was signed. Addresses without a signature are returned as is.
+    pub fn strip_pointer_auth(&self, addr: u64) -> u64 {
+        // user space on arm64 macOS is limited to 47 bits, and the signature is stored in the
+        // bits above that
+        const ADDRESS_MASK: u64 = (1 << 47) - 1;
+        if self.pointer_auth {
+            addr & ADDRESS_MASK
+        } else {
+            addr
+        }
+    }
+}
+
+/// Returns the details of the running macOS
+pub fn platform_info() -> &'static PlatformInfo {
+    static INFO: OnceLock<PlatformInfo> = OnceLock::new();
+    INFO.get_or_init(PlatformInfo::probe)
+}
+
+fn os_version() -> Option<(u32, u32, u32)> {
+    let mut buf = [0u8; 32];
+    sysctl(b"kern.osproductversion\0", &mut buf)?;
+    parse_os_version(CStr::from_bytes_until_nul(&buf).ok()?.to_str().ok()?)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn probe_pointer_auth() -> bool {
+    // this sysctl was added in macOS 12, but every CPU that macOS 11 ran on had FEAT_PAuth
+    let mut buf = [0u8; 4];
+    match sysctl(b"hw.optional.arm.FEAT_PAuth\0", &mut buf) {
+        Some(_) => i32::from_ne_bytes(buf) != 0,
+        None => true,
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn probe_pointer_auth() -> bool {
+    false
+}
+
+/// Reads a sysctl into buf, returning the length of the value
+fn sysctl(name: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let mut len = buf.len();
+    let ret = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr() as *const libc::c_char,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (ret == 0).then_some(len)
+}
+
+/// Parses a product version like "14.2.1" or "13.0"
+fn parse_os_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()
+        .and_then(|minor| minor.parse().ok())
+        .unwrap_or(0);
+    let patch = parts
+        .next()
+        .and_then(|patch| patch.parse().ok())
+        .unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_os_version() {
+        assert_eq!(parse_os_version("14.2.1"), Some((14, 2, 1)));
+        assert_eq!(parse_os_version("13.0"), Some((13, 0, 0)));
+        assert_eq!(parse_os_version("15"), Some((15, 0, 0)));
+        assert_eq!(parse_os_version("10.15.7\n"), Some((10, 15, 7)));
+        assert_eq!(parse_os_version("garbage"), None);
+        assert_eq!(parse_os_version(""), None);
+
+        assert!(platform_info().os_version >= (10, 0, 0));
+    }
+
+    #[test]
+    fn test_strip_pointer_auth() {
+        let info = PlatformInfo {
+            pointer_auth: true,
+            ..platform_info().clone()
+        };
+        assert_eq!(
+            info.strip_pointer_auth(0x3c1b_0001_8a5c_1234),
+            0x0001_8a5c_1234
+        );
+        assert_eq!(info.strip_pointer_auth(0x0001_0000_1000), 0x0001_0000_1000);
+
+        let info = PlatformInfo {
+            pointer_auth: false,
+            ..info
+        };
+        assert_eq!(
+            info.strip_pointer_auth(0x3c1b_0001_8a5c_1234),
+            0x3c1b_0001_8a5c_1234
+        );
+    }
+}
@@ -1,22 +1,31 @@
 use super::*;
 use log::error;
+use std::time::{Duration, Instant};
 
 extern "C" {
     pub fn thread_suspend(thread: thread_act_t) -> kern_return_t;
     pub fn thread_resume(thread: thread_act_t) -> kern_return_t;
+    pub fn pid_for_task(task: mach_port_name_t, pid: *mut c_int) -> kern_return_t;
 }
 
 pub struct TaskLock {
     task: mach_port_name_t,
+    locked_at: Instant,
 }
 
 impl TaskLock {
     pub fn new(task: mach_port_name_t) -> Result<TaskLock, std::io::Error> {
+        let locked_at = Instant::now();
         let result = unsafe { mach::task::task_suspend(task) };
         if result != KERN_SUCCESS {
             return Err(std::io::Error::last_os_error());
         }
-        Ok(TaskLock { task })
+        Ok(TaskLock { task, locked_at })
+    }
+
+    /// How long the task has been held suspended by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.locked_at.elapsed()
     }
 }
 impl Drop for TaskLock {
@@ -29,20 +38,44 @@ impl Drop for TaskLock {
                 std::io::Error::last_os_error()
             );
         }
+        let mut pid: c_int = 0;
+        if unsafe { pid_for_task(self.task, &mut pid) } == KERN_SUCCESS {
+            crate::pause_metrics().record_process(pid, self.locked_at.elapsed());
+        }
     }
 }
 
 pub struct ThreadLock {
     thread: thread_act_t,
+    suspended_at: Instant,
+    pub(super) was_active: Option<bool>,
 }
 
 impl ThreadLock {
     pub fn new(thread: thread_act_t) -> Result<ThreadLock, std::io::Error> {
+        let suspended_at = Instant::now();
         let result = unsafe { thread_suspend(thread) };
         if result != KERN_SUCCESS {
             return Err(std::io::Error::last_os_error());
         }
-        Ok(ThreadLock { thread })
+        Ok(ThreadLock {
+            thread,
+            suspended_at,
+            was_active: None,
+        })
+    }
+
+    /// How long the thread has been held suspended by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.suspended_at.elapsed()
+    }
+
+    /// Whether the thread was running right before it was suspended by this lock, or None if
+    /// its state couldn't be read. This is read from the thread once it's suspended, so it
+    /// can't race with the thread changing state. Suspended threads never look active, so this
+    /// is the only way to tell while the lock is held.
+    pub fn was_active(&self) -> Option<bool> {
+        self.was_active
     }
 }
 impl Drop for ThreadLock {
@@ -55,5 +88,6 @@ impl Drop for ThreadLock {
                 std::io::Error::last_os_error()
             );
         }
+        crate::pause_metrics().record_thread(self.thread, self.suspended_at.elapsed());
     }
 }
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::{Pid, Tid};
+
+/// Aggregate statistics on how long a thread or process was held stopped by this crate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PauseStats {
+    /// The number of times the target was paused
+    pub count: u64,
+    /// The total amount of time the target spent paused
+    pub total: Duration,
+    /// The longest single pause
+    pub max: Duration,
+}
+
+impl PauseStats {
+    pub fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// The average time the target was paused for, or zero if it was never paused
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// The default number of threads, and of processes, that `PauseMetrics` keeps statistics for
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// Records the time that lock guards held threads and processes stopped.
+///
+/// Every lock guard in this crate reports into the global instance returned by `pause_metrics()`
+/// when it is dropped, which lets profilers verify how much overhead they are adding to the
+/// target. Only a limited number of threads and processes are tracked: once that is exceeded
+/// the one that was paused least recently is dropped, so that the metrics don't grow with
+/// every thread that a long running target creates and exits.
+#[derive(Debug)]
+pub struct PauseMetrics {
+    threads: Mutex<StatsMap<Tid>>,
+    processes: Mutex<StatsMap<Pid>>,
+    capacity: usize,
+}
+
+impl Default for PauseMetrics {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl PauseMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates metrics that keep statistics for at most `capacity` threads and `capacity`
+    /// processes
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            threads: Mutex::new(StatsMap::default()),
+            processes: Mutex::new(StatsMap::default()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record_thread(&self, tid: Tid, duration: Duration) {
+        let mut threads = self.threads.lock().unwrap();
+        threads.record(tid, duration, self.capacity);
+    }
+
+    pub fn record_process(&self, pid: Pid, duration: Duration) {
+        let mut processes = self.processes.lock().unwrap();
+        processes.record(pid, duration, self.capacity);
+    }
+
+    /// Returns the pause statistics for a single thread
+    pub fn thread(&self, tid: Tid) -> Option<PauseStats> {
+        self.threads.lock().unwrap().get(&tid)
+    }
+
+    /// Returns the pause statistics for every thread that has been paused
+    pub fn threads(&self) -> HashMap<Tid, PauseStats> {
+        self.threads.lock().unwrap().all()
+    }
+
+    /// Returns the statistics for times a whole process was paused at once
+    pub fn process(&self, pid: Pid) -> Option<PauseStats> {
+        self.processes.lock().unwrap().get(&pid)
+    }
+
+    /// Returns the statistics for every process that has been paused as a whole
+    pub fn processes(&self) -> HashMap<Pid, PauseStats> {
+        self.processes.lock().unwrap().all()
+    }
+
+    /// Combines the statistics of every thread that has been paused
+    pub fn thread_totals(&self) -> PauseStats {
+        let mut ret = PauseStats::default();
+        for (stats, _) in self.threads.lock().unwrap().stats.values() {
+            ret.merge(stats);
+        }
+        ret
+    }
+
+    /// Removes the statistics for a thread, returning what had been recorded for it. Call
+    /// this when a thread exits to free up its slot right away.
+    pub fn remove_thread(&self, tid: Tid) -> Option<PauseStats> {
+        self.threads.lock().unwrap().remove(&tid)
+    }
+
+    /// Removes the statistics for a process, returning what had been recorded for it
+    pub fn remove_process(&self, pid: Pid) -> Option<PauseStats> {
+        self.processes.lock().unwrap().remove(&pid)
+    }
+
+    /// Clears all recorded statistics
+    pub fn reset(&self) {
+        self.threads.lock().unwrap().clear();
+        self.processes.lock().unwrap().clear();
+    }
+}
+
+/// The statistics for each thread or process, along with when each was last recorded
+#[derive(Debug)]
+struct StatsMap<K> {
+    stats: HashMap<K, (PauseStats, u64)>,
+    records: u64,
+}
+
+impl<K> Default for StatsMap<K> {
+    fn default() -> Self {
+        Self {
+            stats: HashMap::new(),
+            records: 0,
+        }
+    }
+}
+
+impl<K: Copy + Eq + Hash> StatsMap<K> {
+    fn record(&mut self, key: K, duration: Duration, capacity: usize) {
+        self.records += 1;
+        if !self.stats.contains_key(&key) && self.stats.len() >= capacity {
+            let oldest = self
+                .stats
+                .iter()
+                .min_by_key(|(_, (_, recorded))| *recorded)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.stats.remove(&oldest);
+            }
+        }
+        let (stats, recorded) = self.stats.entry(key).or_default();
+        stats.record(duration);
+        *recorded = self.records;
+    }
+
+    fn get(&self, key: &K) -> Option<PauseStats> {
+        self.stats.get(key).map(|(stats, _)| *stats)
+    }
+
+    fn all(&self) -> HashMap<K, PauseStats> {
+        self.stats
+            .iter()
+            .map(|(key, (stats, _))| (*key, *stats))
+            .collect()
+    }
+
+    fn remove(&mut self, key: &K) -> Option<PauseStats> {
+        self.stats.remove(key).map(|(stats, _)| stats)
+    }
+
+    fn clear(&mut self) {
+        self.stats.clear();
+    }
+}
+
+/// Returns the metrics that all lock guards record their pause times into
+pub fn pause_metrics() -> &'static PauseMetrics {
+    static METRICS: OnceLock<PauseMetrics> = OnceLock::new();
+    METRICS.get_or_init(PauseMetrics::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_stats() {
+        let metrics = PauseMetrics::new();
+        metrics.record_thread(1, Duration::from_millis(2));
+        metrics.record_thread(1, Duration::from_millis(6));
+        metrics.record_thread(2, Duration::from_millis(1));
+
+        let stats = metrics.thread(1).unwrap();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.total, Duration::from_millis(8));
+        assert_eq!(stats.max, Duration::from_millis(6));
+        assert_eq!(stats.mean(), Duration::from_millis(4));
+        assert_eq!(metrics.thread(3), None);
+
+        let totals = metrics.thread_totals();
+        assert_eq!(totals.count, 3);
+        assert_eq!(totals.total, Duration::from_millis(9));
+        assert_eq!(totals.max, Duration::from_millis(6));
+
+        assert_eq!(metrics.remove_thread(2).map(|s| s.count), Some(1));
+        assert_eq!(metrics.threads().len(), 1);
+        metrics.reset();
+        assert!(metrics.threads().is_empty());
+        assert_eq!(PauseStats::default().mean(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_pause_metrics_capacity() {
+        let metrics = PauseMetrics::with_capacity(2);
+        metrics.record_thread(1, Duration::from_millis(1));
+        metrics.record_thread(2, Duration::from_millis(1));
+        metrics.record_thread(1, Duration::from_millis(1));
+
+        // the thread paused least recently makes room for the new one
+        metrics.record_thread(3, Duration::from_millis(1));
+        assert_eq!(metrics.threads().len(), 2);
+        assert_eq!(metrics.thread(2), None);
+        assert_eq!(metrics.thread(1).map(|s| s.count), Some(2));
+        assert_eq!(metrics.thread(3).map(|s| s.count), Some(1));
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::threads::ThreadKey;
+use crate::{Error, Pid, Process, Thread};
+
+/// Periodically pauses each thread of a process and calls back into the caller while it is
+/// stopped, which is the core loop of most sampling profilers.
+///
+/// ```rust,no_run
+/// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+/// let mut sampler = remoteprocess::Sampler::new(pid, 100.0)?
+///     .with_governor(remoteprocess::Governor::new(0.01)?);
+/// loop {
+///     sampler.wait();
+///     sampler.sample(|thread| {
+///         // the thread is stopped for the duration of this callback
+///         println!("sampled {}", thread.id()?);
+///         Ok(())
+///     })?;
+/// }
+/// # }
+/// ```
+pub struct Sampler {
+    process: Process,
+    interval: Duration,
+    governor: Option<Governor>,
+    next_tick: Instant,
+    last_tick: Option<Instant>,
+    next_thread: usize,
+    mode: SamplingMode,
+    cpu_times: HashMap<ThreadKey, Duration>,
+}
+
+/// Which threads a `Sampler` pauses on each tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplingMode {
+    /// Sample every thread, whether it is running or not. This shows where threads spend
+    /// their wall-clock time, including time blocked on IO or locks.
+    #[default]
+    WallClock,
+    /// Only sample threads that have used CPU time since the previous tick, which shows where
+    /// the process spends its CPU time and avoids pausing idle threads. A thread's first tick
+    /// only records its CPU time, so it is sampled from the second tick on.
+    OnCpu,
+}
+
+/// Information about a single call to `Sampler::sample`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TickStats {
+    /// The number of threads in the process
+    pub threads: usize,
+    /// The number of threads that were paused and passed to the callback
+    pub sampled: usize,
+    /// The number of threads skipped in `SamplingMode::OnCpu` because they hadn't used any CPU
+    /// time since the previous tick, or because this is the first tick they were seen on
+    pub idle: usize,
+    /// The combined time that threads were held paused in this tick
+    pub total_pause: Duration,
+    /// The longest time any single thread was held paused in this tick
+    pub max_pause: Duration,
+}
+
+impl Sampler {
+    /// Creates a sampler for a process, taking `rate` samples per second
+    pub fn new(pid: Pid, rate: f64) -> Result<Self, Error> {
+        let interval = sampling_interval(rate)?;
+        Ok(Self {
+            process: Process::new(pid)?,
+            interval,
+            governor: None,
+            next_tick: Instant::now(),
+            last_tick: None,
+            next_thread: 0,
+            mode: SamplingMode::default(),
+            cpu_times: HashMap::new(),
+        })
+    }
+
+    /// Sets which threads are sampled on each tick
+    pub fn with_mode(mut self, mode: SamplingMode) -> Self {
+        self.mode = mode;
+        self.cpu_times.clear();
+        self
+    }
+
+    /// Adds a governor that backs off when the sampler is pausing the target for too long
+    pub fn with_governor(mut self, governor: Governor) -> Self {
+        self.governor = Some(governor);
+        self
+    }
+
+    pub fn process(&self) -> &Process {
+        &self.process
+    }
+
+    pub fn governor(&self) -> Option<&Governor> {
+        self.governor.as_ref()
+    }
+
+    pub fn mode(&self) -> SamplingMode {
+        self.mode
+    }
+
+    /// The time between samples, including any backoff applied by the governor
+    pub fn interval(&self) -> Duration {
+        match self.governor.as_ref() {
+            Some(governor) => self.interval.mul_f64(governor.interval_scale()),
+            None => self.interval,
+        }
+    }
+
+    /// Sleeps until the next sample is due
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next_tick > now {
+            std::thread::sleep(self.next_tick - now);
+        }
+        // if we've fallen behind, don't try to catch up by sampling in a burst
+        self.next_tick = self.next_tick.max(now) + self.interval();
+    }
+
+    /// Pauses the threads of the process one at a time, calling `callback` for each one while
+    /// it is stopped. Threads that exit before they can be paused are skipped, as are threads
+    /// that haven't run since the previous tick in `SamplingMode::OnCpu`. If the callback
+    /// returns an error, no more threads are sampled on this tick and the error is returned,
+    /// after the pauses so far have been accounted for.
+    pub fn sample<F>(&mut self, mut callback: F) -> Result<TickStats, Error>
+    where
+        F: FnMut(&Thread) -> Result<(), Error>,
+    {
+        let mut threads = self.process.threads()?;
+        let mut stats = TickStats {
+            threads: threads.len(),
+            ..Default::default()
+        };
+
+        if self.mode == SamplingMode::OnCpu {
+            // read the times from the threads listed above rather than listing them again, and
+            // key them by thread_key so that a reused thread id isn't mistaken for the old thread
+            let mut cpu_times = HashMap::with_capacity(threads.len());
+            threads.retain(|thread| {
+                let (Ok(key), Ok(time)) = (thread.thread_key(), thread.cpu_time()) else {
+                    return false;
+                };
+                cpu_times.insert(key, time);
+                self.cpu_times
+                    .get(&key)
+                    .is_some_and(|previous| time > *previous)
+            });
+            stats.idle = stats.threads - threads.len();
+            self.cpu_times = cpu_times;
+        }
+
+        // when the governor is skipping threads, rotate through them so that every thread is
+        // still sampled eventually
+        let count = match self.governor.as_ref() {
+            Some(governor) => governor.threads_per_tick(threads.len()),
+            None => threads.len(),
+        };
+        let start = if threads.is_empty() {
+            0
+        } else {
+            self.next_thread % threads.len()
+        };
+        self.next_thread = start + count;
+
+        let mut result = Ok(());
+        for thread in threads.iter().cycle().skip(start).take(count) {
+            let paused = Instant::now();
+            let lock = match thread.lock() {
+                Ok(lock) => lock,
+                Err(e) => {
+                    debug!("failed to lock thread: {}", e);
+                    continue;
+                }
+            };
+            result = callback(thread);
+            drop(lock);
+
+            let pause = paused.elapsed();
+            stats.sampled += 1;
+            stats.total_pause += pause;
+            stats.max_pause = stats.max_pause.max(pause);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let now = Instant::now();
+        let elapsed = match self.last_tick.replace(now) {
+            Some(last) => now - last,
+            None => self.interval(),
+        };
+        if let Some(governor) = self.governor.as_mut() {
+            governor.update(&stats, elapsed);
+        }
+        result.map(|_| stats)
+    }
+}
+
+/// Converts a rate in samples per second to the time between samples
+pub(crate) fn sampling_interval(rate: f64) -> Result<Duration, Error> {
+    if !(rate > 0.0 && rate.is_finite()) {
+        return Err(Error::Other(format!("Invalid sampling rate {}", rate)));
+    }
+    // a tiny rate gives an interval too long to represent
+    Duration::try_from_secs_f64(1.0 / rate)
+        .map_err(|_| Error::Other(format!("Invalid sampling rate {}", rate)))
+}
+
+/// Limits the overhead a `Sampler` induces in the target.
+///
+/// The overhead is measured as the fraction of time the threads of the target spend paused by
+/// the sampler. When this exceeds the budget the governor first lowers the sampling frequency,
+/// and once that has hit its limit it only samples a subset of the threads each tick. Both are
+/// gradually restored once the overhead drops back under budget.
+#[derive(Debug, Clone)]
+pub struct Governor {
+    budget: f64,
+    max_interval_scale: f64,
+    min_thread_fraction: f64,
+    smoothing: f64,
+    overhead: Option<f64>,
+    interval_scale: f64,
+    thread_fraction: f64,
+}
+
+impl Governor {
+    /// Creates a governor that keeps the fraction of time the target is paused under `budget`
+    /// (so 0.01 allows threads to be paused at most 1% of the time)
+    pub fn new(budget: f64) -> Result<Self, Error> {
+        if !(budget > 0.0 && budget <= 1.0) {
+            return Err(Error::Other(format!("Invalid overhead budget {}", budget)));
+        }
+        Ok(Self {
+            budget,
+            max_interval_scale: 16.0,
+            min_thread_fraction: 0.05,
+            smoothing: 0.3,
+            overhead: None,
+            interval_scale: 1.0,
+            thread_fraction: 1.0,
+        })
+    }
+
+    /// Sets how far the sampling interval can be stretched, as a multiple of the configured
+    /// interval, before threads start being skipped
+    pub fn max_interval_scale(mut self, scale: f64) -> Self {
+        self.max_interval_scale = scale.max(1.0);
+        self
+    }
+
+    /// Sets the smallest fraction of threads that will be sampled on each tick
+    pub fn min_thread_fraction(mut self, fraction: f64) -> Self {
+        self.min_thread_fraction = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The smoothed fraction of time the target spends paused, if it has been measured yet
+    pub fn overhead(&self) -> Option<f64> {
+        self.overhead
+    }
+
+    pub fn budget(&self) -> f64 {
+        self.budget
+    }
+
+    /// The multiple of the configured interval that the sampler currently waits between ticks
+    pub fn interval_scale(&self) -> f64 {
+        self.interval_scale
+    }
+
+    /// The fraction of threads currently sampled on each tick
+    pub fn thread_fraction(&self) -> f64 {
+        self.thread_fraction
+    }
+
+    /// True if the governor has reduced the sampling frequency or is skipping threads
+    pub fn is_throttled(&self) -> bool {
+        self.interval_scale > 1.0 || self.thread_fraction < 1.0
+    }
+
+    /// The number of threads to sample on a tick, out of `threads` total
+    pub fn threads_per_tick(&self, threads: usize) -> usize {
+        if threads == 0 {
+            return 0;
+        }
+        ((threads as f64 * self.thread_fraction).ceil() as usize).clamp(1, threads)
+    }
+
+    /// Updates the overhead estimate with the results of a tick, where `elapsed` is the wall
+    /// time since the previous tick
+    pub fn update(&mut self, stats: &TickStats, elapsed: Duration) {
+        if stats.threads == 0 || elapsed.is_zero() {
+            return;
+        }
+        let sample =
+            stats.total_pause.as_secs_f64() / (elapsed.as_secs_f64() * stats.threads as f64);
+        let overhead = match self.overhead {
+            Some(overhead) => self.smoothing * sample + (1.0 - self.smoothing) * overhead,
+            None => sample,
+        };
+        self.overhead = Some(overhead);
+
+        if overhead > self.budget {
+            if self.interval_scale < self.max_interval_scale {
+                self.interval_scale = (self.interval_scale * 2.0).min(self.max_interval_scale);
+            } else {
+                self.thread_fraction = (self.thread_fraction / 2.0).max(self.min_thread_fraction);
+            }
+            debug!(
+                "sampling overhead {:.4} exceeds budget {:.4}: interval scale {}, thread fraction {}",
+                overhead, self.budget, self.interval_scale, self.thread_fraction
+            );
+        } else if overhead < self.budget / 2.0 {
+            if self.thread_fraction < 1.0 {
+                self.thread_fraction = (self.thread_fraction * 2.0).min(1.0);
+            } else {
+                self.interval_scale = (self.interval_scale * 0.75).max(1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(threads: usize, total_pause: Duration) -> TickStats {
+        TickStats {
+            threads,
+            sampled: threads,
+            total_pause,
+            max_pause: total_pause,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_governor_backoff() {
+        let mut governor = Governor::new(0.01).unwrap().max_interval_scale(4.0);
+        assert!(!governor.is_throttled());
+        assert_eq!(governor.threads_per_tick(10), 10);
+
+        // 2 threads paused for a combined 10ms over 100ms is 5% overhead
+        let busy = tick(2, Duration::from_millis(10));
+        governor.update(&busy, Duration::from_millis(100));
+        assert_eq!(governor.interval_scale(), 2.0);
+        governor.update(&busy, Duration::from_millis(100));
+        assert_eq!(governor.interval_scale(), 4.0);
+        assert_eq!(governor.thread_fraction(), 1.0);
+
+        // once the interval can't grow anymore, threads start getting skipped
+        governor.update(&busy, Duration::from_millis(100));
+        assert_eq!(governor.interval_scale(), 4.0);
+        assert_eq!(governor.thread_fraction(), 0.5);
+        assert_eq!(governor.threads_per_tick(10), 5);
+        assert!(governor.is_throttled());
+
+        // and everything recovers once we're well under budget
+        let idle = tick(2, Duration::ZERO);
+        for _ in 0..32 {
+            governor.update(&idle, Duration::from_millis(100));
+        }
+        assert!(!governor.is_throttled());
+        assert!(governor.overhead().unwrap() < 0.005);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        assert_eq!(sampling_interval(4.0).unwrap(), Duration::from_millis(250));
+        for rate in [0.0, -1.0, f64::NAN, f64::MIN_POSITIVE, f64::INFINITY] {
+            assert!(sampling_interval(rate).is_err(), "{}", rate);
+        }
+        for budget in [0.0, 1.5, f64::NAN] {
+            assert!(Governor::new(budget).is_err(), "{}", budget);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sampling_mode() {
+        let child = crate::tests::TestChild::sleep();
+        let mut sampler = Sampler::new(child.pid(), 100.0).unwrap();
+        assert_eq!(sampler.mode(), SamplingMode::WallClock);
+        let stats = sampler.sample(|_| Ok(())).unwrap();
+        assert_eq!((stats.threads, stats.sampled, stats.idle), (1, 1, 0));
+
+        // a failing callback still counts towards the overhead
+        let mut sampler = sampler.with_governor(Governor::new(0.01).unwrap());
+        assert!(sampler
+            .sample(|_| Err(Error::Other("failed".to_string())))
+            .is_err());
+        assert!(sampler.governor().unwrap().overhead().is_some());
+
+        let mut sampler = sampler.with_mode(SamplingMode::OnCpu);
+        for _ in 0..2 {
+            let stats = sampler.sample(|_| Ok(())).unwrap();
+            assert_eq!((stats.threads, stats.sampled, stats.idle), (1, 0, 1));
+        }
+    }
+
+    #[test]
+    fn test_threads_per_tick() {
+        let governor = Governor::new(0.01).unwrap().min_thread_fraction(0.1);
+        assert_eq!(governor.threads_per_tick(0), 0);
+        assert_eq!(governor.threads_per_tick(1), 1);
+    }
+}
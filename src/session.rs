@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::{Error, Pid, Process, Thread};
+
+/// A change to the processes tracked by a `Session`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A descendant of the root process was found and attached to
+    Attached { pid: Pid, parent: Pid },
+    /// A tracked process exec'd a new binary, and its state was recreated by calling `init`
+    /// again. Samples from before the exec came from the old binary.
+    Exec { pid: Pid },
+    /// A tracked process exited, and its state was dropped
+    Exited { pid: Pid },
+    /// A tracked process exec'd a new binary, but creating the state for it failed, so it
+    /// is no longer sampled
+    Detached { pid: Pid },
+}
+
+/// Creates the state for each process a session attaches to
+type InitFn<T> = Box<dyn FnMut(&Process) -> Result<T, Error>>;
+
+/// A process tracked by a session, along with the state created for it
+struct Member<T> {
+    process: Process,
+    parent: Option<Pid>,
+    /// The binary the process was running when it was attached to, for noticing when it execs
+    binary: BinaryId,
+    state: T,
+}
+
+impl<T> Member<T> {
+    fn attach(init: &mut InitFn<T>, pid: Pid, parent: Option<Pid>) -> Result<Self, Error> {
+        let process = Process::new(pid)?;
+        // without this we could never tell that the process exec'd, so fail the attach
+        let binary = binary_id(&process)?;
+        let state = init(&process)?;
+        Ok(Self {
+            process,
+            parent,
+            binary,
+            state,
+        })
+    }
+
+    /// Returns true if the process is running a different binary than when it was attached to
+    fn has_exec(&self) -> bool {
+        match binary_id(&self.process) {
+            Ok(binary) => binary != self.binary,
+            // the process has probably exited since, which the next refresh picks up
+            Err(_) => false,
+        }
+    }
+}
+
+/// The device and inode of the binary a process is running. Unlike its path, these change
+/// when the process execs the same path after the binary was replaced, and don't depend on
+/// which symlink the binary was started through.
+#[cfg(target_os = "linux")]
+type BinaryId = (u64, u64);
+
+#[cfg(target_os = "linux")]
+fn binary_id(process: &Process) -> Result<BinaryId, Error> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(format!("/proc/{}/exe", process.pid))?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// The path of the binary a process is running, on the platforms where we don't have a
+/// handle to the binary itself
+#[cfg(not(target_os = "linux"))]
+type BinaryId = String;
+
+#[cfg(not(target_os = "linux"))]
+fn binary_id(process: &Process) -> Result<BinaryId, Error> {
+    process.exe()
+}
+
+/// Samples a process along with all of its descendants, which is what profiling multi-process
+/// programs like gunicorn, Chrome or `make -j` needs.
+///
+/// The session periodically looks for new child processes, and calls `init` to create the
+/// state for each one (like its unwinder and symbolicator) when it's attached to. The state
+/// is dropped again once the process exits, and recreated when the process execs a new binary
+/// so that it's never symbolicated against the binary it was running before. Samples from
+/// every process are passed to the same callback, along with the pid and state of the process
+/// they came from:
+///
+/// ```rust,no_run
+/// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+/// let mut session = remoteprocess::Session::new(pid, 100.0, |process| process.exe())?;
+/// while !session.is_finished() {
+///     session.wait();
+///     let events = session.sample(|pid, thread, exe| {
+///         println!("sampled {} in {} ({})", thread.id()?, pid, exe);
+///         Ok(())
+///     })?;
+///     for event in events {
+///         println!("{:?}", event);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Session<T> {
+    root: Pid,
+    init: InitFn<T>,
+    members: BTreeMap<Pid, Member<T>>,
+    /// Descendants that couldn't be attached to, which aren't retried until their pid is reused
+    failed: HashSet<Pid>,
+    interval: Duration,
+    refresh_interval: Duration,
+    next_tick: Instant,
+    next_refresh: Instant,
+}
+
+impl<T> Session<T> {
+    /// Attaches to the process `pid`, taking `rate` samples per second from it and each of its
+    /// descendants. The descendants that already exist are attached to on the first call to
+    /// `sample`.
+    pub fn new<F>(pid: Pid, rate: f64, init: F) -> Result<Self, Error>
+    where
+        F: FnMut(&Process) -> Result<T, Error> + 'static,
+    {
+        let interval = crate::sampler::sampling_interval(rate)?;
+        let mut init: InitFn<T> = Box::new(init);
+        let mut members = BTreeMap::new();
+        members.insert(pid, Member::attach(&mut init, pid, None)?);
+        let now = Instant::now();
+        Ok(Self {
+            root: pid,
+            init,
+            members,
+            failed: HashSet::new(),
+            interval,
+            refresh_interval: Duration::from_secs(1),
+            next_tick: now,
+            next_refresh: now,
+        })
+    }
+
+    /// Sets how often `sample` looks for new and exited processes, which defaults to once a
+    /// second. Each refresh lists every process on the system, so it's much more expensive
+    /// than a sample.
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    pub fn root(&self) -> Pid {
+        self.root
+    }
+
+    /// The pids of the processes currently being tracked
+    pub fn pids(&self) -> Vec<Pid> {
+        self.members.keys().copied().collect()
+    }
+
+    pub fn process(&self, pid: Pid) -> Option<&Process> {
+        self.members.get(&pid).map(|member| &member.process)
+    }
+
+    /// The pid of the process that `pid` was found as a child of, or None for the root
+    pub fn parent(&self, pid: Pid) -> Option<Pid> {
+        self.members.get(&pid)?.parent
+    }
+
+    /// The state created by `init` for a tracked process
+    pub fn state(&self, pid: Pid) -> Option<&T> {
+        self.members.get(&pid).map(|member| &member.state)
+    }
+
+    pub fn state_mut(&mut self, pid: Pid) -> Option<&mut T> {
+        self.members.get_mut(&pid).map(|member| &mut member.state)
+    }
+
+    /// Returns true once every tracked process has exited
+    pub fn is_finished(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Looks for processes that have exited, exec'd or been started since the last refresh.
+    /// This is called by `sample` every refresh interval, so it only needs to be called
+    /// directly to pick up changes sooner.
+    pub fn refresh(&mut self) -> Result<Vec<SessionEvent>, Error> {
+        let mut events = Vec::new();
+        self.members.retain(|&pid, member| {
+            let alive = member.process.is_alive();
+            if !alive {
+                info!("process {} has exited", pid);
+                events.push(SessionEvent::Exited { pid });
+            }
+            alive
+        });
+
+        let execs: Vec<Pid> = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.has_exec())
+            .map(|(&pid, _)| pid)
+            .collect();
+        for pid in execs {
+            let parent = self.members[&pid].parent;
+            match Member::attach(&mut self.init, pid, parent) {
+                Ok(member) => {
+                    info!("process {} has exec'd {:?}", pid, member.process.exe().ok());
+                    self.members.insert(pid, member);
+                    events.push(SessionEvent::Exec { pid });
+                }
+                Err(e) => {
+                    warn!("failed to reattach to process {} after exec: {}", pid, e);
+                    self.members.remove(&pid);
+                    self.failed.insert(pid);
+                    events.push(SessionEvent::Detached { pid });
+                }
+            }
+        }
+
+        // descendants are found from the root, or from the processes whose parents have exited
+        // once the root is gone (since their children are reparented away from the root)
+        let mut children = Vec::new();
+        for (pid, member) in self.members.iter() {
+            if member
+                .parent
+                .is_some_and(|parent| self.members.contains_key(&parent))
+            {
+                continue;
+            }
+            match member.process.child_processes() {
+                Ok(found) => children.extend(found),
+                Err(e) => debug!("failed to get the child processes of {}: {}", pid, e),
+            }
+        }
+        children.sort_unstable();
+
+        let seen: HashSet<Pid> = children.iter().map(|&(child, _)| child).collect();
+        self.failed.retain(|pid| seen.contains(pid));
+
+        for (pid, parent) in children {
+            if self.members.contains_key(&pid) || self.failed.contains(&pid) {
+                continue;
+            }
+            match Member::attach(&mut self.init, pid, Some(parent)) {
+                Ok(member) => {
+                    info!("attached to process {} (a child of {})", pid, parent);
+                    self.members.insert(pid, member);
+                    events.push(SessionEvent::Attached { pid, parent });
+                }
+                Err(e) => {
+                    warn!("failed to attach to process {}: {}", pid, e);
+                    self.failed.insert(pid);
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Sleeps until the next sample is due
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next_tick > now {
+            std::thread::sleep(self.next_tick - now);
+        }
+        self.next_tick = self.next_tick.max(now) + self.interval;
+    }
+
+    /// Pauses the threads of every tracked process one at a time, calling `callback` with the
+    /// pid and state of the process for each thread while it is stopped. Refreshes the
+    /// processes first if the refresh interval has elapsed, and returns what changed.
+    pub fn sample<F>(&mut self, mut callback: F) -> Result<Vec<SessionEvent>, Error>
+    where
+        F: FnMut(Pid, &Thread, &mut T) -> Result<(), Error>,
+    {
+        let now = Instant::now();
+        let events = if now >= self.next_refresh {
+            self.next_refresh = now + self.refresh_interval;
+            self.refresh()?
+        } else {
+            Vec::new()
+        };
+
+        for (&pid, member) in self.members.iter_mut() {
+            // the process may have exited since the last refresh
+            let threads = match member.process.threads() {
+                Ok(threads) => threads,
+                Err(e) => {
+                    debug!("failed to get the threads of {}: {}", pid, e);
+                    continue;
+                }
+            };
+            for thread in threads.iter() {
+                let lock = match thread.lock() {
+                    Ok(lock) => lock,
+                    Err(e) => {
+                        debug!("failed to lock thread: {}", e);
+                        continue;
+                    }
+                };
+                let result = callback(pid, thread, &mut member.state);
+                drop(lock);
+                result?;
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_rate() {
+        let pid = std::process::id() as Pid;
+        for rate in [0.0, f64::NAN, f64::MIN_POSITIVE] {
+            assert!(Session::new(pid, rate, |_| Ok(())).is_err());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_session_exec() {
+        use std::io::Write;
+
+        let mut child = crate::tests::TestChild::spawn(
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg("read line; exec sleep 10")
+                .stdin(std::process::Stdio::piped()),
+        );
+        let pid = child.pid();
+        let mut session = Session::new(pid, 100.0, |process| process.exe()).unwrap();
+        let shell = session.state(pid).unwrap().clone();
+        assert!(session.refresh().unwrap().is_empty());
+
+        child.stdin.take().unwrap().write_all(b"go\n").unwrap();
+        let mut events = Vec::new();
+        for _ in 0..100 {
+            events.extend(session.refresh().unwrap());
+            if !events.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(events, vec![SessionEvent::Exec { pid }]);
+        let exe = session.state(pid).unwrap();
+        assert_ne!(exe, &shell);
+        assert!(exe.ends_with("sleep"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_session() {
+        /// Kills the sleeps, which outlive the shell that started them, if the test fails
+        struct KillChildren(Vec<Pid>);
+
+        impl Drop for KillChildren {
+            fn drop(&mut self) {
+                for &pid in &self.0 {
+                    let _ = Process::new(pid).and_then(|process| process.kill());
+                }
+            }
+        }
+
+        let mut child = crate::tests::TestChild::spawn(
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg("sleep 10 & sleep 10 & wait"),
+        );
+        let root = child.pid();
+
+        // wait for the forked shells to exec the sleeps, so that attaching to them doesn't
+        // race with the exec
+        let process = Process::new(root).unwrap();
+        let mut sleeps = KillChildren(Vec::new());
+        for _ in 0..100 {
+            sleeps.0 = process
+                .child_processes()
+                .unwrap()
+                .iter()
+                .map(|&(pid, _)| pid)
+                .filter(|&pid| {
+                    Process::new(pid)
+                        .and_then(|process| process.exe())
+                        .is_ok_and(|exe| exe.ends_with("sleep"))
+                })
+                .collect();
+            if sleeps.0.len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut session = Session::new(root, 100.0, |process| Ok(process.pid)).unwrap();
+        let mut attached = Vec::new();
+        for _ in 0..100 {
+            attached.extend(session.refresh().unwrap());
+            if attached.len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(attached.len(), 2);
+        assert!(attached.iter().all(
+            |event| matches!(event, SessionEvent::Attached { parent, .. } if *parent == root)
+        ));
+        assert_eq!(session.pids().len(), 3);
+
+        let mut sampled = HashSet::new();
+        let events = session
+            .sample(|pid, thread, state| {
+                assert_eq!(pid, *state);
+                sampled.insert((pid, thread.id()?));
+                Ok(())
+            })
+            .unwrap();
+        assert!(events.is_empty());
+        assert_eq!(sampled.len(), 3);
+
+        // killing the shell leaves the sleeps running, and they're still tracked
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let events = session.refresh().unwrap();
+        assert_eq!(events, vec![SessionEvent::Exited { pid: root }]);
+        assert_eq!(session.pids().len(), 2);
+        for pid in session.pids() {
+            session.process(pid).unwrap().kill().unwrap();
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Finds printable strings in the memory of another process, like running `strings` on a dump of
+//! it but without writing the memory to disk first.
+//!
+//! ```rust,no_run
+//! # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+//! use remoteprocess::{StringEncoding, StringScanner};
+//!
+//! let process = remoteprocess::Process::new(pid)?;
+//! let scanner = StringScanner::new(8).with_encodings(&[StringEncoding::Utf8]);
+//! let regions = StringScanner::readable_regions(pid)?;
+//! scanner.scan(&process, &regions, |found| {
+//!     println!("0x{:016x} {}", found.address, found.value);
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ops::Range;
+
+use crate::{Error, Pid, ProcessMemory};
+
+/// How much memory is copied from the target at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+const PAGE_SIZE: usize = 4096;
+
+/// The encodings strings are searched for in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StringEncoding {
+    /// Printable 7 bit ASCII characters, which is what `strings` looks for by default
+    Ascii,
+    /// Printable characters encoded as UTF-8, which includes the ASCII strings
+    Utf8,
+    /// Printable characters encoded as 2 byte aligned little endian UTF-16, like Windows wide
+    /// strings. This only matches characters below U+0800 (the Latin, Greek, Cyrillic, Hebrew
+    /// and Arabic scripts among others), since allowing the full range finds strings in almost
+    /// any data.
+    Utf16Le,
+}
+
+/// A string found by `StringScanner`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundString {
+    /// The address of the first byte of the string in the target
+    pub address: u64,
+    pub encoding: StringEncoding,
+    pub value: String,
+}
+
+/// Searches memory for runs of printable characters
+#[derive(Debug, Clone)]
+pub struct StringScanner {
+    min_length: usize,
+    encodings: Vec<StringEncoding>,
+}
+
+impl Default for StringScanner {
+    /// Finds ASCII and UTF-16 strings of at least 4 characters, like `strings -e s` and
+    /// `strings -e l` combined
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl StringScanner {
+    /// Creates a scanner for ASCII and UTF-16 strings of at least `min_length` characters
+    pub fn new(min_length: usize) -> Self {
+        Self {
+            min_length: min_length.max(1),
+            encodings: vec![StringEncoding::Ascii, StringEncoding::Utf16Le],
+        }
+    }
+
+    /// Sets the encodings to search for
+    pub fn with_encodings(mut self, encodings: &[StringEncoding]) -> Self {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    /// Returns the readable memory regions of a process, to pass to `scan`
+    pub fn readable_regions(pid: Pid) -> Result<Vec<Range<u64>>, Error> {
+        Ok(proc_maps::get_process_maps(pid)?
+            .iter()
+            .filter(|m| m.is_read())
+            .map(|m| m.start() as u64..(m.start() + m.size()) as u64)
+            .collect())
+    }
+
+    /// Calls `found` with each string in the regions, as it is found. Strings are reported in
+    /// address order for each encoding, and don't continue from one region to the next. Pages
+    /// that can't be read are skipped.
+    pub fn scan<M: ProcessMemory + ?Sized>(
+        &self,
+        memory: &M,
+        regions: &[Range<u64>],
+        mut found: impl FnMut(FoundString),
+    ) -> Result<(), Error> {
+        let mut decoders: Vec<Decoder> = self.encodings.iter().map(|&e| Decoder::new(e)).collect();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        for region in regions {
+            let mut addr = region.start;
+            while addr < region.end {
+                let len = ((region.end - addr) as usize).min(CHUNK_SIZE);
+                let chunk = &mut buf[..len];
+                if memory.read(addr as usize, chunk).is_ok() {
+                    self.feed(&mut decoders, addr, chunk, &mut found);
+                } else {
+                    // part of the chunk isn't readable, so go a page at a time to find out which
+                    let mut page_addr = addr;
+                    while page_addr < addr + len as u64 {
+                        let page_len = (PAGE_SIZE - page_addr as usize % PAGE_SIZE)
+                            .min((addr + len as u64 - page_addr) as usize);
+                        let page = &mut buf[..page_len];
+                        if memory.read(page_addr as usize, page).is_ok() {
+                            self.feed(&mut decoders, page_addr, page, &mut found);
+                        } else {
+                            self.finish(&mut decoders, &mut found);
+                        }
+                        page_addr += page_len as u64;
+                    }
+                }
+                addr += len as u64;
+            }
+            self.finish(&mut decoders, &mut found);
+        }
+        Ok(())
+    }
+
+    fn feed(
+        &self,
+        decoders: &mut [Decoder],
+        addr: u64,
+        data: &[u8],
+        found: &mut impl FnMut(FoundString),
+    ) {
+        for decoder in decoders {
+            for (i, &b) in data.iter().enumerate() {
+                decoder.push(addr + i as u64, b, self.min_length, found);
+            }
+        }
+    }
+
+    fn finish(&self, decoders: &mut [Decoder], found: &mut impl FnMut(FoundString)) {
+        for decoder in decoders {
+            decoder.end_run(self.min_length, found);
+        }
+    }
+}
+
+/// Builds up the current run of printable characters for one encoding, a byte at a time
+struct Decoder {
+    encoding: StringEncoding,
+    start: u64,
+    text: String,
+    chars: usize,
+    /// The bytes of a character that has been partly read, and the address of its first byte
+    pending: Vec<u8>,
+    pending_start: u64,
+}
+
+impl Decoder {
+    fn new(encoding: StringEncoding) -> Self {
+        Self {
+            encoding,
+            start: 0,
+            text: String::new(),
+            chars: 0,
+            pending: Vec::with_capacity(4),
+            pending_start: 0,
+        }
+    }
+
+    fn push(&mut self, addr: u64, b: u8, min_length: usize, found: &mut impl FnMut(FoundString)) {
+        match self.encoding {
+            StringEncoding::Ascii => {
+                let c = b as char;
+                if is_printable(c) && b.is_ascii() {
+                    self.add_char(addr, c);
+                } else {
+                    self.end_run(min_length, found);
+                }
+            }
+            StringEncoding::Utf8 => self.push_utf8(addr, b, min_length, found),
+            StringEncoding::Utf16Le => self.push_utf16(addr, b, min_length, found),
+        }
+    }
+
+    fn push_utf8(
+        &mut self,
+        addr: u64,
+        b: u8,
+        min_length: usize,
+        found: &mut impl FnMut(FoundString),
+    ) {
+        if self.pending.is_empty() {
+            match b {
+                0x00..=0x7f if is_printable(b as char) => self.add_char(addr, b as char),
+                0xc2..=0xf4 => self.start_pending(addr, b),
+                _ => self.end_run(min_length, found),
+            }
+            return;
+        }
+        if b & 0xc0 != 0x80 {
+            // the sequence was cut short, so the run ends and this byte starts over
+            self.end_run(min_length, found);
+            return self.push_utf8(addr, b, min_length, found);
+        }
+        self.pending.push(b);
+        let expected = match self.pending[0] {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        if self.pending.len() < expected {
+            return;
+        }
+        match std::str::from_utf8(&self.pending)
+            .ok()
+            .and_then(|s| s.chars().next())
+        {
+            Some(c) if is_printable(c) => {
+                let start = self.pending_start;
+                self.pending.clear();
+                self.add_char(start, c);
+            }
+            _ => self.end_run(min_length, found),
+        }
+    }
+
+    fn push_utf16(
+        &mut self,
+        addr: u64,
+        b: u8,
+        min_length: usize,
+        found: &mut impl FnMut(FoundString),
+    ) {
+        if addr.is_multiple_of(2) {
+            self.start_pending(addr, b);
+            return;
+        }
+        if self.pending.is_empty() {
+            // the low byte wasn't read, so this can't be part of a string
+            self.end_run(min_length, found);
+            return;
+        }
+        let unit = u16::from_le_bytes([self.pending[0], b]);
+        let start = self.pending_start;
+        self.pending.clear();
+        // surrogates are only used for characters far above U+0800, so they end the run too
+        match char::from_u32(unit as u32) {
+            Some(c) if is_printable(c) && unit < 0x800 => self.add_char(start, c),
+            _ => self.end_run(min_length, found),
+        }
+    }
+
+    fn start_pending(&mut self, addr: u64, b: u8) {
+        self.pending.clear();
+        self.pending.push(b);
+        self.pending_start = addr;
+    }
+
+    fn add_char(&mut self, addr: u64, c: char) {
+        if self.chars == 0 {
+            self.start = addr;
+        }
+        self.text.push(c);
+        self.chars += 1;
+    }
+
+    fn end_run(&mut self, min_length: usize, found: &mut impl FnMut(FoundString)) {
+        if self.chars >= min_length {
+            found(FoundString {
+                address: self.start,
+                encoding: self.encoding,
+                value: std::mem::take(&mut self.text),
+            });
+        }
+        self.text.clear();
+        self.chars = 0;
+        self.pending.clear();
+    }
+}
+
+fn is_printable(c: char) -> bool {
+    c == '\t' || !c.is_control()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalProcess;
+
+    #[test]
+    fn test_scan_strings() {
+        let mut data = vec![0u8; CHUNK_SIZE + 64];
+        // straddles the boundary between the first two chunks of the second region
+        let ascii_at = 32 + CHUNK_SIZE - 3;
+        data[ascii_at..ascii_at + 11].copy_from_slice(b"hello world");
+        data[8..11].copy_from_slice(b"abc");
+        data[16..23].copy_from_slice("h\u{e9}llo!".as_bytes());
+        let wide: Vec<u8> = "W\u{e9}de"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        data[32..32 + wide.len()].copy_from_slice(&wide);
+
+        let start = data.as_ptr() as u64;
+        let regions = [start..start + 32, start + 32..start + data.len() as u64];
+        let mut found = Vec::new();
+        let scanner = StringScanner::new(4).with_encodings(&[
+            StringEncoding::Ascii,
+            StringEncoding::Utf8,
+            StringEncoding::Utf16Le,
+        ]);
+        scanner
+            .scan(&LocalProcess, &regions, |s| {
+                found.push((s.address - start, s.encoding, s.value))
+            })
+            .unwrap();
+
+        // strings are reported a chunk at a time, so the encodings are interleaved
+        let mut expected = vec![
+            (19, StringEncoding::Ascii, "llo!".to_string()),
+            (16, StringEncoding::Utf8, "h\u{e9}llo!".to_string()),
+            (
+                ascii_at as u64,
+                StringEncoding::Ascii,
+                "hello world".to_string(),
+            ),
+            (
+                ascii_at as u64,
+                StringEncoding::Utf8,
+                "hello world".to_string(),
+            ),
+        ];
+        // the UTF-16 string is only found at the right alignment
+        if start.is_multiple_of(2) {
+            expected.insert(2, (32, StringEncoding::Utf16Le, "W\u{e9}de".to_string()));
+        }
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+
+use crate::{Error, Process, Thread, Tid};
+
+/// The threads of a process, and how they changed since an earlier call to `threads`, from
+/// `Process::thread_changes`
+#[derive(Default)]
+pub struct ThreadChanges {
+    /// All the threads of the process now
+    pub threads: Vec<Thread>,
+    /// The ids of the threads that were started since the previous list
+    pub appeared: Vec<Tid>,
+    /// The ids of the threads in the previous list that have exited
+    pub disappeared: Vec<Tid>,
+    /// The keys of `threads`, captured when they were listed so that the next call can still
+    /// compare against the threads that have exited since
+    keys: Vec<(ThreadKey, Tid)>,
+}
+
+/// Identifies a thread across listings: its id (or on OSX, its system wide `thread_id`),
+/// and a value that changes when that id is reused by a new thread, like its start time
+pub(crate) type ThreadKey = (u64, u64);
+
+impl ThreadChanges {
+    /// Returns whether any threads were started or exited
+    pub fn is_empty(&self) -> bool {
+        self.appeared.is_empty() && self.disappeared.is_empty()
+    }
+}
+
+impl Process {
+    /// Lists the threads of the process, and compares them to `previous` (the changes from an
+    /// earlier sample, or `ThreadChanges::default()` on the first call). This lets samplers
+    /// keep per thread state, and only add or drop the entries for the threads that changed:
+    ///
+    /// ```rust,no_run
+    /// # fn run(pid: remoteprocess::Pid) -> Result<(), remoteprocess::Error> {
+    /// let process = remoteprocess::Process::new(pid)?;
+    /// let mut samples = std::collections::HashMap::new();
+    /// let mut changes = remoteprocess::ThreadChanges::default();
+    /// loop {
+    ///     changes = process.thread_changes(&changes)?;
+    ///     for tid in &changes.disappeared {
+    ///         samples.remove(tid);
+    ///     }
+    ///     for tid in &changes.appeared {
+    ///         samples.insert(*tid, 0);
+    ///     }
+    ///     # break;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Threads are compared by a key that isn't reused, so when a thread exits and a new one
+    /// gets the same id between two calls, the id is in both `disappeared` and `appeared`.
+    /// Threads that exit while they are being listed are left out.
+    pub fn thread_changes(&self, previous: &ThreadChanges) -> Result<ThreadChanges, Error> {
+        let mut threads = Vec::new();
+        let mut keys = Vec::new();
+        for thread in self.threads()? {
+            match thread.thread_key() {
+                Ok(key) => {
+                    keys.push((key, thread.id()?));
+                    threads.push(thread);
+                }
+                Err(e) if matches!(e.root(), Error::ThreadExited(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let (appeared, disappeared) = diff(&previous.keys, &keys);
+        Ok(ThreadChanges {
+            threads,
+            appeared,
+            disappeared,
+            keys,
+        })
+    }
+}
+
+/// Returns the ids of the keys in `current` that aren't in `previous`, and of the ones in
+/// `previous` that aren't in `current`, each in the order they were listed
+fn diff(previous: &[(ThreadKey, Tid)], current: &[(ThreadKey, Tid)]) -> (Vec<Tid>, Vec<Tid>) {
+    let previous_set: HashSet<ThreadKey> = previous.iter().map(|(key, _)| *key).collect();
+    let current_set: HashSet<ThreadKey> = current.iter().map(|(key, _)| *key).collect();
+    let appeared = current
+        .iter()
+        .filter(|(key, _)| !previous_set.contains(key))
+        .map(|(_, tid)| *tid)
+        .collect();
+    let disappeared = previous
+        .iter()
+        .filter(|(key, _)| !current_set.contains(key))
+        .map(|(_, tid)| *tid)
+        .collect();
+    (appeared, disappeared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(threads: &[(Tid, u64)]) -> Vec<(ThreadKey, Tid)> {
+        threads
+            .iter()
+            .map(|&(tid, start)| ((tid as u64, start), tid))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff = |previous, current| diff(&keys(previous), &keys(current));
+        assert_eq!(
+            diff(&[(1, 0), (2, 0), (3, 0)], &[(3, 0), (4, 0), (1, 0), (5, 0)]),
+            (vec![4, 5], vec![2])
+        );
+        assert_eq!(diff(&[(1, 0), (2, 0)], &[(2, 0), (1, 0)]), (vec![], vec![]));
+        assert_eq!(diff(&[], &[(7, 0)]), (vec![7], vec![]));
+        // a reused id is reported as exited and started again
+        assert_eq!(
+            diff(&[(1, 0), (2, 5)], &[(1, 0), (2, 9)]),
+            (vec![2], vec![2])
+        );
+    }
+
+    #[test]
+    fn test_thread_changes() {
+        let process = Process::new(std::process::id() as crate::Pid).unwrap();
+        let changes = process.thread_changes(&ThreadChanges::default()).unwrap();
+        assert!(!changes.is_empty());
+        assert!(changes.disappeared.is_empty());
+        assert_eq!(changes.appeared.len(), changes.threads.len());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_thread_exit() {
+        let process = Process::new(std::process::id() as crate::Pid).unwrap();
+        let before = process.thread_changes(&ThreadChanges::default()).unwrap();
+
+        let (started, exit) = (std::sync::Barrier::new(2), std::sync::Barrier::new(2));
+        std::thread::scope(|scope| {
+            let thread = scope.spawn(|| {
+                started.wait();
+                exit.wait();
+                nix::unistd::gettid().as_raw()
+            });
+            started.wait();
+            let running = process.thread_changes(&before).unwrap();
+            exit.wait();
+            let tid = thread.join().unwrap();
+            assert!(running.appeared.contains(&tid));
+
+            // the tid may not be gone from /proc yet right after the join
+            let mut after = process.thread_changes(&running).unwrap();
+            for _ in 0..100 {
+                if after.disappeared.contains(&tid) {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                after = process.thread_changes(&running).unwrap();
+            }
+            assert!(after.disappeared.contains(&tid));
+        });
+    }
+}
@@ -0,0 +1,92 @@
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::time::Duration;
+
+use winapi::shared::winerror::WAIT_TIMEOUT;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::{INFINITE, WAIT_FAILED, WAIT_OBJECT_0};
+
+use super::{Pid, Process, ProcessHandle};
+use crate::Error;
+
+/// A waitable handle that is signalled when a process exits.
+///
+/// This holds a handle to the process, which can be passed to the wait functions through
+/// `AsRawHandle`, or checked directly with `has_exited` and `wait`.
+pub struct ExitNotification {
+    pid: Pid,
+    handle: ProcessHandle,
+}
+
+impl ExitNotification {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns true if the process has exited, without blocking
+    pub fn has_exited(&self) -> Result<bool, Error> {
+        self.wait(Some(Duration::ZERO))
+    }
+
+    /// Blocks until the process exits or the timeout elapses, returning true if the process
+    /// has exited. A timeout of None waits forever.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        let timeout = match timeout {
+            // round up, so that sub millisecond timeouts don't turn into a non-blocking wait
+            Some(timeout) => timeout
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min((INFINITE - 1) as u128) as u32,
+            None => INFINITE,
+        };
+        match unsafe { WaitForSingleObject(*self.handle, timeout) } {
+            WAIT_OBJECT_0 => Ok(true),
+            WAIT_TIMEOUT => Ok(false),
+            WAIT_FAILED => Err(std::io::Error::last_os_error().into()),
+            ret => Err(Error::Other(format!(
+                "unexpected WaitForSingleObject result {}",
+                ret
+            ))),
+        }
+    }
+}
+
+impl AsRawHandle for ExitNotification {
+    fn as_raw_handle(&self) -> RawHandle {
+        *self.handle
+    }
+}
+
+impl Process {
+    /// Returns a handle that can be waited on to find out when this process exits. Since this
+    /// holds a handle to the process, its pid can't be reused while this is alive.
+    pub fn exit_notification(&self) -> Result<ExitNotification, Error> {
+        // waiting on the handle needs SYNCHRONIZE access, which both access modes ask for
+        Ok(ExitNotification {
+            pid: self.pid,
+            handle: self.handle.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_notification() {
+        let mut child = std::process::Command::new("cmd")
+            .args(["/C", "ping -n 30 127.0.0.1 > NUL"])
+            .spawn()
+            .unwrap();
+        let process = Process::new(child.id() as Pid).unwrap();
+        let notification = process.exit_notification().unwrap();
+        assert!(!notification.has_exited().unwrap());
+        assert!(process.exists());
+
+        child.kill().unwrap();
+        assert!(notification.wait(Some(Duration::from_secs(5))).unwrap());
+        assert!(notification.has_exited().unwrap());
+        assert!(!process.exists());
+        child.wait().unwrap();
+    }
+}
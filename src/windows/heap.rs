@@ -0,0 +1,109 @@
+//! Enumerates the heaps of a process and the blocks in them, using the Toolhelp32 heap APIs.
+//!
+//! These are the only documented way to walk the heaps of another process, so this doesn't read
+//! the PEB heap list or the segment and low fragmentation heap structures itself. That limits it
+//! to what Toolhelp32 reports: the heaps created through the process heap API (not private heaps
+//! like the CRT's on older runtimes, or segment heaps), and only for processes with the same
+//! bitness as ours. Windows walks the heap from the start for each call, so walking a heap with
+//! many blocks is slow - Heap32Next takes longer the further into the heap it gets.
+
+use winapi::shared::basetsd::ULONG_PTR;
+use winapi::shared::minwindef::FALSE;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::tlhelp32::{
+    CreateToolhelp32Snapshot, Heap32First, Heap32ListFirst, Heap32ListNext, Heap32Next,
+    HEAPENTRY32, HEAPLIST32, HF32_DEFAULT, LF32_FREE, TH32CS_SNAPHEAPLIST,
+};
+
+use super::{Process, ProcessHandle};
+use crate::Error;
+
+/// A heap of the process. The default process heap is marked as the main one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arena {
+    /// The heap's id, which is the address of the heap
+    pub address: u64,
+    /// Whether this is the default process heap, as returned by GetProcessHeap
+    pub main: bool,
+}
+
+/// A block of memory in a heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapChunk {
+    /// The address of the block, as returned by HeapAlloc
+    pub address: u64,
+    /// The size of the block
+    pub size: u64,
+    /// Whether the block is allocated
+    pub in_use: bool,
+}
+
+impl HeapChunk {
+    /// The address that the allocator returned for this block
+    pub fn mem(&self) -> u64 {
+        self.address
+    }
+}
+
+/// Walks the heaps and heap blocks of a process
+pub struct HeapWalker<'a> {
+    process: &'a Process,
+}
+
+impl<'a> HeapWalker<'a> {
+    pub fn new(process: &'a Process) -> Result<Self, Error> {
+        Ok(Self { process })
+    }
+
+    /// Returns the heaps of the process, as listed by a Toolhelp32 heap list snapshot
+    pub fn arenas(&self) -> Result<Vec<Arena>, Error> {
+        let mut arenas = Vec::new();
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPHEAPLIST, self.process.pid);
+            if snapshot == INVALID_HANDLE_VALUE {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            // closes the snapshot when it goes out of scope
+            let snapshot: ProcessHandle = snapshot.into();
+
+            let mut list = std::mem::zeroed::<HEAPLIST32>();
+            list.dwSize = size_of::<HEAPLIST32>();
+            if Heap32ListFirst(*snapshot, &mut list) == FALSE {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            loop {
+                arenas.push(Arena {
+                    address: list.th32HeapID as u64,
+                    main: list.dwFlags & HF32_DEFAULT != 0,
+                });
+                if Heap32ListNext(*snapshot, &mut list) == FALSE {
+                    break;
+                }
+            }
+        }
+        Ok(arenas)
+    }
+
+    /// Returns the blocks in a heap, in the order Windows walks them
+    pub fn chunks(&self, arena: &Arena) -> Result<Vec<HeapChunk>, Error> {
+        let mut chunks = Vec::new();
+        unsafe {
+            let mut entry = std::mem::zeroed::<HEAPENTRY32>();
+            entry.dwSize = size_of::<HEAPENTRY32>();
+            if Heap32First(&mut entry, self.process.pid, arena.address as ULONG_PTR) == FALSE {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            loop {
+                chunks.push(HeapChunk {
+                    address: entry.dwAddress as u64,
+                    size: entry.dwBlockSize as u64,
+                    in_use: entry.dwFlags & LF32_FREE == 0,
+                });
+                if Heap32Next(&mut entry) == FALSE {
+                    break;
+                }
+            }
+        }
+        Ok(chunks)
+    }
+}
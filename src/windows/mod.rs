@@ -1,28 +1,40 @@
 use std::ffi::OsString;
 use std::os::windows::ffi::OsStringExt;
-use winapi::shared::minwindef::{DWORD, FALSE, MAX_PATH, ULONG};
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::time::{Duration, Instant, SystemTime};
+use winapi::shared::minwindef::{DWORD, FALSE, FILETIME, MAX_PATH, ULONG};
 use winapi::shared::ntdef::PUNICODE_STRING;
 use winapi::shared::ntdef::{NTSTATUS, NULL, PVOID, USHORT, VOID};
+use winapi::shared::winerror::WAIT_TIMEOUT;
+use winapi::um::minwinbase::STILL_ACTIVE;
 use winapi::um::processthreadsapi::{
-    GetThreadId, OpenProcess, OpenThread, ResumeThread, SuspendThread,
+    GetExitCodeProcess, GetProcessId, GetProcessTimes, GetThreadId, GetThreadTimes, OpenProcess,
+    OpenThread, ProcessIdToSessionId, ResumeThread, SuspendThread, TerminateProcess,
 };
-use winapi::um::winbase::QueryFullProcessImageNameW;
+use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS_EX};
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::{QueryFullProcessImageNameW, WAIT_OBJECT_0};
 use winapi::um::winnt::{
-    ACCESS_MASK, HANDLE, MAXIMUM_ALLOWED, PROCESS_QUERY_INFORMATION, PROCESS_SUSPEND_RESUME,
-    PROCESS_VM_READ, THREAD_ALL_ACCESS, THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION, WCHAR,
+    ACCESS_MASK, HANDLE, MAXIMUM_ALLOWED, PROCESS_QUERY_INFORMATION,
+    PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SUSPEND_RESUME, PROCESS_TERMINATE, PROCESS_VM_READ,
+    SYNCHRONIZE, THREAD_ALL_ACCESS, THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION, WCHAR,
 };
 
 pub use read_process_memory::{CopyAddress, Pid, ProcessHandle};
 
 pub type Tid = Pid;
 
-use super::Error;
+use super::{Error, ErrorContext, ResultExt};
 
+mod exit;
+mod heap;
 #[cfg(feature = "unwind")]
 mod symbolication;
 #[cfg(feature = "unwind")]
 mod unwinder;
 
+pub use self::exit::ExitNotification;
+pub use self::heap::{Arena, HeapChunk, HeapWalker};
 #[cfg(feature = "unwind")]
 pub use self::symbolication::Symbolicator;
 #[cfg(feature = "unwind")]
@@ -31,6 +43,55 @@ pub use self::unwinder::Unwinder;
 pub struct Process {
     pub pid: Pid,
     pub handle: ProcessHandle,
+    access: ProcessAccess,
+}
+
+/// The access rights a process is opened with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProcessAccess {
+    /// Everything this crate needs, including suspending the process and reading the
+    /// registers of its threads. This is the default.
+    #[default]
+    Full,
+    /// Only PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_VM_READ and SYNCHRONIZE, which are
+    /// granted for some protected processes and under security policies that deny the full
+    /// set. Memory can be read, basic information queried and the process waited on, but it
+    /// can't be locked and its threads can't be listed.
+    Limited,
+}
+
+/// Converts a FILETIME, which counts 100ns intervals since 1601, to a SystemTime
+fn filetime_to_system_time(time: &FILETIME) -> SystemTime {
+    // the number of 100ns intervals between 1601 and 1970
+    const UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+    SystemTime::UNIX_EPOCH
+        + Duration::from_nanos(filetime_intervals(time).saturating_sub(UNIX_EPOCH) * 100)
+}
+
+/// Converts a FILETIME holding a duration, rather than a point in time, to a Duration
+fn filetime_to_duration(time: &FILETIME) -> Duration {
+    Duration::from_nanos(filetime_intervals(time) * 100)
+}
+
+fn filetime_intervals(time: &FILETIME) -> u64 {
+    ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64
+}
+
+/// Returns whether a thread is running. Getting whether a thread is active or not is
+/// surprisingly difficult on windows, so this assumes that threads in a syscall are idle.
+fn is_active(thread: HANDLE) -> bool {
+    unsafe {
+        let mut data = std::mem::zeroed::<THREAD_LAST_SYSCALL_INFORMATION>();
+        let ret = NtQueryInformationThread(
+            thread,
+            21,
+            &mut data as *mut _ as *mut VOID,
+            size_of::<THREAD_LAST_SYSCALL_INFORMATION>() as u32,
+            NULL as *mut u32,
+        );
+        // if we're not in a syscall, we're active
+        ret != 0
+    }
 }
 
 #[link(name = "ntdll")]
@@ -77,31 +138,144 @@ extern "system" {
 
 impl Process {
     pub fn new(pid: Pid) -> Result<Self, Error> {
+        Self::with_access(pid, ProcessAccess::Full)
+    }
+
+    /// Opens a process with the given access rights
+    pub fn with_access(pid: Pid, access: ProcessAccess) -> Result<Self, Error> {
         // we can't just use try_into_process_handle here because we need some additional permissions
-        unsafe {
-            let handle = OpenProcess(
+        let rights = match access {
+            ProcessAccess::Full => {
                 PROCESS_VM_READ
                     | PROCESS_SUSPEND_RESUME
                     | PROCESS_QUERY_INFORMATION
                     | THREAD_QUERY_INFORMATION
-                    | THREAD_GET_CONTEXT,
-                FALSE,
-                pid,
-            );
-            if handle == (0 as std::os::windows::io::RawHandle) {
+                    | THREAD_GET_CONTEXT
+                    | SYNCHRONIZE
+            }
+            ProcessAccess::Limited => {
+                PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ | SYNCHRONIZE
+            }
+        };
+        unsafe {
+            let handle = OpenProcess(rights, FALSE, pid);
+            if handle == (0 as RawHandle) {
                 return Err(Error::from(std::io::Error::last_os_error()));
             }
             Ok(Self {
                 pid,
                 handle: handle.into(),
+                access,
             })
         }
     }
 
+    pub fn access(&self) -> ProcessAccess {
+        self.access
+    }
+
+    /// Returns what can be done with this process, given the access it was opened with
+    pub fn capabilities(&self) -> crate::Capabilities {
+        let full = self.access == ProcessAccess::Full;
+        crate::Capabilities {
+            can_read_mem: crate::can_read_memory(self, self.pid),
+            can_suspend: full,
+            can_unwind: cfg!(feature = "unwind") && full,
+            can_symbolicate: cfg!(feature = "unwind") && full,
+            // the process isn't opened with PROCESS_VM_WRITE
+            can_write: false,
+        }
+    }
+
+    /// Returns an error if the process was opened with limited access
+    fn check_full_access(&self) -> Result<(), Error> {
+        match self.access {
+            ProcessAccess::Full => Ok(()),
+            ProcessAccess::Limited => Err(Error::Other(format!(
+                "Process {} was opened with limited access",
+                self.pid
+            ))),
+        }
+    }
+
+    /// Creates a process from a handle that was already opened elsewhere, like one inherited
+    /// from or duplicated by a broker process. The handle needs the same access rights that
+    /// `new` asks for: PROCESS_VM_READ, PROCESS_SUSPEND_RESUME, PROCESS_QUERY_INFORMATION and
+    /// SYNCHRONIZE.
+    ///
+    /// # Safety
+    ///
+    /// The handle must be a valid process handle. Ownership of it is transferred to the
+    /// returned process, which closes it when dropped.
+    pub unsafe fn from_raw_handle(handle: RawHandle) -> Result<Self, Error> {
+        let pid = GetProcessId(handle);
+        if pid == 0 {
+            return Err(Error::from(std::io::Error::last_os_error()));
+        }
+        Ok(Self {
+            pid,
+            handle: handle.into(),
+            access: ProcessAccess::Full,
+        })
+    }
+
     pub fn handle(&self) -> ProcessHandle {
         self.handle.clone()
     }
 
+    /// Returns the raw process handle, which stays valid for as long as this process object
+    /// (or any handle returned by `handle`) is alive
+    pub fn raw_handle(&self) -> RawHandle {
+        *self.handle
+    }
+
+    /// Returns true if the process hasn't exited. Since we hold a handle to the process, its
+    /// pid can't be reused by another process while this is alive.
+    pub fn exists(&self) -> bool {
+        unsafe {
+            match WaitForSingleObject(*self.handle, 0) {
+                WAIT_TIMEOUT => true,
+                WAIT_OBJECT_0 => false,
+                // handles passed to from_raw_handle may lack SYNCHRONIZE. The exit code can't
+                // tell a running process from one that exited with STILL_ACTIVE (259), but it's
+                // the best that can be done without waiting on the handle
+                _ => {
+                    let mut code: DWORD = 0;
+                    GetExitCodeProcess(*self.handle, &mut code) != 0 && code == STILL_ACTIVE
+                }
+            }
+        }
+    }
+
+    /// Terminates the process with `TerminateProcess`, making it exit with `exit_code`
+    pub fn terminate(&self, exit_code: u32) -> Result<(), Error> {
+        unsafe {
+            // the handle we already have doesn't have terminate access, so open another one.
+            // Since we are holding a handle to the process, this pid still refers to it.
+            let handle = OpenProcess(PROCESS_TERMINATE, FALSE, self.pid);
+            if handle == (0 as RawHandle) {
+                return Err(Error::from(std::io::Error::last_os_error()));
+            }
+            let handle: ProcessHandle = handle.into();
+            if TerminateProcess(*handle, exit_code) == 0 {
+                return Err(Error::from(std::io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Kills the process. This is the equivalent of sending SIGKILL on unix, and makes the
+    /// process exit with a status of 1. Windows has no signals, so unlike the other platforms
+    /// there is no `Process::signal`: `terminate` and `kill` are the only ways to stop it.
+    pub fn kill(&self) -> Result<(), Error> {
+        self.terminate(1)
+    }
+
+    /// Returns true if the process hasn't exited. This is the same as `exists` on windows.
+    pub fn is_alive(&self) -> bool {
+        self.exists()
+    }
+
     pub fn exe(&self) -> Result<String, Error> {
         unsafe {
             let mut size = MAX_PATH as DWORD;
@@ -116,7 +290,22 @@ impl Process {
         }
     }
 
+    /// Returns the Remote Desktop Services session the process runs in. Services run in
+    /// session 0, and each logged in user gets a session of their own. Windows has no process
+    /// groups, so there is no `process_group` here.
+    pub fn session_id(&self) -> Result<u32, Error> {
+        let mut session: DWORD = 0;
+        if unsafe { ProcessIdToSessionId(self.pid, &mut session) } == FALSE {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(session)
+    }
+
+    /// Suspends the whole process with NtSuspendProcess until the lock is dropped. Unlike
+    /// suspending each thread from a snapshot of the thread list, this also holds threads that
+    /// are created while the lock is being taken (or while it is held).
     pub fn lock(&self) -> Result<Lock, Error> {
+        self.check_full_access()?;
         Lock::new(self.handle.clone())
     }
 
@@ -172,25 +361,62 @@ impl Process {
         }
     }
 
-    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
-        let mut ret = Vec::new();
+    /// Returns the start time, memory usage and thread count of the process. Windows has no
+    /// single call for these, and the threads are counted by walking them with NtGetNextThread
+    /// rather than snapshotting every process on the system, so this needs full access.
+    pub fn stats(&self) -> Result<crate::ProcessStats, Error> {
+        let context = || ErrorContext::new("reading the process stats").pid(self.pid);
         unsafe {
-            let mut thread: HANDLE = std::mem::zeroed();
-            while NtGetNextThread(
+            let mut created = std::mem::zeroed::<FILETIME>();
+            let mut unused = std::mem::zeroed::<FILETIME>();
+            if GetProcessTimes(
                 *self.handle,
-                thread,
-                MAXIMUM_ALLOWED,
-                0,
-                0,
-                &mut thread as *mut HANDLE,
-            ) == 0
+                &mut created,
+                &mut unused,
+                &mut unused,
+                &mut unused,
+            ) == FALSE
+            {
+                return Err(std::io::Error::last_os_error()).context(context);
+            }
+
+            let mut counters = std::mem::zeroed::<PROCESS_MEMORY_COUNTERS_EX>();
+            counters.cb = size_of_val(&counters) as DWORD;
+            if GetProcessMemoryInfo(*self.handle, &mut counters as *mut _ as *mut _, counters.cb)
+                == FALSE
             {
-                ret.push(Thread {
-                    thread: thread.into(),
-                });
+                return Err(std::io::Error::last_os_error()).context(context);
             }
+
+            Ok(crate::ProcessStats {
+                start_time: filetime_to_system_time(&created),
+                uid: None,
+                memory: crate::MemoryStats {
+                    resident: counters.WorkingSetSize as u64,
+                    virtual_size: counters.PrivateUsage as u64,
+                },
+                thread_count: self
+                    .iter_threads()
+                    .context(context)?
+                    .filter(Result::is_ok)
+                    .count(),
+            })
         }
-        Ok(ret)
+    }
+
+    pub fn threads(&self) -> Result<Vec<Thread>, Error> {
+        self.iter_threads()?.collect()
+    }
+
+    /// Returns an iterator over the threads of the process, which opens each thread as it goes
+    /// instead of listing every thread up front. This is cheaper than `threads` for processes
+    /// with many threads, when only some of them are needed.
+    pub fn iter_threads(&self) -> Result<ThreadIter<'_>, Error> {
+        self.check_full_access()?;
+        Ok(ThreadIter {
+            process: self,
+            previous: None,
+        })
     }
 
     pub fn child_processes(&self) -> Result<Vec<(Pid, Pid)>, Error> {
@@ -239,7 +465,11 @@ impl Process {
 
 impl super::ProcessMemory for Process {
     fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
-        Ok(self.handle.copy_address(addr, buf)?)
+        self.handle.copy_address(addr, buf).context(|| {
+            ErrorContext::new("reading memory")
+                .pid(self.pid)
+                .address(addr, buf.len())
+        })
     }
 }
 
@@ -248,12 +478,47 @@ pub struct Thread {
     thread: ProcessHandle,
 }
 
+/// Iterates over the threads of a process, as returned by `Process::iter_threads`
+pub struct ThreadIter<'a> {
+    process: &'a Process,
+    /// NtGetNextThread continues from the last thread that was opened, so we hold on to it
+    previous: Option<ProcessHandle>,
+}
+
+impl Iterator for ThreadIter<'_> {
+    type Item = Result<Thread, Error>;
+
+    fn next(&mut self) -> Option<Result<Thread, Error>> {
+        let previous = match self.previous.as_ref() {
+            Some(previous) => **previous,
+            None => NULL,
+        };
+        let mut thread: HANDLE = NULL;
+        let status = unsafe {
+            NtGetNextThread(
+                *self.process.handle,
+                previous,
+                MAXIMUM_ALLOWED,
+                0,
+                0,
+                &mut thread as *mut HANDLE,
+            )
+        };
+        if status != 0 {
+            return None;
+        }
+        let thread: ProcessHandle = thread.into();
+        self.previous = Some(thread.clone());
+        Some(Ok(Thread { thread }))
+    }
+}
+
 impl Thread {
     pub fn new(tid: Tid) -> Result<Self, Error> {
         // we can't just use try_into_prcess_handle here because we need some additional permissions
         unsafe {
             let thread = OpenThread(THREAD_ALL_ACCESS, FALSE, tid);
-            if thread == (0 as std::os::windows::io::RawHandle) {
+            if thread == (0 as RawHandle) {
                 return Err(Error::from(std::io::Error::last_os_error()));
             }
 
@@ -270,41 +535,110 @@ impl Thread {
         unsafe { Ok(GetThreadId(*self.thread)) }
     }
 
+    /// The id and creation time of the thread, since thread ids are reused after a thread
+    /// exits. Both can still be read from the handle after the thread has exited.
+    pub(crate) fn thread_key(&self) -> Result<crate::threads::ThreadKey, Error> {
+        unsafe {
+            let mut creation = std::mem::zeroed::<FILETIME>();
+            let mut unused = std::mem::zeroed::<FILETIME>();
+            if GetThreadTimes(
+                *self.thread,
+                &mut creation,
+                &mut unused,
+                &mut unused,
+                &mut unused,
+            ) == FALSE
+            {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let created = (creation.dwHighDateTime as u64) << 32 | creation.dwLowDateTime as u64;
+            Ok((GetThreadId(*self.thread) as u64, created))
+        }
+    }
+
+    /// Returns the CPU time (user and kernel) the thread has used
+    pub fn cpu_time(&self) -> Result<Duration, Error> {
+        unsafe {
+            let mut unused = std::mem::zeroed::<FILETIME>();
+            let mut kernel = std::mem::zeroed::<FILETIME>();
+            let mut user = std::mem::zeroed::<FILETIME>();
+            if GetThreadTimes(
+                *self.thread,
+                &mut unused,
+                &mut unused,
+                &mut kernel,
+                &mut user,
+            ) == FALSE
+            {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(filetime_to_duration(&kernel) + filetime_to_duration(&user))
+        }
+    }
+
+    /// Returns the thread handle, which was opened with THREAD_ALL_ACCESS
+    pub fn handle(&self) -> ProcessHandle {
+        self.thread.clone()
+    }
+
+    /// Returns the raw thread handle, which stays valid for as long as this thread object
+    /// (or any handle returned by `handle`) is alive
+    pub fn raw_handle(&self) -> RawHandle {
+        *self.thread
+    }
+
     pub fn thread_name(&self) -> Result<Option<String>, Error> {
         Ok(None)
     }
 
     pub fn active(&self) -> Result<bool, Error> {
-        // Getting whether a thread is active or not is surprisingly difficult on windows
-        // we're getting the syscall the thread is doing here, and then checking against a list
-        // of known waiting syscalls to get this
+        Ok(is_active(*self.thread))
+    }
+
+    /// Returns how many times the thread has been suspended without being resumed, including
+    /// by a `ThreadLock` or a `Lock` on its process. A thread that stays suspended while
+    /// nothing has it locked was usually left behind by a debugger.
+    pub fn suspend_count(&self) -> Result<u32, Error> {
         unsafe {
-            let mut data = std::mem::zeroed::<THREAD_LAST_SYSCALL_INFORMATION>();
+            let mut count: ULONG = 0;
             let ret = NtQueryInformationThread(
                 *self.thread,
-                21,
-                &mut data as *mut _ as *mut VOID,
-                size_of::<THREAD_LAST_SYSCALL_INFORMATION>() as u32,
+                35, // ThreadSuspendCount
+                &mut count as *mut _ as *mut VOID,
+                size_of::<ULONG>() as u32,
                 NULL as *mut u32,
             );
-
-            // if we're not in a syscall, we're active
-            if ret != 0 {
-                return Ok(true);
+            if ret == 0 {
+                return Ok(count);
             }
 
-            // otherwise assume we're idle
-            Ok(false)
+            // ThreadSuspendCount needs Windows 8.1, before that suspending the thread returns
+            // the previous count
+            let previous = SuspendThread(*self.thread);
+            if previous.wrapping_add(1) == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            if ResumeThread(*self.thread).wrapping_add(1) == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(previous)
         }
     }
+
+    /// Returns true if the thread is suspended
+    pub fn is_stopped(&self) -> Result<bool, Error> {
+        Ok(self.suspend_count()? > 0)
+    }
 }
 
 pub struct Lock {
     process: ProcessHandle,
+    locked_at: Instant,
 }
 
 impl Lock {
     pub fn new(process: ProcessHandle) -> Result<Self, Error> {
+        let locked_at = Instant::now();
         unsafe {
             let ret = NtSuspendProcess(*process);
             if ret != 0 {
@@ -313,7 +647,12 @@ impl Lock {
                 )));
             }
         }
-        Ok(Self { process })
+        Ok(Self { process, locked_at })
+    }
+
+    /// How long the process has been held suspended by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.locked_at.elapsed()
     }
 }
 
@@ -327,25 +666,48 @@ impl Drop for Lock {
                     std::io::Error::from_raw_os_error(RtlNtStatusToDosError(ret) as i32)
                 );
             }
+            crate::pause_metrics()
+                .record_process(GetProcessId(*self.process), self.locked_at.elapsed());
         }
     }
 }
 
 pub struct ThreadLock {
     thread: ProcessHandle,
+    suspended_at: Instant,
+    was_active: Option<bool>,
 }
 
 impl ThreadLock {
     pub fn new(thread: ProcessHandle) -> Result<Self, Error> {
         unsafe {
+            let suspended_at = Instant::now();
             let ret = SuspendThread(*thread);
             if ret.wrapping_add(1) == 0 {
                 return Err(std::io::Error::last_os_error().into());
             }
 
-            Ok(Self { thread })
+            // the last syscall of a thread can only be read once it's suspended, so this is
+            // the state it was stopped in
+            let was_active = Some(is_active(*thread));
+            Ok(Self {
+                thread,
+                suspended_at,
+                was_active,
+            })
         }
     }
+
+    /// How long the thread has been held suspended by this lock
+    pub fn paused_for(&self) -> Duration {
+        self.suspended_at.elapsed()
+    }
+
+    /// Whether the thread was running when it was suspended by this lock, going by whether it
+    /// was in the middle of a syscall
+    pub fn was_active(&self) -> Option<bool> {
+        self.was_active
+    }
 }
 
 impl Drop for ThreadLock {
@@ -357,6 +719,8 @@ impl Drop for ThreadLock {
                     std::io::Error::last_os_error()
                 );
             }
+            crate::pause_metrics()
+                .record_thread(GetThreadId(*self.thread), self.suspended_at.elapsed());
         }
     }
 }
@@ -380,3 +744,15 @@ struct PROCESS_BASIC_INFORMATION {
 }
 
 unsafe impl Send for Process {}
+
+impl AsRawHandle for Process {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.raw_handle()
+    }
+}
+
+impl AsRawHandle for Thread {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.raw_handle()
+    }
+}
@@ -42,11 +42,11 @@ impl Symbolicator {
         line_info: bool,
         callback: &mut dyn FnMut(&StackFrame),
     ) -> Result<(), Error> {
-        let function = unsafe { self.symbol_function(addr) };
+        let symbol = unsafe { self.symbol_info(addr) };
 
         let module = match unsafe { self.symbol_module(addr) } {
             Ok(module) => module,
-            Err(Error::NoBinaryForAddress(_)) => unsafe {
+            Err(e) if matches!(e.root(), Error::NoBinaryForAddress(_)) => unsafe {
                 SymRefreshModuleList(self.handle);
                 self.symbol_module(addr).unwrap_or_else(|_| "?".to_owned())
             },
@@ -62,18 +62,49 @@ impl Symbolicator {
                 filename = Some(f);
             }
         }
+        let (function, function_start, function_size) = match symbol {
+            // dbghelp reports a size of 0 when the symbol doesn't have one
+            Some((name, start, size)) => (Some(name), Some(start), Some(size).filter(|&s| s != 0)),
+            None => (None, None, None),
+        };
         callback(&StackFrame {
             function,
+            function_start,
+            function_size,
             filename,
             line,
+            // dbghelp only has line numbers
+            column: None,
             module,
             addr,
         });
         Ok(())
     }
 
+    /// Symbolicates a batch of addresses, returning the frames for each address in the same
+    /// order as `addrs`. dbghelp has no bulk lookup, so this symbolicates each address in turn.
+    pub fn symbolicate_all(
+        &self,
+        addrs: &[u64],
+        line_info: bool,
+    ) -> Vec<Result<Vec<StackFrame>, Error>> {
+        addrs
+            .iter()
+            .map(|&addr| {
+                let mut frames = Vec::new();
+                self.symbolicate(addr, line_info, &mut |frame| frames.push(frame.clone()))?;
+                Ok(frames)
+            })
+            .collect()
+    }
+
     // returns the corresponding function name for an address
     pub unsafe fn symbol_function(&self, addr: u64) -> Option<String> {
+        self.symbol_info(addr).map(|(name, _, _)| name)
+    }
+
+    // returns the name, start address and size of the function containing an address
+    unsafe fn symbol_info(&self, addr: u64) -> Option<(String, u64, u64)> {
         let mut buffer = std::mem::zeroed::<SymbolBuffer>();
         let symbol_info = &mut *(buffer.buffer.as_mut_ptr() as *mut SYMBOL_INFOW);
         symbol_info.MaxNameLen = MAX_SYM_NAME as u32;
@@ -92,7 +123,11 @@ impl Symbolicator {
         );
         let symbol = std::slice::from_raw_parts(symbol_info.Name.as_ptr(), length);
         let symbol = std::ffi::OsString::from_wide(symbol);
-        Some(symbol.to_string_lossy().to_string())
+        Some((
+            symbol.to_string_lossy().to_string(),
+            symbol_info.Address,
+            symbol_info.Size.into(),
+        ))
     }
 
     // get the corresponding filename/link
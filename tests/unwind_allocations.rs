@@ -0,0 +1,74 @@
+//! Checks that unwinding doesn't allocate once the unwind tables are loaded. This is its own
+//! test binary since it replaces the global allocator, which would otherwise count the
+//! allocations of every other test running at the same time.
+#![allow(unused_crate_dependencies)]
+#![cfg(all(
+    target_os = "linux",
+    feature = "unwind",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use remoteprocess::{Pid, Process, SnapshotUnwinder};
+
+/// Counts the allocations made by each thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A child process that is killed when this is dropped, so that a failing test doesn't leave
+/// it running
+struct Sleep(std::process::Child);
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_unwind_without_allocating() {
+    let child = Sleep(
+        std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap(),
+    );
+    // wait for it to finish starting up, so that its stack is the one of sleep
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let pid = child.0.id() as Pid;
+    let process = Process::new(pid).unwrap();
+    let _lock = process.lock().unwrap();
+    let snapshot = process.threads().unwrap()[0].snapshot().unwrap();
+
+    // the first unwind loads the unwind tables and the unwind context
+    let unwinder = SnapshotUnwinder::new(pid).unwrap();
+    let mut frames = Vec::with_capacity(4096);
+    unwinder.unwind_into(&snapshot, &mut frames).unwrap();
+    assert!(frames.len() > 1);
+    let expected = frames.clone();
+
+    let allocations = ALLOCATIONS.with(Cell::get);
+    unwinder.unwind_into(&snapshot, &mut frames).unwrap();
+    assert_eq!(ALLOCATIONS.with(Cell::get), allocations);
+    assert_eq!(frames, expected);
+}